@@ -0,0 +1,289 @@
+// An optional background process that keeps recent helper answers in memory,
+// like git's credential-cache, so a burst of git commands doesn't resolve the
+// account and read the keychain every time. The helper starts it on first
+// use and it exits once its entries have expired. It listens on a unix
+// socket that only the user can open; elsewhere caching is unavailable.
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+#[cfg(unix)]
+use crate::paths;
+#[cfg(unix)]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+// Keeps a freshly started daemon alive until the helper that started it
+// has stored its first entry
+#[cfg(unix)]
+const IDLE_GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedCredential {
+    pub account_id: String,
+    pub host: String,
+    pub username: String,
+    pub token: String,
+    pub source: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum CacheRequest {
+    Get {
+        key: String,
+    },
+    Store {
+        key: String,
+        credential: CachedCredential,
+        ttl_secs: u64,
+    },
+    Erase {
+        host: String,
+        username: String,
+    },
+    Clear,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheResponse {
+    credential: Option<CachedCredential>,
+}
+
+// Directory rules depend on where git runs, so the working directory is part
+// of the key along with the repository
+pub fn cache_key(repo_url: &str, cwd: Option<&Path>) -> String {
+    match cwd {
+        Some(cwd) => format!("{}\n{}", repo_url, cwd.display()),
+        None => repo_url.to_string(),
+    }
+}
+
+// None when nothing is cached or the daemon is not running
+pub fn get(key: &str) -> Option<CachedCredential> {
+    send(&CacheRequest::Get {
+        key: key.to_string(),
+    })
+    .ok()
+    .and_then(|response| response.credential)
+}
+
+// Starts the daemon when it is not running yet
+pub fn store(key: &str, credential: &CachedCredential, ttl_secs: u64) -> io::Result<()> {
+    let request = CacheRequest::Store {
+        key: key.to_string(),
+        credential: credential.clone(),
+        ttl_secs,
+    };
+    if send(&request).is_ok() {
+        return Ok(());
+    }
+
+    start_daemon()?;
+    let mut last_error = None;
+    // Give the new process a moment to bind its socket
+    for _ in 0..20 {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        match send(&request) {
+            Ok(_) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("Credential cache did not start")))
+}
+
+// Drops every cached answer for an account, e.g. after its token was rejected
+pub fn erase(host: &str, username: &str) -> io::Result<()> {
+    ignore_not_running(send(&CacheRequest::Erase {
+        host: host.to_string(),
+        username: username.to_string(),
+    }))
+}
+
+// Drops everything, for when mappings, rules or accounts change
+pub fn clear() -> io::Result<()> {
+    ignore_not_running(send(&CacheRequest::Clear))
+}
+
+// clear() for callers that carry on regardless: a failure only leaves the
+// old answers until they expire
+pub fn invalidate() {
+    if let Err(e) = clear() {
+        tracing::warn!(error = %e, "failed to clear the credential cache");
+    }
+}
+
+#[cfg(unix)]
+fn socket_path() -> io::Result<PathBuf> {
+    Ok(paths::app_data_dir()?.join("helper-cache.sock"))
+}
+
+fn ignore_not_running(result: io::Result<CacheResponse>) -> io::Result<()> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::Unsupported
+            ) =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn send(request: &CacheRequest) -> io::Result<CacheResponse> {
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+fn send(_request: &CacheRequest) -> io::Result<CacheResponse> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Credential caching needs unix sockets",
+    ))
+}
+
+#[cfg(unix)]
+fn start_daemon() -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    // Its own process group, so a Ctrl-C aimed at git doesn't stop it
+    Command::new(std::env::current_exe()?)
+        .arg("credential-cache-daemon")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn start_daemon() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Credential caching needs unix sockets",
+    ))
+}
+
+#[cfg(unix)]
+struct CacheState {
+    entries: HashMap<String, (CachedCredential, Instant)>,
+    last_request: Instant,
+}
+
+// Serves cache requests until every entry has expired
+#[cfg(unix)]
+pub fn run_daemon() -> io::Result<()> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = socket_path()?;
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            // Another daemon is already serving
+            return Ok(());
+        }
+        // Left behind by a daemon that was killed
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    let state = Arc::new(Mutex::new(CacheState {
+        entries: HashMap::new(),
+        last_request: Instant::now(),
+    }));
+
+    let reaper_state = Arc::clone(&state);
+    let reaper_path = path.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let mut state = reaper_state.lock().unwrap();
+        let now = Instant::now();
+        state.entries.retain(|_, (_, expires)| *expires > now);
+        if state.entries.is_empty() && now.duration_since(state.last_request) > IDLE_GRACE {
+            let _ = fs::remove_file(&reaper_path);
+            std::process::exit(0);
+        }
+    });
+
+    // One bad client must not take the cache down
+    for stream in listener.incoming().flatten() {
+        let _ = handle_client(stream, &state);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn run_daemon() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Credential caching needs unix sockets",
+    ))
+}
+
+#[cfg(unix)]
+fn handle_client(stream: UnixStream, state: &Mutex<CacheState>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request: CacheRequest = serde_json::from_str(&line)?;
+
+    let mut response = CacheResponse::default();
+    {
+        let mut state = state.lock().unwrap();
+        let now = Instant::now();
+        state.last_request = now;
+        match request {
+            CacheRequest::Get { key } => {
+                response.credential = state
+                    .entries
+                    .get(&key)
+                    .filter(|(_, expires)| *expires > now)
+                    .map(|(credential, _)| credential.clone());
+            }
+            CacheRequest::Store {
+                key,
+                credential,
+                ttl_secs,
+            } => {
+                let expires = now + Duration::from_secs(ttl_secs);
+                state.entries.insert(key, (credential, expires));
+            }
+            CacheRequest::Erase { host, username } => {
+                state.entries.retain(|_, (credential, _)| {
+                    !(credential.host.eq_ignore_ascii_case(&host)
+                        && credential.username == username)
+                });
+            }
+            CacheRequest::Clear => state.entries.clear(),
+        }
+    }
+
+    let mut stream = stream;
+    serde_json::to_writer(&mut stream, &response)?;
+    stream.write_all(b"\n")
+}
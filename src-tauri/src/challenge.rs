@@ -0,0 +1,243 @@
+// WWW-Authenticate challenges from a failed request, which git 2.41 and
+// later passes to credential helpers as wwwauth[] lines, e.g.
+//   Basic realm="GitHub"
+//   Bearer realm="GitHub", error="insufficient_scope", scope="repo"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthChallenge {
+    pub scheme: String,
+    // Parameter names are lowercased, quoted values unquoted
+    pub params: Vec<(String, String)>,
+}
+
+impl AuthChallenge {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (scheme, rest) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        if scheme.is_empty() {
+            return None;
+        }
+
+        let params = split_params(rest)
+            .into_iter()
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value.trim())))
+            .collect();
+        Some(Self {
+            scheme: scheme.to_string(),
+            params,
+        })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+}
+
+// What the server's challenges say went wrong, phrased as what to do about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeHint {
+    // The organization enforces SAML single sign-on and the token hasn't
+    // been authorized for it
+    SsoRequired {
+        owner: Option<String>,
+        url: Option<String>,
+    },
+    MissingScope {
+        scope: String,
+    },
+}
+
+impl ChallengeHint {
+    pub fn from_challenges(challenges: &[AuthChallenge], owner: Option<&str>) -> Option<Self> {
+        let mentions_sso = |value: &str| {
+            value
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .any(|word| word.eq_ignore_ascii_case("sso") || word.eq_ignore_ascii_case("saml"))
+        };
+        for challenge in challenges {
+            if challenge
+                .params
+                .iter()
+                .any(|(name, value)| mentions_sso(name) || mentions_sso(value))
+            {
+                return Some(ChallengeHint::SsoRequired {
+                    owner: owner.map(|owner| owner.to_string()),
+                    url: challenge
+                        .param("url")
+                        .or_else(|| challenge.param("sso"))
+                        .filter(|url| url.starts_with("https://"))
+                        .map(|url| url.to_string()),
+                });
+            }
+            if challenge.is_scheme("Bearer")
+                && challenge.param("error") == Some("insufficient_scope")
+            {
+                return Some(ChallengeHint::MissingScope {
+                    scope: challenge.param("scope").unwrap_or("required").to_string(),
+                });
+            }
+        }
+        None
+    }
+
+    pub fn message(&self, username: Option<&str>) -> String {
+        let token = match username {
+            Some(username) => format!("the token for {}", username),
+            None => "the token".to_string(),
+        };
+        match self {
+            ChallengeHint::SsoRequired { owner, url } => {
+                let mut message = match owner {
+                    Some(owner) => format!(
+                        "{} enforces SAML single sign-on; authorize {} for the {} organization",
+                        owner, token, owner
+                    ),
+                    None => format!(
+                        "single sign-on is required; authorize {} for this organization",
+                        token
+                    ),
+                };
+                if let Some(url) = url {
+                    message.push_str(&format!(" at {}", url));
+                }
+                message
+            }
+            ChallengeHint::MissingScope { scope } => {
+                format!("{} is missing the {} scope", token, scope)
+            }
+        }
+    }
+}
+
+// Splits auth-params on commas outside quoted strings
+fn split_params(params: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in params.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(params[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(params[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scheme_and_params() {
+        let challenge = AuthChallenge::parse(
+            r#"Bearer realm="GitHub", error="insufficient_scope", scope="repo, workflow""#,
+        )
+        .unwrap();
+        assert!(challenge.is_scheme("bearer"));
+        assert_eq!(challenge.param("realm"), Some("GitHub"));
+        assert_eq!(challenge.param("error"), Some("insufficient_scope"));
+        assert_eq!(challenge.param("scope"), Some("repo, workflow"));
+
+        let challenge = AuthChallenge::parse("Basic").unwrap();
+        assert_eq!(challenge.scheme, "Basic");
+        assert!(challenge.params.is_empty());
+        assert_eq!(AuthChallenge::parse("   "), None);
+    }
+
+    #[test]
+    fn unquotes_escaped_values() {
+        let challenge = AuthChallenge::parse(r#"Basic Realm="say \"hi\", \\o/""#).unwrap();
+        assert_eq!(challenge.param("realm"), Some(r#"say "hi", \o/"#));
+    }
+
+    #[test]
+    fn detects_sso() {
+        let challenges = [
+            AuthChallenge::parse(r#"Basic realm="GitHub""#).unwrap(),
+            AuthChallenge::parse(
+                r#"Basic realm="GitHub", sso="https://github.com/orgs/acme/sso?authorization_request=1""#,
+            )
+            .unwrap(),
+        ];
+        let hint = ChallengeHint::from_challenges(&challenges, Some("acme")).unwrap();
+        assert_eq!(
+            hint,
+            ChallengeHint::SsoRequired {
+                owner: Some("acme".to_string()),
+                url: Some("https://github.com/orgs/acme/sso?authorization_request=1".to_string()),
+            }
+        );
+        assert_eq!(
+            hint.message(Some("octocat")),
+            "acme enforces SAML single sign-on; authorize the token for octocat for the acme \
+             organization at https://github.com/orgs/acme/sso?authorization_request=1"
+        );
+    }
+
+    #[test]
+    fn ignores_non_https_sso_urls() {
+        let challenges =
+            [
+                AuthChallenge::parse(r#"Basic realm="SAML required", url="http://example.com""#)
+                    .unwrap(),
+            ];
+        assert_eq!(
+            ChallengeHint::from_challenges(&challenges, None),
+            Some(ChallengeHint::SsoRequired {
+                owner: None,
+                url: None
+            })
+        );
+    }
+
+    #[test]
+    fn detects_missing_scope() {
+        let challenges = [AuthChallenge::parse(
+            r#"Bearer realm="GitHub", error="insufficient_scope", scope="workflow""#,
+        )
+        .unwrap()];
+        let hint = ChallengeHint::from_challenges(&challenges, None).unwrap();
+        assert_eq!(
+            hint.message(None),
+            "the token is missing the workflow scope"
+        );
+    }
+
+    #[test]
+    fn plain_challenges_give_no_hint() {
+        let challenges = [
+            AuthChallenge::parse(r#"Basic realm="GitHub""#).unwrap(),
+            AuthChallenge::parse(r#"Bearer realm="GitHub", error="invalid_token""#).unwrap(),
+        ];
+        assert_eq!(
+            ChallengeHint::from_challenges(&challenges, Some("acme")),
+            None
+        );
+    }
+}
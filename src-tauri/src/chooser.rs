@@ -0,0 +1,305 @@
+use crate::database::Account;
+use crate::paths;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the app looks for new requests and the helper for an answer
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// The app rewrites its heartbeat on every poll, an older one means it is not running
+const HEARTBEAT_MAX_AGE: Duration = Duration::from_secs(5);
+// Longer than any chooser timeout, so these were left behind by a killed helper
+const STALE_REQUEST_AGE: Duration = Duration::from_secs(15 * 60);
+const HEARTBEAT_FILE: &str = "listener";
+const REQUEST_SUFFIX: &str = ".request.json";
+const RESPONSE_SUFFIX: &str = ".response.json";
+
+pub enum ChooserResult {
+    // Nobody to ask, e.g. git was started from an IDE
+    Unavailable,
+    Cancelled,
+    TimedOut,
+    Chosen { index: usize, remember: bool },
+}
+
+// Written by the credential helper and shown by the app in a chooser window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChooserRequest {
+    pub id: String,
+    pub repo_url: String,
+    pub host: String,
+    pub account_ids: Vec<String>,
+    // Selected when the window opens; requests from older helpers lack it
+    #[serde(default)]
+    pub preselected_id: Option<String>,
+    pub offer_remember: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChooserResponse {
+    // None when the window was dismissed
+    pub account_id: Option<String>,
+    pub remember: bool,
+}
+
+// Hands the choice to the running app through request and response files in
+// the data directory, waiting at most `timeout` for an answer. `accounts`
+// come best first and the first is preselected, see
+// resolver::chooser_accounts.
+pub fn prompt_gui(
+    accounts: &[Account],
+    repo_url: &str,
+    host: &str,
+    offer_remember: bool,
+    timeout: Duration,
+) -> io::Result<ChooserResult> {
+    let dir = chooser_dir()?;
+    if !app_listening(&dir) {
+        return Ok(ChooserResult::Unavailable);
+    }
+
+    let request = ChooserRequest {
+        id: uuid::Uuid::new_v4().to_string(),
+        repo_url: repo_url.to_string(),
+        host: host.to_string(),
+        account_ids: accounts.iter().map(|a| a.id.clone()).collect(),
+        preselected_id: accounts.first().map(|a| a.id.clone()),
+        offer_remember,
+        created_at: Utc::now(),
+    };
+    let request_path = dir.join(format!("{}{}", request.id, REQUEST_SUFFIX));
+    let response_path = dir.join(format!("{}{}", request.id, RESPONSE_SUFFIX));
+    write_atomic(&request_path, &serde_json::to_vec(&request)?)?;
+
+    let deadline = Instant::now() + timeout;
+    let result = loop {
+        if let Ok(contents) = fs::read(&response_path) {
+            break serde_json::from_slice::<ChooserResponse>(&contents)
+                .map_err(io::Error::from)
+                .map(|response| {
+                    let index = response
+                        .account_id
+                        .and_then(|id| accounts.iter().position(|a| a.id == id));
+                    match index {
+                        Some(index) => ChooserResult::Chosen {
+                            index,
+                            remember: offer_remember && response.remember,
+                        },
+                        None => ChooserResult::Cancelled,
+                    }
+                });
+        }
+        if Instant::now() >= deadline {
+            break Ok(ChooserResult::TimedOut);
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let _ = fs::remove_file(&request_path);
+    let _ = fs::remove_file(&response_path);
+    result
+}
+
+// Called by the app on every poll so helpers know their requests will be seen
+pub fn heartbeat() -> io::Result<()> {
+    write_atomic(
+        &chooser_dir()?.join(HEARTBEAT_FILE),
+        Utc::now().to_rfc3339().as_bytes(),
+    )
+}
+
+// Requests still waiting for an answer, oldest first
+pub fn pending_requests() -> io::Result<Vec<ChooserRequest>> {
+    let dir = chooser_dir()?;
+    let mut requests = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let is_request = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(REQUEST_SUFFIX));
+        if !is_request {
+            continue;
+        }
+
+        let request: ChooserRequest = match fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+        {
+            Some(request) => request,
+            None => continue,
+        };
+        let age = (Utc::now() - request.created_at)
+            .to_std()
+            .unwrap_or_default();
+        if age > STALE_REQUEST_AGE {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        if !dir
+            .join(format!("{}{}", request.id, RESPONSE_SUFFIX))
+            .exists()
+        {
+            requests.push(request);
+        }
+    }
+    requests.sort_by_key(|request| request.created_at);
+    Ok(requests)
+}
+
+pub fn get_request(id: &str) -> io::Result<ChooserRequest> {
+    let contents = fs::read(request_path(id)?)?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+pub fn answer_request(id: &str, response: &ChooserResponse) -> io::Result<()> {
+    let request_path = request_path(id)?;
+    if !request_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The credential helper is no longer waiting for this choice",
+        ));
+    }
+    write_atomic(
+        &chooser_dir()?.join(format!("{}{}", id, RESPONSE_SUFFIX)),
+        &serde_json::to_vec(response)?,
+    )
+}
+
+fn chooser_dir() -> io::Result<PathBuf> {
+    let dir = paths::app_data_dir()?.join("chooser");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Ids come from the frontend, so they must not be able to name other files
+fn request_path(id: &str) -> io::Result<PathBuf> {
+    if uuid::Uuid::parse_str(id).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid chooser request id",
+        ));
+    }
+    Ok(chooser_dir()?.join(format!("{}{}", id, REQUEST_SUFFIX)))
+}
+
+fn app_listening(dir: &Path) -> bool {
+    fs::read_to_string(dir.join(HEARTBEAT_FILE))
+        .ok()
+        .and_then(|contents| DateTime::parse_from_rfc3339(contents.trim()).ok())
+        .and_then(|beat| (Utc::now() - beat.with_timezone(&Utc)).to_std().ok())
+        .is_some_and(|age| age <= HEARTBEAT_MAX_AGE)
+}
+
+// The other side polls these files, so it must never see one half written
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+// Asks on the controlling terminal, since stdin and stdout carry the
+// credential protocol and git reads everything printed to stdout
+pub fn prompt_terminal(
+    accounts: &[Account],
+    repo_url: &str,
+    offer_remember: bool,
+    timeout: Duration,
+) -> io::Result<ChooserResult> {
+    let (input, mut output) = match open_terminal() {
+        Ok(terminal) => terminal,
+        Err(_) => return Ok(ChooserResult::Unavailable),
+    };
+
+    // Reading blocks, so lines arrive from a thread and the wait can time
+    // out. The thread is left blocked when the helper exits.
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(line)) = read_line(&mut input) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let deadline = Instant::now() + timeout;
+    let next_line = || lines.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+
+    writeln!(
+        output,
+        "GitSwitchHub: which account should be used for {}?",
+        repo_url
+    )?;
+    for (i, account) in accounts.iter().enumerate() {
+        match account.label.as_deref().or(account.display_name.as_deref()) {
+            Some(label) => writeln!(output, "  {}) {} ({})", i + 1, account.username, label)?,
+            None => writeln!(output, "  {}) {}", i + 1, account.username)?,
+        }
+    }
+
+    let index = loop {
+        write!(
+            output,
+            "Account [1-{}, Enter for 1, q to cancel]: ",
+            accounts.len()
+        )?;
+        output.flush()?;
+        let line = match next_line() {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                writeln!(output)?;
+                return Ok(ChooserResult::TimedOut);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(ChooserResult::Cancelled),
+        };
+        if line.eq_ignore_ascii_case("q") {
+            return Ok(ChooserResult::Cancelled);
+        }
+        if line.is_empty() {
+            break 0;
+        }
+        match line.parse::<usize>() {
+            Ok(n) if (1..=accounts.len()).contains(&n) => break n - 1,
+            _ => writeln!(output, "Enter a number between 1 and {}", accounts.len())?,
+        }
+    };
+
+    let remember = if offer_remember {
+        write!(output, "Remember this account for {}? [Y/n]: ", repo_url)?;
+        output.flush()?;
+        // Not answering in time keeps the choice without remembering it
+        next_line().is_ok_and(|answer| !answer.to_lowercase().starts_with('n'))
+    } else {
+        false
+    };
+
+    Ok(ChooserResult::Chosen { index, remember })
+}
+
+// None at end of input
+fn read_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+#[cfg(unix)]
+fn open_terminal() -> io::Result<(File, File)> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    Ok((tty.try_clone()?, tty))
+}
+
+#[cfg(windows)]
+fn open_terminal() -> io::Result<(File, File)> {
+    let input = OpenOptions::new().read(true).write(true).open("CONIN$")?;
+    let output = OpenOptions::new().write(true).open("CONOUT$")?;
+    Ok((input, output))
+}
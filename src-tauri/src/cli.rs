@@ -0,0 +1,322 @@
+use crate::cache;
+use crate::database::{Account, Database, DatabaseError};
+use crate::git_config::{self, GitConfigError, GitConfigScope};
+use crate::keychain::KeychainManager;
+use crate::remote;
+use crate::resolver::{self, ResolveRequest};
+use crate::settings::SettingKey;
+use clap::{Parser, Subcommand};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Command line use of the app. Without a subcommand the GUI starts; the
+// hidden subcommands are run by git and by hooks rather than by people.
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+    #[error("Git config error: {0}")]
+    GitConfig(#[from] GitConfigError),
+    #[error("{0}")]
+    Usage(String),
+}
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "gitswitchhub",
+    version,
+    about = "Switch between GitHub accounts in git"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    #[command(about = "Manage accounts", subcommand)]
+    Accounts(AccountsCommand),
+    #[command(about = "Make an account the default, or with --repo the account of a repository")]
+    Use {
+        #[arg(help = "Username, label or id of the account")]
+        account: String,
+        #[arg(long, value_name = "PATH", help = "Repository to use the account for")]
+        repo: Option<PathBuf>,
+    },
+    #[command(about = "Show the account git will use here, or in --repo")]
+    Whoami {
+        #[arg(long, value_name = "PATH")]
+        repo: Option<PathBuf>,
+    },
+    #[command(about = "Map a remote url to an account")]
+    Map {
+        url: String,
+        #[arg(help = "Username, label or id of the account")]
+        account: String,
+    },
+    // Git runs the helper with the operation (get, store or erase) appended
+    // after any options
+    #[command(hide = true)]
+    CredentialHelper {
+        // Shows how an account would be picked for a url, for debugging
+        // pushes that went out as the wrong user
+        #[arg(long, value_name = "URL")]
+        explain: Option<String>,
+        #[arg(long)]
+        non_interactive: bool,
+        #[arg(default_value = "get")]
+        operation: String,
+    },
+    // Started by the credential helper when caching is enabled
+    #[command(hide = true)]
+    CredentialCacheDaemon,
+    // Run by the hooks install_identity_guard writes, from the repository;
+    // pre-push passes the remote's name and url
+    #[command(hide = true)]
+    IdentityGuard {
+        hook: String,
+        remote: Option<String>,
+        url: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AccountsCommand {
+    #[command(about = "List the accounts, the default marked with *")]
+    List,
+}
+
+pub fn accounts_list(db: &Database, out: &mut impl io::Write) -> Result<(), CliError> {
+    let accounts = db.get_accounts()?;
+    if accounts.is_empty() {
+        writeln!(out, "No accounts yet; add one in the app")?;
+        return Ok(());
+    }
+    let default_id = resolver::default_account_id(db)?;
+    for account in &accounts {
+        let marker = if default_id.as_deref() == Some(account.id.as_str()) {
+            "*"
+        } else {
+            " "
+        };
+        let mut line = format!("{} {}  {}", marker, account.username, account.host);
+        if let Some(label) = &account.label {
+            line.push_str(&format!("  [{}]", label));
+        }
+        if let Some(email) = account.commit_email() {
+            line.push_str(&format!("  <{}>", email));
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Without a repository the account becomes the default. With one, the
+// repository's remote is mapped to it and its commit identity set.
+pub fn use_account(
+    db: &Database,
+    account: &str,
+    repo: Option<&Path>,
+    out: &mut impl io::Write,
+) -> Result<(), CliError> {
+    let account = find_account(db, account)?;
+    let Some(repo) = repo else {
+        db.set_setting(
+            SettingKey::DefaultAccount,
+            &serde_json::Value::from(account.id.clone()),
+        )?;
+        cache::invalidate();
+        writeln!(out, "{} is now the default account", account.username)?;
+        return Ok(());
+    };
+
+    let toplevel = git_config::toplevel(repo)?
+        .ok_or_else(|| CliError::Usage(format!("{} isn't a git repository", repo.display())))?;
+    let remote_url = repository_remote(&toplevel)?;
+    let mapping_url = mapping_url(&remote_url);
+    db.set_repository_mapping(&mapping_url, &account.id, true, None)?;
+    cache::invalidate();
+    writeln!(out, "{} now uses {}", mapping_url, account.username)?;
+
+    if git_config::write_identity(
+        &GitConfigScope::Repository(toplevel.clone()),
+        account.git_name.as_deref(),
+        account.commit_email().as_deref(),
+    )? {
+        writeln!(
+            out,
+            "Commits in {} are authored as {}",
+            toplevel.display(),
+            commit_identity(&account)
+        )?;
+    }
+    Ok(())
+}
+
+// The account the credential helper would pick for the repository's remote,
+// or the default account outside a repository
+pub fn whoami(
+    db: &Database,
+    keychain: &KeychainManager,
+    repo: Option<&Path>,
+    out: &mut impl io::Write,
+) -> Result<(), CliError> {
+    let dir = match repo {
+        Some(repo) => repo.to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let toplevel = git_config::toplevel(&dir)?;
+    let (account, reason) = match &toplevel {
+        Some(toplevel) => {
+            let remote_url = repository_remote(toplevel)?;
+            let (repo_url, host) = remote::mapping_target(&remote_url)
+                .ok_or_else(|| CliError::Usage(format!("{} isn't a repository url", remote_url)))?;
+            let resolution = resolver::resolve(
+                db,
+                &ResolveRequest {
+                    repo_url: &repo_url,
+                    host: &host,
+                    owner: remote::repo_owner(&repo_url, "").as_deref(),
+                    path: Some(toplevel),
+                },
+            )?;
+            match resolution.candidates.into_iter().next() {
+                Some(candidate) => (Some(candidate.account), candidate.reason),
+                None => (
+                    None,
+                    format!("nothing applies to {}; git will ask", repo_url),
+                ),
+            }
+        }
+        None => match resolver::default_account_id(db)? {
+            Some(id) => (db.get_account(&id)?, "default account".to_string()),
+            None => (None, "no default account is set".to_string()),
+        },
+    };
+
+    let Some(account) = account else {
+        writeln!(out, "No account: {}", reason)?;
+        return Ok(());
+    };
+    writeln!(out, "{} on {} ({})", account.username, account.host, reason)?;
+    if let Err(e) = keychain.get_token(&account.host, &account.username) {
+        writeln!(out, "No usable token: {}", e)?;
+    }
+    if let Some(toplevel) = &toplevel {
+        let email = git_config::get_entries(Some(toplevel), r"^user\.email$")?
+            .pop()
+            .map(|entry| entry.value);
+        writeln!(
+            out,
+            "Commits are authored as {}",
+            email.as_deref().unwrap_or("nobody: user.email isn't set")
+        )?;
+    }
+    Ok(())
+}
+
+pub fn map(
+    db: &Database,
+    url: &str,
+    account: &str,
+    out: &mut impl io::Write,
+) -> Result<(), CliError> {
+    let account = find_account(db, account)?;
+    if remote::host_from_url(url).is_none() {
+        return Err(CliError::Usage(format!("{} isn't a repository url", url)));
+    }
+    let mapping_url = mapping_url(url);
+    db.set_repository_mapping(&mapping_url, &account.id, true, None)?;
+    cache::invalidate();
+    writeln!(out, "{} now uses {}", mapping_url, account.username)?;
+    Ok(())
+}
+
+// By id, username or label; "username@host" picks between accounts with the
+// same username on different hosts
+fn find_account(db: &Database, name: &str) -> Result<Account, CliError> {
+    let accounts = db.get_accounts()?;
+    if let Some(account) = accounts.iter().find(|account| account.id == name) {
+        return Ok(account.clone());
+    }
+    let (username, host) = match name.rsplit_once('@') {
+        Some((username, host)) => (username, Some(host)),
+        None => (name, None),
+    };
+    let matches: Vec<&Account> = accounts
+        .iter()
+        .filter(|account| {
+            account.username.eq_ignore_ascii_case(username)
+                && host.is_none_or(|host| account.host.eq_ignore_ascii_case(host))
+        })
+        .collect();
+    let matches = if matches.is_empty() {
+        accounts
+            .iter()
+            .filter(|account| {
+                account
+                    .label
+                    .as_deref()
+                    .is_some_and(|label| label.eq_ignore_ascii_case(name))
+            })
+            .collect()
+    } else {
+        matches
+    };
+    match matches[..] {
+        [account] => Ok(account.clone()),
+        [] => Err(CliError::Usage(format!("No account named {}", name))),
+        _ => Err(CliError::Usage(format!(
+            "{} is ambiguous, use one of {}",
+            name,
+            matches
+                .iter()
+                .map(|account| format!("{}@{}", account.username, account.host))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+// origin, or the only remote
+fn repository_remote(repo: &Path) -> Result<String, CliError> {
+    let entries: Vec<_> = git_config::get_entries(Some(repo), r"^remote\..*\.url$")?
+        .into_iter()
+        .filter(|entry| entry.scope == "local")
+        .collect();
+    match entries
+        .iter()
+        .find(|entry| entry.key == "remote.origin.url")
+        .map(|entry| entry.value.clone())
+    {
+        Some(url) => Ok(url),
+        None if entries.len() == 1 => Ok(entries[0].value.clone()),
+        None if entries.is_empty() => {
+            Err(CliError::Usage(format!("{} has no remote", repo.display())))
+        }
+        None => Err(CliError::Usage(format!(
+            "{} has no origin remote; map one of its remotes with `gitswitchhub map`",
+            repo.display()
+        ))),
+    }
+}
+
+// SSH aliases map to the repository on GitHub itself
+fn mapping_url(url: &str) -> String {
+    remote::mapping_target(url)
+        .map(|(mapping_url, _)| mapping_url)
+        .unwrap_or_else(|| remote::canonicalize_remote_url(url))
+}
+
+fn commit_identity(account: &Account) -> String {
+    match (&account.git_name, account.commit_email()) {
+        (Some(name), Some(email)) => format!("{} <{}>", name, email),
+        (Some(name), None) => name.clone(),
+        (None, Some(email)) => email,
+        (None, None) => String::new(),
+    }
+}
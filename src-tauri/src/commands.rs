@@ -1,18 +1,133 @@
-use crate::database::{Account, Database};
-use crate::github_auth::GitHubAuth;
-use crate::keychain::KeychainManager;
+use crate::cache;
+use crate::chooser::{self, ChooserResponse, ChooserResult};
+use crate::conditional_include::{self, ConditionalInclude};
+use crate::config::{self, ConfigImportSummary, ConflictStrategy};
+use crate::database::{
+    Account, AccountOrg, AccountToken, AuditEntry, AuditLogFilter, BackupInfo, Database,
+    DatabaseError, DirectoryRule, GitHost, IntegrityReport, NewSshConfigChange, OrgMapping,
+    Profile, RepositoryMapping, SshConfigChange, TokenMetadata,
+};
+use crate::gh_import;
+use crate::git_config::{self, GitConfigScope};
+use crate::git_helper;
+use crate::github_auth::{
+    self, DeviceFlowProgress, DevicePolling, DeviceTokenResponse, GitHubAuth, GitHubAuthError,
+    GitHubGpgKey, GitHubKey, RepoPermission, SsoStatus, TokenDetails, TokenKind,
+    DEFAULT_GITHUB_HOST,
+};
+use crate::github_client::{self, NetworkSettings, RateLimitStatus};
+use crate::identity_guard;
+use crate::keychain::{
+    self, BiometricSettings, KeychainError, KeychainManager, KeychainStatus, RefreshCredentials,
+};
+use crate::logging;
+use crate::oauth;
+use crate::paths;
+use crate::remote;
+use crate::resolver::{self, ResolveRequest};
+use crate::scanner::{self, ScannedRepository};
+use crate::settings::SettingKey;
+use crate::ssh::{self, SSHError, SSHManager, SshConfigAnalysis, SshKeyType};
+use crate::tray;
+use crate::url_rewrite::{self, UrlRewrite};
 use chrono::Utc;
+use notify::Watcher;
 use serde::{Deserialize, Serialize};
-use tauri::State;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewUrl, WebviewWindowBuilder};
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_opener::OpenerExt;
+use tracing::warn;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountInfo {
     pub id: String,
     pub username: String,
+    pub host: String,
     pub avatar_url: Option<String>,
     pub auth_method: String,
     pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub use_count: i64,
+    pub display_name: Option<String>,
+    pub label: Option<String>,
+    pub color: Option<String>,
+    pub notes: Option<String>,
+    pub deleted_at: Option<String>,
+    pub token_rejected_at: Option<String>,
+    pub unverified: bool,
+    pub ssh_key_path: Option<String>,
+    pub ssh_port_443: bool,
+}
+
+impl From<Account> for AccountInfo {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            username: account.username,
+            host: account.host,
+            avatar_url: account.avatar_url,
+            auth_method: account.auth_method,
+            created_at: account.created_at.to_rfc3339(),
+            last_used_at: account.last_used_at.map(|dt| dt.to_rfc3339()),
+            use_count: account.use_count,
+            display_name: account.display_name,
+            label: account.label,
+            color: account.color,
+            notes: account.notes,
+            deleted_at: account.deleted_at.map(|dt| dt.to_rfc3339()),
+            token_rejected_at: account.token_rejected_at.map(|dt| dt.to_rfc3339()),
+            unverified: account.unverified,
+            ssh_key_path: account.ssh_key_path,
+            ssh_port_443: account.ssh_port_443,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitIdentityInfo {
+    pub account_id: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    // GitHub's private noreply address, offered when no email is set
+    pub suggested_email: Option<String>,
+}
+
+impl From<Account> for GitIdentityInfo {
+    fn from(account: Account) -> Self {
+        let suggested_email = match account.git_email {
+            Some(_) => None,
+            None => account.noreply_email(),
+        };
+        Self {
+            account_id: account.id,
+            name: account.git_name,
+            email: account.git_email,
+            suggested_email,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigningConfigInfo {
+    pub account_id: String,
+    pub format: Option<String>,
+    pub key: Option<String>,
+    pub sign_commits: bool,
+}
+
+impl From<Account> for SigningConfigInfo {
+    fn from(account: Account) -> Self {
+        Self {
+            account_id: account.id,
+            format: account.signing_format,
+            key: account.signing_key,
+            sign_commits: account.sign_commits,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +137,100 @@ pub struct RepositoryMappingInfo {
     pub account_id: String,
     pub remember: bool,
     pub created_at: String,
+    pub token_name: Option<String>,
+    pub last_used_at: Option<String>,
+    pub use_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitHostInfo {
+    pub host: String,
+    pub name: Option<String>,
+    pub api_url: String,
+    pub created_at: String,
+    pub oauth_client_id: Option<String>,
+}
+
+impl From<GitHost> for GitHostInfo {
+    fn from(host: GitHost) -> Self {
+        Self {
+            host: host.host,
+            name: host.name,
+            api_url: host.api_url,
+            created_at: host.created_at.to_rfc3339(),
+            oauth_client_id: host.oauth_client_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrgMappingInfo {
+    pub id: String,
+    pub host: String,
+    pub owner: String,
+    pub account_id: String,
+    pub created_at: String,
+}
+
+impl From<OrgMapping> for OrgMappingInfo {
+    fn from(mapping: OrgMapping) -> Self {
+        Self {
+            id: mapping.id,
+            host: mapping.host,
+            owner: mapping.owner,
+            account_id: mapping.account_id,
+            created_at: mapping.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryRuleInfo {
+    pub id: String,
+    pub pattern: String,
+    pub account_id: String,
+    pub created_at: String,
+}
+
+impl From<DirectoryRule> for DirectoryRuleInfo {
+    fn from(rule: DirectoryRule) -> Self {
+        Self {
+            id: rule.id,
+            pattern: rule.pattern,
+            account_id: rule.account_id,
+            created_at: rule.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: String,
+    pub account_ids: Vec<String>,
+    pub active: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountTokenInfo {
+    pub id: String,
+    pub account_id: String,
+    pub name: String,
+    pub owner: Option<String>,
+    pub created_at: String,
+}
+
+impl From<AccountToken> for AccountTokenInfo {
+    fn from(token: AccountToken) -> Self {
+        AccountTokenInfo {
+            id: token.id,
+            account_id: token.account_id,
+            name: token.name,
+            owner: token.owner,
+            created_at: token.created_at.to_rfc3339(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,17 +243,244 @@ pub struct DeviceCodeInfo {
     pub interval: u64,
 }
 
+// Emitted on "device-flow-progress" while poll_device_flow waits, with a
+// status of "pending", "slow_down", "success", "cancelled" or "error"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceFlowEvent {
+    pub device_code: String,
+    pub status: String,
+    pub message: Option<String>,
+}
+
+const DEVICE_FLOW_EVENT: &str = "device-flow-progress";
+
+// Accounts were added, removed or edited, or the default account changed
+pub const ACCOUNTS_CHANGED_EVENT: &str = "accounts-changed";
+
+fn accounts_changed(app: &AppHandle) {
+    let _ = app.emit(ACCOUNTS_CHANGED_EVENT, ());
+}
+
+// Emitted on "browser-sign-in-progress": "waiting" once the browser was
+// opened, with the URL in case it has to be opened by hand, then "success",
+// "cancelled" or "error"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrowserSignInEvent {
+    pub state: String,
+    pub status: String,
+    pub url: Option<String>,
+    pub message: Option<String>,
+}
+
+const BROWSER_SIGN_IN_EVENT: &str = "browser-sign-in-progress";
+const BROWSER_SIGN_IN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+// Sign-ins waiting for the user, by device code or OAuth state
+#[derive(Default)]
+pub struct SignInFlows(Mutex<HashMap<String, SignInFlow>>);
+
+#[derive(Clone)]
+struct SignInFlow {
+    // Set by the cancel commands
+    cancelled: Arc<AtomicBool>,
+    // Renewing the token of this account rather than adding one
+    account_id: Option<String>,
+    // Only for device flows
+    polling: Option<DevicePolling>,
+}
+
+impl SignInFlows {
+    fn start(
+        &self,
+        id: &str,
+        account_id: Option<&str>,
+        polling: Option<DevicePolling>,
+    ) -> Arc<AtomicBool> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().insert(
+            id.to_string(),
+            SignInFlow {
+                cancelled: Arc::clone(&cancelled),
+                account_id: account_id.map(str::to_string),
+                polling,
+            },
+        );
+        cancelled
+    }
+
+    fn get(&self, id: &str) -> Option<SignInFlow> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    fn finish(&self, id: &str) {
+        self.0.lock().unwrap().remove(id);
+    }
+
+    fn cancel(&self, id: &str) {
+        if let Some(flow) = self.get(id) {
+            flow.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TestConnectionResult {
     pub success: bool,
     pub message: String,
     pub scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub token_kind: Option<TokenKind>,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    // Recommended scopes the token lacks, see reauthorize_account
+    #[serde(default)]
+    pub missing_scopes: Vec<String>,
+    // GitHub couldn't be reached, so nothing is known about the token
+    #[serde(default)]
+    pub offline: bool,
+    // Problems that don't stop the token from working today
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+// Tokens expiring sooner than this are flagged by test_connection
+const TOKEN_EXPIRY_WARNING_DAYS: i64 = 7;
+// Mapped repositories checked against a fine-grained token, one request each
+const MAX_REPO_ACCESS_CHECKS: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountOrgInfo {
+    pub login: String,
+    pub description: Option<String>,
+    pub avatar_url: Option<String>,
+    pub fetched_at: String,
+}
+
+impl From<AccountOrg> for AccountOrgInfo {
+    fn from(org: AccountOrg) -> Self {
+        Self {
+            login: org.login,
+            description: org.description,
+            avatar_url: org.avatar_url,
+            fetched_at: org.fetched_at.to_rfc3339(),
+        }
+    }
+}
+
+// How long fetched organizations are used before asking GitHub again
+const ACCOUNT_ORGS_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepoAccessInfo {
+    // "owner/repo"
+    pub repository: String,
+    pub permission: RepoPermission,
+    pub can_push: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountSuggestion {
+    pub account: AccountInfo,
+    // None when the repository wasn't probed with this account
+    pub permission: Option<RepoPermission>,
+    // The account is the repository owner or a member of the owning org
+    pub owner_match: bool,
+    pub reason: String,
+}
+
+// Probing stops when fewer requests than this are left in the rate limit
+const MIN_RATE_LIMIT_FOR_PROBES: u64 = 100;
+const MAX_SUGGESTION_PROBES: usize = 10;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub account_id: String,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_validated_at: Option<String>,
+    pub days_until_expiry: Option<i64>,
+}
+
+impl From<TokenMetadata> for TokenInfo {
+    fn from(metadata: TokenMetadata) -> Self {
+        TokenInfo {
+            account_id: metadata.account_id,
+            scopes: metadata.scopes,
+            created_at: metadata.created_at.to_rfc3339(),
+            expires_at: metadata.expires_at.map(|d| d.to_rfc3339()),
+            last_validated_at: metadata.last_validated_at.map(|d| d.to_rfc3339()),
+            days_until_expiry: metadata.expires_at.map(|d| (d - Utc::now()).num_days()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MissingTokenInfo {
+    pub account_id: String,
+    pub username: String,
+    pub host: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeychainAuditReport {
+    // Keychain entries with no matching database account
+    pub orphaned_entries: Vec<String>,
+    // Database accounts with no keychain entry
+    pub missing_tokens: Vec<MissingTokenInfo>,
+    // Legacy or unparseable GitSwitchHub entries
+    pub stale_entries: Vec<String>,
+    pub repaired: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportSummary {
+    pub accounts_exported: usize,
+    // Secrets the keychain has no entry for, which the archive goes without:
+    // "octocat" for an account token, "octocat/ci" for a named one
+    pub missing_secrets: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub accounts_imported: usize,
+    pub accounts_skipped: usize,
+    pub mappings_imported: usize,
+}
+
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct CredentialArchive {
+    version: u32,
+    exported_at: String,
+    accounts: Vec<ArchivedAccount>,
+    mappings: Vec<RepositoryMapping>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedAccount {
+    account: Account,
+    token: Option<String>,
+    metadata: Option<TokenMetadata>,
+    refresh: Option<RefreshCredentials>,
+    named_tokens: Vec<(AccountToken, String)>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GitHelperStatus {
     pub installed: bool,
     pub configured: bool,
+    // credential.<host>.useHttpPath is set for every host the helper serves
+    pub use_http_path: bool,
+    // Installed for the GitHub hosts only, leaving credential.helper alone
+    pub scoped: bool,
+    // Other configured helpers that also store GitHub credentials
+    pub conflicts: Vec<String>,
+    // Helpers configured before installing, restored by uninstall_git_helper
+    pub replaced_helpers: Vec<String>,
+    // Every helper entry across system, global and local config
+    pub helpers: Vec<git_helper::ConfiguredHelper>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -52,6 +488,45 @@ pub struct SSHKeyInfo {
     pub public_key: String,
     pub private_key_path: String,
     pub key_id: String,
+    pub key_type: SshKeyType,
+}
+
+impl From<ssh::SSHKeyInfo> for SSHKeyInfo {
+    fn from(key: ssh::SSHKeyInfo) -> Self {
+        Self {
+            public_key: key.public_key,
+            private_key_path: key.private_key_path,
+            key_id: key.key_id,
+            key_type: key.key_type,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadedKeyInfo {
+    pub id: u64,
+    pub title: Option<String>,
+    pub key: String,
+}
+
+impl From<GitHubKey> for UploadedKeyInfo {
+    fn from(key: GitHubKey) -> Self {
+        Self {
+            id: key.id,
+            title: key.title,
+            key: key.key,
+        }
+    }
+}
+
+impl From<GitHubGpgKey> for UploadedKeyInfo {
+    fn from(key: GitHubGpgKey) -> Self {
+        Self {
+            id: key.id,
+            title: key.name,
+            key: key.key_id,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,319 +535,6160 @@ pub struct SSHConfig {
     pub hostname: String,
     pub user: String,
     pub identity_file: String,
+    pub port: Option<u16>,
+}
+
+impl From<ssh::SSHConfig> for SSHConfig {
+    fn from(config: ssh::SSHConfig) -> Self {
+        Self {
+            host: config.host,
+            hostname: config.hostname,
+            user: config.user,
+            identity_file: config.identity_file,
+            port: config.port,
+        }
+    }
 }
 
 #[tauri::command]
 pub async fn get_accounts(db: State<'_, Database>) -> Result<Vec<AccountInfo>, String> {
-    let accounts = db.get_accounts().map_err(|e| e.to_string())?;
-
-    let account_infos: Vec<AccountInfo> = accounts
-        .into_iter()
-        .map(|account| AccountInfo {
-            id: account.id,
-            username: account.username,
-            avatar_url: account.avatar_url,
-            auth_method: account.auth_method,
-            created_at: account.created_at.to_rfc3339(),
-        })
-        .collect();
+    let accounts = db
+        .run(|db| db.get_accounts())
+        .await
+        .map_err(|e| e.to_string())?;
 
-    Ok(account_infos)
+    Ok(accounts.into_iter().map(AccountInfo::from).collect())
 }
 
 #[tauri::command]
 pub async fn add_account(
+    app: AppHandle,
     db: State<'_, Database>,
     keychain: State<'_, KeychainManager>,
     username: String,
     token: String,
+    host: Option<String>,
 ) -> Result<AccountInfo, String> {
-    // Validate token with GitHub API
-    let github_auth = GitHubAuth::new();
-    let user = github_auth
-        .validate_token(&token)
-        .await
-        .map_err(|e| format!("Token validation failed: {}", e))?;
+    let host = match host {
+        Some(host) => normalize_host(&host)?,
+        None => DEFAULT_GITHUB_HOST.to_string(),
+    };
+
+    // Validate token with GitHub API. When GitHub can't be reached the
+    // account is added unverified, see watch_unverified_accounts.
+    let github_auth = github_auth_for(&db, &host).await?;
+    let details = match github_auth.get_token_details(&token).await {
+        Ok(details) => Some(details),
+        Err(e) if e.is_network() => None,
+        Err(e) => return Err(format!("Token validation failed: {}", e)),
+    };
+    let username = username.trim().to_string();
+    if details.is_none()
+        && (username.is_empty()
+            || !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    {
+        return Err(format!(
+            "{} can't be reached; enter the account's username to add it offline",
+            host
+        ));
+    }
 
     // Check if account already exists
-    if let Ok(Some(_)) = db.get_account_by_username(&username) {
+    let (lookup_host, lookup_username) = (host.clone(), username.clone());
+    if let Ok(Some(_)) = db
+        .run(move |db| db.get_account_by_username(&lookup_host, &lookup_username))
+        .await
+    {
         return Err("Account already exists".to_string());
     }
 
-    // Store token in keychain
-    keychain
-        .store_token(&username, &token)
-        .map_err(|e| format!("Failed to store token: {}", e))?;
-
-    // Create account record
-    let account = Account {
-        id: Uuid::new_v4().to_string(),
-        username: user.login,
-        avatar_url: Some(user.avatar_url),
-        auth_method: "manual".to_string(),
-        created_at: Utc::now(),
+    let account = match details {
+        Some(details) => {
+            register_account(&db, &keychain, host, &token, details, "manual", None).await?
+        }
+        None => register_unverified_account(&db, &keychain, host, username, &token).await?,
     };
+    accounts_changed(&app);
+    Ok(account)
+}
 
-    db.add_account(&account).map_err(|e| e.to_string())?;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GhImportSkipInfo {
+    pub host: String,
+    pub username: String,
+    pub reason: String,
+}
 
-    Ok(AccountInfo {
-        id: account.id,
-        username: account.username,
-        avatar_url: account.avatar_url,
-        auth_method: account.auth_method,
-        created_at: account.created_at.to_rfc3339(),
-    })
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GhImportReport {
+    pub imported: Vec<AccountInfo>,
+    pub skipped: Vec<GhImportSkipInfo>,
 }
 
+// Adds the accounts the GitHub CLI is logged in to, each once GitHub confirms
+// its token. Accounts already here, and tokens that can't be read or
+// checked, are reported as skipped.
 #[tauri::command]
-pub async fn remove_account(
+pub async fn import_from_gh(
+    app: AppHandle,
     db: State<'_, Database>,
     keychain: State<'_, KeychainManager>,
-    account_id: String,
-) -> Result<(), String> {
-    // Get account info first
-    let accounts = db.get_accounts().map_err(|e| e.to_string())?;
-    let account = accounts
-        .iter()
-        .find(|a| a.id == account_id)
-        .ok_or("Account not found")?;
+) -> Result<GhImportReport, String> {
+    let logins = tokio::task::spawn_blocking(gh_import::read_logins)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read the gh config: {}", e))?
+        .ok_or("gh isn't logged in to any host")?;
 
-    // Remove from keychain
-    keychain
-        .delete_token(&account.username)
-        .map_err(|e| format!("Failed to delete token: {}", e))?;
+    let mut report = GhImportReport {
+        imported: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for login in logins {
+        match import_gh_login(&db, &keychain, &login).await {
+            Ok(account) => report.imported.push(account),
+            Err(reason) => report.skipped.push(GhImportSkipInfo {
+                host: login.host,
+                username: login.username,
+                reason,
+            }),
+        }
+    }
+    if !report.imported.is_empty() {
+        accounts_changed(&app);
+    }
+    Ok(report)
+}
 
-    // Remove from database
-    db.remove_account(&account_id).map_err(|e| e.to_string())?;
+async fn import_gh_login(
+    db: &Database,
+    keychain: &KeychainManager,
+    login: &gh_import::GhLogin,
+) -> Result<AccountInfo, String> {
+    let host = normalize_host(&login.host)?;
+    let exists = |username: String| {
+        let host = host.clone();
+        async move {
+            db.run(move |db| db.get_account_by_username(&host, &username))
+                .await
+                .map(|account| account.is_some())
+                .map_err(|e| e.to_string())
+        }
+    };
+    if exists(login.username.clone()).await? {
+        return Err("Already added".to_string());
+    }
+    let token = login.token.clone()?;
 
-    Ok(())
+    let details = github_auth_for(db, &host)
+        .await?
+        .get_token_details(&token)
+        .await
+        .map_err(|e| {
+            if e.is_network() {
+                format!("{} can't be reached to check the token", host)
+            } else {
+                format!("Token validation failed: {}", e)
+            }
+        })?;
+    if !details.user.login.eq_ignore_ascii_case(&login.username) {
+        return Err(format!("The token belongs to {}", details.user.login));
+    }
+    if exists(details.user.login.clone()).await? {
+        return Err("Already added".to_string());
+    }
+    register_account(db, keychain, host, &token, details, "manual", None).await
 }
 
-#[tauri::command]
-pub async fn test_connection(
-    keychain: State<'_, KeychainManager>,
-    username: String,
-) -> Result<TestConnectionResult, String> {
-    let token = keychain
-        .get_token(&username)
-        .map_err(|e| format!("Failed to get token: {}", e))?;
+// A credential another credential helper holds for a GitHub host. The token
+// itself stays out of it; adopt_stored_credential reads it again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredCredentialInfo {
+    pub host: String,
+    // The credential.helper value it was found with, passed back to
+    // adopt_stored_credential
+    pub helper: String,
+    // "osxkeychain", "manager", ...
+    pub helper_name: String,
+    // As stored, often a placeholder such as "x-access-token"
+    pub username: String,
+    // Who GitHub says the token belongs to
+    pub login: Option<String>,
+    pub token_kind: TokenKind,
+    // The account already added for that login
+    pub account_id: Option<String>,
+    // Why the token can't be adopted, e.g. GitHub rejected it
+    pub error: Option<String>,
+}
 
-    let github_auth = GitHubAuth::new();
+// The other helpers configured for the GitHub hosts, each asked once
+async fn other_helpers(db: &Database) -> Result<(Vec<String>, Vec<String>), String> {
+    let hosts = db
+        .run(git_helper::helper_hosts)
+        .await
+        .map_err(|e| e.to_string())?;
+    let lookup_hosts = hosts.clone();
+    let helpers =
+        tokio::task::spawn_blocking(move || git_helper::configured_helpers(&lookup_hosts, None))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+    let mut seen = HashSet::new();
+    let helpers = helpers
+        .into_iter()
+        .filter(|helper| !helper.ours && !helper.value.is_empty())
+        .map(|helper| helper.value)
+        .filter(|value| seen.insert(value.clone()))
+        .collect();
+    Ok((hosts, helpers))
+}
 
-    match github_auth.validate_token(&token).await {
-        Ok(user) => {
-            let scopes = github_auth.test_token_scopes(&token).await.ok();
-            Ok(TestConnectionResult {
-                success: true,
-                message: format!("Connected as {}", user.login),
-                scopes,
+// First step of importing from the helpers used before this one: asks each
+// of them for what it has stored for the GitHub hosts and checks the tokens
+// with GitHub, without storing anything
+#[tauri::command]
+pub async fn discover_stored_credentials(
+    db: State<'_, Database>,
+) -> Result<Vec<StoredCredentialInfo>, String> {
+    let (hosts, helpers) = other_helpers(&db).await?;
+    let mut found = Vec::new();
+    for host in hosts {
+        for helper in &helpers {
+            let (query_helper, query_host) = (helper.clone(), host.clone());
+            let credential = tokio::task::spawn_blocking(move || {
+                git_helper::fill_from_helper(&query_helper, &query_host)
             })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("Failed to query {}: {}", helper, e))?;
+            let Some(credential) = credential else {
+                continue;
+            };
+
+            let mut info = StoredCredentialInfo {
+                host: host.clone(),
+                helper: helper.clone(),
+                helper_name: git_helper::helper_name(helper).to_string(),
+                username: credential.username.clone(),
+                login: None,
+                token_kind: TokenKind::from_prefix(&credential.password),
+                account_id: None,
+                error: None,
+            };
+            match github_auth_for(&db, &host)
+                .await?
+                .get_token_details(&credential.password)
+                .await
+            {
+                Ok(details) => {
+                    let (lookup_host, login) = (host.clone(), details.user.login.clone());
+                    info.account_id = db
+                        .run(move |db| db.get_account_by_username(&lookup_host, &login))
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .map(|account| account.id);
+                    info.login = Some(details.user.login);
+                }
+                Err(e) if e.is_network() => {
+                    info.error = Some(format!("{} can't be reached to check the token", host))
+                }
+                Err(e) => info.error = Some(format!("GitHub rejected the token: {}", e)),
+            }
+            found.push(info);
         }
-        Err(e) => Ok(TestConnectionResult {
-            success: false,
-            message: format!("Connection failed: {}", e),
-            scopes: None,
-        }),
     }
+    Ok(found)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdoptedCredentialInfo {
+    pub account: AccountInfo,
+    // The account existed already and kept its own token
+    pub already_added: bool,
+    // Erased from the other helper
+    pub removed: bool,
 }
 
+// Second step: adds the account a stored token belongs to and, with
+// `remove_from_helper`, has the old helper forget the token so the two
+// helpers don't keep competing for the host
 #[tauri::command]
-pub async fn get_repository_mappings(
+pub async fn adopt_stored_credential(
+    app: AppHandle,
     db: State<'_, Database>,
-) -> Result<Vec<RepositoryMappingInfo>, String> {
-    let mappings = db.get_repository_mappings().map_err(|e| e.to_string())?;
+    keychain: State<'_, KeychainManager>,
+    host: String,
+    helper: String,
+    remove_from_helper: Option<bool>,
+) -> Result<AdoptedCredentialInfo, String> {
+    let (hosts, helpers) = other_helpers(&db).await?;
+    if !hosts.contains(&host) || !helpers.contains(&helper) {
+        return Err(format!("{} isn't a helper configured for {}", helper, host));
+    }
+    let (query_helper, query_host) = (helper.clone(), host.clone());
+    let credential = tokio::task::spawn_blocking(move || {
+        git_helper::fill_from_helper(&query_helper, &query_host)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to query {}: {}", helper, e))?
+    .ok_or_else(|| format!("{} no longer has a credential for {}", helper, host))?;
 
-    let mapping_infos: Vec<RepositoryMappingInfo> = mappings
+    let details = github_auth_for(&db, &host)
+        .await?
+        .get_token_details(&credential.password)
+        .await
+        .map_err(|e| format!("Token validation failed: {}", e))?;
+    let (lookup_host, login) = (host.clone(), details.user.login.clone());
+    let existing = db
+        .run(move |db| db.get_account_by_username(&lookup_host, &login))
+        .await
+        .map_err(|e| e.to_string())?;
+    let already_added = existing.is_some();
+    let account = match existing {
+        Some(account) => AccountInfo::from(account),
+        None => {
+            register_account(
+                &db,
+                &keychain,
+                host.clone(),
+                &credential.password,
+                details,
+                "manual",
+                None,
+            )
+            .await?
+        }
+    };
+
+    if !already_added {
+        accounts_changed(&app);
+    }
+
+    let removed = remove_from_helper.unwrap_or(false);
+    if removed {
+        tokio::task::spawn_blocking(move || {
+            git_helper::erase_from_helper(&helper, &host, &credential)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| {
+            format!(
+                "The account was added, but the old helper kept the token: {}",
+                e
+            )
+        })?;
+    }
+    Ok(AdoptedCredentialInfo {
+        account,
+        already_added,
+        removed,
+    })
+}
+
+// Stores the token and creates the account for the user it belongs to
+async fn register_account(
+    db: &Database,
+    keychain: &KeychainManager,
+    host: String,
+    token: &str,
+    details: TokenDetails,
+    auth_method: &str,
+    refresh: Option<RefreshCredentials>,
+) -> Result<AccountInfo, String> {
+    let user = details.user;
+    let account = Account {
+        avatar_url: Some(user.avatar_url),
+        github_user_id: Some(user.id as i64),
+        display_name: user.name,
+        ..new_account(host, user.login, auth_method)
+    };
+    let metadata = TokenMetadata {
+        account_id: account.id.clone(),
+        scopes: details.scopes,
+        created_at: account.created_at,
+        expires_at: details.expires_at,
+        last_validated_at: Some(account.created_at),
+    };
+    store_account(db, keychain, account, token, metadata, refresh).await
+}
+
+// Stores a token GitHub couldn't check, under the username the user gave
+async fn register_unverified_account(
+    db: &Database,
+    keychain: &KeychainManager,
+    host: String,
+    username: String,
+    token: &str,
+) -> Result<AccountInfo, String> {
+    let account = Account {
+        unverified: true,
+        ..new_account(host, username, "manual")
+    };
+    let metadata = TokenMetadata {
+        account_id: account.id.clone(),
+        scopes: Vec::new(),
+        created_at: account.created_at,
+        expires_at: None,
+        last_validated_at: None,
+    };
+    store_account(db, keychain, account, token, metadata, None).await
+}
+
+fn new_account(host: String, username: String, auth_method: &str) -> Account {
+    Account {
+        id: Uuid::new_v4().to_string(),
+        username,
+        avatar_url: None,
+        auth_method: auth_method.to_string(),
+        created_at: Utc::now(),
+        github_user_id: None,
+        host,
+        last_used_at: None,
+        use_count: 0,
+        display_name: None,
+        label: None,
+        color: None,
+        notes: None,
+        deleted_at: None,
+        git_name: None,
+        git_email: None,
+        signing_format: None,
+        signing_key: None,
+        sign_commits: false,
+        clone_dir: None,
+        token_rejected_at: None,
+        unverified: false,
+        ssh_key_path: None,
+        ssh_port_443: false,
+    }
+}
+
+async fn store_account(
+    db: &Database,
+    keychain: &KeychainManager,
+    account: Account,
+    token: &str,
+    metadata: TokenMetadata,
+    refresh: Option<RefreshCredentials>,
+) -> Result<AccountInfo, String> {
+    keychain
+        .store_token(&account.host, &account.username, token)
+        .map_err(|e| format!("Failed to store token: {}", e))?;
+    if let Some(refresh) = &refresh {
+        keychain
+            .store_refresh_credentials(&account.host, &account.username, refresh)
+            .map_err(|e| format!("Failed to store token: {}", e))?;
+    }
+
+    let record = account.clone();
+    db.run(move |db| {
+        // Accounts on an Enterprise host that was never added register it
+        if record.host != DEFAULT_GITHUB_HOST && db.get_host(&record.host)?.is_none() {
+            db.add_host(&GitHost {
+                host: record.host.clone(),
+                name: None,
+                api_url: default_api_url(&record.host),
+                created_at: Utc::now(),
+                oauth_client_id: None,
+            })?;
+        }
+        db.add_account(&record)?;
+        db.set_token_metadata(&metadata)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(account.into())
+}
+
+// GitHubAuth for the host's web and API URLs, github.com unless it is an
+// Enterprise host
+async fn github_auth_for(db: &Database, host: &str) -> Result<GitHubAuth, String> {
+    let lookup = host.to_string();
+    let record = db
+        .run(move |db| db.get_host(&lookup))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(match record {
+        Some(record) => GitHubAuth::for_host(
+            &record.host,
+            Some(&record.api_url),
+            record.oauth_client_id.as_deref(),
+        ),
+        None => GitHubAuth::for_host(host, None, None),
+    })
+}
+
+// Like github_auth_for, but fails early when the host has no OAuth app to
+// sign in with
+async fn sign_in_auth_for(db: &Database, host: Option<String>) -> Result<GitHubAuth, String> {
+    let host = match host {
+        Some(host) => normalize_host(&host)?,
+        None => DEFAULT_GITHUB_HOST.to_string(),
+    };
+    let github_auth = github_auth_for(db, &host).await?;
+    if host != DEFAULT_GITHUB_HOST && !github_auth.has_client_id() {
+        return Err(format!(
+            "Signing in to {} needs the client ID of an OAuth app registered there; add it to the host or sign in with a token",
+            host
+        ));
+    }
+    Ok(github_auth)
+}
+
+// Starts signing in with a code the user enters on GitHub. The returned
+// device code is then passed to poll_device_flow with the same host.
+#[tauri::command]
+pub async fn start_device_flow(
+    db: State<'_, Database>,
+    flows: State<'_, SignInFlows>,
+    host: Option<String>,
+) -> Result<DeviceCodeInfo, String> {
+    let response = sign_in_auth_for(&db, host)
+        .await?
+        .start_device_flow()
+        .await
+        .map_err(|e| format!("Failed to start device flow: {}", e))?;
+    flows.start(&response.device_code, None, Some((&response).into()));
+
+    Ok(DeviceCodeInfo {
+        device_code: response.device_code,
+        user_code: response.user_code,
+        verification_uri: response.verification_uri,
+        verification_uri_complete: response.verification_uri_complete,
+        expires_in: response.expires_in,
+        interval: response.interval,
+    })
+}
+
+// Waits for the user to approve the code, emitting progress events, then
+// adds the account. Signing in to an account that already exists replaces
+// its token instead.
+#[tauri::command]
+pub async fn poll_device_flow(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    flows: State<'_, SignInFlows>,
+    device_code: String,
+    host: Option<String>,
+) -> Result<AccountInfo, String> {
+    let github_auth = sign_in_auth_for(&db, host).await?;
+    let flow = flows
+        .get(&device_code)
+        .ok_or("No device flow in progress for this code")?;
+    let emit = |status: &str, message: Option<String>| {
+        let _ = app.emit(
+            DEVICE_FLOW_EVENT,
+            DeviceFlowEvent {
+                device_code: device_code.clone(),
+                status: status.to_string(),
+                message,
+            },
+        );
+    };
+
+    let result = complete_device_flow(
+        &db,
+        &keychain,
+        &github_auth,
+        &device_code,
+        &flow,
+        |progress| match progress {
+            DeviceFlowProgress::Pending => emit("pending", None),
+            DeviceFlowProgress::SlowDown { interval } => emit(
+                "slow_down",
+                Some(format!("Checking every {} seconds", interval.as_secs())),
+            ),
+        },
+    )
+    .await;
+    flows.finish(&device_code);
+
+    match &result {
+        Ok(account) => {
+            emit("success", Some(account.username.clone()));
+            accounts_changed(&app);
+        }
+        Err(e) if flow.cancelled.load(Ordering::SeqCst) => emit("cancelled", Some(e.clone())),
+        Err(e) => emit("error", Some(e.clone())),
+    }
+    result
+}
+
+async fn complete_device_flow(
+    db: &Database,
+    keychain: &KeychainManager,
+    github_auth: &GitHubAuth,
+    device_code: &str,
+    flow: &SignInFlow,
+    on_progress: impl FnMut(DeviceFlowProgress),
+) -> Result<AccountInfo, String> {
+    let polling = flow
+        .polling
+        .ok_or("No device flow in progress for this code")?;
+    let response = github_auth
+        .poll_for_token_with_progress(device_code, polling, &flow.cancelled, on_progress)
+        .await
+        .map_err(|e| match e {
+            GitHubAuthError::Cancelled => "Sign in was cancelled".to_string(),
+            e => format!("Device flow failed: {}", e),
+        })?;
+    save_oauth_account(
+        db,
+        keychain,
+        github_auth,
+        response,
+        "device_flow",
+        flow.account_id.as_deref(),
+    )
+    .await
+}
+
+// Adds the account a sign-in returned a token for, or replaces the token of
+// the account when it already exists. With `account_id` only that account's
+// token may be replaced.
+async fn save_oauth_account(
+    db: &Database,
+    keychain: &KeychainManager,
+    github_auth: &GitHubAuth,
+    response: DeviceTokenResponse,
+    auth_method: &str,
+    account_id: Option<&str>,
+) -> Result<AccountInfo, String> {
+    let details = github_auth
+        .get_token_details(&response.access_token)
+        .await
+        .map_err(|e| format!("Token validation failed: {}", e))?;
+    let refresh = RefreshCredentials::from_token_response(&response);
+
+    if let Some(account_id) = account_id {
+        let lookup_id = account_id.to_string();
+        let expected = db
+            .run(move |db| db.get_account(&lookup_id))
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Account not found")?;
+        let same_user = match expected.github_user_id {
+            Some(id) => id == details.user.id as i64,
+            None => details.user.login.eq_ignore_ascii_case(&expected.username),
+        };
+        if !same_user {
+            return Err(format!(
+                "Signed in as {}, not {}; the token was not changed",
+                details.user.login, expected.username
+            ));
+        }
+    }
+
+    let host = github_auth.host().to_string();
+    let (lookup_host, login) = (host.clone(), details.user.login.clone());
+    let existing = db
+        .run(move |db| db.get_account_by_username(&lookup_host, &login))
+        .await
+        .map_err(|e| e.to_string())?;
+    let account = match existing {
+        Some(account) => account,
+        None => {
+            return register_account(
+                db,
+                keychain,
+                host,
+                &response.access_token,
+                details,
+                auth_method,
+                refresh,
+            )
+            .await
+        }
+    };
+
+    keychain
+        .replace_token(&account.host, &account.username, &response.access_token)
+        .map_err(|e| format!("Failed to store token: {}", e))?;
+    if let Some(refresh) = &refresh {
+        keychain
+            .store_refresh_credentials(&account.host, &account.username, refresh)
+            .map_err(|e| format!("Failed to store token: {}", e))?;
+    }
+    let metadata = TokenMetadata {
+        account_id: account.id.clone(),
+        scopes: details.scopes,
+        created_at: Utc::now(),
+        expires_at: details.expires_at,
+        last_validated_at: Some(Utc::now()),
+    };
+    let account_id = account.id.clone();
+    let verify = account.unverified.then_some(details.user);
+    db.run(move |db| {
+        if let Some(user) = verify {
+            db.set_account_verified(
+                &account_id,
+                user.id as i64,
+                &user.avatar_url,
+                user.name.as_deref(),
+            )?;
+        }
+        db.set_token_metadata(&metadata)?;
+        db.set_token_rejected(&account_id, false)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(account.into())
+}
+
+#[tauri::command]
+pub async fn cancel_device_flow(
+    flows: State<'_, SignInFlows>,
+    device_code: String,
+) -> Result<(), String> {
+    flows.cancel(&device_code);
+    Ok(())
+}
+
+// Signs in through GitHub's authorize page in the system browser, which
+// redirects back to a temporary listener on localhost
+#[tauri::command]
+pub async fn sign_in_with_browser(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    flows: State<'_, SignInFlows>,
+    host: Option<String>,
+) -> Result<AccountInfo, String> {
+    browser_sign_in(&app, &db, &keychain, &flows, host, None).await
+}
+
+async fn browser_sign_in(
+    app: &AppHandle,
+    db: &Database,
+    keychain: &KeychainManager,
+    flows: &SignInFlows,
+    host: Option<String>,
+    account_id: Option<&str>,
+) -> Result<AccountInfo, String> {
+    let github_auth = sign_in_auth_for(db, host).await?;
+    let listener = oauth::CallbackListener::bind()
+        .await
+        .map_err(|e| format!("Failed to listen for the sign in redirect: {}", e))?;
+    let pkce = oauth::Pkce::new();
+    let state = oauth::random_state();
+    let url = github_auth
+        .authorize_url(&listener.redirect_uri, &state, &pkce.challenge)
+        .map_err(|e| e.to_string())?;
+
+    let cancelled = flows.start(&state, account_id, None);
+    let emit = |status: &str, url: Option<String>, message: Option<String>| {
+        let _ = app.emit(
+            BROWSER_SIGN_IN_EVENT,
+            BrowserSignInEvent {
+                state: state.clone(),
+                status: status.to_string(),
+                url,
+                message,
+            },
+        );
+    };
+    let opened = app.opener().open_url(url.as_str(), None::<&str>);
+    emit(
+        "waiting",
+        Some(url.clone()),
+        opened
+            .err()
+            .map(|e| format!("Couldn't open a browser: {}", e)),
+    );
+
+    let result = async {
+        let code = listener
+            .wait_for_code(&state, &cancelled, BROWSER_SIGN_IN_TIMEOUT)
+            .await
+            .map_err(|e| e.to_string())?;
+        let response = github_auth
+            .exchange_code(&code, &pkce.verifier, &listener.redirect_uri)
+            .await
+            .map_err(|e| format!("Sign in failed: {}", e))?;
+        save_oauth_account(db, keychain, &github_auth, response, "oauth", account_id).await
+    }
+    .await;
+    flows.finish(&state);
+
+    match &result {
+        Ok(account) => {
+            emit("success", None, Some(account.username.clone()));
+            accounts_changed(app);
+        }
+        Err(e) if cancelled.load(Ordering::SeqCst) => emit("cancelled", None, Some(e.clone())),
+        Err(e) => emit("error", None, Some(e.clone())),
+    }
+    result
+}
+
+#[tauri::command]
+pub async fn cancel_browser_sign_in(
+    flows: State<'_, SignInFlows>,
+    state: String,
+) -> Result<(), String> {
+    flows.cancel(&state);
+    Ok(())
+}
+
+// Removal can be undone with restore_account until the retention period
+// runs out; the tokens stay in the keychain until the account is purged.
+// `delete_ssh_keys` also deletes the SSH key generated for the account, from
+// GitHub and from disk, along with its SSH config alias. Restoring the
+// account doesn't bring those back. Only github.com accounts have them.
+#[tauri::command]
+pub async fn remove_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    delete_ssh_keys: Option<bool>,
+) -> Result<(), String> {
+    if delete_ssh_keys.unwrap_or(false) {
+        let lookup_id = account_id.clone();
+        let account = db
+            .run(move |db| db.get_account(&lookup_id))
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Account not found")?;
+        delete_account_ssh_key(&db, &keychain, &account).await?;
+        // Rules for the alias would send the owners' urls nowhere
+        if let Ok(alias) = ssh_alias(&account) {
+            tokio::task::spawn_blocking(move || url_rewrite::remove_rules(&alias))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Failed to update the git config: {}", e))?;
+        }
+    }
+
+    let removed = db
+        .run(move |db| db.soft_delete_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    if !removed {
+        return Err("Account not found".to_string());
+    }
+    cache::invalidate();
+    accounts_changed(&app);
+    Ok(())
+}
+
+// GitHub goes first, so when it refuses nothing is lost and the removal can
+// be retried. A key the account was linked to rather than generated for it
+// may be used elsewhere, so only its alias is removed.
+async fn delete_account_ssh_key(
+    db: &Database,
+    keychain: &KeychainManager,
+    account: &Account,
+) -> Result<(), String> {
+    // Keys and aliases are named after the username alone and point at
+    // github.com, so on another host they'd belong to someone else
+    if account.host != DEFAULT_GITHUB_HOST {
+        return Ok(());
+    }
+    let manager = SSHManager::new();
+    if account.ssh_key_path.is_some() {
+        let writes = manager
+            .remove_from_ssh_config(&account.username)
+            .map_err(|e| format!("Failed to update the SSH config: {}", e))?;
+        return record_ssh_config_writes(db, writes).await;
+    }
+    let public_key_path = manager
+        .public_key_path(&account.username)
+        .map_err(|e| e.to_string())?;
+
+    if public_key_path.exists() {
+        let public_key = read_public_key(&public_key_path)?;
+        let token = keychain
+            .get_token(&account.host, &account.username)
+            .map_err(|e| format!("Failed to read token: {}", e))?;
+        let github_auth = github_auth_for(db, &account.host).await?;
+        let delete_error = |e| match e {
+            GitHubAuthError::MissingScope(scope) => format!(
+                "Deleting the SSH key of {} from GitHub needs the {} scope; \
+                 reauthorize the account or keep the key",
+                account.username, scope
+            ),
+            e => format!("Failed to delete the SSH key from GitHub: {}", e),
+        };
+        let keys = github_auth
+            .list_ssh_keys(&token)
+            .await
+            .map_err(delete_error)?;
+        for key in keys
+            .iter()
+            .filter(|key| same_public_key(&key.key, &public_key))
+        {
+            github_auth
+                .delete_ssh_key(&token, key.id)
+                .await
+                .map_err(delete_error)?;
+        }
+    }
+
+    let writes = manager
+        .remove_from_ssh_config(&account.username)
+        .map_err(|e| format!("Failed to update the SSH config: {}", e))?;
+    record_ssh_config_writes(db, writes).await?;
+    manager
+        .delete_key(&account.username)
+        .map_err(|e| format!("Failed to delete the SSH key: {}", e))?;
+    Ok(())
+}
+
+// Compares the type and key data, ignoring the comment
+fn same_public_key(a: &str, b: &str) -> bool {
+    let fields = |key: &str| key.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    fields(a) == fields(b)
+}
+
+#[tauri::command]
+pub async fn get_deleted_accounts(db: State<'_, Database>) -> Result<Vec<AccountInfo>, String> {
+    let accounts = db
+        .run(|db| db.get_deleted_accounts())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(accounts.into_iter().map(AccountInfo::from).collect())
+}
+
+#[tauri::command]
+pub async fn restore_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<AccountInfo, String> {
+    let account = db
+        .run(move |db| {
+            if !db.restore_account(&account_id)? {
+                return Err("No removed account to restore".to_string());
+            }
+            db.get_account(&account_id)?
+                .ok_or_else(|| "Account not found".to_string())
+        })
+        .await?;
+    accounts_changed(&app);
+    Ok(AccountInfo::from(account))
+}
+
+// Deletes an account and its tokens for good, whether or not it was removed first
+#[tauri::command]
+pub async fn purge_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+) -> Result<(), String> {
+    let lookup_id = account_id.clone();
+    let (account, tokens) = db
+        .run(move |db| {
+            let account = match db.get_account(&lookup_id)? {
+                Some(account) => account,
+                None => db
+                    .get_deleted_accounts()?
+                    .into_iter()
+                    .find(|a| a.id == lookup_id)
+                    .ok_or("Account not found")?,
+            };
+            let tokens = db.get_account_tokens(&account.id)?;
+            Ok::<_, String>((account, tokens))
+        })
+        .await?;
+
+    delete_account_secrets(&keychain, &account, &tokens)?;
+
+    db.run(move |db| db.remove_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    accounts_changed(&app);
+    Ok(())
+}
+
+// Purges removed accounts whose retention period has passed. Runs at
+// startup, so it calls the database directly. An account that can't be
+// purged is left for the next run rather than blocking the others.
+pub fn purge_expired_accounts(db: &Database, keychain: &KeychainManager) -> Result<usize, String> {
+    let retention_days = db
+        .get_setting(SettingKey::DeletedAccountRetentionDays)?
+        .as_i64()
+        .unwrap_or(30);
+    let cutoff = Utc::now() - chrono::Duration::days(retention_days);
+
+    let mut purged = 0;
+    for account in db.get_deleted_accounts()? {
+        if account
+            .deleted_at
+            .is_none_or(|deleted_at| deleted_at > cutoff)
+        {
+            continue;
+        }
+        let result = db
+            .get_account_tokens(&account.id)
+            .map_err(|e| e.to_string())
+            .and_then(|tokens| delete_account_secrets(keychain, &account, &tokens))
+            .and_then(|_| db.remove_account(&account.id).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => purged += 1,
+            Err(e) => warn!(account = %account.username, error = %e, "failed to purge account"),
+        }
+    }
+    Ok(purged)
+}
+
+fn delete_account_secrets(
+    keychain: &KeychainManager,
+    account: &Account,
+    tokens: &[AccountToken],
+) -> Result<(), String> {
+    keychain
+        .delete_token(&account.host, &account.username)
+        .map_err(|e| format!("Failed to delete token: {}", e))?;
+    for token in tokens {
+        keychain
+            .delete_named_token(&account.host, &account.username, &token.name)
+            .map_err(|e| format!("Failed to delete token: {}", e))?;
+    }
+    Ok(())
+}
+
+// Editable fields are left alone when omitted and cleared when empty.
+// refresh_profile re-fetches the avatar and display name from GitHub.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    label: Option<String>,
+    color: Option<String>,
+    notes: Option<String>,
+    refresh_profile: Option<bool>,
+) -> Result<AccountInfo, String> {
+    let lookup_id = account_id.clone();
+    let account = db
+        .run(move |db| db.get_account(&lookup_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    let color = match color.map(|c| c.trim().to_string()) {
+        Some(c) if !c.is_empty() && !is_hex_color(&c) => {
+            return Err(format!("Invalid color {}, expected #rgb or #rrggbb", c));
+        }
+        color => color,
+    };
+
+    let profile = if refresh_profile.unwrap_or(false) {
+        let token = keychain
+            .get_token(&account.host, &account.username)
+            .map_err(|e| format!("Failed to read token: {}", e))?;
+        let user = github_auth_for(&db, &account.host)
+            .await?
+            .validate_token(&token)
+            .await
+            .map_err(|e| format!("Failed to fetch GitHub profile: {}", e))?;
+        if account
+            .github_user_id
+            .is_some_and(|id| id != user.id as i64)
+        {
+            return Err(format!(
+                "The stored token belongs to {}, not {}",
+                user.login, account.username
+            ));
+        }
+        Some(user)
+    } else {
+        None
+    };
+
+    let merge = |update: Option<String>, current: Option<String>| match update {
+        Some(value) if value.trim().is_empty() => None,
+        Some(value) => Some(value.trim().to_string()),
+        None => current,
+    };
+    let label = merge(label, account.label);
+    let color = merge(color, account.color);
+    let notes = merge(notes, account.notes);
+
+    let account = db
+        .run(move |db| {
+            if let Some(user) = &profile {
+                db.update_account_profile(
+                    &account_id,
+                    Some(&user.avatar_url),
+                    user.name.as_deref(),
+                    user.id as i64,
+                )?;
+            }
+            db.update_account_details(
+                &account_id,
+                label.as_deref(),
+                color.as_deref(),
+                notes.as_deref(),
+            )?;
+            db.get_account(&account_id)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Account not found".to_string())?;
+    accounts_changed(&app);
+    Ok(AccountInfo::from(account))
+}
+
+fn is_hex_color(value: &str) -> bool {
+    value
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[tauri::command]
+pub async fn get_git_identity(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<GitIdentityInfo, String> {
+    db.run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(GitIdentityInfo::from)
+        .ok_or_else(|| "Account not found".to_string())
+}
+
+// Empty values clear the identity
+#[tauri::command]
+pub async fn set_git_identity(
+    db: State<'_, Database>,
+    account_id: String,
+    name: Option<String>,
+    email: Option<String>,
+) -> Result<GitIdentityInfo, String> {
+    let name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    let email = email
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty());
+    if let Some(email) = &email {
+        if !email.contains('@')
+            || email
+                .chars()
+                .any(|c| c.is_whitespace() || c == '<' || c == '>')
+        {
+            return Err(format!("Invalid email address: {}", email));
+        }
+    }
+    if name
+        .as_deref()
+        .is_some_and(|n| n.contains(['<', '>', '\n']))
+    {
+        return Err("Names may not contain '<', '>' or line breaks".to_string());
+    }
+
+    db.run(move |db| {
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())?;
+        db.set_account_git_identity(&account_id, name.as_deref(), email.as_deref())?;
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())
+    })
+    .await
+    .map(GitIdentityInfo::from)
+}
+
+#[tauri::command]
+pub async fn get_signing_config(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<SigningConfigInfo, String> {
+    db.run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(SigningConfigInfo::from)
+        .ok_or_else(|| "Account not found".to_string())
+}
+
+// An empty key clears the signing configuration
+#[tauri::command]
+pub async fn set_signing_config(
+    db: State<'_, Database>,
+    account_id: String,
+    format: Option<String>,
+    key: Option<String>,
+    sign_commits: bool,
+) -> Result<SigningConfigInfo, String> {
+    let key = key.map(|k| k.trim().to_string()).filter(|k| !k.is_empty());
+    let format = match (&key, format.as_deref()) {
+        (None, _) => None,
+        (Some(_), None) | (Some(_), Some("openpgp")) | (Some(_), Some("gpg")) => {
+            Some("openpgp".to_string())
+        }
+        (Some(_), Some(format @ ("ssh" | "x509"))) => Some(format.to_string()),
+        (Some(_), Some(other)) => {
+            return Err(format!(
+                "Unsupported signing format {}, expected openpgp, ssh or x509",
+                other
+            ))
+        }
+    };
+    let key = match (format.as_deref(), key) {
+        (Some("ssh"), Some(key)) => Some(ssh_signing_key(&key)?),
+        (_, key) => key,
+    };
+    if sign_commits && key.is_none() {
+        return Err("Commit signing needs a signing key".to_string());
+    }
+
+    db.run(move |db| {
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())?;
+        db.set_account_signing(&account_id, format.as_deref(), key.as_deref(), sign_commits)?;
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())
+    })
+    .await
+    .map(SigningConfigInfo::from)
+}
+
+// git accepts either a public key file or a literal "key::ssh-..." value
+fn ssh_signing_key(key: &str) -> Result<String, String> {
+    if key.starts_with("key::") || key.starts_with("ssh-") {
+        return Ok(key.to_string());
+    }
+    let path = std::path::PathBuf::from(paths::expand_home(key));
+    if !path.is_file() {
+        return Err(format!("SSH signing key not found: {}", path.display()));
+    }
+    Ok(path.display().to_string())
+}
+
+// Registers the account's signing key (see set_signing_config) with GitHub so
+// its commits show as verified. GPG keys are exported with gpg.
+#[tauri::command]
+pub async fn upload_signing_key(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    title: Option<String>,
+) -> Result<UploadedKeyInfo, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let key = account
+        .signing_key
+        .clone()
+        .ok_or("The account has no signing key; set one first")?;
+    let title = title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| format!("GitSwitchHub {}", account.username));
+
+    let github_auth = github_auth_for(&db, &account.host).await?;
+    let token = keychain
+        .get_token(&account.host, &account.username)
+        .map_err(|e| format!("Failed to read token: {}", e))?;
+    let uploaded = match account.signing_format.as_deref().unwrap_or("openpgp") {
+        "ssh" => {
+            let public_key = match key.strip_prefix("key::") {
+                Some(literal) => literal.trim().to_string(),
+                None if key.starts_with("ssh-") => key.clone(),
+                None => read_public_key(std::path::Path::new(&key))?,
+            };
+            github_auth
+                .add_ssh_signing_key(&token, &title, &public_key)
+                .await
+                .map(UploadedKeyInfo::from)
+        }
+        "openpgp" => {
+            let armored = export_gpg_public_key(&key)?;
+            github_auth
+                .add_gpg_key(&token, &title, &armored)
+                .await
+                .map(UploadedKeyInfo::from)
+        }
+        format => {
+            return Err(format!(
+                "GitHub doesn't accept {} signing keys; upload openpgp or ssh keys",
+                format
+            ))
+        }
+    };
+    uploaded.map_err(|e| key_upload_error(e, &account.username))
+}
+
+fn export_gpg_public_key(key_id: &str) -> Result<String, String> {
+    let output = std::process::Command::new("gpg")
+        .args(["--armor", "--export", key_id])
+        .output()
+        .map_err(|e| format!("Failed to run gpg: {}", e))?;
+    let armored = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // gpg exits successfully with no output for unknown keys
+    if !output.status.success() || armored.is_empty() {
+        return Err(format!("gpg has no public key {}", key_id));
+    }
+    Ok(armored)
+}
+
+// Writes the account's signing setup into the global git config, or into a
+// single repository's config for the local scope. An account without a key
+// turns signing off so switching to it does not keep another account's key.
+#[tauri::command]
+pub async fn apply_signing_config(
+    db: State<'_, Database>,
+    account_id: String,
+    scope: String,
+    repo_path: Option<String>,
+) -> Result<(), String> {
+    let scope = GitConfigScope::parse(&scope, repo_path.as_deref()).map_err(|e| e.to_string())?;
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    write_signing_config(&scope, &account)
+        .map_err(|e| format!("Failed to apply signing config: {}", e))
+}
+
+fn write_signing_config(
+    scope: &GitConfigScope,
+    account: &Account,
+) -> Result<(), git_config::GitConfigError> {
+    match &account.signing_key {
+        Some(key) => {
+            let format = account.signing_format.as_deref().unwrap_or("openpgp");
+            git_config::set_value(scope, "user.signingkey", key)?;
+            git_config::set_value(scope, "gpg.format", format)?;
+            git_config::set_value(scope, "commit.gpgsign", &account.sign_commits.to_string())
+        }
+        None => {
+            git_config::unset_value(scope, "user.signingkey")?;
+            git_config::unset_value(scope, "gpg.format")?;
+            git_config::set_value(scope, "commit.gpgsign", "false")
+        }
+    }
+}
+
+// Signs the account's commits with its SSH key: the key becomes its signing
+// key, goes into the scope's git config and is listed in the allowed signers
+// file under the commit email, so git can verify the signatures locally
+#[tauri::command]
+pub async fn configure_ssh_signing(
+    db: State<'_, Database>,
+    account_id: String,
+    scope: String,
+    repo_path: Option<String>,
+    sign_commits: Option<bool>,
+) -> Result<SigningConfigInfo, String> {
+    let scope = GitConfigScope::parse(&scope, repo_path.as_deref()).map_err(|e| e.to_string())?;
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let principal = account
+        .commit_email()
+        .ok_or("Set a commit email for the account first; signatures are checked against it")?;
+    let key_path = account_public_key_path(&account)?;
+    let public_key = read_public_key(&key_path)?;
+
+    let key = key_path.display().to_string();
+    let sign_commits = sign_commits.unwrap_or(true);
+    let account = db
+        .run(move |db| {
+            db.set_account_signing(&account.id, Some("ssh"), Some(&key), sign_commits)?;
+            db.get_account(&account.id)?
+                .ok_or_else(|| "Account not found".to_string())
+        })
+        .await?;
+
+    let signing_account = account.clone();
+    tokio::task::spawn_blocking(move || {
+        let global = GitConfigScope::Global;
+        let signers = match git_config::get_value(&global, "gpg.ssh.allowedSignersFile")
+            .map_err(|e| e.to_string())?
+        {
+            Some(file) => std::path::PathBuf::from(paths::expand_home(&file)),
+            None => {
+                let file = paths::ssh_dir()
+                    .map_err(|e| e.to_string())?
+                    .join("allowed_signers");
+                git_config::set_value(
+                    &global,
+                    "gpg.ssh.allowedSignersFile",
+                    &file.display().to_string(),
+                )
+                .map_err(|e| e.to_string())?;
+                file
+            }
+        };
+        ssh::add_allowed_signer(&signers, &principal, &public_key)
+            .map_err(|e| format!("Failed to update {}: {}", signers.display(), e))?;
+        write_signing_config(&scope, &signing_account)
+            .map_err(|e| format!("Failed to apply signing config: {}", e))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    Ok(SigningConfigInfo::from(account))
+}
+
+// Empty clears the clone directory
+#[tauri::command]
+pub async fn set_clone_directory(
+    db: State<'_, Database>,
+    account_id: String,
+    path: Option<String>,
+) -> Result<AccountInfo, String> {
+    let path = path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    if let Some(path) = &path {
+        if path.contains('*') || !std::path::Path::new(&paths::expand_home(path)).is_absolute() {
+            return Err(format!(
+                "Clone directory must be an absolute path: {}",
+                path
+            ));
+        }
+    }
+
+    db.run(move |db| {
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())?;
+        db.set_account_clone_dir(&account_id, path.as_deref())?;
+        db.get_account(&account_id)?
+            .ok_or_else(|| "Account not found".to_string())
+    })
+    .await
+    .map(AccountInfo::from)
+}
+
+// Where a clone of the remote would go for the account: its clone directory
+// plus the repository name
+#[tauri::command]
+pub async fn suggest_clone_path(
+    db: State<'_, Database>,
+    account_id: String,
+    remote_url: String,
+) -> Result<String, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    clone_path(&account, &remote_url).map(|path| path.display().to_string())
+}
+
+fn clone_path(account: &Account, remote_url: &str) -> Result<std::path::PathBuf, String> {
+    let clone_dir = account
+        .clone_dir
+        .as_deref()
+        .ok_or("No clone directory is set for this account")?;
+
+    // "https://host/owner/repo" once canonical; the name keeps its case
+    if remote::canonicalize_remote_url(remote_url)
+        .split('/')
+        .count()
+        < 5
+    {
+        return Err(format!("Invalid remote URL: {}", remote_url));
+    }
+    let trimmed = remote_url.trim().trim_end_matches('/');
+    let name = trimmed
+        .strip_suffix(".git")
+        .unwrap_or(trimmed)
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    Ok(std::path::Path::new(&paths::expand_home(clone_dir)).join(name))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloneResultInfo {
+    pub path: String,
+    // What origin points at
+    pub remote_url: String,
+    pub identity_written: bool,
+    pub signing_configured: bool,
+    // Setup after the clone that didn't work out; the clone itself is kept
+    pub warnings: Vec<String>,
+}
+
+// Clones a repository for an account and sets it up in one go: a mapping so
+// the credential helper uses the account, origin through the account's SSH
+// alias when cloning over SSH, and the account's identity and signing key in
+// the new repository's config. The protocol defaults to the
+// preferred_protocol setting, the destination to the account's clone
+// directory and signing to whether the account has a signing key.
+#[tauri::command]
+pub async fn clone_repository(
+    db: State<'_, Database>,
+    url: String,
+    account_id: String,
+    destination: Option<String>,
+    protocol: Option<String>,
+    configure_signing: Option<bool>,
+) -> Result<CloneResultInfo, String> {
+    let (account, preferred_protocol) = db
+        .run(move |db| {
+            let account = db.get_account(&account_id)?.ok_or("Account not found")?;
+            Ok::<_, String>((account, db.get_setting(SettingKey::PreferredProtocol)?))
+        })
+        .await?;
+    let (mapping_url, host) =
+        remote::mapping_target(&url).ok_or_else(|| format!("Invalid remote URL: {}", url))?;
+    if !account.host.eq_ignore_ascii_case(&host) {
+        return Err(format!(
+            "{} is registered for {}, not {}",
+            account.username, account.host, host
+        ));
+    }
+
+    let protocol = protocol
+        .or_else(|| preferred_protocol.as_str().map(|p| p.to_string()))
+        .unwrap_or_else(|| "https".to_string());
+    let clone_url = match protocol.as_str() {
+        "ssh" => {
+            let alias = ssh_alias(&account)?;
+            if !account_public_key_path(&account)?.exists() {
+                return Err(format!(
+                    "{} has no SSH key; add one or clone over HTTPS",
+                    account.username
+                ));
+            }
+            // Puts the alias back should it have gone missing
+            refresh_ssh_alias(&db, &account).await?;
+            remote::ssh_alias_url(&url, &alias)
+        }
+        "https" => remote::https_url(&url, &account.host),
+        other => return Err(format!("Unknown protocol: {}", other)),
+    }
+    .ok_or_else(|| format!("Invalid remote URL: {}", url))?;
+
+    let destination = match destination
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+    {
+        Some(destination) => std::path::PathBuf::from(paths::expand_home(&destination)),
+        None => clone_path(&account, &url)?,
+    };
+    if !destination.is_absolute() {
+        return Err(format!(
+            "The destination must be an absolute path: {}",
+            destination.display()
+        ));
+    }
+    if destination
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(format!(
+            "{} already exists and is not empty",
+            destination.display()
+        ));
+    }
+
+    // Before cloning, so the credential helper already picks the account
+    let mapping_account = account.id.clone();
+    db.run(move |db| db.set_repository_mapping(&mapping_url, &mapping_account, true, None))
+        .await
+        .map_err(|e| e.to_string())?;
+    cache::invalidate();
+
+    let (source, target) = (clone_url.clone(), destination.clone());
+    tokio::task::spawn_blocking(move || git_config::clone_repository(&source, &target))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to clone {}: {}", url, e))?;
+
+    let sign = configure_signing.unwrap_or(account.signing_key.is_some());
+    let scope = GitConfigScope::Repository(destination.clone());
+    let (identity_written, signing_configured, warnings) = tokio::task::spawn_blocking(move || {
+        let mut warnings = Vec::new();
+        let identity_written = match git_config::write_identity(
+            &scope,
+            account.git_name.as_deref(),
+            account.commit_email().as_deref(),
+        ) {
+            Ok(true) => true,
+            Ok(false) => {
+                warnings.push(format!(
+                    "{} has no commit name or email to write",
+                    account.username
+                ));
+                false
+            }
+            Err(e) => {
+                warnings.push(format!("Failed to write the commit identity: {}", e));
+                false
+            }
+        };
+        let signing_configured = match (sign, &account.signing_key) {
+            (false, _) => false,
+            (true, None) => {
+                warnings.push(format!("{} has no signing key", account.username));
+                false
+            }
+            (true, Some(_)) => match write_signing_config(&scope, &account) {
+                Ok(()) => true,
+                Err(e) => {
+                    warnings.push(format!("Failed to configure signing: {}", e));
+                    false
+                }
+            },
+        };
+        (identity_written, signing_configured, warnings)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(CloneResultInfo {
+        path: destination.display().to_string(),
+        remote_url: clone_url,
+        identity_written,
+        signing_configured,
+        warnings,
+    })
+}
+
+#[tauri::command]
+pub async fn rotate_token(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    new_token: String,
+) -> Result<TokenInfo, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    let github_auth = github_auth_for(&db, &account.host).await?;
+    let details = github_auth
+        .get_token_details(&new_token)
+        .await
+        .map_err(|e| format!("Token validation failed: {}", e))?;
+
+    // Accounts created before the GitHub user id was recorded fall back to
+    // the id behind the current token, then to the login name
+    let expected_id = match account.github_user_id {
+        Some(id) => Some(id),
+        None => match keychain.get_token(&account.host, &account.username) {
+            Ok(old_token) => github_auth
+                .validate_token(&old_token)
+                .await
+                .ok()
+                .map(|user| user.id as i64),
+            Err(_) => None,
+        },
+    };
+    let same_user = match expected_id {
+        Some(id) => id == details.user.id as i64,
+        None => details.user.login.eq_ignore_ascii_case(&account.username),
+    };
+    if !same_user {
+        return Err(format!(
+            "The new token belongs to {}, not {}",
+            details.user.login, account.username
+        ));
+    }
+
+    keychain
+        .replace_token(&account.host, &account.username, &new_token)
+        .map_err(|e| format!("Failed to store token: {}", e))?;
+
+    let now = Utc::now();
+    let metadata = TokenMetadata {
+        account_id: account.id,
+        scopes: details.scopes,
+        created_at: now,
+        expires_at: details.expires_at,
+        last_validated_at: Some(now),
+    };
+    // The old token may still be cached
+    cache::invalidate();
+
+    let record_user_id = account.github_user_id.is_none();
+    let verify = account.unverified;
+    let user = details.user;
+    db.run(move |db| {
+        if verify {
+            db.set_account_verified(
+                &metadata.account_id,
+                user.id as i64,
+                &user.avatar_url,
+                user.name.as_deref(),
+            )?;
+        } else if record_user_id {
+            db.set_account_github_user_id(&metadata.account_id, user.id as i64)?;
+        }
+        db.set_token_rejected(&metadata.account_id, false)?;
+        db.set_token_metadata(&metadata)?;
+        Ok::<_, DatabaseError>(metadata)
+    })
+    .await
+    .map(TokenInfo::from)
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn add_account_token(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    name: String,
+    token: String,
+    owner: Option<String>,
+) -> Result<AccountTokenInfo, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.contains(':') || name.contains('#') {
+        return Err("Token names must be non-empty and may not contain ':' or '#'".to_string());
+    }
+
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    let user = github_auth_for(&db, &account.host)
+        .await?
+        .validate_token(&token)
+        .await
+        .map_err(|e| format!("Token validation failed: {}", e))?;
+    let same_user = match account.github_user_id {
+        Some(id) => id == user.id as i64,
+        None => user.login.eq_ignore_ascii_case(&account.username),
+    };
+    if !same_user {
+        return Err(format!(
+            "The token belongs to {}, not {}",
+            user.login, account.username
+        ));
+    }
+
+    keychain
+        .store_named_token(&account.host, &account.username, &name, &token)
+        .map_err(|e| format!("Failed to store token: {}", e))?;
+
+    let account_token = AccountToken {
+        id: Uuid::new_v4().to_string(),
+        account_id: account.id,
+        name,
+        owner: owner.filter(|o| !o.trim().is_empty()),
+        created_at: Utc::now(),
+    };
+    db.run(move |db| {
+        db.add_account_token(&account_token)?;
+        Ok::<_, DatabaseError>(account_token)
+    })
+    .await
+    .map(AccountTokenInfo::from)
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_account_tokens(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<Vec<AccountTokenInfo>, String> {
+    let tokens = db
+        .run(move |db| db.get_account_tokens(&account_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(tokens.into_iter().map(AccountTokenInfo::from).collect())
+}
+
+#[tauri::command]
+pub async fn remove_account_token(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    name: String,
+) -> Result<(), String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    keychain
+        .delete_named_token(&account.host, &account.username, &name)
+        .map_err(|e| format!("Failed to delete token: {}", e))?;
+    db.run(move |db| db.remove_account_token(&account.id, &name))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn test_connection(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    username: String,
+    host: Option<String>,
+) -> Result<TestConnectionResult, String> {
+    let host = host.unwrap_or_else(|| DEFAULT_GITHUB_HOST.to_string());
+    let token = keychain
+        .get_token(&host, &username)
+        .map_err(|e| format!("Failed to get token: {}", e))?;
+
+    let github_auth = github_auth_for(&db, &host).await?;
+
+    match github_auth.get_token_details(&token).await {
+        Ok(details) => {
+            // Keep the stored metadata in sync with what GitHub reports, and
+            // collect the repositories mapped to this token
+            let scopes = details.scopes.clone();
+            let expires_at = details.expires_at;
+            let user = details.user.clone();
+            let mapped_repos = db
+                .run(move |db| {
+                    let account = match db.get_account_by_username(&host, &username)? {
+                        Some(account) => account,
+                        None => return Ok(Vec::new()),
+                    };
+                    // An account added offline is confirmed by its first
+                    // successful test
+                    if account.unverified && user.login.eq_ignore_ascii_case(&account.username) {
+                        db.set_account_verified(
+                            &account.id,
+                            user.id as i64,
+                            &user.avatar_url,
+                            user.name.as_deref(),
+                        )?;
+                    }
+                    let created_at = db
+                        .get_token_metadata(&account.id)?
+                        .map(|m| m.created_at)
+                        .unwrap_or(account.created_at);
+                    db.set_token_metadata(&TokenMetadata {
+                        account_id: account.id.clone(),
+                        scopes,
+                        created_at,
+                        expires_at,
+                        last_validated_at: Some(Utc::now()),
+                    })?;
+
+                    // Mappings to a named token don't use the account token
+                    let repos: Vec<String> = db
+                        .get_repository_mappings()?
+                        .into_iter()
+                        .filter(|m| m.account_id == account.id && m.token_name.is_none())
+                        .filter_map(|m| remote::repo_full_name(&m.remote_url))
+                        .collect();
+                    Ok::<_, DatabaseError>(repos)
+                })
+                .await
+                .unwrap_or_default();
+
+            let mut warnings = Vec::new();
+            if let Some(expires_at) = details.expires_at {
+                let days = (expires_at - Utc::now()).num_days();
+                if days < 0 {
+                    warnings.push("This token has expired".to_string());
+                } else if days < TOKEN_EXPIRY_WARNING_DAYS {
+                    warnings.push(format!(
+                        "This token expires in {} day{}",
+                        days,
+                        if days == 1 { "" } else { "s" }
+                    ));
+                }
+            }
+            // Fine-grained tokens have permissions instead of scopes
+            let missing_scopes = match details.kind {
+                TokenKind::Classic | TokenKind::OAuth => {
+                    github_auth::missing_scopes(&details.scopes, github_auth::RECOMMENDED_SCOPES)
+                }
+                _ => Vec::new(),
+            };
+            if !missing_scopes.is_empty() {
+                warnings.push(format!(
+                    "The token is missing the {} scope{}; reauthorize the account to add {}",
+                    missing_scopes.join(", "),
+                    if missing_scopes.len() == 1 { "" } else { "s" },
+                    if missing_scopes.len() == 1 {
+                        "it"
+                    } else {
+                        "them"
+                    }
+                ));
+            }
+            if details.kind == TokenKind::FineGrained {
+                let mut missing = Vec::new();
+                for repo in mapped_repos.iter().take(MAX_REPO_ACCESS_CHECKS) {
+                    if let Ok(RepoPermission::None) =
+                        github_auth.get_repo_permission(&token, repo).await
+                    {
+                        missing.push(repo.clone());
+                    }
+                }
+                if !missing.is_empty() {
+                    warnings.push(format!(
+                        "This fine-grained token can't access {}; grant it those repositories on GitHub or map them to another token",
+                        missing.join(", ")
+                    ));
+                }
+            }
+
+            Ok(TestConnectionResult {
+                success: true,
+                message: format!(
+                    "Connected as {} with a {}",
+                    details.user.login,
+                    details.kind.as_str()
+                ),
+                scopes: Some(details.scopes),
+                token_kind: Some(details.kind),
+                expires_at: details.expires_at.map(|d| d.to_rfc3339()),
+                missing_scopes,
+                offline: false,
+                warnings,
+            })
+        }
+        Err(e) => Ok(TestConnectionResult {
+            success: false,
+            message: if e.is_network() {
+                format!("{} can't be reached: {}", host, e)
+            } else {
+                format!("Connection failed: {}", e)
+            },
+            scopes: None,
+            token_kind: Some(TokenKind::from_prefix(&token)),
+            expires_at: None,
+            missing_scopes: Vec::new(),
+            offline: e.is_network(),
+            warnings: Vec::new(),
+        }),
+    }
+}
+
+// Organizations the account belongs to, from the cache unless it is older
+// than a day or `refresh` is set. When GitHub can't be reached the cached
+// list is returned as it is.
+#[tauri::command]
+pub async fn get_account_orgs(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    refresh: Option<bool>,
+) -> Result<Vec<AccountOrgInfo>, String> {
+    let lookup_id = account_id.clone();
+    let (account, cached) = db
+        .run(move |db| {
+            let account = db.get_account(&lookup_id)?.ok_or("Account not found")?;
+            let cached = db.get_account_orgs(&lookup_id)?;
+            Ok::<_, String>((account, cached))
+        })
+        .await?;
+
+    // An account in no organization has nothing cached, so its last fetch
+    // isn't known and it's fetched every time
+    let fresh = cached.first().is_some_and(|org| {
+        Utc::now() - org.fetched_at < chrono::Duration::hours(ACCOUNT_ORGS_TTL_HOURS)
+    });
+    if fresh && !refresh.unwrap_or(false) {
+        return Ok(cached.into_iter().map(AccountOrgInfo::from).collect());
+    }
+
+    let fetched = match keychain.get_token(&account.host, &account.username) {
+        Ok(token) => github_auth_for(&db, &account.host)
+            .await?
+            .list_user_orgs(&token)
+            .await
+            .map_err(|e| format!("Failed to fetch organizations: {}", e)),
+        Err(e) => Err(format!("Failed to read token: {}", e)),
+    };
+    let orgs = match fetched {
+        Ok(orgs) => orgs,
+        Err(e) if !cached.is_empty() => {
+            warn!(error = %e, account = %account.username, "using the cached organization list");
+            return Ok(cached.into_iter().map(AccountOrgInfo::from).collect());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let fetched_at = Utc::now();
+    let orgs: Vec<AccountOrg> = orgs
+        .into_iter()
+        .map(|org| AccountOrg {
+            account_id: account_id.clone(),
+            login: org.login,
+            description: org.description.filter(|d| !d.is_empty()),
+            avatar_url: org.avatar_url,
+            fetched_at,
+        })
+        .collect();
+    let stored = orgs.clone();
+    db.run(move |db| db.set_account_orgs(&account_id, &stored))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut orgs: Vec<AccountOrgInfo> = orgs.into_iter().map(AccountOrgInfo::from).collect();
+    orgs.sort_by_key(|org| org.login.to_lowercase());
+    Ok(orgs)
+}
+
+// The account's access to the repository at `remote_url`, so a mapping can
+// be refused before the first push fails with a 403
+#[tauri::command]
+pub async fn check_repo_access(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    remote_url: String,
+    account_id: String,
+) -> Result<RepoAccessInfo, String> {
+    let repository = remote::repo_full_name(&remote_url)
+        .ok_or_else(|| format!("Not a repository URL: {}", remote_url))?;
+    let host = remote::host_from_url(&remote::canonicalize_remote_url(&remote_url))
+        .ok_or_else(|| format!("Not a repository URL: {}", remote_url))?;
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    if !account.host.eq_ignore_ascii_case(&host) {
+        return Err(format!(
+            "{} is an account on {}, not {}",
+            account.username, account.host, host
+        ));
+    }
+
+    let token = keychain
+        .get_token(&account.host, &account.username)
+        .map_err(|e| format!("Failed to read token: {}", e))?;
+    let permission = github_auth_for(&db, &account.host)
+        .await?
+        .get_repo_permission(&token, &repository)
+        .await
+        .map_err(|e| format!("Failed to check access to {}: {}", repository, e))?;
+    Ok(RepoAccessInfo {
+        repository,
+        can_push: permission.can_push(),
+        permission,
+    })
+}
+
+// Ranks the accounts on the repository's host for an unmapped repository:
+// best access first, then owners and members of the owning org, then the
+// most recently used. Accounts that can't see the repository are left out.
+#[tauri::command]
+pub async fn suggest_account(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    remote_url: String,
+) -> Result<Vec<AccountSuggestion>, String> {
+    let repository = remote::repo_full_name(&remote_url)
+        .ok_or_else(|| format!("Not a repository URL: {}", remote_url))?;
+    let host = remote::host_from_url(&remote::canonicalize_remote_url(&remote_url))
+        .ok_or_else(|| format!("Not a repository URL: {}", remote_url))?;
+    let owner = repository.split('/').next().unwrap_or_default().to_string();
+
+    let (lookup_host, lookup_owner) = (host.clone(), owner.clone());
+    let candidates = db
+        .run(move |db| {
+            let profile = resolver::active_profile_accounts(db)?;
+            let mut candidates = Vec::new();
+            for account in db.get_accounts()? {
+                if !account.host.eq_ignore_ascii_case(&lookup_host)
+                    || profile
+                        .as_ref()
+                        .is_some_and(|ids| !ids.contains(&account.id))
+                {
+                    continue;
+                }
+                let in_org = db
+                    .get_account_orgs(&account.id)?
+                    .iter()
+                    .any(|org| org.login.eq_ignore_ascii_case(&lookup_owner));
+                let owner_match = in_org || account.username.eq_ignore_ascii_case(&lookup_owner);
+                candidates.push((account, owner_match));
+            }
+            Ok::<_, DatabaseError>(candidates)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let github_auth = github_auth_for(&db, &host).await?;
+    let mut suggestions = Vec::new();
+    for (index, (account, owner_match)) in candidates.into_iter().enumerate() {
+        let rate_limited = github_client::rate_limit_status()
+            .iter()
+            .any(|status| status.host == host && status.remaining < MIN_RATE_LIMIT_FOR_PROBES);
+        let permission = if index < MAX_SUGGESTION_PROBES && !rate_limited {
+            match keychain.get_token(&account.host, &account.username) {
+                Ok(token) => github_auth
+                    .get_repo_permission(&token, &repository)
+                    .await
+                    .ok(),
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+        if permission == Some(RepoPermission::None) {
+            continue;
+        }
+
+        let reason = match (permission, owner_match) {
+            (Some(permission), _) if permission.can_push() => "can push".to_string(),
+            (Some(_), true) => format!("read-only, owner or member of {}", owner),
+            (Some(_), false) => "read-only".to_string(),
+            (None, true) => "owner or member of the owning organization".to_string(),
+            (None, false) => "access not checked".to_string(),
+        };
+        suggestions.push((
+            account.last_used_at,
+            AccountSuggestion {
+                account: AccountInfo::from(account),
+                permission,
+                owner_match,
+                reason,
+            },
+        ));
+    }
+
+    suggestions.sort_by(|(a_used, a), (b_used, b)| {
+        b.permission
+            .cmp(&a.permission)
+            .then(b.owner_match.cmp(&a.owner_match))
+            .then(b_used.cmp(a_used))
+    });
+    Ok(suggestions
+        .into_iter()
+        .map(|(_, suggestion)| suggestion)
+        .collect())
+}
+
+// Whether the account's token works in `org`. When the org enforces SAML
+// single sign-on the result carries the page that authorizes the token.
+#[tauri::command]
+pub async fn check_org_sso(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    org: String,
+) -> Result<SsoStatus, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let token = keychain
+        .get_token(&account.host, &account.username)
+        .map_err(|e| format!("Failed to read token: {}", e))?;
+
+    github_auth_for(&db, &account.host)
+        .await?
+        .check_sso_requirement(&token, org.trim())
+        .await
+        .map_err(|e| format!("Failed to check {}: {}", org, e))
+}
+
+#[tauri::command]
+pub async fn get_account_token_info(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<TokenInfo, String> {
+    let metadata = db
+        .run(move |db| db.get_token_metadata(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("No token metadata recorded for this account")?;
+
+    Ok(metadata.into())
+}
+
+#[tauri::command]
+pub async fn keychain_audit(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    repair: bool,
+) -> Result<KeychainAuditReport, String> {
+    // Removed accounts keep their tokens until they are purged
+    let accounts = db
+        .run(|db| {
+            let mut accounts = db.get_accounts()?;
+            accounts.extend(db.get_deleted_accounts()?);
+            Ok::<_, DatabaseError>(accounts)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut entries = keychain
+        .list_entries()
+        .map_err(|e| format!("Failed to list keychain entries: {}", e))?;
+
+    if repair {
+        // Legacy entries that still belong to an account are migrated rather than dropped
+        for account in accounts.iter().filter(|a| a.host == DEFAULT_GITHUB_HOST) {
+            keychain
+                .migrate_legacy_entry(&account.username)
+                .map_err(|e| format!("Failed to migrate token: {}", e))?;
+        }
+        entries = keychain
+            .list_entries()
+            .map_err(|e| format!("Failed to list keychain entries: {}", e))?;
+    }
+
+    let known: HashSet<(String, String)> = accounts
+        .iter()
+        .map(|a| (a.host.to_lowercase(), a.username.clone()))
+        .collect();
+
+    let mut orphaned_entries = Vec::new();
+    let mut stale_entries = Vec::new();
+    let mut stored = HashSet::new();
+    for entry in entries {
+        match (&entry.host, &entry.username) {
+            (Some(host), Some(username)) if entry.refresh || entry.token_name.is_some() => {
+                if !known.contains(&(host.to_lowercase(), username.clone())) {
+                    orphaned_entries.push(entry.key);
+                }
+            }
+            (Some(host), Some(username)) if !entry.legacy => {
+                let pair = (host.to_lowercase(), username.clone());
+                if known.contains(&pair) {
+                    stored.insert(pair);
+                } else {
+                    orphaned_entries.push(entry.key);
+                }
+            }
+            (Some(host), Some(username)) => {
+                // Legacy entries still resolve through get_token's fallback
+                stored.insert((host.to_lowercase(), username.clone()));
+                stale_entries.push(entry.key);
+            }
+            _ => stale_entries.push(entry.key),
+        }
+    }
+
+    let missing_tokens: Vec<MissingTokenInfo> = accounts
+        .iter()
+        .filter(|a| !stored.contains(&(a.host.to_lowercase(), a.username.clone())))
+        .map(|a| MissingTokenInfo {
+            account_id: a.id.clone(),
+            username: a.username.clone(),
+            host: a.host.clone(),
+        })
+        .collect();
+
+    if repair {
+        for key in orphaned_entries.iter().chain(stale_entries.iter()) {
+            keychain
+                .delete_entry(key)
+                .map_err(|e| format!("Failed to delete keychain entry: {}", e))?;
+        }
+    }
+
+    Ok(KeychainAuditReport {
+        orphaned_entries,
+        missing_tokens,
+        stale_entries,
+        repaired: repair,
+    })
+}
+
+#[tauri::command]
+pub async fn export_vault(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    path: String,
+    password: String,
+) -> Result<ExportSummary, String> {
+    let (records, mappings) = db
+        .run(|db| {
+            let mut records = Vec::new();
+            for account in db.get_accounts()? {
+                let metadata = db.get_token_metadata(&account.id)?;
+                let named = db.get_account_tokens(&account.id)?;
+                records.push((account, metadata, named));
+            }
+            Ok::<_, DatabaseError>((records, db.get_repository_mappings()?))
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // A locked keychain or a refused prompt fails the export rather than
+    // leaving tokens out of it
+    let read_error = |e| format!("Failed to read the keychain: {}", e);
+    let mut missing_secrets = Vec::new();
+    let mut accounts = Vec::new();
+    for (account, metadata, named) in records {
+        let token = match keychain.get_token(&account.host, &account.username) {
+            Ok(token) => Some(token),
+            Err(KeychainError::ItemNotFound) => {
+                missing_secrets.push(account.username.clone());
+                None
+            }
+            Err(e) => return Err(read_error(e)),
+        };
+        let refresh = keychain
+            .get_refresh_credentials(&account.host, &account.username)
+            .map_err(read_error)?;
+
+        let mut named_tokens = Vec::new();
+        for named in named {
+            match keychain.get_named_token(&account.host, &account.username, &named.name) {
+                Ok(secret) => named_tokens.push((named, secret)),
+                Err(KeychainError::ItemNotFound) => {
+                    missing_secrets.push(format!("{}/{}", account.username, named.name))
+                }
+                Err(e) => return Err(read_error(e)),
+            }
+        }
+
+        accounts.push(ArchivedAccount {
+            account,
+            token,
+            metadata,
+            refresh,
+            named_tokens,
+        });
+    }
+
+    let archive = CredentialArchive {
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        accounts,
+        mappings,
+    };
+    let accounts_exported = archive.accounts.len();
+
+    let plaintext = zeroize::Zeroizing::new(
+        serde_json::to_vec(&archive).map_err(|e| format!("Failed to serialize archive: {}", e))?,
+    );
+    let sealed = keychain::seal(&password, &plaintext)
+        .map_err(|e| format!("Failed to encrypt archive: {}", e))?;
+    std::fs::write(&path, sealed).map_err(|e| format!("Failed to write archive: {}", e))?;
+
+    Ok(ExportSummary {
+        accounts_exported,
+        missing_secrets,
+    })
+}
+
+#[tauri::command]
+pub async fn import_vault(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    path: String,
+    password: String,
+) -> Result<ImportSummary, String> {
+    let sealed =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read archive: {}", e))?;
+    let plaintext = keychain::unseal(&password, &sealed)
+        .map_err(|e| format!("Failed to decrypt archive: {}", e))?;
+    let archive: CredentialArchive =
+        serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid archive: {}", e))?;
+    if archive.version != ARCHIVE_VERSION {
+        return Err(format!("Unsupported archive version {}", archive.version));
+    }
+
+    let mut summary = ImportSummary {
+        accounts_imported: 0,
+        accounts_skipped: 0,
+        mappings_imported: 0,
+    };
+    // Archived account ids may differ from ids already on this machine
+    let mut account_ids = std::collections::HashMap::new();
+
+    for archived in archive.accounts {
+        let account = archived.account;
+        let (host, username) = (account.host.clone(), account.username.clone());
+        if let Some(existing) = db
+            .run(move |db| db.get_account_by_username(&host, &username))
+            .await
+            .map_err(|e| e.to_string())?
+        {
+            account_ids.insert(account.id, existing.id);
+            summary.accounts_skipped += 1;
+            continue;
+        }
+
+        if let Some(token) = &archived.token {
+            keychain
+                .store_token(&account.host, &account.username, token)
+                .map_err(|e| format!("Failed to store token: {}", e))?;
+        }
+        if let Some(refresh) = &archived.refresh {
+            keychain
+                .store_refresh_credentials(&account.host, &account.username, refresh)
+                .map_err(|e| format!("Failed to store token: {}", e))?;
+        }
+        for (named, secret) in &archived.named_tokens {
+            keychain
+                .store_named_token(&account.host, &account.username, &named.name, secret)
+                .map_err(|e| format!("Failed to store token: {}", e))?;
+        }
+
+        let record = account.clone();
+        let named_tokens: Vec<AccountToken> = archived
+            .named_tokens
+            .into_iter()
+            .map(|(named, _)| named)
+            .collect();
+        let metadata = archived.metadata;
+        db.run(move |db| {
+            db.add_account(&record)?;
+            for named in &named_tokens {
+                db.add_account_token(named)?;
+            }
+            if let Some(metadata) = &metadata {
+                db.set_token_metadata(metadata)?;
+            }
+            Ok::<_, DatabaseError>(())
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+        account_ids.insert(account.id.clone(), account.id);
+        summary.accounts_imported += 1;
+    }
+
+    summary.mappings_imported = db
+        .run(move |db| {
+            let mut imported = 0;
+            for mapping in archive.mappings {
+                let Some(account_id) = account_ids.get(&mapping.account_id) else {
+                    continue;
+                };
+                // Don't clobber mappings the user already set up on this machine
+                if db.get_repository_mapping(&mapping.remote_url)?.is_some() {
+                    continue;
+                }
+                // Named tokens only came along for accounts that were imported
+                let token_name = if *account_id == mapping.account_id {
+                    mapping.token_name.as_deref()
+                } else {
+                    None
+                };
+                db.set_repository_mapping(
+                    &mapping.remote_url,
+                    account_id,
+                    mapping.remember,
+                    token_name,
+                )?;
+                imported += 1;
+            }
+            Ok::<_, DatabaseError>(imported)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if summary.accounts_imported > 0 {
+        accounts_changed(&app);
+    }
+    Ok(summary)
+}
+
+// The rate limit each GitHub host last reported to this app
+#[tauri::command]
+pub async fn get_api_status() -> Result<Vec<RateLimitStatus>, String> {
+    Ok(github_client::rate_limit_status())
+}
+
+#[tauri::command]
+pub async fn get_keychain_status(
+    keychain: State<'_, KeychainManager>,
+) -> Result<KeychainStatus, String> {
+    Ok(keychain.status())
+}
+
+#[tauri::command]
+pub async fn set_master_password(
+    keychain: State<'_, KeychainManager>,
+    current_password: Option<String>,
+    new_password: String,
+) -> Result<(), String> {
+    keychain
+        .set_master_password(current_password.as_deref(), &new_password)
+        .map_err(|e| format!("Failed to set master password: {}", e))
+}
+
+#[tauri::command]
+pub async fn unlock_vault(
+    keychain: State<'_, KeychainManager>,
+    password: String,
+) -> Result<(), String> {
+    keychain
+        .unlock_vault(&password)
+        .map_err(|e| format!("Failed to unlock vault: {}", e))
+}
+
+#[tauri::command]
+pub async fn lock_vault(keychain: State<'_, KeychainManager>) -> Result<(), String> {
+    keychain.lock_vault();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_vault_auto_lock(
+    keychain: State<'_, KeychainManager>,
+    minutes: u64,
+) -> Result<(), String> {
+    if minutes == 0 {
+        return Err("Auto-lock timeout must be at least one minute".to_string());
+    }
+    keychain.set_vault_auto_lock(std::time::Duration::from_secs(minutes * 60));
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_biometric_settings(
+    keychain: State<'_, KeychainManager>,
+) -> Result<BiometricSettings, String> {
+    Ok(keychain.biometric_settings())
+}
+
+#[tauri::command]
+pub async fn set_biometric_settings(
+    keychain: State<'_, KeychainManager>,
+    enabled: bool,
+    grace_period_secs: u64,
+) -> Result<(), String> {
+    keychain
+        .set_biometric_settings(BiometricSettings {
+            enabled,
+            grace_period_secs,
+        })
+        .map_err(|e| format!("Failed to update biometric settings: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_repository_mappings(
+    db: State<'_, Database>,
+) -> Result<Vec<RepositoryMappingInfo>, String> {
+    let mappings = db
+        .run(|db| db.get_repository_mappings())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mapping_infos: Vec<RepositoryMappingInfo> = mappings
+        .into_iter()
+        .map(|mapping| RepositoryMappingInfo {
+            id: mapping.id,
+            remote_url: mapping.remote_url,
+            account_id: mapping.account_id,
+            remember: mapping.remember,
+            created_at: mapping.created_at.to_rfc3339(),
+            token_name: mapping.token_name,
+            last_used_at: mapping.last_used_at.map(|dt| dt.to_rfc3339()),
+            use_count: mapping.use_count,
+        })
+        .collect();
+
+    Ok(mapping_infos)
+}
+
+#[tauri::command]
+pub async fn set_repository_mapping(
+    db: State<'_, Database>,
+    remote_url: String,
+    account_id: String,
+    remember: bool,
+    token_name: Option<String>,
+    // The checkout the mapping was made for; its user.name and user.email
+    // are set from the account when `write_identity`, which defaults to the
+    // write_repo_identity setting
+    repo_path: Option<String>,
+    write_identity: Option<bool>,
+) -> Result<(), String> {
+    let mapping_url = remote_url.clone();
+    let (account, write_identity) = db
+        .run(move |db| {
+            if let Some(name) = &token_name {
+                let tokens = db.get_account_tokens(&account_id)?;
+                if !tokens.iter().any(|t| &t.name == name) {
+                    return Err(format!("Account has no token named \"{}\"", name));
+                }
+            }
+
+            db.set_repository_mapping(&mapping_url, &account_id, remember, token_name.as_deref())?;
+            let account = db.get_account(&account_id)?.ok_or("Account not found")?;
+            let write_identity = match write_identity {
+                Some(write) => write,
+                None => db
+                    .get_setting(SettingKey::WriteRepoIdentity)?
+                    .as_bool()
+                    .unwrap_or(false),
+            };
+            Ok::<_, String>((account, write_identity))
+        })
+        .await?;
+    cache::invalidate();
+
+    if let (Some(path), true) = (repo_path, write_identity) {
+        tokio::task::spawn_blocking(move || {
+            let path = std::path::PathBuf::from(path);
+            if !git_config::has_remote(&path, &remote_url)? {
+                return Ok(false);
+            }
+            git_config::write_identity(
+                &GitConfigScope::Repository(path),
+                account.git_name.as_deref(),
+                account.commit_email().as_deref(),
+            )
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| {
+            format!(
+                "Mapping saved, but setting the commit identity failed: {}",
+                e
+            )
+        })?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_repository_mapping(
+    db: State<'_, Database>,
+    mapping_id: String,
+) -> Result<(), String> {
+    db.run(move |db| db.remove_repository_mapping(&mapping_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_org_mappings(db: State<'_, Database>) -> Result<Vec<OrgMappingInfo>, String> {
+    let mappings = db
+        .run(|db| db.get_org_mappings())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(mappings.into_iter().map(OrgMappingInfo::from).collect())
+}
+
+#[tauri::command]
+pub async fn set_org_mapping(
+    db: State<'_, Database>,
+    owner: String,
+    account_id: String,
+    host: Option<String>,
+) -> Result<(), String> {
+    let owner = owner.trim().trim_matches('/').to_string();
+    if owner.is_empty() || owner.contains('/') {
+        return Err("Organization must be a single owner name".to_string());
+    }
+
+    db.run(move |db| {
+        let account = db.get_account(&account_id)?.ok_or("Account not found")?;
+        let host = host.unwrap_or_else(|| account.host.clone());
+        if !account.host.eq_ignore_ascii_case(&host) {
+            return Err(format!(
+                "Account {} is registered for {}, not {}",
+                account.username, account.host, host
+            ));
+        }
+
+        db.set_org_mapping(&host, &owner, &account.id)?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_org_mapping(db: State<'_, Database>, mapping_id: String) -> Result<(), String> {
+    db.run(move |db| db.remove_org_mapping(&mapping_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_hosts(db: State<'_, Database>) -> Result<Vec<GitHostInfo>, String> {
+    let hosts = db
+        .run(|db| db.get_hosts())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(hosts.into_iter().map(GitHostInfo::from).collect())
+}
+
+// Adds or updates a GitHub Enterprise Server host. The API URL defaults to
+// https://<host>/api/v3.
+#[tauri::command]
+pub async fn add_host(
+    db: State<'_, Database>,
+    host: String,
+    name: Option<String>,
+    api_url: Option<String>,
+    oauth_client_id: Option<String>,
+) -> Result<GitHostInfo, String> {
+    let host = normalize_host(&host)?;
+    if host == DEFAULT_GITHUB_HOST {
+        return Err("github.com is always available".to_string());
+    }
+    let api_url = match api_url.map(|url| url.trim().trim_end_matches('/').to_string()) {
+        Some(url) if url.starts_with("https://") || url.starts_with("http://") => url,
+        Some(url) if !url.is_empty() => return Err(format!("Invalid API URL: {}", url)),
+        _ => default_api_url(&host),
+    };
+
+    let existing = db
+        .run({
+            let host = host.clone();
+            move |db| db.get_host(&host)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    let record = GitHost {
+        host,
+        name: name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+        api_url,
+        created_at: existing.map_or_else(Utc::now, |h| h.created_at),
+        oauth_client_id: oauth_client_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty()),
+    };
+    let info = GitHostInfo::from(record.clone());
+
+    // Otherwise the helper only answers for the new host once reinstalled.
+    // Done first so that a host is never saved without it.
+    let helper_host = info.host.clone();
+    db.run(move |db| git_helper::install_for_host(db, &helper_host))
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to set up the credential helper for {}: {}",
+                info.host, e
+            )
+        })?;
+    db.run(move |db| db.add_host(&record))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn remove_host(db: State<'_, Database>, host: String) -> Result<(), String> {
+    let host = normalize_host(&host)?;
+    db.run(move |db| {
+        if db
+            .get_accounts()?
+            .iter()
+            .any(|account| account.host.eq_ignore_ascii_case(&host))
+        {
+            return Err(format!("Accounts still use {}; remove them first", host));
+        }
+        db.remove_host(&host)?;
+        Ok(())
+    })
+    .await
+}
+
+// Accepts "ghe.example.com" as well as "https://ghe.example.com/"
+fn normalize_host(input: &str) -> Result<String, String> {
+    let host = input.trim();
+    let host = host
+        .split_once("://")
+        .map_or(host, |(_, rest)| rest)
+        .trim_end_matches('/')
+        .to_lowercase();
+    if host.is_empty() || host.contains(['/', '@', ' ']) {
+        return Err(format!("Invalid host: {}", input));
+    }
+    Ok(host)
+}
+
+fn default_api_url(host: &str) -> String {
+    format!("https://{}/api/v3", host)
+}
+
+// The account the chooser preselects and the credential helper falls back
+// to when there is nobody to ask. Unlike a host default it doesn't stop the
+// chooser from being shown.
+#[tauri::command]
+pub async fn get_default_account(db: State<'_, Database>) -> Result<Option<AccountInfo>, String> {
+    let account = db
+        .run(move |db| match resolver::default_account_id(db)? {
+            Some(id) => db.get_account(&id),
+            None => Ok(None),
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(account.map(AccountInfo::from))
+}
+
+// No account clears the default, leaving the most recently used one to lead
+// the chooser
+#[tauri::command]
+pub async fn set_default_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    account_id: Option<String>,
+) -> Result<(), String> {
+    db.run(move |db| {
+        if let Some(id) = &account_id {
+            db.get_account(id)?.ok_or("Account not found")?;
+        }
+        db.set_setting(
+            SettingKey::DefaultAccount,
+            &account_id.map_or(serde_json::Value::Null, serde_json::Value::from),
+        )?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    accounts_changed(&app);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_host_default_account(
+    db: State<'_, Database>,
+    host: Option<String>,
+) -> Result<Option<String>, String> {
+    let host = host.unwrap_or_else(|| DEFAULT_GITHUB_HOST.to_string());
+    db.run(move |db| db.get_host_default_account(&host))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_host_default_account(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<(), String> {
+    db.run(move |db| {
+        let account = db.get_account(&account_id)?.ok_or("Account not found")?;
+
+        // An account can only be the default for the host it belongs to
+        db.set_host_default_account(&account.host, &account.id)?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn clear_host_default_account(
+    db: State<'_, Database>,
+    host: Option<String>,
+) -> Result<(), String> {
+    let host = host.unwrap_or_else(|| DEFAULT_GITHUB_HOST.to_string());
+    db.run(move |db| db.clear_host_default_account(&host))
+        .await
+        .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_directory_rules(
+    db: State<'_, Database>,
+) -> Result<Vec<DirectoryRuleInfo>, String> {
+    let rules = db
+        .run(|db| db.get_directory_rules())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(rules.into_iter().map(DirectoryRuleInfo::from).collect())
+}
+
+#[tauri::command]
+pub async fn add_directory_rule(
+    db: State<'_, Database>,
+    pattern: String,
+    account_id: String,
+) -> Result<(), String> {
+    let pattern = pattern.trim().to_string();
+    if pattern.is_empty() {
+        return Err("Directory pattern cannot be empty".to_string());
+    }
+
+    db.run(move |db| {
+        db.get_account(&account_id)?.ok_or("Account not found")?;
+        db.add_directory_rule(&pattern, &account_id)?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_directory_rule(db: State<'_, Database>, rule_id: String) -> Result<(), String> {
+    db.run(move |db| db.remove_directory_rule(&rule_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn resolve_account_for_path(
+    db: State<'_, Database>,
+    path: String,
+) -> Result<Option<AccountInfo>, String> {
+    let account = db
+        .run(
+            move |db| match db.find_directory_rule(std::path::Path::new(&path))? {
+                Some(rule) => db.get_account(&rule.account_id),
+                None => Ok(None),
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(account.map(AccountInfo::from))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolutionInfo {
+    pub account: Option<AccountInfo>,
+    pub source: Option<String>,
+    pub reason: String,
+    // Lower-precedence rules that also matched
+    pub overridden: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+// Explains which account the credential helper would pick for a remote,
+// optionally from a checkout directory, without touching any token
+#[tauri::command]
+pub async fn resolve_account(
+    db: State<'_, Database>,
+    remote_url: String,
+    path: Option<String>,
+) -> Result<ResolutionInfo, String> {
+    let host = remote::host_from_url(&remote_url)
+        .ok_or_else(|| format!("Invalid remote URL: {}", remote_url))?;
+    let owner = remote::repo_owner(&remote_url, "");
+    let remote_url = remote::canonicalize_remote_url(&remote_url);
+
+    let resolution = db
+        .run(move |db| {
+            resolver::resolve(
+                db,
+                &ResolveRequest {
+                    repo_url: &remote_url,
+                    host: &host,
+                    owner: owner.as_deref(),
+                    path: path.as_deref().map(std::path::Path::new),
+                },
+            )
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut candidates = resolution.candidates.into_iter();
+    let winner = candidates.next();
+    let overridden = candidates.map(|candidate| candidate.reason).collect();
+    Ok(match winner {
+        Some(winner) => ResolutionInfo {
+            source: Some(winner.source.as_str().to_string()),
+            reason: winner.reason,
+            account: Some(winner.account.into()),
+            overridden,
+            skipped: resolution.skipped,
+        },
+        None => ResolutionInfo {
+            account: None,
+            source: None,
+            reason: "No mapping, rule or default applies; the account chooser would be shown"
+                .to_string(),
+            overridden,
+            skipped: resolution.skipped,
+        },
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScannedRepositoryInfo {
+    pub path: String,
+    // The remote the account is resolved for: origin, else the first one on
+    // a host with an account
+    pub remote: Option<String>,
+    pub remote_url: Option<String>,
+    // "owner/repo"
+    pub repo_name: Option<String>,
+    pub user_name: Option<String>,
+    pub user_email: Option<String>,
+    // "local", "global" or "system"
+    pub user_email_scope: Option<String>,
+    pub account: Option<AccountInfo>,
+    pub source: Option<String>,
+    pub reason: Option<String>,
+    // What disagrees with the resolved account, empty when nothing does
+    pub mismatches: Vec<String>,
+}
+
+// Finds the checkouts under `paths`, the project directories setting by
+// default, and reports the account each resolves to along with anything in
+// the checkout that disagrees with it
+#[tauri::command]
+pub async fn scan_repositories(
+    db: State<'_, Database>,
+    paths: Option<Vec<String>>,
+) -> Result<Vec<ScannedRepositoryInfo>, String> {
+    let roots = match paths {
+        Some(paths) => paths
+            .iter()
+            .map(|path| std::path::PathBuf::from(crate::paths::expand_home(path.trim())))
+            .collect(),
+        None => db
+            .run(project_directories)
+            .await
+            .map_err(|e| e.to_string())?,
+    };
+    if roots.is_empty() {
+        return Err("No project directories to scan".to_string());
+    }
+
+    let repositories = tokio::task::spawn_blocking(move || {
+        let mut repositories = Vec::new();
+        for root in roots {
+            let found = scanner::find_repositories(&root)
+                .map_err(|e| format!("Failed to scan {}: {}", root.display(), e))?;
+            for path in found {
+                if repositories.len() >= scanner::MAX_REPOSITORIES {
+                    break;
+                }
+                // A broken config is reported rather than failing the scan
+                repositories.push(match scanner::read_repository(&path) {
+                    Ok(repository) => (repository, None),
+                    Err(e) => (
+                        ScannedRepository {
+                            path,
+                            ..Default::default()
+                        },
+                        Some(e.to_string()),
+                    ),
+                });
+            }
+        }
+        Ok::<_, String>(repositories)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    db.run(move |db| {
+        let accounts = db.get_accounts()?;
+        repositories
+            .into_iter()
+            .map(|(repository, error)| scanned_repository_info(db, &accounts, repository, error))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+// The project directories setting, with ~ expanded
+fn project_directories(db: &Database) -> Result<Vec<std::path::PathBuf>, DatabaseError> {
+    Ok(db
+        .get_setting(SettingKey::ProjectDirectories)?
+        .as_array()
+        .map(|dirs| {
+            dirs.iter()
+                .filter_map(|dir| dir.as_str())
+                .map(|dir| std::path::PathBuf::from(crate::paths::expand_home(dir.trim())))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn scanned_repository_info(
+    db: &Database,
+    accounts: &[Account],
+    repository: ScannedRepository,
+    error: Option<String>,
+) -> Result<ScannedRepositoryInfo, DatabaseError> {
+    let mut info = ScannedRepositoryInfo {
+        path: repository.path.display().to_string(),
+        remote: None,
+        remote_url: None,
+        repo_name: None,
+        user_name: repository.user_name,
+        user_email: repository.user_email,
+        user_email_scope: repository.user_email_scope,
+        account: None,
+        source: None,
+        reason: None,
+        mismatches: Vec::new(),
+    };
+    if let Some(error) = error {
+        info.mismatches
+            .push(format!("Failed to read the repository config: {}", error));
+        return Ok(info);
+    }
+
+    let remote = repository
+        .remotes
+        .iter()
+        .filter_map(|(name, url)| {
+            let (mapping_url, host) = remote::mapping_target(url)?;
+            accounts
+                .iter()
+                .any(|account| account.host.eq_ignore_ascii_case(&host))
+                .then_some((name, url, mapping_url, host))
+        })
+        .min_by_key(|(name, ..)| name.as_str() != "origin");
+    // Checkouts of other hosts are listed with nothing to check
+    let Some((name, url, mapping_url, host)) = remote else {
+        return Ok(info);
+    };
+    info.remote = Some(name.clone());
+    info.remote_url = Some(url.clone());
+    info.repo_name = remote::repo_full_name(url);
+
+    let owner = remote::repo_owner(&mapping_url, "");
+    let resolution = resolver::resolve(
+        db,
+        &ResolveRequest {
+            repo_url: &mapping_url,
+            host: &host,
+            owner: owner.as_deref(),
+            path: Some(&repository.path),
+        },
+    )?;
+    let Some(winner) = resolution.candidates.into_iter().next() else {
+        info.mismatches.push(
+            "No mapping, rule or default applies, so the account chooser would be shown"
+                .to_string(),
+        );
+        return Ok(info);
+    };
+    let account = winner.account;
+
+    // SSH remotes sign in with whichever key the alias names
+    let remote_host = remote::host_from_url(url);
+    if let Some(other) = accounts.iter().find(|other| {
+        other.id != account.id
+            && ssh_alias(other)
+                .is_ok_and(|alias| remote_host.as_deref() == Some(alias.to_lowercase().as_str()))
+    }) {
+        info.mismatches.push(format!(
+            "{} goes through {}'s SSH alias, but the repository resolves to {}",
+            name, other.username, account.username
+        ));
+    }
+
+    let expected_email = account.commit_email();
+    match (&info.user_email, expected_email) {
+        (Some(email), Some(expected)) if !email.eq_ignore_ascii_case(&expected) => {
+            info.mismatches.push(format!(
+                "Commits are authored as {}, but {} commits as {}",
+                email, account.username, expected
+            ))
+        }
+        (None, Some(expected)) => info.mismatches.push(format!(
+            "No user.email is set; {} commits as {}",
+            account.username, expected
+        )),
+        _ => {}
+    }
+
+    info.source = Some(winner.source.as_str().to_string());
+    info.reason = Some(winner.reason);
+    info.account = Some(AccountInfo::from(account));
+    Ok(info)
+}
+
+fn active_profile_id(db: &Database) -> Result<Option<String>, DatabaseError> {
+    Ok(db
+        .get_setting(SettingKey::ActiveProfile)?
+        .as_str()
+        .map(|id| id.to_string()))
+}
+
+fn profile_info(profile: Profile, active_id: Option<&str>) -> ProfileInfo {
+    ProfileInfo {
+        active: active_id == Some(profile.id.as_str()),
+        id: profile.id,
+        name: profile.name,
+        created_at: profile.created_at.to_rfc3339(),
+        account_ids: profile.account_ids,
+    }
+}
+
+#[tauri::command]
+pub async fn get_profiles(db: State<'_, Database>) -> Result<Vec<ProfileInfo>, String> {
+    let (active_id, profiles) = db
+        .run(|db| Ok::<_, DatabaseError>((active_profile_id(db)?, db.get_profiles()?)))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(profiles
+        .into_iter()
+        .map(|profile| profile_info(profile, active_id.as_deref()))
+        .collect())
+}
+
+#[tauri::command]
+pub async fn create_profile(
+    db: State<'_, Database>,
+    name: String,
+    account_ids: Vec<String>,
+) -> Result<ProfileInfo, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Profile name cannot be empty".to_string());
+    }
+
+    db.run(move |db| {
+        if db
+            .get_profiles()?
+            .iter()
+            .any(|p| p.name.eq_ignore_ascii_case(&name))
+        {
+            return Err(format!("A profile named \"{}\" already exists", name));
+        }
+
+        let mut profile = db.create_profile(&name)?;
+        db.set_profile_accounts(&profile.id, &account_ids)?;
+        profile.account_ids = account_ids;
+
+        Ok(profile_info(profile, None))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn set_profile_accounts(
+    db: State<'_, Database>,
+    profile_id: String,
+    account_ids: Vec<String>,
+) -> Result<(), String> {
+    db.run(move |db| {
+        db.get_profile(&profile_id)?.ok_or("Profile not found")?;
+        db.set_profile_accounts(&profile_id, &account_ids)?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    Ok(())
+}
+
+// Passing no profile makes every account available again
+#[tauri::command]
+pub async fn switch_profile(
+    db: State<'_, Database>,
+    profile_id: Option<String>,
+) -> Result<(), String> {
+    db.run(move |db| {
+        let value = match profile_id {
+            Some(profile_id) => {
+                db.get_profile(&profile_id)?.ok_or("Profile not found")?;
+                serde_json::Value::String(profile_id)
+            }
+            None => serde_json::Value::Null,
+        };
+        db.set_setting(SettingKey::ActiveProfile, &value)?;
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_profile(db: State<'_, Database>, profile_id: String) -> Result<(), String> {
+    db.run(move |db| {
+        if active_profile_id(db)?.as_deref() == Some(profile_id.as_str()) {
+            db.set_setting(SettingKey::ActiveProfile, &serde_json::Value::Null)?;
+        }
+        db.delete_profile(&profile_id)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_audit_log(
+    db: State<'_, Database>,
+    filter: Option<AuditLogFilter>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<Vec<AuditEntry>, String> {
+    db.run(move |db| {
+        db.get_audit_log(
+            &filter.unwrap_or_default(),
+            limit.unwrap_or(100).min(1000),
+            offset.unwrap_or(0),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn export_config(db: State<'_, Database>, path: String) -> Result<(), String> {
+    let config = db
+        .run(config::export_config)
+        .await
+        .map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(&config)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write config: {}", e))?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn import_config(
+    app: AppHandle,
+    db: State<'_, Database>,
+    path: String,
+    strategy: Option<ConflictStrategy>,
+) -> Result<ConfigImportSummary, String> {
+    let json =
+        std::fs::read_to_string(&path).map_err(|e| format!("Failed to read config: {}", e))?;
+    let config = serde_json::from_str(&json).map_err(|e| format!("Invalid config: {}", e))?;
+    let strategy = strategy.unwrap_or(ConflictStrategy::Skip);
+    let summary = db
+        .run(move |db| config::import_config(db, config, strategy))
+        .await
+        .map_err(|e| e.to_string())?;
+    // Settings come along too, the default account among them
+    accounts_changed(&app);
+    Ok(summary)
+}
+
+#[tauri::command]
+pub async fn list_backups(db: State<'_, Database>) -> Result<Vec<BackupInfo>, String> {
+    db.run(|db| db.list_backups())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_backup(db: State<'_, Database>) -> Result<BackupInfo, String> {
+    db.run(|db| db.create_backup("manual"))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn restore_backup(
+    app: AppHandle,
+    db: State<'_, Database>,
+    name: String,
+) -> Result<(), String> {
+    db.run(move |db| db.restore_backup(&name))
+        .await
+        .map_err(|e| format!("Failed to restore backup: {}", e))?;
+    accounts_changed(&app);
+    Ok(())
+}
+
+// Checks the database for corruption and unreadable rows. Corruption cannot
+// be repaired in place; restore a backup instead.
+#[tauri::command]
+pub async fn db_doctor(db: State<'_, Database>, repair: bool) -> Result<IntegrityReport, String> {
+    db.run(move |db| db.check_integrity(repair))
+        .await
+        .map_err(|e| format!("Database check failed: {}", e))
+}
+
+// Installs for every URL, or with `scoped` only for the GitHub hosts. Returns
+// the other helpers that will also store GitHub credentials.
+#[tauri::command]
+pub async fn install_git_helper(
+    db: State<'_, Database>,
+    scoped: Option<bool>,
+) -> Result<Vec<String>, String> {
+    let scoped = scoped.unwrap_or(false);
+    db.run(move |db| git_helper::install_helper(db, scoped))
+        .await
+        .map_err(|e| format!("Failed to install credential helper: {}", e))
+}
+
+// Returns the previously configured helpers that were put back
+#[tauri::command]
+pub async fn uninstall_git_helper(db: State<'_, Database>) -> Result<Vec<String>, String> {
+    db.run(git_helper::uninstall_helper)
+        .await
+        .map_err(|e| format!("Failed to uninstall credential helper: {}", e))
+}
+
+#[tauri::command]
+pub async fn get_git_helper_status(
+    db: State<'_, Database>,
+    repo_path: Option<String>,
+) -> Result<GitHelperStatus, String> {
+    git_helper_status(&db, repo_path).await
+}
+
+async fn git_helper_status(
+    db: &Database,
+    repo_path: Option<String>,
+) -> Result<GitHelperStatus, String> {
+    let replaced_helpers = db
+        .run(|db| db.get_replaced_helpers())
+        .await
+        .map_err(|e| e.to_string())?;
+    let hosts = db
+        .run(git_helper::helper_hosts)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let own = git_helper::own_helper_commands().map_err(|e| e.to_string())?;
+    let generic = git_config::get_all_values(&GitConfigScope::Global, "credential.helper")
+        .map_err(|e| format!("Git config failed: {}", e))?;
+    let scoped = git_helper::scoped_install_enabled(&hosts).map_err(|e| e.to_string())?;
+    let global = generic.iter().any(|value| own.contains(value));
+
+    let mut use_http_path = true;
+    for host in &hosts {
+        use_http_path &= git_helper::http_path_enabled(host).map_err(|e| e.to_string())?;
+    }
+    let helpers =
+        git_helper::configured_helpers(&hosts, repo_path.as_deref().map(std::path::Path::new))
+            .map_err(|e| e.to_string())?;
+
+    Ok(GitHelperStatus {
+        installed: !generic.is_empty() || scoped,
+        configured: global || scoped,
+        use_http_path,
+        scoped,
+        conflicts: helpers
+            .iter()
+            .filter(|helper| helper.conflict)
+            .map(|helper| helper.value.clone())
+            .collect(),
+        replaced_helpers,
+        helpers,
+    })
+}
+
+// Removes a helper entry listed by get_git_helper_status from the global
+// config, or a repository's config for the local scope
+#[tauri::command]
+pub async fn remove_credential_helper(
+    scope: String,
+    repo_path: Option<String>,
+    key: String,
+    value: String,
+) -> Result<(), String> {
+    let scope = GitConfigScope::parse(&scope, repo_path.as_deref()).map_err(|e| e.to_string())?;
+    git_helper::remove_configured_helper(&scope, &key, &value)
+        .map_err(|e| format!("Failed to remove credential helper: {}", e))
+}
+
+// Oldest git with every config option the app uses, --fixed-value being
+// the newest of them
+const MIN_GIT_VERSION: (u32, u32) = (2, 30);
+
+// What fixes a doctor finding: a command the app can run with the arguments
+// it knows, or only a label when the user has to act, including when the fix
+// needs something only they can give, such as a password or a new token
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoctorFixInfo {
+    pub label: String,
+    pub command: Option<String>,
+    pub args: serde_json::Value,
+}
+
+impl DoctorFixInfo {
+    fn command(label: impl Into<String>, command: &str, args: serde_json::Value) -> Self {
+        Self {
+            label: label.into(),
+            command: Some(command.to_string()),
+            args,
+        }
+    }
+
+    fn manual(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            command: None,
+            args: serde_json::Value::Null,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoctorCheckInfo {
+    // "git", "credential_helper", "keychain", "token", "ssh", "ssh_key",
+    // "ssh_agent", "ssh_config" or "database"
+    pub kind: String,
+    // The account checked, for "token" and "ssh_key"
+    pub account_id: Option<String>,
+    // "ok", "warning", "error", or "skipped" when it couldn't be checked
+    pub status: String,
+    pub message: String,
+    pub fixes: Vec<DoctorFixInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoctorReport {
+    // Nothing needs fixing
+    pub ok: bool,
+    pub checks: Vec<DoctorCheckInfo>,
+}
+
+fn doctor_check(
+    kind: &str,
+    account: Option<&Account>,
+    status: &str,
+    message: impl Into<String>,
+    fixes: Vec<DoctorFixInfo>,
+) -> DoctorCheckInfo {
+    DoctorCheckInfo {
+        kind: kind.to_string(),
+        account_id: account.map(|account| account.id.clone()),
+        status: status.to_string(),
+        message: message.into(),
+        fixes,
+    }
+}
+
+// Checks everything the app depends on in one go: git, the credential
+// helper, the keychain, each account's token, SSH and the database.
+// Nothing is changed; each finding lists the fixes for it.
+#[tauri::command]
+pub async fn run_doctor(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+) -> Result<DoctorReport, String> {
+    let mut checks = Vec::new();
+
+    let git_version = tokio::task::spawn_blocking(git_config::git_version)
+        .await
+        .map_err(|e| e.to_string())?;
+    let git_found = git_version.is_ok();
+    checks.push(doctor_git_check(git_version));
+
+    checks.push(if git_found {
+        match git_helper_status(&db, None).await {
+            Ok(status) => doctor_helper_check(status),
+            Err(e) => doctor_check(
+                "credential_helper",
+                None,
+                "error",
+                format!("The credential helper config can't be read: {}", e),
+                Vec::new(),
+            ),
+        }
+    } else {
+        doctor_check(
+            "credential_helper",
+            None,
+            "skipped",
+            "Needs git",
+            Vec::new(),
+        )
+    });
+
+    let keychain_status = keychain.status();
+    checks.push(if keychain_status.available {
+        doctor_check(
+            "keychain",
+            None,
+            "ok",
+            format!("Tokens are stored in {}", keychain_status.backend),
+            Vec::new(),
+        )
+    } else if keychain_status.locked {
+        doctor_check(
+            "keychain",
+            None,
+            "warning",
+            format!(
+                "{} is locked: {}",
+                keychain_status.backend,
+                keychain_status
+                    .message
+                    .as_deref()
+                    .unwrap_or("no tokens can be read")
+            ),
+            vec![DoctorFixInfo::manual(
+                "Unlock the vault with the master password",
+            )],
+        )
+    } else {
+        doctor_check(
+            "keychain",
+            None,
+            "error",
+            format!(
+                "{} can't be used: {}",
+                keychain_status.backend,
+                keychain_status
+                    .message
+                    .as_deref()
+                    .unwrap_or("unknown error")
+            ),
+            vec![DoctorFixInfo::manual(
+                "Set a master password to keep tokens in the encrypted vault instead",
+            )],
+        )
+    });
+
+    let accounts = db
+        .run(|db| db.get_accounts())
+        .await
+        .map_err(|e| e.to_string())?;
+    for account in &accounts {
+        let check = if keychain_status.available {
+            doctor_token_check(&db, &keychain, account).await
+        } else {
+            doctor_check(
+                "token",
+                Some(account),
+                "skipped",
+                format!("{}: the keychain can't be read", account.username),
+                Vec::new(),
+            )
+        };
+        checks.push(check);
+    }
+
+    let ssh_accounts = accounts.clone();
+    checks.extend(
+        tokio::task::spawn_blocking(move || doctor_ssh_checks(&ssh_accounts))
+            .await
+            .map_err(|e| e.to_string())?,
+    );
+
+    checks.push(match db.run(|db| db.check_integrity(false)).await {
+        Ok(report) if report.healthy => doctor_check(
+            "database",
+            None,
+            "ok",
+            "The database is healthy",
+            Vec::new(),
+        ),
+        Ok(report) if !report.corruption.is_empty() => doctor_check(
+            "database",
+            None,
+            "error",
+            format!("The database is corrupt: {}", report.corruption.join("; ")),
+            vec![DoctorFixInfo::manual("Restore one of the backups")],
+        ),
+        Ok(report) => doctor_check(
+            "database",
+            None,
+            "warning",
+            format!(
+                "{} rows can't be used",
+                report.orphaned_rows.len() + report.invalid_timestamps.len()
+            ),
+            vec![DoctorFixInfo::command(
+                "Repair the database",
+                "db_doctor",
+                serde_json::json!({ "repair": true }),
+            )],
+        ),
+        Err(e) => doctor_check(
+            "database",
+            None,
+            "error",
+            format!("The database can't be checked: {}", e),
+            Vec::new(),
+        ),
+    });
+
+    Ok(DoctorReport {
+        ok: checks
+            .iter()
+            .all(|check| check.status == "ok" || check.status == "skipped"),
+        checks,
+    })
+}
+
+fn doctor_git_check(version: Result<String, git_config::GitConfigError>) -> DoctorCheckInfo {
+    let update = || DoctorFixInfo::manual("Install git from https://git-scm.com/downloads");
+    let version = match version {
+        Ok(version) => version,
+        Err(e) => {
+            return doctor_check(
+                "git",
+                None,
+                "error",
+                format!("git can't be run: {}", e),
+                vec![update()],
+            )
+        }
+    };
+    let mut numbers = version.split('.').map(|part| part.parse::<u32>().ok());
+    let parsed = (numbers.next().flatten(), numbers.next().flatten());
+    match parsed {
+        (Some(major), Some(minor)) if (major, minor) < MIN_GIT_VERSION => doctor_check(
+            "git",
+            None,
+            "warning",
+            format!(
+                "git {} is older than {}.{}; some settings can't be written",
+                version, MIN_GIT_VERSION.0, MIN_GIT_VERSION.1
+            ),
+            vec![update()],
+        ),
+        _ => doctor_check("git", None, "ok", format!("git {}", version), Vec::new()),
+    }
+}
+
+fn doctor_helper_check(status: GitHelperStatus) -> DoctorCheckInfo {
+    let install = || {
+        DoctorFixInfo::command(
+            "Install the credential helper",
+            "install_git_helper",
+            serde_json::json!({ "scoped": status.scoped }),
+        )
+    };
+    if !status.configured {
+        return doctor_check(
+            "credential_helper",
+            None,
+            "error",
+            "The credential helper isn't configured, so git doesn't ask GitSwitchHub for tokens",
+            vec![install()],
+        );
+    }
+    if !status.use_http_path {
+        return doctor_check(
+            "credential_helper",
+            None,
+            "warning",
+            "credential.useHttpPath isn't set for every host, so repositories can't be told apart",
+            vec![install()],
+        );
+    }
+    let conflicts: Vec<&git_helper::ConfiguredHelper> = status
+        .helpers
+        .iter()
+        .filter(|helper| helper.conflict)
+        .collect();
+    if conflicts.is_empty() {
+        return doctor_check(
+            "credential_helper",
+            None,
+            "ok",
+            "The credential helper is configured",
+            Vec::new(),
+        );
+    }
+    doctor_check(
+        "credential_helper",
+        None,
+        "warning",
+        format!(
+            "Other helpers also store GitHub credentials: {}",
+            status.conflicts.join(", ")
+        ),
+        conflicts
+            .into_iter()
+            .map(|helper| {
+                if helper.removable {
+                    DoctorFixInfo::command(
+                        format!("Remove {} from the {} config", helper.value, helper.scope),
+                        "remove_credential_helper",
+                        serde_json::json!({
+                            "scope": helper.scope,
+                            "key": helper.key,
+                            "value": helper.value,
+                        }),
+                    )
+                } else {
+                    DoctorFixInfo::manual(format!(
+                        "Ask an administrator to remove {} from {}",
+                        helper.value, helper.origin
+                    ))
+                }
+            })
+            .collect(),
+    )
+}
+
+async fn doctor_token_check(
+    db: &Database,
+    keychain: &KeychainManager,
+    account: &Account,
+) -> DoctorCheckInfo {
+    let renew = if account.auth_method == "manual" {
+        DoctorFixInfo::manual(format!("Enter a new token for {}", account.username))
+    } else {
+        DoctorFixInfo::command(
+            "Sign in again",
+            "reauthenticate_account",
+            serde_json::json!({ "accountId": account.id }),
+        )
+    };
+    let token = match keychain.get_token(&account.host, &account.username) {
+        Ok(token) => token,
+        Err(e) => {
+            return doctor_check(
+                "token",
+                Some(account),
+                "error",
+                format!("{} has no token: {}", account.username, e),
+                vec![renew],
+            )
+        }
+    };
+    let github_auth = match github_auth_for(db, &account.host).await {
+        Ok(github_auth) => github_auth,
+        Err(e) => return doctor_check("token", Some(account), "skipped", e, Vec::new()),
+    };
+
+    match github_auth.get_token_details(&token).await {
+        Ok(details) if !details.user.login.eq_ignore_ascii_case(&account.username) => doctor_check(
+            "token",
+            Some(account),
+            "error",
+            format!(
+                "The token stored for {} belongs to {}",
+                account.username, details.user.login
+            ),
+            vec![renew],
+        ),
+        Ok(details) => match details
+            .expires_at
+            .map(|expires_at| (expires_at - Utc::now()).num_days())
+        {
+            Some(days) if days < 0 => doctor_check(
+                "token",
+                Some(account),
+                "error",
+                format!("The token for {} has expired", account.username),
+                vec![renew],
+            ),
+            Some(days) if days < TOKEN_EXPIRY_WARNING_DAYS => doctor_check(
+                "token",
+                Some(account),
+                "warning",
+                format!(
+                    "The token for {} expires in {} day{}",
+                    account.username,
+                    days,
+                    if days == 1 { "" } else { "s" }
+                ),
+                vec![renew],
+            ),
+            _ => doctor_check(
+                "token",
+                Some(account),
+                "ok",
+                format!("The token for {} works", account.username),
+                Vec::new(),
+            ),
+        },
+        Err(e) if e.is_network() => doctor_check(
+            "token",
+            Some(account),
+            "skipped",
+            format!("{} can't be reached: {}", account.host, e),
+            Vec::new(),
+        ),
+        Err(e) => doctor_check(
+            "token",
+            Some(account),
+            "error",
+            format!("The token for {} doesn't work: {}", account.username, e),
+            vec![renew],
+        ),
+    }
+}
+
+// The key and alias of each github.com account that uses SSH, the agent and
+// conflicting SSH config entries. Nothing is reported without such accounts.
+fn doctor_ssh_checks(accounts: &[Account]) -> Vec<DoctorCheckInfo> {
+    let ssh_accounts: Vec<(&Account, std::path::PathBuf, String)> = accounts
+        .iter()
+        .filter(|account| account.host == DEFAULT_GITHUB_HOST)
+        .filter_map(|account| {
+            let public_key = account_public_key_path(account).ok()?;
+            let alias = ssh_alias(account).ok()?;
+            (account.ssh_key_path.is_some() || public_key.exists())
+                .then_some((account, public_key, alias))
+        })
+        .collect();
+    if ssh_accounts.is_empty() {
+        return Vec::new();
+    }
+    if let Err(e) = ssh::openssh_version() {
+        return vec![doctor_check(
+            "ssh",
+            None,
+            "error",
+            format!("OpenSSH can't be run: {}", e),
+            vec![DoctorFixInfo::manual("Install the OpenSSH client")],
+        )];
+    }
+
+    let mut checks = Vec::new();
+    let aliases: Vec<String> = ssh_accounts
+        .iter()
+        .map(|(_, _, alias)| alias.clone())
+        .collect();
+    let analysis = ssh::analyze_config(&aliases);
+    let agent = ssh::agent_fingerprints();
+
+    for (account, public_key, alias) in &ssh_accounts {
+        let relink = DoctorFixInfo::command(
+            "Set up the SSH key again",
+            "set_account_ssh_key",
+            serde_json::json!({
+                "accountId": account.id,
+                "privateKeyPath": account.ssh_key_path,
+            }),
+        );
+        let details = match ssh::key_details(public_key) {
+            Ok(details) => details,
+            Err(e) => {
+                checks.push(doctor_check(
+                    "ssh_key",
+                    Some(account),
+                    "error",
+                    format!(
+                        "The SSH key of {} can't be read from {}: {}",
+                        account.username,
+                        public_key.display(),
+                        e
+                    ),
+                    vec![DoctorFixInfo::command(
+                        "Link another key",
+                        "set_account_ssh_key",
+                        serde_json::json!({ "accountId": account.id }),
+                    )],
+                ));
+                continue;
+            }
+        };
+        let configured = analysis
+            .as_ref()
+            .map_or(true, |analysis| analysis.managed_aliases.contains(alias));
+        checks.push(if configured {
+            let loaded = match &agent {
+                Ok(fingerprints) if fingerprints.contains(&details.fingerprint) => {
+                    ", loaded in the agent"
+                }
+                _ => "",
+            };
+            doctor_check(
+                "ssh_key",
+                Some(account),
+                "ok",
+                format!(
+                    "{} uses {} through {}{}",
+                    account.username, details.fingerprint, alias, loaded
+                ),
+                Vec::new(),
+            )
+        } else {
+            doctor_check(
+                "ssh_key",
+                Some(account),
+                "error",
+                format!(
+                    "The SSH config has no {} entry for {}",
+                    alias, account.username
+                ),
+                vec![relink],
+            )
+        });
+    }
+
+    checks.push(match agent {
+        Ok(fingerprints) => doctor_check(
+            "ssh_agent",
+            None,
+            "ok",
+            format!("The SSH agent holds {} keys", fingerprints.len()),
+            Vec::new(),
+        ),
+        Err(e) => doctor_check(
+            "ssh_agent",
+            None,
+            "warning",
+            format!("The SSH agent can't be queried: {}", e),
+            vec![DoctorFixInfo::manual(
+                "Start ssh-agent and add the keys with ssh-add",
+            )],
+        ),
+    });
+
+    checks.push(match analysis {
+        Ok(analysis) if analysis.issues.is_empty() => doctor_check(
+            "ssh_config",
+            None,
+            "ok",
+            format!("{} has no conflicting entries", analysis.config_path),
+            Vec::new(),
+        ),
+        Ok(analysis) => doctor_check(
+            "ssh_config",
+            None,
+            "warning",
+            analysis
+                .issues
+                .iter()
+                .map(|issue| issue.message.clone())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            analysis
+                .issues
+                .iter()
+                .map(|issue| {
+                    DoctorFixInfo::manual(format!("Edit {} line {}", issue.file, issue.line))
+                })
+                .collect(),
+        ),
+        Err(e) => doctor_check(
+            "ssh_config",
+            None,
+            "error",
+            format!("The SSH config can't be read: {}", e),
+            Vec::new(),
+        ),
+    });
+    checks
+}
+
+// Generates a key for the account, ed25519 unless `key_type` says otherwise.
+// Security key types wait until the key is touched.
+#[tauri::command]
+pub async fn generate_ssh_key(
+    username: String,
+    key_type: Option<String>,
+) -> Result<SSHKeyInfo, String> {
+    let key_type = match key_type {
+        Some(key_type) => SshKeyType::parse(&key_type).map_err(|e| e.to_string())?,
+        None => SshKeyType::default(),
+    };
+    tokio::task::spawn_blocking(move || SSHManager::new().generate_key(&username, key_type))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(SSHKeyInfo::from)
+        .map_err(|e| match e {
+            SSHError::Process(stderr) => format!("SSH key generation failed: {}", stderr.trim()),
+            e => format!("Failed to generate SSH key: {}", e),
+        })
+}
+
+// Key types generate_ssh_key can create with the local OpenSSH
+#[tauri::command]
+pub async fn get_ssh_key_types() -> Result<Vec<SshKeyType>, String> {
+    tokio::task::spawn_blocking(ssh::available_key_types)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to query OpenSSH: {}", e))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshKeyDetails {
+    pub public_key_path: String,
+    pub algorithm: String,
+    pub fingerprint: String,
+    pub comment: String,
+    pub created_at: Option<String>,
+    // None when GitHub couldn't say, see github_error
+    pub uploaded: Option<bool>,
+    pub github_key: Option<UploadedKeyInfo>,
+    pub github_error: Option<String>,
+}
+
+// The account's SSH key and whether GitHub has it. Listing the account's
+// keys needs the read:public_key scope.
+#[tauri::command]
+pub async fn get_ssh_key_details(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+) -> Result<SshKeyDetails, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let path = account_public_key_path(&account)?;
+    let details_path = path.clone();
+    let details = tokio::task::spawn_blocking(move || ssh::key_details(&details_path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let github_keys = match keychain.get_token(&account.host, &account.username) {
+        Ok(token) => match github_auth_for(&db, &account.host).await {
+            Ok(github_auth) => github_auth
+                .list_ssh_keys(&token)
+                .await
+                .map_err(|e| match e {
+                    GitHubAuthError::MissingScope(scope) => {
+                        format!("Checking the keys on GitHub needs the {} scope", scope)
+                    }
+                    e => format!("Failed to list the keys on GitHub: {}", e),
+                }),
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(format!("Failed to read token: {}", e)),
+    };
+    let (uploaded, github_key, github_error) = match github_keys {
+        Ok(keys) => {
+            let key = keys
+                .into_iter()
+                .find(|key| same_public_key(&key.key, &details.public_key));
+            (Some(key.is_some()), key.map(UploadedKeyInfo::from), None)
+        }
+        Err(e) => (None, None, Some(e)),
+    };
+
+    Ok(SshKeyDetails {
+        public_key_path: path.display().to_string(),
+        algorithm: details.algorithm,
+        fingerprint: details.fingerprint,
+        comment: details.comment,
+        created_at: details
+            .created_at
+            .map(|time| chrono::DateTime::<Utc>::from(time).to_rfc3339()),
+        uploaded,
+        github_key,
+        github_error,
+    })
+}
+
+// The public half of the key the account uses over SSH
+fn account_public_key_path(account: &Account) -> Result<std::path::PathBuf, String> {
+    match &account.ssh_key_path {
+        Some(key) => Ok(std::path::PathBuf::from(format!("{}.pub", key))),
+        None => SSHManager::new()
+            .public_key_path(&account.username)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+// Adds a public key to the account on GitHub so it can be used over SSH.
+// Defaults to the account's key, see set_account_ssh_key.
+#[tauri::command]
+pub async fn upload_ssh_key(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    account_id: String,
+    public_key_path: Option<String>,
+    title: Option<String>,
+) -> Result<UploadedKeyInfo, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    let key_name = format!("gitswitchhub_{}", account.username);
+    let path = match public_key_path {
+        Some(path) => std::path::PathBuf::from(path.trim()),
+        None => account_public_key_path(&account)?,
+    };
+    let public_key = read_public_key(&path)?;
+    let title = title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| format!("GitSwitchHub {}", key_name));
+
+    let token = keychain
+        .get_token(&account.host, &account.username)
+        .map_err(|e| format!("Failed to read token: {}", e))?;
+    github_auth_for(&db, &account.host)
+        .await?
+        .add_ssh_key(&token, &title, &public_key)
+        .await
+        .map(UploadedKeyInfo::from)
+        .map_err(|e| key_upload_error(e, &account.username))
+}
+
+// Reads an OpenSSH public key, refusing anything that looks like a private key
+fn read_public_key(path: &std::path::Path) -> Result<String, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let key = contents.trim();
+    if key.contains("PRIVATE KEY") {
+        return Err(format!(
+            "{} is a private key; choose the .pub file next to it",
+            path.display()
+        ));
+    }
+    if !(key.starts_with("ssh-") || key.starts_with("ecdsa-") || key.starts_with("sk-")) {
+        return Err(format!("{} is not an SSH public key", path.display()));
+    }
+    Ok(key.to_string())
+}
+
+fn key_upload_error(error: GitHubAuthError, username: &str) -> String {
+    match error {
+        GitHubAuthError::MissingScope(scope) => format!(
+            "Uploading keys for {} needs the {} scope; reauthorize the account to grant it",
+            username, scope
+        ),
+        e => format!("Failed to upload key: {}", e),
+    }
+}
+
+#[tauri::command]
+pub async fn get_ssh_config(
+    db: State<'_, Database>,
+    username: String,
+) -> Result<SSHConfig, String> {
+    let lookup_username = username.clone();
+    let account = db
+        .run(move |db| db.get_account_by_username(DEFAULT_GITHUB_HOST, &lookup_username))
+        .await
+        .map_err(|e| e.to_string())?;
+    let key = account
+        .as_ref()
+        .and_then(|account| account.ssh_key_path.as_deref());
+    let config = SSHManager::new()
+        .get_ssh_config(&username, key.map(std::path::Path::new))
+        .map_err(|e| e.to_string())?;
+    Ok(SSHConfig::from(
+        if account.is_some_and(|account| account.ssh_port_443) {
+            config.with_port_443()
+        } else {
+            config
+        },
+    ))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshConnectivity {
+    pub port_22: bool,
+    pub port_443: bool,
+    // Port 22 is blocked but 443 works, see set_ssh_port_443
+    pub suggest_port_443: bool,
+}
+
+#[tauri::command]
+pub async fn probe_ssh_connectivity() -> Result<SshConnectivity, String> {
+    let probe = tokio::task::spawn_blocking(ssh::probe_ssh_ports)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(SshConnectivity {
+        port_22: probe.port_22,
+        port_443: probe.port_443,
+        suggest_port_443: !probe.port_22 && probe.port_443,
+    })
+}
+
+// Sends the account's SSH alias to ssh.github.com:443 instead of port 22
+#[tauri::command]
+pub async fn set_ssh_port_443(
+    db: State<'_, Database>,
+    account_id: String,
+    enabled: bool,
+) -> Result<AccountInfo, String> {
+    let account = db
+        .run(move |db| {
+            db.get_account(&account_id)?
+                .ok_or_else(|| "Account not found".to_string())?;
+            db.set_account_ssh_port_443(&account_id, enabled)?;
+            db.get_account(&account_id)?
+                .ok_or_else(|| "Account not found".to_string())
+        })
+        .await?;
+    ssh_alias(&account)?;
+    refresh_ssh_alias(&db, &account).await?;
+    Ok(AccountInfo::from(account))
+}
+
+// Records the files one SSH config update wrote, so undo_ssh_change can put
+// them back
+async fn record_ssh_config_writes(
+    db: &Database,
+    writes: Vec<ssh::ConfigWrite>,
+) -> Result<(), String> {
+    if writes.is_empty() {
+        return Ok(());
+    }
+    db.run(move |db| {
+        let paths: Vec<(String, Option<String>)> = writes
+            .iter()
+            .map(|write| {
+                (
+                    write.path.display().to_string(),
+                    write.backup_path.as_ref().map(|p| p.display().to_string()),
+                )
+            })
+            .collect();
+        let changes: Vec<NewSshConfigChange> = writes
+            .iter()
+            .zip(&paths)
+            .map(|(write, (path, backup_path))| NewSshConfigChange {
+                path,
+                backup_path: backup_path.as_deref(),
+                sha256: write.sha256.as_deref(),
+                diff: &write.diff,
+            })
+            .collect();
+        db.add_ssh_config_changes(&Uuid::new_v4().to_string(), &changes)
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshConfigChangeInfo {
+    pub id: i64,
+    pub operation_id: String,
+    pub changed_at: String,
+    pub path: String,
+    pub backup_path: Option<String>,
+    pub diff: String,
+    pub undone_at: Option<String>,
+}
+
+impl From<SshConfigChange> for SshConfigChangeInfo {
+    fn from(change: SshConfigChange) -> Self {
+        Self {
+            id: change.id,
+            operation_id: change.operation_id,
+            changed_at: change.changed_at.to_rfc3339(),
+            path: change.path,
+            backup_path: change.backup_path,
+            diff: change.diff,
+            undone_at: change.undone_at.map(|dt| dt.to_rfc3339()),
+        }
+    }
+}
+
+// Newest first
+#[tauri::command]
+pub async fn get_ssh_config_changes(
+    db: State<'_, Database>,
+    limit: Option<u32>,
+) -> Result<Vec<SshConfigChangeInfo>, String> {
+    let limit = limit.unwrap_or(50).min(500);
+    db.run(move |db| db.get_ssh_config_changes(limit))
+        .await
+        .map(|changes| changes.into_iter().map(SshConfigChangeInfo::from).collect())
+        .map_err(|e| e.to_string())
+}
+
+// Puts the files an operation changed back from their backups, the latest
+// operation not yet undone by default. Nothing is restored when any of the
+// files has been edited since. Returns the files restored.
+#[tauri::command]
+pub async fn undo_ssh_change(
+    db: State<'_, Database>,
+    operation_id: Option<String>,
+) -> Result<Vec<String>, String> {
+    let changes = db
+        .run(move |db| db.get_ssh_config_operation(operation_id.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(first) = changes.first() else {
+        return Err("There is no SSH config change to undo".to_string());
+    };
+    if first.undone_at.is_some() {
+        return Err("That change has already been undone".to_string());
+    }
+    let operation_id = first.operation_id.clone();
+
+    let writes = tokio::task::spawn_blocking(move || {
+        for change in &changes {
+            let path = std::path::Path::new(&change.path);
+            if !ssh::unchanged_since_write(path, change.sha256.as_deref())? {
+                return Err(SSHError::ChangedSinceWrite(change.path.clone()));
+            }
+        }
+        changes
+            .iter()
+            .rev()
+            .map(|change| {
+                ssh::undo_config_write(
+                    std::path::Path::new(&change.path),
+                    change.backup_path.as_deref().map(std::path::Path::new),
+                    change.sha256.as_deref(),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to undo the SSH config change: {}", e))?;
+
+    let restored = writes
+        .iter()
+        .map(|write| write.path.display().to_string())
+        .collect();
+    record_ssh_config_writes(&db, writes).await?;
+    db.run(move |db| db.mark_ssh_config_operation_undone(&operation_id))
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(restored)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetectedSshKeyInfo {
+    pub public_key_path: String,
+    pub private_key_path: Option<String>,
+    pub key_type: String,
+    pub bits: u32,
+    pub fingerprint: String,
+    pub comment: String,
+    // The account using the key, whether generated for it or linked to it
+    pub account_id: Option<String>,
+}
+
+// Keys already in ~/.ssh, which set_account_ssh_key can link to an account
+// instead of generating a new one
+#[tauri::command]
+pub async fn detect_ssh_keys(db: State<'_, Database>) -> Result<Vec<DetectedSshKeyInfo>, String> {
+    let accounts = db
+        .run(|db| db.get_accounts())
+        .await
+        .map_err(|e| e.to_string())?;
+    let keys = tokio::task::spawn_blocking(ssh::detect_keys)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read SSH keys: {}", e))?;
+
+    let manager = SSHManager::new();
+    Ok(keys
         .into_iter()
-        .map(|mapping| RepositoryMappingInfo {
-            id: mapping.id,
-            remote_url: mapping.remote_url,
-            account_id: mapping.account_id,
-            remember: mapping.remember,
-            created_at: mapping.created_at.to_rfc3339(),
+        .map(|key| {
+            let account_id = accounts
+                .iter()
+                .find(|account| match &account.ssh_key_path {
+                    Some(path) => key.private_key_path.as_ref() == Some(path),
+                    None => manager
+                        .public_key_path(&account.username)
+                        .is_ok_and(|path| path.display().to_string() == key.public_key_path),
+                })
+                .map(|account| account.id.clone());
+            DetectedSshKeyInfo {
+                public_key_path: key.public_key_path,
+                private_key_path: key.private_key_path,
+                key_type: key.key_type,
+                bits: key.bits,
+                fingerprint: key.fingerprint,
+                comment: key.comment,
+                account_id,
+            }
+        })
+        .collect())
+}
+
+// Uses an existing private key for the account's SSH alias and key upload.
+// The public key has to be next to it with a .pub extension. Empty goes back
+// to the generated key. The github-<username> alias is rewritten to match.
+#[tauri::command]
+pub async fn set_account_ssh_key(
+    db: State<'_, Database>,
+    account_id: String,
+    private_key_path: Option<String>,
+) -> Result<AccountInfo, String> {
+    let path = private_key_path
+        .map(|p| paths::expand_home(p.trim()))
+        .filter(|p| !p.is_empty());
+    if let Some(path) = &path {
+        let key = std::path::Path::new(path);
+        if !key.is_absolute() || !key.is_file() {
+            return Err(format!("No private key at {}", path));
+        }
+        read_public_key(std::path::Path::new(&format!("{}.pub", path)))?;
+    }
+
+    let account = db
+        .run(move |db| {
+            db.get_account(&account_id)?
+                .ok_or_else(|| "Account not found".to_string())?;
+            db.set_account_ssh_key(&account_id, path.as_deref())?;
+            db.get_account(&account_id)?
+                .ok_or_else(|| "Account not found".to_string())
+        })
+        .await?;
+
+    if account.host == DEFAULT_GITHUB_HOST {
+        refresh_ssh_alias(&db, &account).await?;
+    }
+    Ok(AccountInfo::from(account))
+}
+
+// Rewrites the account's alias from its settings, or removes it when the
+// account has no key for it to use. Returns whether the alias is in place.
+async fn refresh_ssh_alias(db: &Database, account: &Account) -> Result<bool, String> {
+    let username = account.username.clone();
+    let key = account.ssh_key_path.clone();
+    let port_443 = account.ssh_port_443;
+    let (writes, in_place) = tokio::task::spawn_blocking(move || {
+        let manager = SSHManager::new();
+        match key {
+            Some(key) => manager
+                .add_to_ssh_config(&username, Some(std::path::Path::new(&key)), port_443)
+                .map(|writes| (writes, true)),
+            None if manager.public_key_path(&username)?.exists() => manager
+                .add_to_ssh_config(&username, None, port_443)
+                .map(|writes| (writes, true)),
+            None => manager
+                .remove_from_ssh_config(&username)
+                .map(|writes| (writes, false)),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to update the SSH config: {}", e))?;
+    record_ssh_config_writes(db, writes).await?;
+    Ok(in_place)
+}
+
+// Reports entries in the user's SSH config that would interfere with the
+// host aliases of the github.com accounts
+#[tauri::command]
+pub async fn analyze_ssh_config(db: State<'_, Database>) -> Result<SshConfigAnalysis, String> {
+    let accounts = db
+        .run(|db| db.get_accounts())
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || {
+        let manager = SSHManager::new();
+        let aliases = accounts
+            .iter()
+            .filter(|account| account.host == DEFAULT_GITHUB_HOST)
+            .map(|account| {
+                manager
+                    .get_ssh_config(&account.username, None)
+                    .map(|config| config.host)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        ssh::analyze_config(&aliases)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to read SSH config: {}", e))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteRewriteInfo {
+    pub repo_path: String,
+    // "remote.<name>.url", "remote.<name>.pushurl" or "submodule.<name>.url"
+    pub key: String,
+    pub old_url: String,
+    // The same as old_url when the remote already used the alias
+    pub new_url: String,
+}
+
+// Points every GitHub remote of the repository at the account's SSH alias:
+// fetch and push urls, the urls of submodules yet to be cloned and the
+// remotes of checked out submodules. Each repository is then mapped to the
+// account. .gitmodules itself is left alone since it is shared.
+#[tauri::command]
+pub async fn apply_ssh_remote(
+    db: State<'_, Database>,
+    repo_path: String,
+    account_id: String,
+) -> Result<Vec<RemoteRewriteInfo>, String> {
+    let lookup_id = account_id.clone();
+    let account = db
+        .run(move |db| db.get_account(&lookup_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let alias = usable_ssh_alias(&db, &account).await?;
+
+    let repo_path = std::path::PathBuf::from(paths::expand_home(repo_path.trim()));
+    if !repo_path.join(".git").exists() {
+        return Err(format!(
+            "{} is not the top of a git repository",
+            repo_path.display()
+        ));
+    }
+    let rewrites = tokio::task::spawn_blocking(move || {
+        let mut rewrites = Vec::new();
+        rewrite_remotes(&repo_path, &alias, &mut rewrites).map(|_| rewrites)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to update remotes: {}", e))?;
+
+    let repositories: HashSet<String> = rewrites
+        .iter()
+        .filter_map(|rewrite| remote::repo_full_name(&rewrite.new_url))
+        .map(|name| format!("https://{}/{}", DEFAULT_GITHUB_HOST, name))
+        .collect();
+    db.run(move |db| {
+        for url in &repositories {
+            // A token picked for the repository stays when the account does
+            let token_name = db
+                .get_repository_mapping(url)?
+                .filter(|mapping| &mapping.remote_url == url && mapping.account_id == account_id)
+                .and_then(|mapping| mapping.token_name);
+            db.set_repository_mapping(url, &account_id, true, token_name.as_deref())?;
+        }
+        Ok::<_, DatabaseError>(())
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    cache::invalidate();
+    Ok(rewrites)
+}
+
+// Makes git use the account's key for SSH in this repository through
+// core.sshCommand, for those who would rather keep git@github.com remotes
+// than rewrite them to the alias. No account removes the setting.
+#[tauri::command]
+pub async fn set_repo_ssh_command(
+    db: State<'_, Database>,
+    repo_path: String,
+    account_id: Option<String>,
+) -> Result<Option<String>, String> {
+    let repo_path = std::path::PathBuf::from(paths::expand_home(repo_path.trim()));
+    if !repo_path.join(".git").exists() {
+        return Err(format!(
+            "{} is not the top of a git repository",
+            repo_path.display()
+        ));
+    }
+    let scope = GitConfigScope::Repository(repo_path);
+
+    let Some(account_id) = account_id else {
+        tokio::task::spawn_blocking(move || git_config::unset_value(&scope, "core.sshCommand"))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("Failed to update the repository config: {}", e))?;
+        return Ok(None);
+    };
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let command = SSHManager::new()
+        .ssh_command(
+            &account.username,
+            account.ssh_key_path.as_deref().map(std::path::Path::new),
+            account.ssh_port_443,
+        )
+        .map_err(|e| match e {
+            SSHError::KeyNotFound => "The account has no SSH key".to_string(),
+            e => e.to_string(),
+        })?;
+
+    let value = command.clone();
+    tokio::task::spawn_blocking(move || git_config::set_value(&scope, "core.sshCommand", &value))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to update the repository config: {}", e))?;
+    Ok(Some(command))
+}
+
+// The github-<username> host the account's SSH config entry is under
+fn ssh_alias(account: &Account) -> Result<String, String> {
+    if account.host != DEFAULT_GITHUB_HOST {
+        return Err("SSH aliases are only set up for github.com accounts".to_string());
+    }
+    SSHManager::new()
+        .get_ssh_config(&account.username, None)
+        .map(|config| config.host)
+        .map_err(|e| e.to_string())
+}
+
+// The account's alias, written to the SSH config again in case it went
+// missing. Remotes and rules pointed at it only work with a key behind it.
+async fn usable_ssh_alias(db: &Database, account: &Account) -> Result<String, String> {
+    let alias = ssh_alias(account)?;
+    if !refresh_ssh_alias(db, account).await? {
+        return Err(format!(
+            "{} has no SSH key; generate one or choose an existing key first",
+            account.username
+        ));
+    }
+    Ok(alias)
+}
+
+fn rewrite_remotes(
+    repo_path: &std::path::Path,
+    alias: &str,
+    rewrites: &mut Vec<RemoteRewriteInfo>,
+) -> Result<(), git_config::GitConfigError> {
+    let entries = git_config::get_entries(
+        Some(repo_path),
+        r"^(remote\..*\.(url|pushurl)|submodule\..*\.url)$",
+    )?;
+    for entry in entries.into_iter().filter(|entry| entry.scope == "local") {
+        let Some(new_url) = remote::ssh_alias_url(&entry.value, alias) else {
+            continue;
+        };
+        if new_url != entry.value {
+            if let Some(name) = entry.key.strip_prefix("remote.") {
+                match name.strip_suffix(".pushurl") {
+                    Some(remote) => {
+                        git_config::set_remote_url(repo_path, remote, true, &entry.value, &new_url)?
+                    }
+                    None => git_config::set_remote_url(
+                        repo_path,
+                        name.strip_suffix(".url").unwrap_or(name),
+                        false,
+                        &entry.value,
+                        &new_url,
+                    )?,
+                }
+            } else {
+                let scope = GitConfigScope::Repository(repo_path.to_path_buf());
+                git_config::set_value(&scope, &entry.key, &new_url)?;
+            }
+        }
+        rewrites.push(RemoteRewriteInfo {
+            repo_path: repo_path.display().to_string(),
+            key: entry.key,
+            old_url: entry.value,
+            new_url,
+        });
+    }
+
+    for submodule in git_config::submodule_paths(repo_path)? {
+        if submodule.join(".git").exists() {
+            rewrite_remotes(&submodule, alias, rewrites)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshTestResult {
+    pub success: bool,
+    pub message: String,
+    pub authenticated_as: Option<String>,
+}
+
+// Signs in over SSH with only the account's key and checks GitHub greets
+// the account, not whichever user the key turns out to belong to
+#[tauri::command]
+pub async fn test_ssh_connection(
+    db: State<'_, Database>,
+    account_id: String,
+) -> Result<SshTestResult, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    ssh_alias(&account)?;
+
+    let username = account.username.clone();
+    let key = account.ssh_key_path.clone();
+    let port_443 = account.ssh_port_443;
+    let test = tokio::task::spawn_blocking(move || {
+        SSHManager::new().test_ssh_connection(
+            &username,
+            key.as_deref().map(std::path::Path::new),
+            port_443,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| match e {
+        SSHError::KeyNotFound => "The account has no SSH key".to_string(),
+        e => format!("Failed to run ssh: {}", e),
+    })?;
+
+    Ok(match test.authenticated_as {
+        Some(user) if user.eq_ignore_ascii_case(&account.username) => SshTestResult {
+            success: true,
+            message: format!("Authenticated as {}", user),
+            authenticated_as: Some(user),
+        },
+        Some(user) => SshTestResult {
+            success: false,
+            message: format!(
+                "The key belongs to {} on GitHub, not {}",
+                user, account.username
+            ),
+            authenticated_as: Some(user),
+        },
+        None => SshTestResult {
+            success: false,
+            message: test
+                .output
+                .lines()
+                .last()
+                .unwrap_or("No response from GitHub")
+                .to_string(),
+            authenticated_as: None,
+        },
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshIssueInfo {
+    // "no_remote", "not_ssh", "alias_missing", "not_github", "no_account",
+    // "auth_failed", "connection_failed", "key_on_other_account",
+    // "wrong_alias", "default_key", "agent_key_shadowing" or "wrong_account"
+    pub kind: String,
+    pub message: String,
+    // What to do about it, naming the command that does it where there is one
+    pub remediation: String,
+}
+
+fn ssh_issue(kind: &str, message: String, remediation: String) -> SshIssueInfo {
+    SshIssueInfo {
+        kind: kind.to_string(),
+        message,
+        remediation,
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SshDiagnosisInfo {
+    pub remote: Option<String>,
+    // After insteadOf rules
+    pub remote_url: Option<String>,
+    pub ssh_command: Option<String>,
+    // "user@host" as git hands it to ssh
+    pub destination: Option<String>,
+    // Where ssh connects for the destination
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub expected_account: Option<AccountInfo>,
+    pub resolution_reason: Option<String>,
+    pub authenticated_as: Option<String>,
+    // "SHA256:..." of the key GitHub accepted
+    pub accepted_key: Option<String>,
+    pub offered_keys: Vec<String>,
+    pub ok: bool,
+    pub issues: Vec<SshIssueInfo>,
+    pub output: String,
+}
+
+// Signs in to GitHub through the repository's own remote and SSH settings,
+// then compares who GitHub says that is with the account the repository
+// resolves to and explains any mismatch
+#[tauri::command]
+pub async fn diagnose_ssh(
+    db: State<'_, Database>,
+    repo_path: String,
+) -> Result<SshDiagnosisInfo, String> {
+    let repo_path = std::path::PathBuf::from(paths::expand_home(repo_path.trim()));
+    if !repo_path.join(".git").exists() {
+        return Err(format!(
+            "{} is not the top of a git repository",
+            repo_path.display()
+        ));
+    }
+    let mut diagnosis = SshDiagnosisInfo::default();
+
+    let lookup_path = repo_path.clone();
+    let (remote, remote_url, ssh_command) = tokio::task::spawn_blocking(move || {
+        let remotes: Vec<String> =
+            git_config::get_entries(Some(&lookup_path), r"^remote\..*\.url$")?
+                .into_iter()
+                .filter_map(|entry| {
+                    let name = entry.key.strip_prefix("remote.")?.strip_suffix(".url")?;
+                    Some(name.to_string())
+                })
+                .collect();
+        let Some(remote) = remotes
+            .iter()
+            .find(|name| *name == "origin")
+            .or(remotes.first())
+            .cloned()
+        else {
+            return Ok((None, None, None));
+        };
+        let url = git_config::remote_get_url(&lookup_path, &remote)?;
+        // The last one read wins, as in git
+        let ssh_command = git_config::get_entries(Some(&lookup_path), r"^core\.sshcommand$")?
+            .pop()
+            .map(|entry| entry.value);
+        Ok::<_, git_config::GitConfigError>((Some(remote), Some(url), ssh_command))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to read the repository config: {}", e))?;
+    diagnosis.remote = remote.clone();
+    diagnosis.remote_url = remote_url.clone();
+    diagnosis.ssh_command = ssh_command.clone();
+
+    let (Some(remote), Some(remote_url)) = (remote, remote_url) else {
+        diagnosis.issues.push(ssh_issue(
+            "no_remote",
+            "The repository has no remote".to_string(),
+            "Add one with git remote add origin <url>".to_string(),
+        ));
+        return Ok(diagnosis);
+    };
+    let Some((destination, port)) = remote::ssh_destination(&remote_url) else {
+        diagnosis.issues.push(ssh_issue(
+            "not_ssh",
+            format!(
+                "{} fetches from {}, which doesn't go through SSH",
+                remote, remote_url
+            ),
+            "HTTPS remotes sign in with a token from the credential helper. To use the \
+             account's SSH key instead, switch the remote with apply_ssh_remote."
+                .to_string(),
+        ));
+        return Ok(diagnosis);
+    };
+    diagnosis.destination = Some(destination.clone());
+
+    let full_name = remote::repo_full_name(&remote_url);
+    let owner = remote::repo_owner(&remote_url, "");
+    let resolve_path = repo_path.clone();
+    let (resolution, accounts) = db
+        .run(move |db| {
+            // Mappings are kept under github.com whatever alias the remote uses
+            let resolution = match &full_name {
+                Some(full_name) => {
+                    let repo_url = format!("https://{}/{}", DEFAULT_GITHUB_HOST, full_name);
+                    Some(resolver::resolve(
+                        db,
+                        &ResolveRequest {
+                            repo_url: &repo_url,
+                            host: DEFAULT_GITHUB_HOST,
+                            owner: owner.as_deref(),
+                            path: Some(&resolve_path),
+                        },
+                    )?)
+                }
+                None => None,
+            };
+            Ok::<_, DatabaseError>((resolution, db.get_accounts()?))
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    let expected = resolution.and_then(|resolution| resolution.candidates.into_iter().next());
+
+    let key_path = expected
+        .as_ref()
+        .and_then(|candidate| account_public_key_path(&candidate.account).ok());
+    let trace_destination = destination.clone();
+    let (trace, agent_keys, account_fingerprint) = tokio::task::spawn_blocking(move || {
+        let trace = ssh::trace_connection(ssh_command.as_deref(), &trace_destination, port)?;
+        let agent_keys = ssh::agent_fingerprints().unwrap_or_default();
+        let account_fingerprint = key_path
+            .and_then(|path| ssh::key_details(&path).ok())
+            .map(|details| details.fingerprint);
+        Ok::<_, SSHError>((trace, agent_keys, account_fingerprint))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to run ssh: {}", e))?;
+    diagnosis.hostname = Some(trace.resolved.hostname.clone());
+    diagnosis.port = trace.resolved.port;
+    diagnosis.authenticated_as = trace.authenticated_as.clone();
+    diagnosis.accepted_key = trace.accepted_fingerprint.clone();
+    diagnosis.offered_keys = trace.offered_keys.clone();
+    diagnosis.output = trace.output.clone();
+
+    let host = destination
+        .rsplit_once('@')
+        .map_or(destination.as_str(), |(_, host)| host)
+        .to_lowercase();
+    let alias_account = accounts
+        .iter()
+        .find(|account| ssh_alias(account).is_ok_and(|alias| alias.eq_ignore_ascii_case(&host)));
+
+    let hostname = trace.resolved.hostname.to_lowercase();
+    if hostname != "github.com" && hostname != "ssh.github.com" {
+        if host.starts_with("github-") {
+            diagnosis.issues.push(ssh_issue(
+                "alias_missing",
+                format!(
+                    "There is no SSH config entry for {}, so ssh looks it up as a hostname",
+                    host
+                ),
+                match alias_account {
+                    Some(account) => format!(
+                        "Set {}'s SSH key again with set_account_ssh_key to restore the alias",
+                        account.username
+                    ),
+                    None => {
+                        "Switch the remote to an account's alias with apply_ssh_remote".to_string()
+                    }
+                },
+            ));
+        } else {
+            diagnosis.issues.push(ssh_issue(
+                "not_github",
+                format!(
+                    "{} connects to {}, not GitHub",
+                    remote, trace.resolved.hostname
+                ),
+                "Only GitHub remotes can be checked".to_string(),
+            ));
+        }
+        return Ok(diagnosis);
+    }
+
+    let Some(expected) = expected else {
+        diagnosis.issues.push(ssh_issue(
+            "no_account",
+            format!("No account is set up for {}", remote_url),
+            "Map the repository to an account, or switch its remote to an account's alias \
+             with apply_ssh_remote"
+                .to_string(),
+        ));
+        return Ok(diagnosis);
+    };
+    diagnosis.resolution_reason = Some(expected.reason);
+    let account = expected.account;
+
+    match &trace.authenticated_as {
+        None if trace.output.contains("Permission denied") => diagnosis.issues.push(ssh_issue(
+            "auth_failed",
+            format!(
+                "GitHub accepted none of the {} keys ssh offered",
+                trace.offered_keys.len()
+            ),
+            format!(
+                "Upload {}'s key to GitHub with upload_ssh_key, then check it with \
+                     test_ssh_connection",
+                account.username
+            ),
+        )),
+        None => diagnosis.issues.push(ssh_issue(
+            "connection_failed",
+            trace
+                .output
+                .lines()
+                .last()
+                .unwrap_or("No response from GitHub")
+                .to_string(),
+            "Check the network connection. Where port 22 is blocked, probe_ssh_connectivity \
+             and set_ssh_port_443 move SSH to port 443."
+                .to_string(),
+        )),
+        Some(user) if user.eq_ignore_ascii_case(&account.username) => {}
+        Some(user) => {
+            let accepted = trace.accepted_fingerprint.as_deref();
+            if accepted.is_some() && accepted == account_fingerprint.as_deref() {
+                diagnosis.issues.push(ssh_issue(
+                    "key_on_other_account",
+                    format!(
+                        "{}'s key {} is registered to {} on GitHub",
+                        account.username,
+                        accepted.unwrap_or_default(),
+                        user
+                    ),
+                    format!(
+                        "Remove the key from {}'s SSH keys on GitHub and upload it to {} with \
+                         upload_ssh_key, or give {} a key of its own",
+                        user, account.username, account.username
+                    ),
+                ));
+            } else {
+                match alias_account {
+                    Some(other) if other.id != account.id => diagnosis.issues.push(ssh_issue(
+                        "wrong_alias",
+                        format!(
+                            "The remote goes through {}, the alias of {}",
+                            host, other.username
+                        ),
+                        format!(
+                            "Switch the remote to {}'s alias with apply_ssh_remote",
+                            account.username
+                        ),
+                    )),
+                    None if diagnosis.ssh_command.is_none() => diagnosis.issues.push(ssh_issue(
+                        "default_key",
+                        format!(
+                            "The remote connects to {} directly, so ssh signs in with your \
+                             default keys, which belong to {}",
+                            host, user
+                        ),
+                        format!(
+                            "Switch the remote to {}'s alias with apply_ssh_remote, or pin the \
+                             account's key with set_repo_ssh_command",
+                            account.username
+                        ),
+                    )),
+                    _ => {}
+                }
+                if let Some(accepted) = accepted.filter(|accepted| {
+                    !trace.resolved.identities_only && agent_keys.iter().any(|key| key == accepted)
+                }) {
+                    diagnosis.issues.push(ssh_issue(
+                        "agent_key_shadowing",
+                        format!(
+                            "ssh offered {} from the agent before {}'s key, and GitHub took it \
+                             as {}",
+                            accepted, account.username, user
+                        ),
+                        "Go through an alias or core.sshCommand that sets IdentitiesOnly \
+                         (apply_ssh_remote or set_repo_ssh_command), or remove the key from the \
+                         agent with ssh-add -d"
+                            .to_string(),
+                    ));
+                }
+                if diagnosis.issues.is_empty() {
+                    diagnosis.issues.push(ssh_issue(
+                        "wrong_account",
+                        format!("GitHub signed in {} instead of {}", user, account.username),
+                        format!(
+                            "Find which of the keys ssh offered belongs to {}: {}",
+                            user,
+                            trace.offered_keys.join(", ")
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    diagnosis.expected_account = Some(AccountInfo::from(account));
+    diagnosis.ok = diagnosis.issues.is_empty();
+    Ok(diagnosis)
+}
+
+#[tauri::command]
+pub async fn get_ssh_url_rewrites() -> Result<Vec<UrlRewrite>, String> {
+    tokio::task::spawn_blocking(url_rewrite::list_rules)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read the git config: {}", e))
+}
+
+// Sends the account's GitHub urls through its SSH alias with insteadOf rules
+// rather than by editing remotes. Owners default to the account itself and
+// the owners mapped to it; with a directory the rules only apply there.
+#[tauri::command]
+pub async fn set_ssh_url_rewrites(
+    db: State<'_, Database>,
+    account_id: String,
+    owners: Option<Vec<String>>,
+    directory: Option<String>,
+) -> Result<Vec<UrlRewrite>, String> {
+    let (account, org_mappings) = db
+        .run(move |db| {
+            Ok::<_, DatabaseError>((db.get_account(&account_id)?, db.get_org_mappings()?))
         })
+        .await
+        .map_err(|e| e.to_string())?;
+    let account = account.ok_or("Account not found")?;
+    let alias = usable_ssh_alias(&db, &account).await?;
+
+    let owners: Vec<String> = match owners {
+        Some(owners) => owners,
+        None => std::iter::once(account.username.clone())
+            .chain(
+                org_mappings
+                    .into_iter()
+                    .filter(|mapping| {
+                        mapping.host == account.host && mapping.account_id == account.id
+                    })
+                    .map(|mapping| mapping.owner),
+            )
+            .collect(),
+    };
+    let mut seen = HashSet::new();
+    let owners: Vec<String> = owners
+        .iter()
+        .map(|owner| owner.trim().to_string())
+        .filter(|owner| !owner.is_empty() && seen.insert(owner.to_lowercase()))
         .collect();
+    if owners.is_empty() {
+        return Err("No owners to route through the SSH alias".to_string());
+    }
+    let directory = directory
+        .map(|dir| dir.trim().to_string())
+        .filter(|dir| !dir.is_empty());
 
-    Ok(mapping_infos)
+    tokio::task::spawn_blocking(move || {
+        url_rewrite::set_rules(&alias, &owners, directory.as_deref())?;
+        url_rewrite::list_rules().map(|rules| {
+            rules
+                .into_iter()
+                .filter(|rule| rule.alias == alias)
+                .collect()
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to update the git config: {}", e))
 }
 
 #[tauri::command]
-pub async fn set_repository_mapping(
+pub async fn remove_ssh_url_rewrites(
     db: State<'_, Database>,
-    remote_url: String,
     account_id: String,
-    remember: bool,
 ) -> Result<(), String> {
-    db.set_repository_mapping(&remote_url, &account_id, remember)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let alias = ssh_alias(&account)?;
+    tokio::task::spawn_blocking(move || url_rewrite::remove_rules(&alias))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to update the git config: {}", e))
 }
 
 #[tauri::command]
-pub async fn remove_repository_mapping(
+pub async fn get_conditional_includes() -> Result<Vec<ConditionalInclude>, String> {
+    tokio::task::spawn_blocking(conditional_include::list)
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read the git config: {}", e))
+}
+
+// Gives repositories under `directories` the account's commit identity,
+// signing settings and, when it has an SSH key, core.sshCommand, through
+// includeIf entries in the global config. Running it again rewrites the
+// account's file and replaces its directories.
+#[tauri::command]
+pub async fn set_conditional_include(
     db: State<'_, Database>,
-    mapping_id: String,
+    account_id: String,
+    directories: Vec<String>,
+) -> Result<ConditionalInclude, String> {
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    let mut seen = HashSet::new();
+    let directories: Vec<String> = directories
+        .iter()
+        .map(|dir| dir.trim().trim_end_matches(['/', '\\']).to_string())
+        .filter(|dir| !dir.is_empty() && seen.insert(dir.clone()))
+        .collect();
+    if directories.is_empty() {
+        return Err("No directories to apply the account to".to_string());
+    }
+    let conditions =
+        conditional_include::gitdir_conditions(&directories).map_err(|e| e.to_string())?;
+    let ssh_command = match SSHManager::new().ssh_command(
+        &account.username,
+        account.ssh_key_path.as_deref().map(std::path::Path::new),
+        account.ssh_port_443,
+    ) {
+        Ok(command) => Some(command),
+        Err(SSHError::KeyNotFound) => None,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    tokio::task::spawn_blocking(move || {
+        conditional_include::write_file(&account.username, |scope| {
+            git_config::write_identity(
+                scope,
+                account.git_name.as_deref(),
+                account.commit_email().as_deref(),
+            )?;
+            write_signing_config(scope, &account)?;
+            if let Some(command) = &ssh_command {
+                git_config::set_value(scope, "core.sshCommand", command)?;
+            }
+            Ok(())
+        })?;
+        conditional_include::set_directories(&account.username, &conditions)?;
+        conditional_include::list().map(|includes| {
+            includes
+                .into_iter()
+                .find(|include| include.username == account.username)
+                .unwrap_or_default()
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to update the git config: {}", e))
+}
+
+#[tauri::command]
+pub async fn remove_conditional_include(
+    db: State<'_, Database>,
+    account_id: String,
 ) -> Result<(), String> {
-    db.remove_repository_mapping(&mapping_id)
-        .map_err(|e| e.to_string())?;
-    Ok(())
+    let account = db
+        .run(move |db| db.get_account(&account_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+    tokio::task::spawn_blocking(move || conditional_include::remove(&account.username))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to update the git config: {}", e))
 }
 
+// Adds pre-commit and pre-push hooks to the repository that stop commits
+// and pushes whose user.email isn't the one of the account assigned to it.
+// Returns the hooks written.
 #[tauri::command]
-pub async fn install_git_helper() -> Result<(), String> {
-    use std::process::Command;
+pub async fn install_identity_guard(repo_path: String) -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(move || {
+        let exe = std::env::current_exe()?;
+        identity_guard::install(std::path::Path::new(&repo_path), &exe)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map(|hooks| {
+        hooks
+            .iter()
+            .map(|hook| hook.display().to_string())
+            .collect()
+    })
+    .map_err(|e| format!("Failed to install the identity guard: {}", e))
+}
 
-    let current_exe =
-        std::env::current_exe().map_err(|e| format!("Failed to get current executable: {}", e))?;
-    let helper_command = format!("!{} credential-helper", current_exe.display());
+#[tauri::command]
+pub async fn uninstall_identity_guard(repo_path: String) -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(move || identity_guard::uninstall(std::path::Path::new(&repo_path)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|hooks| {
+            hooks
+                .iter()
+                .map(|hook| hook.display().to_string())
+                .collect()
+        })
+        .map_err(|e| format!("Failed to remove the identity guard: {}", e))
+}
 
-    // Clear existing credential helpers
-    let _ = Command::new("git")
-        .args(["config", "--global", "--unset-all", "credential.helper"])
-        .output();
+#[tauri::command]
+pub async fn convert_remote_to_ssh(remote_url: String, username: String) -> Result<String, String> {
+    // Convert HTTPS URL to SSH format
+    if remote_url.starts_with("https://github.com/") {
+        let repo_path = remote_url
+            .strip_prefix("https://github.com/")
+            .ok_or("Invalid GitHub URL")?;
+        Ok(format!("git@github-{}:{}", username, repo_path))
+    } else {
+        Err("Not a GitHub HTTPS URL".to_string())
+    }
+}
 
-    // Set our credential helper
-    let output = Command::new("git")
-        .args(["config", "--global", "credential.helper", &helper_command])
-        .output()
-        .map_err(|e| format!("Failed to run git config: {}", e))?;
+// Asks which account a repository should use in the same chooser window the
+// credential helper opens, remembering the choice as a mapping when the user
+// ticks the box. Returns the username chosen.
+#[tauri::command]
+pub async fn show_account_chooser(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    repo_url: String,
+) -> Result<String, String> {
+    // Remotes through an SSH alias pick among the github.com accounts, and
+    // the choice is remembered under the url the helper looks up
+    let (mapping_url, host) = match remote::mapping_target(&repo_url) {
+        Some((mapping_url, host)) => (Some(mapping_url), host),
+        None => (
+            None,
+            remote::host_from_url(&repo_url)
+                .ok_or_else(|| format!("Invalid remote URL: {}", repo_url))?,
+        ),
+    };
+    let lookup_host = host.clone();
+    let (accounts, remember_setting, timeout) = db
+        .run(move |db| {
+            Ok::<_, DatabaseError>((
+                resolver::chooser_accounts(db, &lookup_host)?,
+                db.get_setting(SettingKey::HelperRememberChoice)?
+                    .as_bool()
+                    .unwrap_or(true),
+                db.get_setting(SettingKey::HelperChooserTimeoutSecs)?
+                    .as_u64()
+                    .unwrap_or(60),
+            ))
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    if accounts.is_empty() {
+        return Err(format!("No accounts configured for {}", host));
+    }
+    // A mapping needs the owner and repository, not just the host
+    let offer_remember = remember_setting && mapping_url.is_some();
 
-    if !output.status.success() {
-        return Err(format!(
-            "Git config failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    let prompt_url = repo_url.clone();
+    let (accounts, result) = tokio::task::spawn_blocking(move || {
+        chooser::prompt_gui(
+            &accounts,
+            &prompt_url,
+            &host,
+            offer_remember,
+            std::time::Duration::from_secs(timeout),
+        )
+        .map(|result| (accounts, result))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to show the account chooser: {}", e))?;
+    let (index, remember) = match result {
+        ChooserResult::Chosen { index, remember } => (index, remember),
+        ChooserResult::Cancelled => return Err("No account was chosen".to_string()),
+        ChooserResult::TimedOut => {
+            return Err(format!("No account was chosen within {} seconds", timeout))
+        }
+        ChooserResult::Unavailable => return Err("The account chooser is not running".to_string()),
+    };
+    let account = accounts.into_iter().nth(index).ok_or("Account not found")?;
+
+    keychain
+        .get_token(&account.host, &account.username)
+        .map_err(|e| format!("No token found for account: {}", e))?;
+
+    if let (true, Some(mapping_url)) = (remember, mapping_url) {
+        let account_id = account.id.clone();
+        db.run(move |db| db.set_repository_mapping(&mapping_url, &account_id, true, None))
+            .await
+            .map_err(|e| e.to_string())?;
+        cache::invalidate();
+    }
+    Ok(account.username)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChooserRequestInfo {
+    pub id: String,
+    pub repo_url: String,
+    // "owner/repo", when the url names a repository
+    pub repo_name: Option<String>,
+    pub host: String,
+    pub accounts: Vec<AccountInfo>,
+    pub preselected_id: Option<String>,
+    pub offer_remember: bool,
+}
+
+// Opens a chooser window for every request a credential helper is waiting on.
+// Runs for the lifetime of the app.
+pub fn watch_chooser_requests(app: AppHandle) {
+    let mut shown = HashSet::new();
+    loop {
+        if let Err(e) = chooser::heartbeat() {
+            warn!(error = %e, "failed to update the account chooser heartbeat");
+        }
+        for request in chooser::pending_requests().unwrap_or_default() {
+            if shown.insert(request.id.clone()) {
+                if let Err(e) = open_chooser_window(&app, &request.id) {
+                    warn!(error = %e, "failed to open the account chooser");
+                }
+            }
+        }
+        std::thread::sleep(chooser::POLL_INTERVAL);
     }
+}
 
+fn open_chooser_window(app: &AppHandle, request_id: &str) -> tauri::Result<()> {
+    WebviewWindowBuilder::new(
+        app,
+        format!("chooser-{}", request_id),
+        WebviewUrl::App(format!("index.html?chooser={}", request_id).into()),
+    )
+    .title("Choose a GitHub account")
+    .inner_size(420.0, 520.0)
+    .resizable(false)
+    .always_on_top(true)
+    .center()
+    .focused(true)
+    .build()?;
     Ok(())
 }
 
-#[tauri::command]
-pub async fn get_git_helper_status() -> Result<GitHelperStatus, String> {
-    use std::process::Command;
+const NEW_REPOSITORY_EVENT: &str = "new-repository";
+// How long after a checkout appears it is looked at, so that a clone has
+// written its remotes
+const NEW_REPOSITORY_SETTLE: std::time::Duration = std::time::Duration::from_secs(3);
+// How often the project directories setting is checked for changes
+const PROJECT_DIRECTORIES_RELOAD: std::time::Duration = std::time::Duration::from_secs(10);
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
-    let output = Command::new("git")
-        .args(["config", "--global", "credential.helper"])
-        .output()
-        .map_err(|e| format!("Failed to run git config: {}", e))?;
+// A checkout that appeared in a project directory with no mapping for it.
+// The app asks which account it should use and saves the answer with
+// set_repository_mapping.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewRepositoryEvent {
+    pub path: String,
+    pub remote_url: String,
+    pub repo_name: Option<String>,
+    // The account it would use for now, to preselect
+    pub suggested_account: Option<AccountInfo>,
+    pub reason: Option<String>,
+}
 
-    if !output.status.success() {
-        return Ok(GitHelperStatus {
-            installed: false,
-            configured: false,
-        });
+// Watches the project directories for new checkouts, emitting a
+// NewRepositoryEvent for each on a host with an account. Runs for the
+// lifetime of the app.
+pub fn watch_project_directories(app: AppHandle) {
+    let (sender, changes) = std::sync::mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(sender) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!(error = %e, "failed to watch project directories");
+            return;
+        }
+    };
+    let mut roots: Vec<std::path::PathBuf> = Vec::new();
+    // Each directory is watched on its own, never whole trees, so that
+    // checkouts and skipped directories like node_modules aren't watched
+    let mut watched: HashSet<std::path::PathBuf> = HashSet::new();
+    // Checkouts seen already, starting with those there before watching
+    let mut known: HashSet<std::path::PathBuf> = HashSet::new();
+    // New checkouts and when they last changed
+    let mut pending: HashMap<std::path::PathBuf, std::time::Instant> = HashMap::new();
+    let mut reloaded_at: Option<std::time::Instant> = None;
+    loop {
+        if reloaded_at.is_none_or(|at| at.elapsed() >= PROJECT_DIRECTORIES_RELOAD) {
+            reloaded_at = Some(std::time::Instant::now());
+            let wanted = project_directories(&app.state::<Database>()).unwrap_or_default();
+            if wanted != roots {
+                for root in roots.iter().filter(|root| !wanted.contains(root)) {
+                    watched.retain(|dir| {
+                        // Still watched for another root containing this one
+                        if !dir.starts_with(root)
+                            || wanted.iter().any(|other| dir.starts_with(other))
+                        {
+                            return true;
+                        }
+                        let _ = watcher.unwatch(dir);
+                        false
+                    });
+                }
+                for root in wanted.iter().filter(|root| !roots.contains(root)) {
+                    match scanner::search(root, root) {
+                        Ok((repositories, dirs)) => {
+                            known.extend(repositories);
+                            watch_directories(&mut watcher, &mut watched, dirs);
+                        }
+                        Err(e) => {
+                            warn!(error = %e, path = %root.display(), "failed to watch a project directory")
+                        }
+                    }
+                }
+                roots = wanted;
+            }
+        }
+
+        match changes.recv_timeout(WATCH_POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    if event.kind.is_remove() {
+                        watched.retain(|dir| !dir.starts_with(&path));
+                        continue;
+                    }
+                    let repository = roots
+                        .iter()
+                        .find_map(|root| scanner::repository_for_change(root, &path));
+                    if let Some(repository) = repository.filter(|r| !known.contains(r)) {
+                        pending.insert(repository, std::time::Instant::now());
+                        continue;
+                    }
+                    // A new directory in a watched one, e.g. a clone's target or
+                    // a directory moved in, gets searched and watched in turn
+                    let is_new_dir = path.symlink_metadata().is_ok_and(|m| m.is_dir())
+                        && !watched.contains(&path)
+                        && path.parent().is_some_and(|parent| {
+                            watched.contains(parent) && !parent.join(".git").exists()
+                        });
+                    if !is_new_dir {
+                        continue;
+                    }
+                    let Some(root) = roots.iter().find(|root| path.starts_with(root)) else {
+                        continue;
+                    };
+                    if let Ok((repositories, dirs)) = scanner::search(root, &path) {
+                        for repository in repositories.into_iter().filter(|r| !known.contains(r)) {
+                            pending.insert(repository, std::time::Instant::now());
+                        }
+                        watch_directories(&mut watcher, &mut watched, dirs);
+                    }
+                }
+            }
+            Ok(Err(e)) => warn!(error = %e, "failed to watch project directories"),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+
+        let settled: Vec<_> = pending
+            .iter()
+            .filter(|(_, changed_at)| changed_at.elapsed() >= NEW_REPOSITORY_SETTLE)
+            .map(|(repository, _)| repository.clone())
+            .collect();
+        for repository in settled {
+            pending.remove(&repository);
+            // Gone again, e.g. a clone that failed
+            if !repository.join(".git").exists() {
+                continue;
+            }
+            known.insert(repository.clone());
+            // Watched while it was a plain directory, e.g. a clone's target
+            if watched.remove(&repository) {
+                let _ = watcher.unwatch(&repository);
+            }
+            match new_repository_event(&app.state::<Database>(), &repository) {
+                Ok(Some(event)) => {
+                    let message = format!(
+                        "Which account should {} use?",
+                        event.repo_name.as_deref().unwrap_or(&event.path)
+                    );
+                    let _ = app.emit(NEW_REPOSITORY_EVENT, event);
+                    if let Err(e) = app
+                        .notification()
+                        .builder()
+                        .title("GitSwitchHub")
+                        .body(&message)
+                        .show()
+                    {
+                        warn!(error = %e, "failed to show the new repository notification");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(error = %e, path = %repository.display(), "failed to check a new repository")
+                }
+            }
+        }
     }
+}
 
-    let config = String::from_utf8_lossy(&output.stdout);
-    let current_exe =
-        std::env::current_exe().map_err(|e| format!("Failed to get current executable: {}", e))?;
-    let expected_helper = format!("!{} credential-helper", current_exe.display());
+// Watches each directory on its own, warning once when some can't be, e.g.
+// past the system's limit on watches
+fn watch_directories(
+    watcher: &mut impl Watcher,
+    watched: &mut HashSet<std::path::PathBuf>,
+    dirs: Vec<std::path::PathBuf>,
+) {
+    let mut failed = 0;
+    let mut last_error = None;
+    for dir in dirs {
+        if watched.contains(&dir) {
+            continue;
+        }
+        match watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                watched.insert(dir);
+            }
+            Err(e) => {
+                failed += 1;
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_error {
+        warn!(error = %e, failed, "failed to watch some project directories");
+    }
+}
 
-    Ok(GitHelperStatus {
-        installed: true,
-        configured: config.trim() == expected_helper,
+// None when the checkout isn't on a host with an account or is mapped already
+fn new_repository_event(
+    db: &Database,
+    repository: &std::path::Path,
+) -> Result<Option<NewRepositoryEvent>, String> {
+    let repository = scanner::read_repository(repository).map_err(|e| e.to_string())?;
+    let accounts = db.get_accounts().map_err(|e| e.to_string())?;
+    let info =
+        scanned_repository_info(db, &accounts, repository, None).map_err(|e| e.to_string())?;
+    let mapped = info.source.as_deref() == Some(resolver::ResolutionSource::Mapping.as_str());
+    Ok(match info.remote_url {
+        Some(remote_url) if !mapped => Some(NewRepositoryEvent {
+            path: info.path,
+            remote_url,
+            repo_name: info.repo_name,
+            suggested_account: info.account,
+            reason: info.reason,
+        }),
+        _ => None,
     })
 }
 
+const TOKEN_EXPIRY_EVENT: &str = "token-expiry";
+// How often the background check looks at every account's token
+const TOKEN_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+// A token that needs the user's attention. The window shows it with a button
+// that signs in again with reauthenticate_account; desktop notifications
+// can't carry actions, so the notification only tells.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenExpiryEvent {
+    pub account_id: String,
+    pub username: String,
+    pub host: String,
+    // "expiring", "expired" or "invalid"
+    pub status: String,
+    pub days_left: Option<i64>,
+    pub message: String,
+}
+
+// Warns about tokens that are about to expire or that GitHub no longer
+// accepts, with an event and a system notification. Runs for the lifetime of
+// the app.
+pub async fn watch_token_expiry(app: AppHandle) {
+    // What was last reported per account, so each day's warning shows once
+    let mut notified: HashMap<String, (String, Option<i64>)> = HashMap::new();
+    loop {
+        let db = app.state::<Database>();
+        let enabled = db
+            .run(|db| db.get_setting(SettingKey::ExpiryNotifications))
+            .await
+            .ok()
+            .and_then(|value| value.as_bool())
+            .unwrap_or(true);
+        if enabled {
+            let keychain = app.state::<KeychainManager>();
+            for event in check_token_expiry(&db, &keychain).await {
+                let seen = (event.status.clone(), event.days_left);
+                if notified.get(&event.account_id) == Some(&seen) {
+                    continue;
+                }
+                notified.insert(event.account_id.clone(), seen);
+
+                let _ = app.emit(TOKEN_EXPIRY_EVENT, event.clone());
+                if let Err(e) = app
+                    .notification()
+                    .builder()
+                    .title("GitSwitchHub")
+                    .body(&event.message)
+                    .show()
+                {
+                    warn!(error = %e, "failed to show the token expiry notification");
+                }
+            }
+        }
+        tokio::time::sleep(TOKEN_CHECK_INTERVAL).await;
+    }
+}
+
+// The tokens that need attention now, for a window opened after the watcher
+// reported them
 #[tauri::command]
-pub async fn generate_ssh_key(username: String) -> Result<SSHKeyInfo, String> {
-    use std::fs;
-    use std::path::PathBuf;
-    use std::process::Command;
+pub async fn get_token_expiry(
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+) -> Result<Vec<TokenExpiryEvent>, String> {
+    Ok(check_token_expiry(&db, &keychain).await)
+}
 
-    let home_dir = std::env::var("HOME").map_err(|_| "HOME directory not found")?;
+// Asks GitHub about each token when that doesn't need the user, falling back
+// to the expiry recorded when the token was last validated
+async fn check_token_expiry(db: &Database, keychain: &KeychainManager) -> Vec<TokenExpiryEvent> {
+    let accounts = match db.run(|db| db.get_accounts()).await {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            warn!(error = %e, "failed to check token expiry");
+            return Vec::new();
+        }
+    };
+    // Reading a token would ask for Touch ID, or fail on a locked vault
+    let can_read_tokens = !keychain.biometric_settings().enabled && !keychain.status().locked;
 
-    let ssh_dir = PathBuf::from(home_dir).join(".ssh");
-    fs::create_dir_all(&ssh_dir).map_err(|e| format!("Failed to create SSH directory: {}", e))?;
+    let mut events = Vec::new();
+    for account in accounts {
+        let account_id = account.id.clone();
+        let metadata = db
+            .run(move |db| db.get_token_metadata(&account_id))
+            .await
+            .ok()
+            .flatten();
+        let mut expires_at = metadata.as_ref().and_then(|m| m.expires_at);
 
-    let key_name = format!("gitswitchhub_{}", username);
-    let private_key_path = ssh_dir.join(&key_name);
-    let public_key_path = ssh_dir.join(format!("{}.pub", key_name));
+        // Expiring OAuth tokens are renewed as they're used; only the refresh
+        // token running out needs a new sign in
+        if let Ok(Some(refresh)) =
+            keychain.get_refresh_credentials(&account.host, &account.username)
+        {
+            expires_at = refresh.refresh_token_expires_at;
+        } else if can_read_tokens {
+            if let Ok(token) = keychain.get_token(&account.host, &account.username) {
+                let details = match github_auth_for(db, &account.host).await {
+                    Ok(github_auth) => github_auth.get_token_details(&token).await,
+                    Err(_) => continue,
+                };
+                match details {
+                    Ok(details) => {
+                        expires_at = details.expires_at;
+                        if let Some(mut metadata) = metadata {
+                            metadata.scopes = details.scopes;
+                            metadata.expires_at = details.expires_at;
+                            metadata.last_validated_at = Some(Utc::now());
+                            let _ = db.run(move |db| db.set_token_metadata(&metadata)).await;
+                        }
+                    }
+                    Err(GitHubAuthError::InvalidToken) => {
+                        events.push(TokenExpiryEvent {
+                            message: format!(
+                                "{} token no longer works, sign in again",
+                                account_display_name(&account)
+                            ),
+                            account_id: account.id,
+                            username: account.username,
+                            host: account.host,
+                            status: "invalid".to_string(),
+                            days_left: None,
+                        });
+                        continue;
+                    }
+                    // Offline or GitHub is down; go by what was recorded
+                    Err(_) => {}
+                }
+            }
+        }
 
-    // Generate SSH key
-    let output = Command::new("ssh-keygen")
-        .args([
-            "-t",
-            "ed25519",
-            "-f",
-            &private_key_path.to_string_lossy(),
-            "-C",
-            &format!("{}@gitswitchhub", username),
-            "-N",
-            "", // No passphrase
-        ])
-        .output()
-        .map_err(|e| format!("Failed to generate SSH key: {}", e))?;
+        let expires_at = match expires_at {
+            Some(expires_at) => expires_at,
+            None => continue,
+        };
+        let days_left = (expires_at - Utc::now()).num_days();
+        let name = account_display_name(&account);
+        let (status, message) = if expires_at <= Utc::now() {
+            ("expired", format!("{} token has expired", name))
+        } else if days_left < TOKEN_EXPIRY_WARNING_DAYS {
+            let when = match days_left {
+                0 => "today".to_string(),
+                1 => "tomorrow".to_string(),
+                days => format!("in {} days", days),
+            };
+            ("expiring", format!("{} token expires {}", name, when))
+        } else {
+            continue;
+        };
+        events.push(TokenExpiryEvent {
+            account_id: account.id,
+            username: account.username,
+            host: account.host,
+            status: status.to_string(),
+            days_left: Some(days_left.max(0)),
+            message,
+        });
+    }
+    events
+}
 
-    if !output.status.success() {
-        return Err(format!(
-            "SSH key generation failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+// The label the user gave the account, e.g. "Work", or its username
+const ACCOUNT_VERIFIED_EVENT: &str = "account-verified";
+// How often accounts added offline are retried
+const VERIFY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+// The outcome of checking the token of an account added offline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountVerifiedEvent {
+    pub account_id: String,
+    pub username: String,
+    pub host: String,
+    // False when GitHub refused the token or it belongs to another user
+    pub verified: bool,
+    pub message: String,
+}
+
+// Checks the tokens of accounts added while offline as soon as GitHub can be
+// reached, emitting the outcome for each. Runs for the lifetime of the app.
+pub async fn watch_unverified_accounts(app: AppHandle) {
+    loop {
+        let db = app.state::<Database>();
+        let keychain = app.state::<KeychainManager>();
+        for event in verify_pending_accounts(&db, &keychain).await {
+            let _ = app.emit(ACCOUNT_VERIFIED_EVENT, event);
+        }
+        tokio::time::sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+async fn verify_pending_accounts(
+    db: &Database,
+    keychain: &KeychainManager,
+) -> Vec<AccountVerifiedEvent> {
+    let accounts = match db.run(|db| db.get_accounts()).await {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            warn!(error = %e, "failed to verify accounts");
+            return Vec::new();
+        }
+    };
+    let pending: Vec<Account> = accounts
+        .into_iter()
+        .filter(|account| account.unverified && account.token_rejected_at.is_none())
+        .collect();
+    // Reading a token would ask for Touch ID, or fail on a locked vault; the
+    // accounts are verified by test_connection instead
+    if pending.is_empty() || keychain.biometric_settings().enabled || keychain.status().locked {
+        return Vec::new();
     }
 
-    // Read public key
-    let public_key = fs::read_to_string(&public_key_path)
-        .map_err(|e| format!("Failed to read public key: {}", e))?;
+    let mut events = Vec::new();
+    for account in pending {
+        match verify_account(db, keychain, &account).await {
+            Ok(Some(event)) => events.push(event),
+            Ok(None) => {}
+            // Still offline, the other accounts would fail the same way
+            Err(_) => break,
+        }
+    }
+    events
+}
 
-    Ok(SSHKeyInfo {
-        public_key: public_key.trim().to_string(),
-        private_key_path: private_key_path.to_string_lossy().to_string(),
-        key_id: key_name,
-    })
+// Asks GitHub who the token of an account added offline belongs to. A token
+// that's refused, or that belongs to someone else, is marked rejected.
+async fn verify_account(
+    db: &Database,
+    keychain: &KeychainManager,
+    account: &Account,
+) -> Result<Option<AccountVerifiedEvent>, GitHubAuthError> {
+    let token = match keychain.get_token(&account.host, &account.username) {
+        Ok(token) => token,
+        Err(_) => return Ok(None),
+    };
+    let github_auth = match github_auth_for(db, &account.host).await {
+        Ok(github_auth) => github_auth,
+        Err(_) => return Ok(None),
+    };
+    let name = account_display_name(account);
+    let (verified, message) = match github_auth.get_token_details(&token).await {
+        Err(e) if e.is_network() => return Err(e),
+        Ok(details) if details.user.login.eq_ignore_ascii_case(&account.username) => {
+            let user = details.user;
+            let metadata = TokenMetadata {
+                account_id: account.id.clone(),
+                scopes: details.scopes,
+                created_at: account.created_at,
+                expires_at: details.expires_at,
+                last_validated_at: Some(Utc::now()),
+            };
+            let saved = db
+                .run(move |db| {
+                    db.set_account_verified(
+                        &metadata.account_id,
+                        user.id as i64,
+                        &user.avatar_url,
+                        user.name.as_deref(),
+                    )?;
+                    db.set_token_metadata(&metadata)
+                })
+                .await;
+            if let Err(e) = saved {
+                warn!(error = %e, "failed to save the verified account");
+                return Ok(None);
+            }
+            (true, format!("{} is verified", name))
+        }
+        Ok(details) => (
+            false,
+            format!(
+                "The token added for {} belongs to {}; add it again with the right token",
+                name, details.user.login
+            ),
+        ),
+        Err(e) => (
+            false,
+            format!("The token added for {} doesn't work: {}", name, e),
+        ),
+    };
+
+    if !verified {
+        let account_id = account.id.clone();
+        let _ = db
+            .run(move |db| db.set_token_rejected(&account_id, true))
+            .await;
+        cache::invalidate();
+    }
+    Ok(Some(AccountVerifiedEvent {
+        account_id: account.id.clone(),
+        username: account.username.clone(),
+        host: account.host.clone(),
+        verified,
+        message,
+    }))
+}
+
+fn account_display_name(account: &Account) -> String {
+    account
+        .label
+        .clone()
+        .filter(|label| !label.is_empty())
+        .unwrap_or_else(|| account.username.clone())
+}
+
+// One-click renewal for an expiring token: signs in again in the browser,
+// which replaces the token of the account when the same user signs in
+#[tauri::command]
+pub async fn reauthenticate_account(
+    app: AppHandle,
+    db: State<'_, Database>,
+    keychain: State<'_, KeychainManager>,
+    flows: State<'_, SignInFlows>,
+    account_id: String,
+) -> Result<AccountInfo, String> {
+    let lookup_id = account_id.clone();
+    let account = db
+        .run(move |db| db.get_account(&lookup_id))
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Account not found")?;
+
+    browser_sign_in(
+        &app,
+        &db,
+        &keychain,
+        &flows,
+        Some(account.host),
+        Some(&account_id),
+    )
+    .await
 }
 
+// Starts a device flow that replaces the account's token with one that has
+// the scopes it has now plus `scopes`, by default the recommended ones it
+// lacks. Finish it with poll_device_flow as for a new sign in; a different
+// user signing in is refused.
 #[tauri::command]
-pub async fn get_ssh_config(username: String) -> Result<SSHConfig, String> {
-    let home_dir = std::env::var("HOME").map_err(|_| "HOME directory not found")?;
+pub async fn reauthorize_account(
+    db: State<'_, Database>,
+    flows: State<'_, SignInFlows>,
+    account_id: String,
+    scopes: Option<Vec<String>>,
+) -> Result<DeviceCodeInfo, String> {
+    if let Some(scope) = scopes.iter().flatten().find(|scope| {
+        scope.is_empty()
+            || !scope
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c == ':' || c == '_')
+    }) {
+        return Err(format!("Invalid scope: {}", scope));
+    }
+
+    let lookup_id = account_id.clone();
+    let (account, granted) = db
+        .run(move |db| {
+            let account = db.get_account(&lookup_id)?.ok_or("Account not found")?;
+            let granted = db
+                .get_token_metadata(&lookup_id)?
+                .map(|metadata| metadata.scopes)
+                .unwrap_or_default();
+            Ok::<_, String>((account, granted))
+        })
+        .await?;
+    let wanted = scopes
+        .unwrap_or_else(|| github_auth::missing_scopes(&granted, github_auth::RECOMMENDED_SCOPES));
+    let requested: Vec<String> = granted.into_iter().chain(wanted).collect();
 
-    let key_name = format!("gitswitchhub_{}", username);
-    let private_key_path = format!("{}/.ssh/{}", home_dir, key_name);
+    let response = sign_in_auth_for(&db, Some(account.host))
+        .await?
+        .start_device_flow_with_scopes(&github_auth::sign_in_scopes(&requested))
+        .await
+        .map_err(|e| format!("Failed to start device flow: {}", e))?;
+    flows.start(
+        &response.device_code,
+        Some(&account_id),
+        Some((&response).into()),
+    );
 
-    Ok(SSHConfig {
-        host: format!("github-{}", username),
-        hostname: "github.com".to_string(),
-        user: "git".to_string(),
-        identity_file: private_key_path,
+    Ok(DeviceCodeInfo {
+        device_code: response.device_code,
+        user_code: response.user_code,
+        verification_uri: response.verification_uri,
+        verification_uri_complete: response.verification_uri_complete,
+        expires_in: response.expires_in,
+        interval: response.interval,
     })
 }
 
+// What the chooser window shows: the repository and the accounts offered
 #[tauri::command]
-pub async fn convert_remote_to_ssh(remote_url: String, username: String) -> Result<String, String> {
-    // Convert HTTPS URL to SSH format
-    if remote_url.starts_with("https://github.com/") {
-        let repo_path = remote_url
-            .strip_prefix("https://github.com/")
-            .ok_or("Invalid GitHub URL")?;
-        Ok(format!("git@github-{}:{}", username, repo_path))
-    } else {
-        Err("Not a GitHub HTTPS URL".to_string())
-    }
+pub async fn get_chooser_request(
+    db: State<'_, Database>,
+    request_id: String,
+) -> Result<ChooserRequestInfo, String> {
+    let request = chooser::get_request(&request_id).map_err(|e| e.to_string())?;
+    let account_ids = request.account_ids.clone();
+    let accounts = db
+        .run(move |db| {
+            let mut accounts = Vec::new();
+            for id in &account_ids {
+                if let Some(account) = db.get_account(id)? {
+                    accounts.push(AccountInfo::from(account));
+                }
+            }
+            Ok::<_, DatabaseError>(accounts)
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(ChooserRequestInfo {
+        id: request.id,
+        repo_name: remote::repo_full_name(&request.repo_url),
+        repo_url: request.repo_url,
+        host: request.host,
+        accounts,
+        preselected_id: request.preselected_id,
+        offer_remember: request.offer_remember,
+    })
 }
 
+// Hands the selection back to the waiting helper; no account means the user
+// dismissed the window
 #[tauri::command]
-pub async fn show_account_chooser(
+pub async fn answer_chooser_request(
+    request_id: String,
+    account_id: Option<String>,
+    remember: bool,
+) -> Result<(), String> {
+    chooser::answer_request(
+        &request_id,
+        &ChooserResponse {
+            account_id,
+            remember,
+        },
+    )
+    .map_err(|e| e.to_string())
+}
+
+// The newest log lines from the app and credential helper, for troubleshooting
+#[tauri::command]
+pub async fn get_recent_logs(lines: Option<usize>) -> Result<Vec<String>, String> {
+    let lines = lines.unwrap_or(200);
+    tokio::task::spawn_blocking(move || logging::recent_logs(lines))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_setting(
     db: State<'_, Database>,
-    keychain: State<'_, KeychainManager>,
-    _repo_url: String,
-) -> Result<String, String> {
-    // Get all accounts
-    let accounts = db.get_accounts().map_err(|e| e.to_string())?;
+    key: String,
+) -> Result<serde_json::Value, String> {
+    let key = SettingKey::parse(&key).map_err(|e| e.to_string())?;
+    db.run(move |db| db.get_setting(key))
+        .await
+        .map_err(|e| e.to_string())
+}
 
-    if accounts.is_empty() {
-        return Err("No accounts configured".to_string());
-    }
+#[tauri::command]
+pub async fn set_setting(
+    app: AppHandle,
+    db: State<'_, Database>,
+    key: String,
+    value: serde_json::Value,
+) -> Result<(), String> {
+    let key = SettingKey::parse(&key).map_err(|e| e.to_string())?;
+    key.validate(&value).map_err(|e| e.to_string())?;
 
-    // For now, return the first account
-    // In a real implementation, this would show a GUI chooser
-    let account = &accounts[0];
+    db.run(move |db| {
+        if let (SettingKey::DefaultAccount, Some(account_id)) = (key, value.as_str()) {
+            db.get_account(account_id)?.ok_or("Account not found")?;
+        }
+        if let (SettingKey::ActiveProfile, Some(profile_id)) = (key, value.as_str()) {
+            db.get_profile(profile_id)?.ok_or("Profile not found")?;
+        }
 
-    // Verify token exists
-    keychain
-        .get_token(&account.username)
-        .map_err(|e| format!("No token found for account: {}", e))?;
+        // Refuse proxy settings the HTTP client can't use, such as a CA
+        // bundle that doesn't parse
+        let network_key = matches!(
+            key,
+            SettingKey::HttpProxy | SettingKey::NoProxy | SettingKey::CaBundlePath
+        );
+        let previous = db.get_setting(key)?;
+        db.set_setting(key, &value)?;
+        if network_key {
+            if let Err(e) = github_client::configure(&NetworkSettings::from_db(db)) {
+                db.set_setting(key, &previous)?;
+                return Err(e.to_string());
+            }
+        }
+        Ok::<_, String>(())
+    })
+    .await?;
+    cache::invalidate();
+    match key {
+        SettingKey::DefaultAccount => accounts_changed(&app),
+        SettingKey::ShowTray => tray::refresh(&app),
+        _ => {}
+    }
+    Ok(())
+}
 
-    Ok(account.username.clone())
+#[tauri::command]
+pub async fn get_all_settings(
+    db: State<'_, Database>,
+) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let settings = db
+        .run(|db| db.get_all_settings())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(settings
+        .into_iter()
+        .map(|(key, value)| (key.as_str().to_string(), value))
+        .collect())
 }
 
 // Auto-detection commands
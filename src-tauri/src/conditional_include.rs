@@ -0,0 +1,153 @@
+use crate::git_config::{self, GitConfigError, GitConfigScope};
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Gives every repository under some directories an account's identity, with
+// a file per account pulled in from the global config:
+//   [includeIf "gitdir:/home/me/work/"]
+//       path = /home/me/.gitconfig-gitswitchhub-me
+// The file holds user.name, user.email, the signing settings and
+// core.sshCommand, and is written from scratch each time.
+
+#[derive(Error, Debug)]
+pub enum ConditionalIncludeError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Git config error: {0}")]
+    GitConfig(#[from] GitConfigError),
+    #[error("Not an absolute directory: {0}")]
+    InvalidDirectory(String),
+}
+
+const FILE_PREFIX: &str = ".gitconfig-gitswitchhub-";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConditionalInclude {
+    pub username: String,
+    pub file: String,
+    // Where repositories pick up the file
+    pub directories: Vec<String>,
+}
+
+pub fn include_file(username: &str) -> io::Result<PathBuf> {
+    Ok(paths::home_dir()?.join(format!("{}{}", FILE_PREFIX, username)))
+}
+
+// The includeIf condition for each directory, failing on the first one that
+// can't have one, before anything is written
+pub fn gitdir_conditions(directories: &[String]) -> Result<Vec<String>, ConditionalIncludeError> {
+    directories
+        .iter()
+        .map(|dir| {
+            git_config::gitdir_condition(dir)
+                .ok_or_else(|| ConditionalIncludeError::InvalidDirectory(dir.to_string()))
+        })
+        .collect()
+}
+
+// Writes the account's file from scratch with `write`, into a temporary file
+// that replaces it only once complete, so git never includes half a file
+pub fn write_file<F>(username: &str, write: F) -> Result<PathBuf, ConditionalIncludeError>
+where
+    F: FnOnce(&GitConfigScope) -> Result<(), GitConfigError>,
+{
+    let file = include_file(username)?;
+    let temp = file.with_file_name(format!("{}{}.tmp", FILE_PREFIX, username));
+    fs::write(
+        &temp,
+        format!(
+            "# Written by GitSwitchHub for {}; changes are overwritten\n",
+            username
+        ),
+    )?;
+    let written = write(&GitConfigScope::File(temp.clone()))
+        .map_err(ConditionalIncludeError::from)
+        .and_then(|()| Ok(fs::rename(&temp, &file)?));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written.map(|()| file)
+}
+
+// Points an includeIf for each condition at the account's file, replacing
+// the ones it had. The user's own includeIf entries are left alone.
+pub fn set_directories(
+    username: &str,
+    conditions: &[String],
+) -> Result<(), ConditionalIncludeError> {
+    let file = include_file(username)?;
+    remove_includes(&file)?;
+    let value = file.display().to_string();
+    for condition in conditions {
+        git_config::add_value(
+            &GitConfigScope::Global,
+            &format!("includeIf.{}.path", condition),
+            &value,
+        )?;
+    }
+    Ok(())
+}
+
+// Removes the account's includeIf entries and its file
+pub fn remove(username: &str) -> Result<(), ConditionalIncludeError> {
+    let file = include_file(username)?;
+    remove_includes(&file)?;
+    match fs::remove_file(&file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Every account file the global config includes, with its directories
+pub fn list() -> Result<Vec<ConditionalInclude>, ConditionalIncludeError> {
+    let home = paths::home_dir()?;
+    let mut includes: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    for (key, value) in includes_of_global()? {
+        let file = PathBuf::from(&value);
+        let Some(username) = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(FILE_PREFIX))
+        else {
+            continue;
+        };
+        if file.parent() != Some(home.as_path()) {
+            continue;
+        }
+        let (_, directories) = includes
+            .entry(username.to_string())
+            .or_insert_with(|| (value.clone(), Vec::new()));
+        directories.extend(git_config::gitdir_directory(&key));
+    }
+
+    Ok(includes
+        .into_iter()
+        .map(|(username, (file, directories))| ConditionalInclude {
+            username,
+            file,
+            directories,
+        })
+        .collect())
+}
+
+fn remove_includes(file: &Path) -> Result<(), ConditionalIncludeError> {
+    let file_value = file.display().to_string();
+    for (key, value) in includes_of_global()? {
+        if value == file_value {
+            git_config::unset_fixed_value(&GitConfigScope::Global, &key, &value)?;
+        }
+    }
+    Ok(())
+}
+
+fn includes_of_global() -> Result<Vec<(String, String)>, ConditionalIncludeError> {
+    Ok(git_config::get_regexp(
+        &GitConfigScope::Global,
+        r"^includeif\..*\.path$",
+    )?)
+}
@@ -0,0 +1,507 @@
+use crate::database::{Account, Database, DatabaseError, GitHost};
+use crate::remote::canonicalize_remote_url;
+use crate::settings::SettingKey;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+    #[error("Unsupported config version {0}")]
+    UnsupportedVersion(u32),
+}
+
+const CONFIG_VERSION: u32 = 1;
+
+// Everything except secrets, so the file can be shared with a team
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigDocument {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    #[serde(default)]
+    pub hosts: Vec<ConfigHost>,
+    pub accounts: Vec<ConfigAccount>,
+    pub repository_mappings: Vec<ConfigRepositoryMapping>,
+    pub org_mappings: Vec<ConfigOrgMapping>,
+    pub host_defaults: Vec<ConfigHostDefault>,
+    pub directory_rules: Vec<ConfigDirectoryRule>,
+    pub profiles: Vec<ConfigProfile>,
+    pub settings: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigHost {
+    pub host: String,
+    pub name: Option<String>,
+    pub api_url: String,
+    #[serde(default)]
+    pub oauth_client_id: Option<String>,
+}
+
+// Rows refer to accounts by the id they had on the exporting machine
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigAccount {
+    pub id: String,
+    pub username: String,
+    pub host: String,
+    pub avatar_url: Option<String>,
+    pub auth_method: String,
+    pub github_user_id: Option<i64>,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub git_name: Option<String>,
+    #[serde(default)]
+    pub git_email: Option<String>,
+    #[serde(default)]
+    pub signing_format: Option<String>,
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    #[serde(default)]
+    pub sign_commits: bool,
+    #[serde(default)]
+    pub clone_dir: Option<String>,
+    #[serde(default)]
+    pub ssh_key_path: Option<String>,
+    #[serde(default)]
+    pub ssh_port_443: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigRepositoryMapping {
+    pub remote_url: String,
+    pub account_id: String,
+    pub remember: bool,
+    pub token_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigOrgMapping {
+    pub host: String,
+    pub owner: String,
+    pub account_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigHostDefault {
+    pub host: String,
+    pub account_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigDirectoryRule {
+    pub pattern: String,
+    pub account_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigProfile {
+    pub id: String,
+    pub name: String,
+    pub account_ids: Vec<String>,
+}
+
+// What to do when an imported entry already exists locally:
+// skip keeps the local entry, overwrite replaces it, and merge keeps it but
+// fills in whatever it lacks (account details, settings still at default,
+// profile members).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    Skip,
+    Overwrite,
+    Merge,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConfigImportSummary {
+    pub imported: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+pub fn export_config(db: &Database) -> Result<ConfigDocument, ConfigError> {
+    let accounts = db
+        .get_accounts()?
+        .into_iter()
+        .map(|account| ConfigAccount {
+            id: account.id,
+            username: account.username,
+            host: account.host,
+            avatar_url: account.avatar_url,
+            auth_method: account.auth_method,
+            github_user_id: account.github_user_id,
+            display_name: account.display_name,
+            label: account.label,
+            color: account.color,
+            notes: account.notes,
+            git_name: account.git_name,
+            git_email: account.git_email,
+            signing_format: account.signing_format,
+            signing_key: account.signing_key,
+            sign_commits: account.sign_commits,
+            clone_dir: account.clone_dir,
+            ssh_key_path: account.ssh_key_path,
+            ssh_port_443: account.ssh_port_443,
+        })
+        .collect();
+
+    let repository_mappings = db
+        .get_repository_mappings()?
+        .into_iter()
+        .map(|mapping| ConfigRepositoryMapping {
+            remote_url: mapping.remote_url,
+            account_id: mapping.account_id,
+            remember: mapping.remember,
+            token_name: mapping.token_name,
+        })
+        .collect();
+
+    let org_mappings = db
+        .get_org_mappings()?
+        .into_iter()
+        .map(|mapping| ConfigOrgMapping {
+            host: mapping.host,
+            owner: mapping.owner,
+            account_id: mapping.account_id,
+        })
+        .collect();
+
+    let hosts = db
+        .get_hosts()?
+        .into_iter()
+        .map(|host| ConfigHost {
+            host: host.host,
+            name: host.name,
+            api_url: host.api_url,
+            oauth_client_id: host.oauth_client_id,
+        })
+        .collect();
+
+    let host_defaults = db
+        .get_host_default_accounts()?
+        .into_iter()
+        .map(|(host, account_id)| ConfigHostDefault { host, account_id })
+        .collect();
+
+    let directory_rules = db
+        .get_directory_rules()?
+        .into_iter()
+        .map(|rule| ConfigDirectoryRule {
+            pattern: rule.pattern,
+            account_id: rule.account_id,
+        })
+        .collect();
+
+    let profiles = db
+        .get_profiles()?
+        .into_iter()
+        .map(|profile| ConfigProfile {
+            id: profile.id,
+            name: profile.name,
+            account_ids: profile.account_ids,
+        })
+        .collect();
+
+    let settings = db
+        .get_all_settings()?
+        .into_iter()
+        .map(|(key, value)| (key.as_str().to_string(), value))
+        .collect();
+
+    Ok(ConfigDocument {
+        version: CONFIG_VERSION,
+        exported_at: Utc::now(),
+        hosts,
+        accounts,
+        repository_mappings,
+        org_mappings,
+        host_defaults,
+        directory_rules,
+        profiles,
+        settings,
+    })
+}
+
+pub fn import_config(
+    db: &Database,
+    config: ConfigDocument,
+    strategy: ConflictStrategy,
+) -> Result<ConfigImportSummary, ConfigError> {
+    if config.version != CONFIG_VERSION {
+        return Err(ConfigError::UnsupportedVersion(config.version));
+    }
+
+    let mut summary = ConfigImportSummary::default();
+    // Exported ids may differ from ids already on this machine
+    let mut account_ids = HashMap::new();
+
+    for imported in config.hosts {
+        let existing = db.get_host(&imported.host)?;
+        if existing.is_some() && strategy != ConflictStrategy::Overwrite {
+            summary.skipped += 1;
+            continue;
+        }
+        match existing {
+            Some(_) => summary.updated += 1,
+            None => summary.imported += 1,
+        }
+        db.add_host(&GitHost {
+            host: imported.host,
+            name: imported.name,
+            api_url: imported.api_url,
+            created_at: existing.map_or_else(Utc::now, |host| host.created_at),
+            oauth_client_id: imported.oauth_client_id,
+        })?;
+    }
+
+    for imported in config.accounts {
+        let existing = db.get_account_by_username(&imported.host, &imported.username)?;
+        let account = match (existing, strategy) {
+            (Some(existing), ConflictStrategy::Skip) => {
+                account_ids.insert(imported.id, existing.id);
+                summary.skipped += 1;
+                continue;
+            }
+            (Some(existing), ConflictStrategy::Overwrite) => {
+                summary.updated += 1;
+                Account {
+                    avatar_url: imported.avatar_url,
+                    auth_method: imported.auth_method,
+                    github_user_id: imported.github_user_id,
+                    display_name: imported.display_name,
+                    label: imported.label,
+                    color: imported.color,
+                    notes: imported.notes,
+                    git_name: imported.git_name,
+                    git_email: imported.git_email,
+                    signing_format: imported.signing_format,
+                    signing_key: imported.signing_key,
+                    sign_commits: imported.sign_commits,
+                    clone_dir: imported.clone_dir,
+                    ssh_key_path: imported.ssh_key_path,
+                    ssh_port_443: imported.ssh_port_443,
+                    ..existing
+                }
+            }
+            (Some(existing), ConflictStrategy::Merge) => {
+                summary.updated += 1;
+                Account {
+                    avatar_url: existing.avatar_url.or(imported.avatar_url),
+                    github_user_id: existing.github_user_id.or(imported.github_user_id),
+                    display_name: existing.display_name.or(imported.display_name),
+                    label: existing.label.or(imported.label),
+                    color: existing.color.or(imported.color),
+                    notes: existing.notes.or(imported.notes),
+                    git_name: existing.git_name.or(imported.git_name),
+                    git_email: existing.git_email.or(imported.git_email),
+                    signing_format: existing.signing_format.or(imported.signing_format),
+                    signing_key: existing.signing_key.or(imported.signing_key),
+                    clone_dir: existing.clone_dir.or(imported.clone_dir),
+                    ssh_key_path: existing.ssh_key_path.or(imported.ssh_key_path),
+                    ..existing
+                }
+            }
+            (None, _) => {
+                summary.imported += 1;
+                Account {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    username: imported.username,
+                    avatar_url: imported.avatar_url,
+                    auth_method: imported.auth_method,
+                    created_at: Utc::now(),
+                    github_user_id: imported.github_user_id,
+                    host: imported.host,
+                    last_used_at: None,
+                    use_count: 0,
+                    display_name: imported.display_name,
+                    label: imported.label,
+                    color: imported.color,
+                    notes: imported.notes,
+                    deleted_at: None,
+                    git_name: imported.git_name,
+                    git_email: imported.git_email,
+                    signing_format: imported.signing_format,
+                    signing_key: imported.signing_key,
+                    sign_commits: imported.sign_commits,
+                    clone_dir: imported.clone_dir,
+                    token_rejected_at: None,
+                    unverified: false,
+                    ssh_key_path: imported.ssh_key_path,
+                    ssh_port_443: imported.ssh_port_443,
+                }
+            }
+        };
+        db.add_account(&account)?;
+        account_ids.insert(imported.id, account.id);
+    }
+
+    let existing_mappings = db.get_repository_mappings()?;
+    for mapping in config.repository_mappings {
+        let account_id = match account_ids.get(&mapping.account_id) {
+            Some(id) => id,
+            None => continue,
+        };
+        let exists = existing_mappings
+            .iter()
+            .any(|m| m.remote_url == canonicalize_remote_url(&mapping.remote_url));
+        if exists && strategy != ConflictStrategy::Overwrite {
+            summary.skipped += 1;
+            continue;
+        }
+        // Named tokens are secrets and don't travel with the config
+        let token_name = db
+            .get_account_tokens(account_id)?
+            .into_iter()
+            .find(|t| Some(&t.name) == mapping.token_name.as_ref())
+            .map(|t| t.name);
+        db.set_repository_mapping(
+            &mapping.remote_url,
+            account_id,
+            mapping.remember,
+            token_name.as_deref(),
+        )?;
+        count(&mut summary, exists);
+    }
+
+    for mapping in config.org_mappings {
+        let account_id = match account_ids.get(&mapping.account_id) {
+            Some(id) => id,
+            None => continue,
+        };
+        let exists = db.get_org_mapping(&mapping.host, &mapping.owner)?.is_some();
+        if exists && strategy != ConflictStrategy::Overwrite {
+            summary.skipped += 1;
+            continue;
+        }
+        db.set_org_mapping(&mapping.host, &mapping.owner, account_id)?;
+        count(&mut summary, exists);
+    }
+
+    for default in config.host_defaults {
+        let account_id = match account_ids.get(&default.account_id) {
+            Some(id) => id,
+            None => continue,
+        };
+        let exists = db.get_host_default_account(&default.host)?.is_some();
+        if exists && strategy != ConflictStrategy::Overwrite {
+            summary.skipped += 1;
+            continue;
+        }
+        db.set_host_default_account(&default.host, account_id)?;
+        count(&mut summary, exists);
+    }
+
+    let existing_rules = db.get_directory_rules()?;
+    for rule in config.directory_rules {
+        let account_id = match account_ids.get(&rule.account_id) {
+            Some(id) => id,
+            None => continue,
+        };
+        let exists = existing_rules.iter().any(|r| r.pattern == rule.pattern);
+        if exists && strategy != ConflictStrategy::Overwrite {
+            summary.skipped += 1;
+            continue;
+        }
+        db.add_directory_rule(&rule.pattern, account_id)?;
+        count(&mut summary, exists);
+    }
+
+    let mut profile_ids = HashMap::new();
+    for profile in config.profiles {
+        let members: Vec<String> = profile
+            .account_ids
+            .iter()
+            .filter_map(|id| account_ids.get(id).cloned())
+            .collect();
+        let existing = db
+            .get_profiles()?
+            .into_iter()
+            .find(|p| p.name.eq_ignore_ascii_case(&profile.name));
+
+        let (local_id, members) = match (existing, strategy) {
+            (Some(existing), ConflictStrategy::Skip) => {
+                profile_ids.insert(profile.id, existing.id);
+                summary.skipped += 1;
+                continue;
+            }
+            (Some(existing), ConflictStrategy::Overwrite) => {
+                summary.updated += 1;
+                (existing.id, members)
+            }
+            (Some(existing), ConflictStrategy::Merge) => {
+                summary.updated += 1;
+                let mut merged = existing.account_ids;
+                for id in members {
+                    if !merged.contains(&id) {
+                        merged.push(id);
+                    }
+                }
+                (existing.id, merged)
+            }
+            (None, _) => {
+                summary.imported += 1;
+                (db.create_profile(&profile.name)?.id, members)
+            }
+        };
+        db.set_profile_accounts(&local_id, &members)?;
+        profile_ids.insert(profile.id, local_id);
+    }
+
+    for (key, value) in config.settings {
+        let key = match SettingKey::parse(&key) {
+            Ok(key) => key,
+            Err(_) => continue,
+        };
+        // Ids in settings refer to the exporting machine
+        let value = match (key, value.as_str()) {
+            (SettingKey::DefaultAccount, Some(id)) => match account_ids.get(id) {
+                Some(id) => serde_json::Value::from(id.as_str()),
+                None => continue,
+            },
+            (SettingKey::ActiveProfile, Some(id)) => match profile_ids.get(id) {
+                Some(id) => serde_json::Value::from(id.as_str()),
+                None => continue,
+            },
+            _ => value,
+        };
+        if key.validate(&value).is_err() {
+            continue;
+        }
+
+        let current = db.get_setting(key)?;
+        if current == value {
+            continue;
+        }
+        let apply = match strategy {
+            ConflictStrategy::Skip => false,
+            ConflictStrategy::Overwrite => true,
+            ConflictStrategy::Merge => current == key.default_value(),
+        };
+        if apply {
+            db.set_setting(key, &value)?;
+            summary.updated += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+fn count(summary: &mut ConfigImportSummary, existed: bool) {
+    if existed {
+        summary.updated += 1;
+    } else {
+        summary.imported += 1;
+    }
+}
@@ -1,7 +1,11 @@
+use crate::github_auth::DEFAULT_GITHUB_HOST;
+use crate::paths;
+use crate::remote::{canonicalize_remote_url, host_from_url};
+use crate::settings::SettingKey;
 use chrono::{DateTime, Utc};
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
@@ -11,6 +15,18 @@ pub enum DatabaseError {
     Sqlite(#[from] rusqlite::Error),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Database task failed: {0}")]
+    Task(String),
+}
+
+// Tauri commands report errors as strings, which lets closures passed to
+// Database::run use `?` on both database calls and validation messages
+impl From<DatabaseError> for String {
+    fn from(error: DatabaseError) -> Self {
+        error.to_string()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,6 +36,69 @@ pub struct Account {
     pub avatar_url: Option<String>,
     pub auth_method: String, // "device_flow" or "manual"
     pub created_at: DateTime<Utc>,
+    pub github_user_id: Option<i64>,
+    pub host: String,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub use_count: i64,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    // Set while a removed account waits to be purged
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Commit identity (user.name / user.email) used with this account
+    #[serde(default)]
+    pub git_name: Option<String>,
+    #[serde(default)]
+    pub git_email: Option<String>,
+    // Base directory new clones for this account go into
+    #[serde(default)]
+    pub clone_dir: Option<String>,
+    // Set when git reported the token as rejected, cleared once it works again
+    #[serde(default)]
+    pub token_rejected_at: Option<DateTime<Utc>>,
+    // "openpgp", "ssh" or "x509", as understood by gpg.format
+    #[serde(default)]
+    pub signing_format: Option<String>,
+    // GPG key id, or SSH public key path for the ssh format
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    #[serde(default)]
+    pub sign_commits: bool,
+    // Added while GitHub couldn't be reached; the token hasn't been checked
+    #[serde(default)]
+    pub unverified: bool,
+    // An existing private key used instead of the generated one
+    #[serde(default)]
+    pub ssh_key_path: Option<String>,
+    // SSH goes to ssh.github.com on port 443, for networks blocking port 22
+    #[serde(default)]
+    pub ssh_port_443: bool,
+}
+
+impl Account {
+    // GitHub's private noreply address, for accounts on github.com
+    pub fn noreply_email(&self) -> Option<String> {
+        match self.github_user_id {
+            Some(id) if self.host == DEFAULT_GITHUB_HOST => {
+                Some(format!("{}+{}@users.noreply.github.com", id, self.username))
+            }
+            _ => None,
+        }
+    }
+
+    // The email commits are authored with: the one set for the account,
+    // else the noreply address
+    pub fn commit_email(&self) -> Option<String> {
+        self.git_email.clone().or_else(|| self.noreply_email())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -29,243 +108,2521 @@ pub struct RepositoryMapping {
     pub account_id: String,
     pub remember: bool,
     pub created_at: DateTime<Utc>,
+    pub token_name: Option<String>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub use_count: i64,
+}
+
+// Default account for every repository under an organization or user
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrgMapping {
+    pub id: String,
+    pub host: String,
+    pub owner: String,
+    pub account_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A GitHub Enterprise Server instance accounts can belong to. github.com is
+// implied and never stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GitHost {
+    pub host: String,
+    pub name: Option<String>,
+    pub api_url: String,
+    pub created_at: DateTime<Utc>,
+    // OAuth app registered on the instance, needed to sign in with the
+    // device flow or a browser
+    pub oauth_client_id: Option<String>,
+}
+
+// Account used for repositories checked out under a local directory
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirectoryRule {
+    pub id: String,
+    pub pattern: String,
+    pub account_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Named group of accounts, e.g. "Work" or "Personal"
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub account_ids: Vec<String>,
+}
+
+// One credential helper invocation. source says how the account was picked
+// ("mapping", "directory_rule", "org_default", "host_default", "chooser").
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub repo_url: String,
+    pub host: String,
+    pub account_id: Option<String>,
+    pub username: Option<String>,
+    pub source: Option<String>,
+    pub outcome: String,
+    pub message: Option<String>,
+}
+
+pub struct NewAuditEntry<'a> {
+    pub repo_url: &'a str,
+    pub host: &'a str,
+    pub account_id: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub source: Option<&'a str>,
+    pub outcome: &'a str,
+    pub message: Option<&'a str>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct AuditLogFilter {
+    pub account_id: Option<String>,
+    // Substring match
+    pub repo_url: Option<String>,
+    pub outcome: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IntegrityIssue {
+    pub table: String,
+    // Primary key of the row, when there is a row to point at
+    pub key: Option<String>,
+    pub problem: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IntegrityReport {
+    // Output of PRAGMA integrity_check; not repairable in place
+    pub corruption: Vec<String>,
+    pub orphaned_rows: Vec<IntegrityIssue>,
+    pub invalid_timestamps: Vec<IntegrityIssue>,
+    pub healthy: bool,
+    pub repaired: bool,
+    // Taken before repairing, so a repair can be undone
+    pub backup: Option<BackupInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountToken {
+    pub id: String,
+    pub account_id: String,
+    pub name: String,
+    // Org or user a fine-grained PAT is scoped to
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// An organization an account belongs to, as last fetched from GitHub
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountOrg {
+    pub account_id: String,
+    pub login: String,
+    pub description: Option<String>,
+    pub avatar_url: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+// A file GitSwitchHub changed under ~/.ssh. The files one operation touched
+// share operation_id and are undone together.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SshConfigChange {
+    pub id: i64,
+    pub operation_id: String,
+    pub changed_at: DateTime<Utc>,
+    pub path: String,
+    // A copy of the file from before the change, None when it didn't exist
+    pub backup_path: Option<String>,
+    // Of the file after the change, None when it was removed
+    pub sha256: Option<String>,
+    pub diff: String,
+    pub undone_at: Option<DateTime<Utc>>,
+}
+
+pub struct NewSshConfigChange<'a> {
+    pub path: &'a str,
+    pub backup_path: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    pub diff: &'a str,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenMetadata {
+    pub account_id: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_validated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+const ACCOUNT_COLUMNS: &str = "id, username, avatar_url, auth_method, created_at, github_user_id, host, last_used_at, use_count, display_name, label, color, notes, deleted_at, git_name, git_email, signing_format, signing_key, sign_commits, clone_dir, token_rejected_at, unverified, ssh_key_path, ssh_port_443";
+
+fn account_from_row(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        avatar_url: row.get::<_, Option<String>>(2)?.filter(|s| !s.is_empty()),
+        auth_method: row.get(3)?,
+        created_at: parse_timestamp(row, 4)?,
+        github_user_id: row.get(5)?,
+        host: row.get(6)?,
+        last_used_at: parse_optional_timestamp(row.get(7)?),
+        use_count: row.get(8)?,
+        display_name: row.get(9)?,
+        label: row.get(10)?,
+        color: row.get(11)?,
+        notes: row.get(12)?,
+        deleted_at: parse_optional_timestamp(row.get(13)?),
+        git_name: row.get(14)?,
+        git_email: row.get(15)?,
+        signing_format: row.get(16)?,
+        signing_key: row.get(17)?,
+        sign_commits: row.get(18)?,
+        clone_dir: row.get(19)?,
+        token_rejected_at: parse_optional_timestamp(row.get(20)?),
+        unverified: row.get(21)?,
+        ssh_key_path: row.get(22)?,
+        ssh_port_443: row.get(23)?,
+    })
+}
+
+const MAPPING_COLUMNS: &str =
+    "id, remote_url, account_id, remember, created_at, token_name, last_used_at, use_count";
+
+fn mapping_from_row(row: &rusqlite::Row) -> rusqlite::Result<RepositoryMapping> {
+    Ok(RepositoryMapping {
+        id: row.get(0)?,
+        remote_url: row.get(1)?,
+        account_id: row.get(2)?,
+        // Early versions stored the flag as the text "true"/"false"
+        remember: match row.get::<_, rusqlite::types::Value>(3)? {
+            rusqlite::types::Value::Integer(value) => value != 0,
+            rusqlite::types::Value::Text(value) => value == "true",
+            _ => false,
+        },
+        created_at: parse_timestamp(row, 4)?,
+        token_name: row.get(5)?,
+        last_used_at: parse_optional_timestamp(row.get(6)?),
+        use_count: row.get(7)?,
+    })
+}
+
+const ORG_MAPPING_COLUMNS: &str = "id, host, owner, account_id, created_at";
+
+fn org_mapping_from_row(row: &rusqlite::Row) -> rusqlite::Result<OrgMapping> {
+    Ok(OrgMapping {
+        id: row.get(0)?,
+        host: row.get(1)?,
+        owner: row.get(2)?,
+        account_id: row.get(3)?,
+        created_at: parse_timestamp(row, 4)?,
+    })
+}
+
+const DIRECTORY_RULE_COLUMNS: &str = "id, pattern, account_id, created_at";
+
+fn directory_rule_from_row(row: &rusqlite::Row) -> rusqlite::Result<DirectoryRule> {
+    Ok(DirectoryRule {
+        id: row.get(0)?,
+        pattern: row.get(1)?,
+        account_id: row.get(2)?,
+        created_at: parse_timestamp(row, 3)?,
+    })
+}
+
+const ACCOUNT_TOKEN_COLUMNS: &str = "id, account_id, name, owner, created_at";
+
+fn account_token_from_row(row: &rusqlite::Row) -> rusqlite::Result<AccountToken> {
+    Ok(AccountToken {
+        id: row.get(0)?,
+        account_id: row.get(1)?,
+        name: row.get(2)?,
+        owner: row.get(3)?,
+        created_at: parse_timestamp(row, 4)?,
+    })
+}
+
+// A malformed timestamp fails the query instead of panicking; db_doctor
+// finds and repairs such rows
+fn parse_timestamp(row: &rusqlite::Row, index: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let value: String = row.get(index)?;
+    DateTime::parse_from_rfc3339(&value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(
+                index,
+                rusqlite::types::Type::Text,
+                Box::new(e),
+            )
+        })
+}
+
+const HOST_COLUMNS: &str = "host, name, api_url, created_at, oauth_client_id";
+
+fn host_from_row(row: &rusqlite::Row) -> rusqlite::Result<GitHost> {
+    Ok(GitHost {
+        host: row.get(0)?,
+        name: row.get(1)?,
+        api_url: row.get(2)?,
+        created_at: parse_timestamp(row, 3)?,
+        oauth_client_id: row.get(4)?,
+    })
+}
+
+fn ssh_config_change_from_row(row: &rusqlite::Row) -> rusqlite::Result<SshConfigChange> {
+    Ok(SshConfigChange {
+        id: row.get(0)?,
+        operation_id: row.get(1)?,
+        changed_at: parse_timestamp(row, 2)?,
+        path: row.get(3)?,
+        backup_path: row.get(4)?,
+        sha256: row.get(5)?,
+        diff: row.get(6)?,
+        undone_at: parse_optional_timestamp(row.get(7)?),
+    })
+}
+
+fn parse_optional_timestamp(value: Option<String>) -> Option<DateTime<Utc>> {
+    value
+        .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+// "*" matches any run of characters. A trailing "/*" also matches the bare
+// prefix, since Git only sends "https://host" unless credential.useHttpPath is set.
+fn mapping_pattern_matches(pattern: &str, url: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let url = url.to_lowercase();
+
+    if let Some(prefix) = pattern.strip_suffix("/*") {
+        if url == prefix {
+            return true;
+        }
+    }
+
+    wildcard_matches(&pattern, &url)
+}
+
+fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return text == pattern;
+    }
+    if !text.starts_with(first) || !text[first.len()..].ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// Directory rules use "**" for any number of directories and "*" within a
+// single directory name. A pattern without wildcards covers the directory
+// and everything below it.
+fn directory_pattern_matches(pattern: &str, path: &Path) -> bool {
+    let normalize = |s: &str| {
+        let s = s.replace('\\', "/");
+        if cfg!(windows) {
+            s.to_lowercase()
+        } else {
+            s
+        }
+    };
+    let pattern = normalize(&paths::expand_home(pattern));
+    let path = normalize(&path.to_string_lossy());
+
+    let mut pattern_segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    if !pattern.contains('*') {
+        pattern_segments.push("**");
+    }
+    let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    segments_match(&pattern_segments, &path_segments)
+}
+
+fn segments_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| segments_match(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                wildcard_matches(segment, name) && segments_match(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+// Patterns with more literal characters are more specific
+fn pattern_specificity(pattern: &str) -> usize {
+    pattern.chars().filter(|c| *c != '*').count()
+}
+
+// Older databases were created before some columns existed
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), DatabaseError> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+// Tables whose rows belong to an account (or profile) and are deleted with it
+const CASCADE_TABLES: &[&str] = &[
+    "repository_mappings",
+    "org_mappings",
+    "host_defaults",
+    "directory_rules",
+    "profile_accounts",
+    "account_tokens",
+    "token_metadata",
+    "credential_rejections",
+    "account_orgs",
+];
+
+// Bump whenever init_tables gains a migration, so existing databases are
+// backed up before it runs
+const SCHEMA_VERSION: i32 = 16;
+
+// Timestamp columns db_doctor validates: (table, column, nullable)
+const TIMESTAMP_COLUMNS: &[(&str, &str, bool)] = &[
+    ("accounts", "created_at", false),
+    ("accounts", "last_used_at", true),
+    ("accounts", "deleted_at", true),
+    ("accounts", "token_rejected_at", true),
+    ("repository_mappings", "created_at", false),
+    ("repository_mappings", "last_used_at", true),
+    ("org_mappings", "created_at", false),
+    ("hosts", "created_at", false),
+    ("directory_rules", "created_at", false),
+    ("profiles", "created_at", false),
+    ("audit_log", "timestamp", false),
+    ("account_tokens", "created_at", false),
+    ("token_metadata", "created_at", false),
+    ("token_metadata", "expires_at", true),
+    ("token_metadata", "last_validated_at", true),
+    ("replaced_helpers", "replaced_at", false),
+    ("http_path_hosts", "enabled_at", false),
+    ("credential_rejections", "rejected_at", false),
+    ("account_orgs", "fetched_at", false),
+    ("ssh_config_changes", "changed_at", false),
+    ("ssh_config_changes", "undone_at", true),
+];
+
+// Expression identifying a row of the table in reports
+fn row_key_expression(table: &str) -> &'static str {
+    match table {
+        "token_metadata" => "account_id",
+        "host_defaults" | "hosts" | "http_path_hosts" => "host",
+        "settings" => "key",
+        "replaced_helpers" => "position",
+        "profile_accounts" => "profile_id || '/' || account_id",
+        "credential_rejections" => "repo_url || ' ' || account_id",
+        "account_orgs" => "account_id || '/' || login",
+        _ => "id",
+    }
 }
 
-pub struct Database {
-    conn: Arc<Mutex<Connection>>,
-}
+const MAX_BACKUPS: usize = 10;
+const BACKUP_INTERVAL_HOURS: i64 = 24;
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+impl Database {
+    pub fn new() -> Result<Self, DatabaseError> {
+        let db_path = Self::get_db_path()?;
+        let conn = Connection::open(db_path)?;
+
+        // The GUI and credential helper processes share the file. WAL lets
+        // readers run alongside a writer, and the busy timeout makes a
+        // contended write wait instead of failing with SQLITE_BUSY.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+
+        let db = Database {
+            conn: Arc::new(Mutex::new(conn)),
+        };
+
+        let (user_version, has_tables) = {
+            let conn = db.conn.lock().unwrap();
+            let user_version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            let has_tables: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'accounts')",
+                [],
+                |row| row.get(0),
+            )?;
+            (user_version, has_tables)
+        };
+        if has_tables && user_version < SCHEMA_VERSION {
+            db.create_backup("pre-migration")?;
+        }
+
+        db.init_tables()?;
+        db.conn
+            .lock()
+            .unwrap()
+            .pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        if has_tables {
+            db.backup_if_due()?;
+        }
+        Ok(db)
+    }
+
+    // Runs queries on tokio's blocking pool, so SQLite I/O and waits on the
+    // connection lock or busy timeout never stall the async runtime that
+    // Tauri commands run on. The credential helper is a short-lived CLI and
+    // calls the blocking methods directly.
+    pub async fn run<F, T, E>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&Database) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<DatabaseError> + Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(|e| E::from(DatabaseError::Task(e.to_string())))?
+    }
+
+    fn get_backup_dir() -> Result<PathBuf, DatabaseError> {
+        let db_path = Self::get_db_path()?;
+        let backup_dir = db_path
+            .parent()
+            .map(|dir| dir.join("backups"))
+            .unwrap_or_else(|| PathBuf::from("backups"));
+        std::fs::create_dir_all(&backup_dir)?;
+        Ok(backup_dir)
+    }
+
+    // Snapshots the live database to backups/database-<timestamp>-<reason>.db
+    // and prunes the oldest copies beyond MAX_BACKUPS
+    pub fn create_backup(&self, reason: &str) -> Result<BackupInfo, DatabaseError> {
+        let now = Utc::now();
+        let name = format!(
+            "database-{}-{}.db",
+            now.format(BACKUP_TIMESTAMP_FORMAT),
+            reason
+        );
+        let path = Self::get_backup_dir()?.join(&name);
+
+        {
+            let conn = self.conn.lock().unwrap();
+            conn.backup(rusqlite::DatabaseName::Main, &path, None)?;
+        }
+        self.prune_backups()?;
+
+        Ok(BackupInfo {
+            name,
+            created_at: now,
+            size_bytes: std::fs::metadata(&path)?.len(),
+        })
+    }
+
+    // Newest first
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>, DatabaseError> {
+        let mut backups = Vec::new();
+        for entry in std::fs::read_dir(Self::get_backup_dir()?)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let timestamp = match name
+                .strip_prefix("database-")
+                .and_then(|rest| rest.split('-').next())
+            {
+                Some(timestamp) => timestamp,
+                None => continue,
+            };
+            let created_at =
+                match chrono::NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT) {
+                    Ok(created_at) => created_at.and_utc(),
+                    Err(_) => continue,
+                };
+
+            backups.push(BackupInfo {
+                name,
+                created_at,
+                size_bytes: entry.metadata()?.len(),
+            });
+        }
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+        Ok(backups)
+    }
+
+    // Replaces the live database with a backup. The current state is backed
+    // up first so a restore can itself be undone.
+    pub fn restore_backup(&self, name: &str) -> Result<(), DatabaseError> {
+        if !self
+            .list_backups()?
+            .iter()
+            .any(|backup| backup.name == name)
+        {
+            return Err(DatabaseError::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Backup {} not found", name),
+            )));
+        }
+        let path = Self::get_backup_dir()?.join(name);
+
+        self.create_backup("pre-restore")?;
+
+        let mut conn = self.conn.lock().unwrap();
+        conn.restore(
+            rusqlite::DatabaseName::Main,
+            &path,
+            None::<fn(rusqlite::backup::Progress)>,
+        )?;
+        drop(conn);
+
+        // The backup may predate newer columns
+        self.init_tables()
+    }
+
+    // Runs SQLite's integrity check and looks for rows the application cannot
+    // read: rows whose owner is gone and malformed timestamps. With repair,
+    // orphaned rows are deleted, and bad timestamps are cleared when optional
+    // or reset to now when required, after taking a backup.
+    pub fn check_integrity(&self, repair: bool) -> Result<IntegrityReport, DatabaseError> {
+        let mut report = IntegrityReport::default();
+        // (table, rowid) of orphans and (table, column, nullable, rowid) of bad timestamps
+        let mut orphans = Vec::new();
+        let mut bad_timestamps = Vec::new();
+        {
+            let conn = self.conn.lock().unwrap();
+            report.corruption = conn
+                .prepare("PRAGMA integrity_check")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|line| line != "ok")
+                .collect();
+
+            let violations: Vec<(String, Option<i64>, String)> = conn
+                .prepare("PRAGMA foreign_key_check")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?;
+            for (table, rowid, parent) in violations {
+                let key = match rowid {
+                    Some(rowid) => conn.query_row(
+                        &format!(
+                            "SELECT CAST({} AS TEXT) FROM {} WHERE rowid = ?1",
+                            row_key_expression(&table),
+                            table
+                        ),
+                        [rowid],
+                        |row| row.get(0),
+                    )?,
+                    None => None,
+                };
+                report.orphaned_rows.push(IntegrityIssue {
+                    table: table.clone(),
+                    key,
+                    problem: format!("refers to a missing row in {}", parent),
+                });
+                if let Some(rowid) = rowid {
+                    orphans.push((table, rowid));
+                }
+            }
+
+            for &(table, column, nullable) in TIMESTAMP_COLUMNS {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT rowid, CAST({} AS TEXT), {} FROM {} WHERE {} IS NOT NULL",
+                    row_key_expression(table),
+                    column,
+                    table,
+                    column
+                ))?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, rusqlite::types::Value>(2)?,
+                    ))
+                })?;
+                for row in rows {
+                    let (rowid, key, value) = row?;
+                    let problem = match &value {
+                        rusqlite::types::Value::Text(text) => {
+                            if DateTime::parse_from_rfc3339(text).is_ok() {
+                                continue;
+                            }
+                            format!("{} is not a valid timestamp: {:?}", column, text)
+                        }
+                        _ => format!("{} is not stored as text", column),
+                    };
+                    report.invalid_timestamps.push(IntegrityIssue {
+                        table: table.to_string(),
+                        key,
+                        problem,
+                    });
+                    bad_timestamps.push((table, column, nullable, rowid));
+                }
+            }
+        }
+
+        report.healthy = report.corruption.is_empty()
+            && report.orphaned_rows.is_empty()
+            && report.invalid_timestamps.is_empty();
+        if !repair || (orphans.is_empty() && bad_timestamps.is_empty()) {
+            return Ok(report);
+        }
+
+        report.backup = Some(self.create_backup("pre-repair")?);
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for (table, rowid) in &orphans {
+            tx.execute(&format!("DELETE FROM {} WHERE rowid = ?1", table), [rowid])?;
+        }
+        let now = Utc::now().to_rfc3339();
+        for (table, column, nullable, rowid) in bad_timestamps {
+            let value = if nullable { None } else { Some(now.as_str()) };
+            tx.execute(
+                &format!("UPDATE {} SET {} = ?1 WHERE rowid = ?2", table, column),
+                rusqlite::params![value, rowid],
+            )?;
+        }
+        tx.commit()?;
+        report.repaired = true;
+        Ok(report)
+    }
+
+    fn backup_if_due(&self) -> Result<(), DatabaseError> {
+        let due = match self.list_backups()?.first() {
+            Some(latest) => {
+                Utc::now() - latest.created_at > chrono::Duration::hours(BACKUP_INTERVAL_HOURS)
+            }
+            None => true,
+        };
+        if due {
+            self.create_backup("scheduled")?;
+        }
+        Ok(())
+    }
+
+    fn prune_backups(&self) -> Result<(), DatabaseError> {
+        let backup_dir = Self::get_backup_dir()?;
+        for backup in self.list_backups()?.into_iter().skip(MAX_BACKUPS) {
+            std::fs::remove_file(backup_dir.join(backup.name))?;
+        }
+        Ok(())
+    }
+
+    fn get_db_path() -> Result<PathBuf, DatabaseError> {
+        Ok(paths::app_data_dir()?.join("database.db"))
+    }
+
+    fn init_tables(&self) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+
+        // Migrations rebuild tables by dropping them, which must neither
+        // cascade nor trip foreign key checks
+        conn.pragma_update(None, "foreign_keys", false)?;
+        let result = Self::create_tables(&conn);
+        conn.pragma_update(None, "foreign_keys", true)?;
+        result
+    }
+
+    fn create_tables(conn: &Connection) -> Result<(), DatabaseError> {
+        // Create accounts table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                avatar_url TEXT,
+                auth_method TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                github_user_id INTEGER,
+                host TEXT NOT NULL DEFAULT 'github.com',
+                last_used_at TEXT,
+                use_count INTEGER NOT NULL DEFAULT 0,
+                display_name TEXT,
+                label TEXT,
+                color TEXT,
+                notes TEXT,
+                deleted_at TEXT,
+                git_name TEXT,
+                git_email TEXT,
+                signing_format TEXT,
+                signing_key TEXT,
+                sign_commits BOOLEAN NOT NULL DEFAULT 0,
+                clone_dir TEXT,
+                token_rejected_at TEXT,
+                unverified BOOLEAN NOT NULL DEFAULT 0,
+                ssh_key_path TEXT,
+                ssh_port_443 BOOLEAN NOT NULL DEFAULT 0,
+                UNIQUE (username, host)
+            )",
+            [],
+        )?;
+        add_column_if_missing(conn, "accounts", "github_user_id", "INTEGER")?;
+        Self::migrate_accounts_host(conn)?;
+        add_column_if_missing(conn, "accounts", "last_used_at", "TEXT")?;
+        add_column_if_missing(conn, "accounts", "use_count", "INTEGER NOT NULL DEFAULT 0")?;
+        for column in [
+            "display_name",
+            "label",
+            "color",
+            "notes",
+            "deleted_at",
+            "git_name",
+            "git_email",
+            "signing_format",
+            "signing_key",
+            "clone_dir",
+            "token_rejected_at",
+            "ssh_key_path",
+        ] {
+            add_column_if_missing(conn, "accounts", column, "TEXT")?;
+        }
+        add_column_if_missing(
+            conn,
+            "accounts",
+            "sign_commits",
+            "BOOLEAN NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(conn, "accounts", "unverified", "BOOLEAN NOT NULL DEFAULT 0")?;
+        add_column_if_missing(
+            conn,
+            "accounts",
+            "ssh_port_443",
+            "BOOLEAN NOT NULL DEFAULT 0",
+        )?;
+
+        // Create repository_mappings table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS repository_mappings (
+                id TEXT PRIMARY KEY,
+                remote_url TEXT NOT NULL,
+                account_id TEXT NOT NULL,
+                remember BOOLEAN NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                token_name TEXT,
+                last_used_at TEXT,
+                use_count INTEGER NOT NULL DEFAULT 0,
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+        add_column_if_missing(conn, "repository_mappings", "token_name", "TEXT")?;
+        add_column_if_missing(conn, "repository_mappings", "last_used_at", "TEXT")?;
+        add_column_if_missing(
+            conn,
+            "repository_mappings",
+            "use_count",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        Self::canonicalize_mapping_urls(conn)?;
+        Self::repair_mapping_account_ids(conn)?;
+
+        // Create org_mappings table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS org_mappings (
+                id TEXT PRIMARY KEY,
+                host TEXT NOT NULL,
+                owner TEXT NOT NULL,
+                account_id TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                UNIQUE (host, owner),
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create hosts table for GitHub Enterprise Server instances
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS hosts (
+                host TEXT PRIMARY KEY,
+                name TEXT,
+                api_url TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        add_column_if_missing(conn, "hosts", "oauth_client_id", "TEXT")?;
+        // Accounts were added for Enterprise hosts before hosts were tracked
+        conn.execute(
+            "INSERT OR IGNORE INTO hosts (host, name, api_url, created_at)
+             SELECT DISTINCT lower(host), NULL, 'https://' || lower(host) || '/api/v3', ?1
+             FROM accounts WHERE lower(host) != 'github.com'",
+            [Utc::now().to_rfc3339()],
+        )?;
+
+        // Create host_defaults table, one default account per host
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS host_defaults (
+                host TEXT PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create directory_rules table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS directory_rules (
+                id TEXT PRIMARY KEY,
+                pattern TEXT NOT NULL UNIQUE,
+                account_id TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create profiles and their account membership
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS profiles (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                created_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS profile_accounts (
+                profile_id TEXT NOT NULL,
+                account_id TEXT NOT NULL,
+                PRIMARY KEY (profile_id, account_id),
+                FOREIGN KEY (profile_id) REFERENCES profiles (id) ON DELETE CASCADE,
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create audit_log table. Entries outlive the accounts they mention,
+        // so there is no foreign key.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                repo_url TEXT NOT NULL,
+                host TEXT NOT NULL,
+                account_id TEXT,
+                username TEXT,
+                source TEXT,
+                outcome TEXT NOT NULL,
+                message TEXT
+            )",
+            [],
+        )?;
+
+        // Create account_tokens table for additional named tokens
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS account_tokens (
+                id TEXT PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                owner TEXT,
+                created_at TEXT NOT NULL,
+                UNIQUE (account_id, name),
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create settings table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Create token_metadata table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS token_metadata (
+                account_id TEXT PRIMARY KEY,
+                scopes TEXT NOT NULL DEFAULT '',
+                created_at TEXT NOT NULL,
+                expires_at TEXT,
+                last_validated_at TEXT,
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create replaced_helpers table, the credential.helper values that
+        // were configured before ours was installed, in git's order
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS replaced_helpers (
+                position INTEGER PRIMARY KEY,
+                value TEXT NOT NULL,
+                replaced_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Create http_path_hosts table, the hosts whose useHttpPath was
+        // turned on by the install rather than by the user
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS http_path_hosts (
+                host TEXT PRIMARY KEY,
+                enabled_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Create credential_rejections table, accounts the server turned
+        // down for a repository, so the next attempt tries another one
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS credential_rejections (
+                repo_url TEXT NOT NULL,
+                account_id TEXT NOT NULL,
+                rejected_at TEXT NOT NULL,
+                PRIMARY KEY (repo_url, account_id),
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create account_orgs table, a cache of each account's organizations
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS account_orgs (
+                account_id TEXT NOT NULL,
+                login TEXT NOT NULL,
+                description TEXT,
+                avatar_url TEXT,
+                fetched_at TEXT NOT NULL,
+                PRIMARY KEY (account_id, login),
+                FOREIGN KEY (account_id) REFERENCES accounts (id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+
+        // Create ssh_config_changes table, see undo_ssh_change
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ssh_config_changes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                operation_id TEXT NOT NULL,
+                changed_at TEXT NOT NULL,
+                path TEXT NOT NULL,
+                backup_path TEXT,
+                sha256 TEXT,
+                diff TEXT NOT NULL,
+                undone_at TEXT
+            )",
+            [],
+        )?;
+
+        Self::migrate_cascading_foreign_keys(conn)?;
+
+        Ok(())
+    }
+
+    // Early tables declared their foreign keys without ON DELETE CASCADE and
+    // SQLite can't alter a constraint, so rebuild them from their stored
+    // schema. Rows left behind by earlier account removals are dropped.
+    fn migrate_cascading_foreign_keys(conn: &Connection) -> Result<(), DatabaseError> {
+        for table in CASCADE_TABLES {
+            let mut stmt = conn.prepare(&format!("PRAGMA foreign_key_list({})", table))?;
+            let needs_cascade = stmt
+                .query_map([], |row| row.get::<_, String>(6))?
+                .filter_map(|on_delete| on_delete.ok())
+                .any(|on_delete| on_delete != "CASCADE");
+            if !needs_cascade {
+                continue;
+            }
+
+            let table_sql: String = conn.query_row(
+                "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [table],
+                |row| row.get(0),
+            )?;
+            let new_sql = table_sql
+                .replacen(
+                    &format!("CREATE TABLE {}", table),
+                    &format!("CREATE TABLE {}_new", table),
+                    1,
+                )
+                .replace(
+                    "REFERENCES accounts (id)",
+                    "REFERENCES accounts (id) ON DELETE CASCADE",
+                )
+                .replace(
+                    "REFERENCES profiles (id)",
+                    "REFERENCES profiles (id) ON DELETE CASCADE",
+                );
+            let mut owned = "account_id IN (SELECT id FROM accounts)".to_string();
+            if *table == "profile_accounts" {
+                owned.push_str(" AND profile_id IN (SELECT id FROM profiles)");
+            }
+
+            conn.execute_batch(&format!(
+                "BEGIN IMMEDIATE;
+                 {new_sql};
+                 INSERT INTO {table}_new SELECT * FROM {table} WHERE {owned};
+                 DROP TABLE {table};
+                 ALTER TABLE {table}_new RENAME TO {table};
+                 COMMIT;"
+            ))?;
+        }
+        Ok(())
+    }
+
+    // Mappings used to be stored with the url exactly as given. Rewrite them
+    // in canonical form, keeping the newest when several collapse into one.
+    fn canonicalize_mapping_urls(conn: &Connection) -> Result<(), DatabaseError> {
+        let mappings: Vec<(String, String)> = conn
+            .prepare("SELECT id, remote_url FROM repository_mappings ORDER BY created_at DESC")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        let mut seen = std::collections::HashSet::new();
+        for (id, remote_url) in mappings {
+            let canonical = canonicalize_remote_url(&remote_url);
+            if !seen.insert(canonical.clone()) {
+                conn.execute("DELETE FROM repository_mappings WHERE id = ?1", [&id])?;
+            } else if canonical != remote_url {
+                conn.execute(
+                    "UPDATE repository_mappings SET remote_url = ?1 WHERE id = ?2",
+                    [&canonical, &id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // The credential helper used to look mapped accounts up by username, so
+    // some mappings hold a username where the account id belongs. Point them
+    // at the account with that username on the mapping's host, or on
+    // github.com for patterns without one.
+    fn repair_mapping_account_ids(conn: &Connection) -> Result<(), DatabaseError> {
+        let mappings: Vec<(String, String, String)> = conn
+            .prepare(
+                "SELECT id, remote_url, account_id FROM repository_mappings
+                 WHERE account_id NOT IN (SELECT id FROM accounts)",
+            )?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+
+        for (id, remote_url, username) in mappings {
+            let host = host_from_url(&remote_url)
+                .filter(|host| !host.contains('*'))
+                .unwrap_or_else(|| "github.com".to_string());
+            let account_id: Option<String> = conn
+                .query_row(
+                    "SELECT id FROM accounts WHERE username = ?1 AND lower(host) = ?2",
+                    [&username, &host],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(account_id) = account_id {
+                conn.execute(
+                    "UPDATE repository_mappings SET account_id = ?1 WHERE id = ?2",
+                    [&account_id, &id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Usernames used to be globally unique; the same login can now exist on
+    // github.com and an Enterprise host, so rebuild the table with a
+    // (username, host) constraint.
+    fn migrate_accounts_host(conn: &Connection) -> Result<(), DatabaseError> {
+        let table_sql: String = conn.query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'accounts'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !table_sql.contains("username TEXT NOT NULL UNIQUE") {
+            return Ok(());
+        }
+
+        conn.execute_batch(
+            "BEGIN IMMEDIATE;
+             CREATE TABLE accounts_new (
+                id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                avatar_url TEXT,
+                auth_method TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                github_user_id INTEGER,
+                host TEXT NOT NULL DEFAULT 'github.com',
+                UNIQUE (username, host)
+             );
+             INSERT INTO accounts_new (id, username, avatar_url, auth_method, created_at, github_user_id)
+                SELECT id, username, avatar_url, auth_method, created_at, github_user_id FROM accounts;
+             DROP TABLE accounts;
+             ALTER TABLE accounts_new RENAME TO accounts;
+             COMMIT;",
+        )?;
+        Ok(())
+    }
+
+    pub fn add_account(&self, account: &Account) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        // Adding an account again replaces a removed one still waiting to be purged
+        tx.execute(
+            "DELETE FROM accounts
+             WHERE username = ?1 AND host = ?2 AND id != ?3 AND deleted_at IS NOT NULL",
+            [&account.username, &account.host, &account.id],
+        )?;
+        // An upsert rather than INSERT OR REPLACE, whose implicit delete
+        // would cascade to everything that belongs to the account
+        tx.execute(
+            "INSERT INTO accounts (id, username, avatar_url, auth_method, created_at, github_user_id, host, last_used_at, use_count, display_name, label, color, notes, deleted_at, git_name, git_email, signing_format, signing_key, sign_commits, clone_dir, token_rejected_at, unverified, ssh_key_path, ssh_port_443)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24)
+             ON CONFLICT (id) DO UPDATE SET
+                username = excluded.username,
+                avatar_url = excluded.avatar_url,
+                auth_method = excluded.auth_method,
+                created_at = excluded.created_at,
+                github_user_id = excluded.github_user_id,
+                host = excluded.host,
+                last_used_at = excluded.last_used_at,
+                use_count = excluded.use_count,
+                display_name = excluded.display_name,
+                label = excluded.label,
+                color = excluded.color,
+                notes = excluded.notes,
+                deleted_at = excluded.deleted_at,
+                git_name = excluded.git_name,
+                git_email = excluded.git_email,
+                signing_format = excluded.signing_format,
+                signing_key = excluded.signing_key,
+                sign_commits = excluded.sign_commits,
+                clone_dir = excluded.clone_dir,
+                token_rejected_at = excluded.token_rejected_at,
+                unverified = excluded.unverified,
+                ssh_key_path = excluded.ssh_key_path,
+                ssh_port_443 = excluded.ssh_port_443",
+            rusqlite::params![
+                account.id,
+                account.username,
+                account.avatar_url.as_deref().unwrap_or(""),
+                account.auth_method,
+                account.created_at.to_rfc3339(),
+                account.github_user_id,
+                account.host,
+                account.last_used_at.map(|dt| dt.to_rfc3339()),
+                account.use_count,
+                account.display_name,
+                account.label,
+                account.color,
+                account.notes,
+                account.deleted_at.map(|dt| dt.to_rfc3339()),
+                account.git_name,
+                account.git_email,
+                account.signing_format,
+                account.signing_key,
+                account.sign_commits,
+                account.clone_dir,
+                account.token_rejected_at.map(|dt| dt.to_rfc3339()),
+                account.unverified,
+                account.ssh_key_path,
+                account.ssh_port_443,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_accounts(&self) -> Result<Vec<Account>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE deleted_at IS NULL ORDER BY created_at DESC",
+            ACCOUNT_COLUMNS
+        ))?;
+
+        let account_iter = stmt.query_map([], account_from_row)?;
+
+        let mut accounts = Vec::new();
+        for account in account_iter {
+            accounts.push(account?);
+        }
+        Ok(accounts)
+    }
+
+    pub fn get_account(&self, account_id: &str) -> Result<Option<Account>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE id = ?1 AND deleted_at IS NULL",
+            ACCOUNT_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query_map([account_id], account_from_row)?;
+
+        if let Some(account) = rows.next() {
+            Ok(Some(account?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_account_by_username(
+        &self,
+        host: &str,
+        username: &str,
+    ) -> Result<Option<Account>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE username = ?1 AND host = ?2 AND deleted_at IS NULL",
+            ACCOUNT_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query_map([username, host], account_from_row)?;
+
+        if let Some(account) = rows.next() {
+            Ok(Some(account?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Updates the fields fetched from the GitHub profile
+    pub fn update_account_profile(
+        &self,
+        account_id: &str,
+        avatar_url: Option<&str>,
+        display_name: Option<&str>,
+        github_user_id: i64,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET avatar_url = ?1, display_name = ?2, github_user_id = ?3 WHERE id = ?4",
+            rusqlite::params![avatar_url, display_name, github_user_id, account_id],
+        )?;
+        Ok(())
+    }
+
+    // Updates the user-editable fields; None clears a field
+    pub fn update_account_details(
+        &self,
+        account_id: &str,
+        label: Option<&str>,
+        color: Option<&str>,
+        notes: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET label = ?1, color = ?2, notes = ?3 WHERE id = ?4",
+            rusqlite::params![label, color, notes, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_account_git_identity(
+        &self,
+        account_id: &str,
+        name: Option<&str>,
+        email: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET git_name = ?1, git_email = ?2 WHERE id = ?3",
+            rusqlite::params![name, email, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_account_signing(
+        &self,
+        account_id: &str,
+        format: Option<&str>,
+        key: Option<&str>,
+        sign_commits: bool,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET signing_format = ?1, signing_key = ?2, sign_commits = ?3 WHERE id = ?4",
+            rusqlite::params![format, key, sign_commits, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_token_rejected(
+        &self,
+        account_id: &str,
+        rejected: bool,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET token_rejected_at = ?1 WHERE id = ?2",
+            rusqlite::params![rejected.then(|| Utc::now().to_rfc3339()), account_id],
+        )?;
+        Ok(())
+    }
+
+    // Records who an unverified account's token belongs to once GitHub
+    // confirmed it
+    pub fn set_account_verified(
+        &self,
+        account_id: &str,
+        github_user_id: i64,
+        avatar_url: &str,
+        display_name: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET unverified = 0, github_user_id = ?1, avatar_url = ?2,
+                display_name = COALESCE(display_name, ?3)
+             WHERE id = ?4",
+            rusqlite::params![github_user_id, avatar_url, display_name, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_account_ssh_key(
+        &self,
+        account_id: &str,
+        ssh_key_path: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET ssh_key_path = ?1 WHERE id = ?2",
+            rusqlite::params![ssh_key_path, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_account_ssh_port_443(
+        &self,
+        account_id: &str,
+        enabled: bool,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET ssh_port_443 = ?1 WHERE id = ?2",
+            rusqlite::params![enabled, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_account_clone_dir(
+        &self,
+        account_id: &str,
+        clone_dir: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET clone_dir = ?1 WHERE id = ?2",
+            rusqlite::params![clone_dir, account_id],
+        )?;
+        Ok(())
+    }
+
+    // The account whose clone directory contains the path, the deepest
+    // directory winning when they are nested
+    pub fn find_account_by_clone_dir(&self, path: &Path) -> Result<Option<Account>, DatabaseError> {
+        let mut best: Option<(usize, Account)> = None;
+        for account in self.get_accounts()? {
+            let depth = match &account.clone_dir {
+                Some(dir) if directory_pattern_matches(dir, path) => {
+                    pattern_specificity(&paths::expand_home(dir))
+                }
+                _ => continue,
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(best_depth, _)| depth > *best_depth)
+            {
+                best = Some((depth, account));
+            }
+        }
+        Ok(best.map(|(_, account)| account))
+    }
+
+    pub fn set_account_github_user_id(
+        &self,
+        account_id: &str,
+        github_user_id: i64,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET github_user_id = ?1 WHERE id = ?2",
+            rusqlite::params![github_user_id, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_deleted_accounts(&self) -> Result<Vec<Account>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM accounts WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC",
+            ACCOUNT_COLUMNS
+        ))?;
+
+        let account_iter = stmt.query_map([], account_from_row)?;
+
+        let mut accounts = Vec::new();
+        for account in account_iter {
+            accounts.push(account?);
+        }
+        Ok(accounts)
+    }
+
+    // Hides the account but keeps its mappings and rules so it can be
+    // restored. Returns false when there is no such active account.
+    pub fn soft_delete_account(&self, account_id: &str) -> Result<bool, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE accounts SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            [Utc::now().to_rfc3339().as_str(), account_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn restore_account(&self, account_id: &str) -> Result<bool, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            "UPDATE accounts SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+            [account_id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    // Permanently deletes the account. Mappings, rules, tokens and metadata go
+    // with it through ON DELETE CASCADE.
+    pub fn remove_account(&self, account_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM accounts WHERE id = ?1", [account_id])?;
+        Ok(())
+    }
+
+    pub fn set_repository_mapping(
+        &self,
+        remote_url: &str,
+        account_id: &str,
+        remember: bool,
+        token_name: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let remote_url = canonicalize_remote_url(remote_url);
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mapping_id = uuid::Uuid::new_v4().to_string();
+        let now = Utc::now();
+
+        // Remove existing mapping for this URL
+        tx.execute(
+            "DELETE FROM repository_mappings WHERE remote_url = ?1",
+            [&remote_url],
+        )?;
+
+        // Add new mapping
+        tx.execute(
+            "INSERT INTO repository_mappings (id, remote_url, account_id, remember, created_at, token_name)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                mapping_id,
+                remote_url,
+                account_id,
+                remember,
+                now.to_rfc3339(),
+                token_name,
+            ],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_repository_mapping(
+        &self,
+        remote_url: &str,
+    ) -> Result<Option<RepositoryMapping>, DatabaseError> {
+        let remote_url = canonicalize_remote_url(remote_url);
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM repository_mappings WHERE remote_url = ?1",
+            MAPPING_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query_map([&remote_url], mapping_from_row)?;
+
+        if let Some(mapping) = rows.next() {
+            return Ok(Some(mapping?));
+        }
+
+        // No exact mapping, fall back to the most specific matching pattern so
+        // "https://github.com/my-org/*" wins over "https://github.com/*"
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM repository_mappings WHERE instr(remote_url, '*') > 0",
+            MAPPING_COLUMNS
+        ))?;
+        let mut best: Option<RepositoryMapping> = None;
+        for mapping in stmt.query_map([], mapping_from_row)? {
+            let mapping = mapping?;
+            if !mapping_pattern_matches(&mapping.remote_url, &remote_url) {
+                continue;
+            }
+            if best.as_ref().is_none_or(|b| {
+                pattern_specificity(&mapping.remote_url) > pattern_specificity(&b.remote_url)
+            }) {
+                best = Some(mapping);
+            }
+        }
+        Ok(best)
+    }
+
+    pub fn get_repository_mappings(&self) -> Result<Vec<RepositoryMapping>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM repository_mappings ORDER BY created_at DESC",
+            MAPPING_COLUMNS
+        ))?;
+
+        let mapping_iter = stmt.query_map([], mapping_from_row)?;
+
+        let mut mappings = Vec::new();
+        for mapping in mapping_iter {
+            mappings.push(mapping?);
+        }
+        Ok(mappings)
+    }
+
+    // Called by the credential helper each time it hands out credentials
+    pub fn record_account_use(&self, account_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE accounts SET last_used_at = ?1, use_count = use_count + 1 WHERE id = ?2",
+            [&Utc::now().to_rfc3339(), account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_credential_rejection(
+        &self,
+        repo_url: &str,
+        account_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO credential_rejections (repo_url, account_id, rejected_at)
+             VALUES (?1, ?2, ?3)",
+            rusqlite::params![
+                canonicalize_remote_url(repo_url),
+                account_id,
+                Utc::now().to_rfc3339()
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Ids of the accounts rejected for the repository since `since`
+    pub fn get_credential_rejections(
+        &self,
+        repo_url: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT account_id, rejected_at FROM credential_rejections WHERE repo_url = ?1",
+        )?;
+        let rows = stmt
+            .query_map([canonicalize_remote_url(repo_url)], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    parse_optional_timestamp(row.get(1)?),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows
+            .into_iter()
+            .filter(|(_, rejected_at)| rejected_at.is_some_and(|at| at >= since))
+            .map(|(account_id, _)| account_id)
+            .collect())
+    }
+
+    pub fn clear_credential_rejections(&self, repo_url: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM credential_rejections WHERE repo_url = ?1",
+            [canonicalize_remote_url(repo_url)],
+        )?;
+        Ok(())
+    }
+
+    // Replaces the cached organizations of the account
+    pub fn set_account_orgs(
+        &self,
+        account_id: &str,
+        orgs: &[AccountOrg],
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM account_orgs WHERE account_id = ?1",
+            [account_id],
+        )?;
+        for org in orgs {
+            tx.execute(
+                "INSERT INTO account_orgs (account_id, login, description, avatar_url, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![
+                    account_id,
+                    org.login,
+                    org.description,
+                    org.avatar_url,
+                    org.fetched_at.to_rfc3339(),
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_account_orgs(&self, account_id: &str) -> Result<Vec<AccountOrg>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT account_id, login, description, avatar_url, fetched_at FROM account_orgs
+             WHERE account_id = ?1 ORDER BY lower(login)",
+        )?;
+        let orgs = stmt
+            .query_map([account_id], |row| {
+                Ok(AccountOrg {
+                    account_id: row.get(0)?,
+                    login: row.get(1)?,
+                    description: row.get(2)?,
+                    avatar_url: row.get(3)?,
+                    fetched_at: parse_timestamp(row, 4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(orgs)
+    }
+
+    pub fn record_mapping_use(&self, mapping_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE repository_mappings SET last_used_at = ?1, use_count = use_count + 1 WHERE id = ?2",
+            [&Utc::now().to_rfc3339(), mapping_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_repository_mapping(&self, mapping_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM repository_mappings WHERE id = ?1",
+            [mapping_id],
+        )?;
+        Ok(())
+    }
+
+    // Owners are case-insensitive on GitHub, so keys are stored lowercased
+    pub fn set_org_mapping(
+        &self,
+        host: &str,
+        owner: &str,
+        account_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO org_mappings (id, host, owner, account_id, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            [
+                &uuid::Uuid::new_v4().to_string(),
+                &host.to_lowercase(),
+                &owner.to_lowercase(),
+                account_id,
+                &Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_org_mapping(
+        &self,
+        host: &str,
+        owner: &str,
+    ) -> Result<Option<OrgMapping>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM org_mappings WHERE host = ?1 AND owner = ?2",
+            ORG_MAPPING_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query_map(
+            [host.to_lowercase(), owner.to_lowercase()],
+            org_mapping_from_row,
+        )?;
+
+        if let Some(mapping) = rows.next() {
+            Ok(Some(mapping?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_org_mappings(&self) -> Result<Vec<OrgMapping>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM org_mappings ORDER BY host, owner",
+            ORG_MAPPING_COLUMNS
+        ))?;
+
+        let mapping_iter = stmt.query_map([], org_mapping_from_row)?;
+
+        let mut mappings = Vec::new();
+        for mapping in mapping_iter {
+            mappings.push(mapping?);
+        }
+        Ok(mappings)
+    }
+
+    pub fn remove_org_mapping(&self, mapping_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM org_mappings WHERE id = ?1", [mapping_id])?;
+        Ok(())
+    }
 
-impl Database {
-    pub fn new() -> Result<Self, DatabaseError> {
-        let db_path = Self::get_db_path()?;
-        let conn = Connection::open(db_path)?;
-        let db = Database {
-            conn: Arc::new(Mutex::new(conn)),
-        };
-        db.init_tables()?;
-        Ok(db)
+    pub fn add_host(&self, host: &GitHost) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO hosts (host, name, api_url, created_at, oauth_client_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (host) DO UPDATE SET name = excluded.name, api_url = excluded.api_url,
+                oauth_client_id = excluded.oauth_client_id",
+            rusqlite::params![
+                host.host.to_lowercase(),
+                host.name,
+                host.api_url,
+                host.created_at.to_rfc3339(),
+                host.oauth_client_id,
+            ],
+        )?;
+        Ok(())
     }
 
-    fn get_db_path() -> Result<PathBuf, DatabaseError> {
-        let home_dir = std::env::var("HOME").map_err(|_| {
-            DatabaseError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "HOME directory not found",
-            ))
-        })?;
+    pub fn get_hosts(&self) -> Result<Vec<GitHost>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare(&format!("SELECT {} FROM hosts ORDER BY host", HOST_COLUMNS))?;
+        let hosts = stmt
+            .query_map([], host_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hosts)
+    }
 
-        let app_dir = PathBuf::from(home_dir).join(".gitswitchhub");
-        std::fs::create_dir_all(&app_dir)?;
+    pub fn get_host(&self, host: &str) -> Result<Option<GitHost>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM hosts WHERE host = ?1",
+            HOST_COLUMNS
+        ))?;
+        let mut rows = stmt.query_map([host.to_lowercase()], host_from_row)?;
 
-        Ok(app_dir.join("database.db"))
+        if let Some(host) = rows.next() {
+            Ok(Some(host?))
+        } else {
+            Ok(None)
+        }
     }
 
-    fn init_tables(&self) -> Result<(), DatabaseError> {
+    pub fn remove_host(&self, host: &str) -> Result<(), DatabaseError> {
         let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM hosts WHERE host = ?1", [host.to_lowercase()])?;
+        Ok(())
+    }
 
-        // Create accounts table
+    pub fn set_host_default_account(
+        &self,
+        host: &str,
+        account_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS accounts (
-                id TEXT PRIMARY KEY,
-                username TEXT NOT NULL UNIQUE,
-                avatar_url TEXT,
-                auth_method TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-            [],
+            "INSERT OR REPLACE INTO host_defaults (host, account_id, updated_at) VALUES (?1, ?2, ?3)",
+            [&host.to_lowercase(), account_id, &Utc::now().to_rfc3339()],
         )?;
+        Ok(())
+    }
 
-        // Create repository_mappings table
+    pub fn get_host_default_account(&self, host: &str) -> Result<Option<String>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT account_id FROM host_defaults WHERE host = ?1")?;
+        let mut rows = stmt.query_map([host.to_lowercase()], |row| row.get::<_, String>(0))?;
+
+        if let Some(account_id) = rows.next() {
+            Ok(Some(account_id?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn get_host_default_accounts(&self) -> Result<Vec<(String, String)>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT host, account_id FROM host_defaults ORDER BY host")?;
+        let default_iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut defaults = Vec::new();
+        for default in default_iter {
+            defaults.push(default?);
+        }
+        Ok(defaults)
+    }
+
+    pub fn clear_host_default_account(&self, host: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS repository_mappings (
-                id TEXT PRIMARY KEY,
-                remote_url TEXT NOT NULL,
-                account_id TEXT NOT NULL,
-                remember BOOLEAN NOT NULL DEFAULT 0,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (account_id) REFERENCES accounts (id)
-            )",
-            [],
+            "DELETE FROM host_defaults WHERE host = ?1",
+            [host.to_lowercase()],
         )?;
-
         Ok(())
     }
 
-    pub fn add_account(&self, account: &Account) -> Result<(), DatabaseError> {
+    pub fn add_directory_rule(&self, pattern: &str, account_id: &str) -> Result<(), DatabaseError> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT OR REPLACE INTO accounts (id, username, avatar_url, auth_method, created_at)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
+            "INSERT OR REPLACE INTO directory_rules (id, pattern, account_id, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
             [
-                &account.id,
-                &account.username,
-                &account.avatar_url.as_deref().unwrap_or("").to_string(),
-                &account.auth_method,
-                &account.created_at.to_rfc3339(),
+                &uuid::Uuid::new_v4().to_string(),
+                pattern,
+                account_id,
+                &Utc::now().to_rfc3339(),
             ],
         )?;
         Ok(())
     }
 
-    pub fn get_accounts(&self) -> Result<Vec<Account>, DatabaseError> {
+    pub fn get_directory_rules(&self) -> Result<Vec<DirectoryRule>, DatabaseError> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, username, avatar_url, auth_method, created_at FROM accounts ORDER BY created_at DESC"
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM directory_rules ORDER BY pattern",
+            DIRECTORY_RULE_COLUMNS
+        ))?;
+
+        let rule_iter = stmt.query_map([], directory_rule_from_row)?;
+
+        let mut rules = Vec::new();
+        for rule in rule_iter {
+            rules.push(rule?);
+        }
+        Ok(rules)
+    }
+
+    // Most specific rule covering the path, if any
+    pub fn find_directory_rule(&self, path: &Path) -> Result<Option<DirectoryRule>, DatabaseError> {
+        let mut best: Option<DirectoryRule> = None;
+        for rule in self.get_directory_rules()? {
+            if !directory_pattern_matches(&rule.pattern, path) {
+                continue;
+            }
+            if best.as_ref().is_none_or(|b| {
+                pattern_specificity(&rule.pattern) > pattern_specificity(&b.pattern)
+            }) {
+                best = Some(rule);
+            }
+        }
+        Ok(best)
+    }
+
+    pub fn remove_directory_rule(&self, rule_id: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM directory_rules WHERE id = ?1", [rule_id])?;
+        Ok(())
+    }
+
+    pub fn create_profile(&self, name: &str) -> Result<Profile, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let profile = Profile {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            created_at: Utc::now(),
+            account_ids: Vec::new(),
+        };
+        conn.execute(
+            "INSERT INTO profiles (id, name, created_at) VALUES (?1, ?2, ?3)",
+            [&profile.id, &profile.name, &profile.created_at.to_rfc3339()],
         )?;
+        Ok(profile)
+    }
 
-        let account_iter = stmt.query_map([], |row| {
-            Ok(Account {
+    pub fn get_profiles(&self) -> Result<Vec<Profile>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, created_at FROM profiles ORDER BY name")?;
+        let profile_iter = stmt.query_map([], |row| {
+            Ok(Profile {
                 id: row.get(0)?,
-                username: row.get(1)?,
-                avatar_url: row.get::<_, Option<String>>(2)?.filter(|s| !s.is_empty()),
-                auth_method: row.get(3)?,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
+                name: row.get(1)?,
+                created_at: parse_timestamp(row, 2)?,
+                account_ids: Vec::new(),
             })
         })?;
 
-        let mut accounts = Vec::new();
-        for account in account_iter {
-            accounts.push(account?);
+        let mut members =
+            conn.prepare("SELECT account_id FROM profile_accounts WHERE profile_id = ?1")?;
+        let mut profiles = Vec::new();
+        for profile in profile_iter {
+            let mut profile = profile?;
+            for account_id in members.query_map([&profile.id], |row| row.get(0))? {
+                profile.account_ids.push(account_id?);
+            }
+            profiles.push(profile);
         }
-        Ok(accounts)
+        Ok(profiles)
     }
 
-    pub fn get_account_by_username(
+    pub fn get_profile(&self, profile_id: &str) -> Result<Option<Profile>, DatabaseError> {
+        Ok(self
+            .get_profiles()?
+            .into_iter()
+            .find(|profile| profile.id == profile_id))
+    }
+
+    pub fn set_profile_accounts(
         &self,
-        username: &str,
-    ) -> Result<Option<Account>, DatabaseError> {
+        profile_id: &str,
+        account_ids: &[String],
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM profile_accounts WHERE profile_id = ?1",
+            [profile_id],
+        )?;
+        for account_id in account_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO profile_accounts (profile_id, account_id) VALUES (?1, ?2)",
+                [profile_id, account_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn delete_profile(&self, profile_id: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM profile_accounts WHERE profile_id = ?1",
+            [profile_id],
+        )?;
+        tx.execute("DELETE FROM profiles WHERE id = ?1", [profile_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn add_audit_entry(&self, entry: &NewAuditEntry) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO audit_log (timestamp, repo_url, host, account_id, username, source, outcome, message)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![
+                Utc::now().to_rfc3339(),
+                entry.repo_url,
+                entry.host,
+                entry.account_id,
+                entry.username,
+                entry.source,
+                entry.outcome,
+                entry.message,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_ssh_config_changes(
+        &self,
+        operation_id: &str,
+        changes: &[NewSshConfigChange],
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = Utc::now().to_rfc3339();
+        for change in changes {
+            tx.execute(
+                "INSERT INTO ssh_config_changes (operation_id, changed_at, path, backup_path, sha256, diff)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    operation_id,
+                    now,
+                    change.path,
+                    change.backup_path,
+                    change.sha256,
+                    change.diff,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Newest first
+    pub fn get_ssh_config_changes(
+        &self,
+        limit: u32,
+    ) -> Result<Vec<SshConfigChange>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, operation_id, changed_at, path, backup_path, sha256, diff, undone_at
+             FROM ssh_config_changes ORDER BY id DESC LIMIT {}",
+            limit
+        ))?;
+        let change_iter = stmt.query_map([], ssh_config_change_from_row)?;
+
+        let mut changes = Vec::new();
+        for change in change_iter {
+            changes.push(change?);
+        }
+        Ok(changes)
+    }
+
+    // The changes of one operation, or of the latest one not yet undone
+    pub fn get_ssh_config_operation(
+        &self,
+        operation_id: Option<&str>,
+    ) -> Result<Vec<SshConfigChange>, DatabaseError> {
         let conn = self.conn.lock().unwrap();
+        let operation_id: Option<String> = match operation_id {
+            Some(id) => Some(id.to_string()),
+            None => conn
+                .query_row(
+                    "SELECT operation_id FROM ssh_config_changes
+                     WHERE undone_at IS NULL ORDER BY id DESC LIMIT 1",
+                    [],
+                    |row| row.get(0),
+                )
+                .optional()?,
+        };
+        let Some(operation_id) = operation_id else {
+            return Ok(Vec::new());
+        };
+
         let mut stmt = conn.prepare(
-            "SELECT id, username, avatar_url, auth_method, created_at FROM accounts WHERE username = ?1"
+            "SELECT id, operation_id, changed_at, path, backup_path, sha256, diff, undone_at
+             FROM ssh_config_changes WHERE operation_id = ?1 ORDER BY id",
+        )?;
+        let change_iter = stmt.query_map([&operation_id], ssh_config_change_from_row)?;
+
+        let mut changes = Vec::new();
+        for change in change_iter {
+            changes.push(change?);
+        }
+        Ok(changes)
+    }
+
+    pub fn mark_ssh_config_operation_undone(
+        &self,
+        operation_id: &str,
+    ) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE ssh_config_changes SET undone_at = ?1 WHERE operation_id = ?2",
+            rusqlite::params![Utc::now().to_rfc3339(), operation_id],
         )?;
+        Ok(())
+    }
+
+    // Newest first
+    pub fn get_audit_log(
+        &self,
+        filter: &AuditLogFilter,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let mut conditions = Vec::new();
+        let mut params: Vec<String> = Vec::new();
+        if let Some(account_id) = &filter.account_id {
+            params.push(account_id.clone());
+            conditions.push(format!("account_id = ?{}", params.len()));
+        }
+        if let Some(repo_url) = &filter.repo_url {
+            params.push(repo_url.clone());
+            conditions.push(format!("instr(repo_url, ?{}) > 0", params.len()));
+        }
+        if let Some(outcome) = &filter.outcome {
+            params.push(outcome.clone());
+            conditions.push(format!("outcome = ?{}", params.len()));
+        }
+        if let Some(since) = &filter.since {
+            params.push(since.to_rfc3339());
+            conditions.push(format!("timestamp >= ?{}", params.len()));
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, timestamp, repo_url, host, account_id, username, source, outcome, message
+             FROM audit_log {} ORDER BY id DESC LIMIT {} OFFSET {}",
+            where_clause, limit, offset
+        ))?;
 
-        let mut rows = stmt.query_map([username], |row| {
-            Ok(Account {
+        let entry_iter = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok(AuditEntry {
                 id: row.get(0)?,
-                username: row.get(1)?,
-                avatar_url: row.get::<_, Option<String>>(2)?.filter(|s| !s.is_empty()),
-                auth_method: row.get(3)?,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
+                timestamp: parse_timestamp(row, 1)?,
+                repo_url: row.get(2)?,
+                host: row.get(3)?,
+                account_id: row.get(4)?,
+                username: row.get(5)?,
+                source: row.get(6)?,
+                outcome: row.get(7)?,
+                message: row.get(8)?,
             })
         })?;
 
-        if let Some(account) = rows.next() {
-            Ok(Some(account?))
-        } else {
-            Ok(None)
+        let mut entries = Vec::new();
+        for entry in entry_iter {
+            entries.push(entry?);
         }
+        Ok(entries)
     }
 
-    pub fn remove_account(&self, account_id: &str) -> Result<(), DatabaseError> {
+    pub fn add_account_token(&self, token: &AccountToken) -> Result<(), DatabaseError> {
         let conn = self.conn.lock().unwrap();
-
-        // Remove repository mappings first
         conn.execute(
-            "DELETE FROM repository_mappings WHERE account_id = ?1",
-            [account_id],
+            "INSERT OR REPLACE INTO account_tokens (id, account_id, name, owner, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                token.id,
+                token.account_id,
+                token.name,
+                token.owner,
+                token.created_at.to_rfc3339(),
+            ],
         )?;
+        Ok(())
+    }
 
-        // Remove account
-        conn.execute("DELETE FROM accounts WHERE id = ?1", [account_id])?;
+    pub fn get_account_tokens(&self, account_id: &str) -> Result<Vec<AccountToken>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM account_tokens WHERE account_id = ?1 ORDER BY name",
+            ACCOUNT_TOKEN_COLUMNS
+        ))?;
+
+        let token_iter = stmt.query_map([account_id], account_token_from_row)?;
+
+        let mut tokens = Vec::new();
+        for token in token_iter {
+            tokens.push(token?);
+        }
+        Ok(tokens)
+    }
 
+    pub fn remove_account_token(&self, account_id: &str, name: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM account_tokens WHERE account_id = ?1 AND name = ?2",
+            [account_id, name],
+        )?;
+        // Mappings pinned to this token fall back to the account's default token
+        tx.execute(
+            "UPDATE repository_mappings SET token_name = NULL WHERE account_id = ?1 AND token_name = ?2",
+            [account_id, name],
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn set_repository_mapping(
+    // Returns the stored value, or the key's default when it was never set
+    pub fn get_setting(&self, key: SettingKey) -> Result<serde_json::Value, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
+        let mut rows = stmt.query_map([key.as_str()], |row| row.get::<_, String>(0))?;
+
+        match rows.next() {
+            Some(value) => Ok(serde_json::from_str(&value?)?),
+            None => Ok(key.default_value()),
+        }
+    }
+
+    pub fn set_setting(
         &self,
-        remote_url: &str,
-        account_id: &str,
-        remember: bool,
+        key: SettingKey,
+        value: &serde_json::Value,
     ) -> Result<(), DatabaseError> {
         let conn = self.conn.lock().unwrap();
-        let mapping_id = uuid::Uuid::new_v4().to_string();
-        let now = Utc::now();
+        conn.execute(
+            "INSERT OR REPLACE INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)",
+            [
+                key.as_str(),
+                &serde_json::to_string(value)?,
+                &Utc::now().to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
 
-        // Remove existing mapping for this URL
+    pub fn get_all_settings(&self) -> Result<Vec<(SettingKey, serde_json::Value)>, DatabaseError> {
+        let mut settings = Vec::new();
+        for key in SettingKey::ALL {
+            settings.push((*key, self.get_setting(*key)?));
+        }
+        Ok(settings)
+    }
+
+    pub fn set_replaced_helpers(&self, helpers: &[String]) -> Result<(), DatabaseError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute("DELETE FROM replaced_helpers", [])?;
+        let now = Utc::now().to_rfc3339();
+        for (position, helper) in helpers.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO replaced_helpers (position, value, replaced_at) VALUES (?1, ?2, ?3)",
+                rusqlite::params![position as i64, helper, now],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_replaced_helpers(&self) -> Result<Vec<String>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT value FROM replaced_helpers ORDER BY position")?;
+        let helpers = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(helpers)
+    }
+
+    pub fn add_http_path_host(&self, host: &str) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "DELETE FROM repository_mappings WHERE remote_url = ?1",
-            [remote_url],
+            "INSERT OR IGNORE INTO http_path_hosts (host, enabled_at) VALUES (?1, ?2)",
+            rusqlite::params![host, Utc::now().to_rfc3339()],
         )?;
+        Ok(())
+    }
 
-        // Add new mapping
+    pub fn get_http_path_hosts(&self) -> Result<Vec<String>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT host FROM http_path_hosts ORDER BY host")?;
+        let hosts = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hosts)
+    }
+
+    pub fn clear_http_path_hosts(&self) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM http_path_hosts", [])?;
+        Ok(())
+    }
+
+    pub fn set_token_metadata(&self, metadata: &TokenMetadata) -> Result<(), DatabaseError> {
+        let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT INTO repository_mappings (id, remote_url, account_id, remember, created_at)
+            "INSERT OR REPLACE INTO token_metadata (account_id, scopes, created_at, expires_at, last_validated_at)
              VALUES (?1, ?2, ?3, ?4, ?5)",
-            [
-                &mapping_id,
-                remote_url,
-                account_id,
-                &remember.to_string(),
-                &now.to_rfc3339(),
+            rusqlite::params![
+                metadata.account_id,
+                metadata.scopes.join(","),
+                metadata.created_at.to_rfc3339(),
+                metadata.expires_at.map(|d| d.to_rfc3339()),
+                metadata.last_validated_at.map(|d| d.to_rfc3339()),
             ],
         )?;
-
         Ok(())
     }
 
-    pub fn get_repository_mapping(
+    pub fn get_token_metadata(
         &self,
-        remote_url: &str,
-    ) -> Result<Option<RepositoryMapping>, DatabaseError> {
+        account_id: &str,
+    ) -> Result<Option<TokenMetadata>, DatabaseError> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, remote_url, account_id, remember, created_at FROM repository_mappings WHERE remote_url = ?1"
+            "SELECT account_id, scopes, created_at, expires_at, last_validated_at FROM token_metadata WHERE account_id = ?1"
         )?;
 
-        let mut rows = stmt.query_map([remote_url], |row| {
-            Ok(RepositoryMapping {
-                id: row.get(0)?,
-                remote_url: row.get(1)?,
-                account_id: row.get(2)?,
-                remember: row.get::<_, i64>(3)? != 0,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
+        let mut rows = stmt.query_map([account_id], |row| {
+            Ok(TokenMetadata {
+                account_id: row.get(0)?,
+                scopes: row
+                    .get::<_, String>(1)?
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect(),
+                created_at: parse_timestamp(row, 2)?,
+                expires_at: row
+                    .get::<_, Option<String>>(3)?
+                    .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+                    .map(|d| d.with_timezone(&Utc)),
+                last_validated_at: row
+                    .get::<_, Option<String>>(4)?
+                    .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+                    .map(|d| d.with_timezone(&Utc)),
             })
         })?;
 
-        if let Some(mapping) = rows.next() {
-            Ok(Some(mapping?))
+        if let Some(metadata) = rows.next() {
+            Ok(Some(metadata?))
         } else {
             Ok(None)
         }
     }
 
-    pub fn get_repository_mappings(&self) -> Result<Vec<RepositoryMapping>, DatabaseError> {
+    pub fn touch_token_validated(&self, account_id: &str) -> Result<(), DatabaseError> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, remote_url, account_id, remember, created_at FROM repository_mappings ORDER BY created_at DESC"
+        conn.execute(
+            "UPDATE token_metadata SET last_validated_at = ?1 WHERE account_id = ?2",
+            [&Utc::now().to_rfc3339(), account_id],
         )?;
+        Ok(())
+    }
+}
 
-        let mapping_iter = stmt.query_map([], |row| {
-            Ok(RepositoryMapping {
-                id: row.get(0)?,
-                remote_url: row.get(1)?,
-                account_id: row.get(2)?,
-                remember: row.get::<_, i64>(3)? != 0,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
-            })
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut mappings = Vec::new();
-        for mapping in mapping_iter {
-            mappings.push(mapping?);
+    #[test]
+    fn mapping_patterns_match_urls() {
+        let cases = [
+            (
+                "https://github.com/org/repo",
+                "https://github.com/org/repo",
+                true,
+            ),
+            (
+                "https://github.com/org/repo",
+                "https://github.com/org/other",
+                false,
+            ),
+            (
+                "https://github.com/Org/*",
+                "https://github.com/org/repo",
+                true,
+            ),
+            (
+                "https://github.com/org/*",
+                "https://github.com/other/repo",
+                false,
+            ),
+            // Git only sends the host without credential.useHttpPath
+            ("https://github.com/org/*", "https://github.com/org", true),
+            ("https://github.com/*", "https://github.com", true),
+            (
+                "https://github.com/org/api-*",
+                "https://github.com/org/api-server",
+                true,
+            ),
+            (
+                "https://github.com/org/api-*",
+                "https://github.com/org/web",
+                false,
+            ),
+            (
+                "https://github.com/*/docs",
+                "https://github.com/org/docs",
+                true,
+            ),
+            (
+                "https://github.com/*/docs",
+                "https://github.com/org/docs-site",
+                false,
+            ),
+            (
+                "https://*.example.com/*",
+                "https://ghe.example.com/org/repo",
+                true,
+            ),
+            ("https://github.com/*-*-x", "https://github.com/a-b-x", true),
+            ("https://github.com/*-*-x", "https://github.com/ab-x", false),
+            ("https://github.com/ab*ba", "https://github.com/aba", false),
+        ];
+        for (pattern, url, expected) in cases {
+            assert_eq!(
+                mapping_pattern_matches(pattern, url),
+                expected,
+                "{} against {}",
+                pattern,
+                url
+            );
         }
-        Ok(mappings)
     }
 
-    pub fn remove_repository_mapping(&self, mapping_id: &str) -> Result<(), DatabaseError> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "DELETE FROM repository_mappings WHERE id = ?1",
-            [mapping_id],
-        )?;
-        Ok(())
+    #[test]
+    fn longer_literals_are_more_specific() {
+        assert!(
+            pattern_specificity("https://github.com/org/*")
+                > pattern_specificity("https://github.com/*")
+        );
+        assert_eq!(pattern_specificity("*"), 0);
+    }
+
+    #[test]
+    fn wildcard_matches_within_text() {
+        assert!(wildcard_matches("work", "work"));
+        assert!(!wildcard_matches("work", "works"));
+        assert!(wildcard_matches("*", ""));
+        assert!(wildcard_matches("client-*", "client-acme"));
+        assert!(wildcard_matches("*-api", "billing-api"));
+        assert!(wildcard_matches("a*b*c", "aXbYc"));
+        assert!(!wildcard_matches("a*b*c", "aXcYb"));
+        assert!(!wildcard_matches("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn directory_patterns_match_paths() {
+        let cases = [
+            // A plain directory covers everything below it
+            ("/home/me/work", "/home/me/work", true),
+            ("/home/me/work", "/home/me/work/acme/api", true),
+            ("/home/me/work/", "/home/me/work/acme", true),
+            ("/home/me/work", "/home/me/workshop", false),
+            // "*" stays within one directory name
+            ("/home/me/*/acme", "/home/me/work/acme", true),
+            ("/home/me/*/acme", "/home/me/work/x/acme", false),
+            ("/home/me/client-*", "/home/me/client-acme", true),
+            ("/home/me/client-*", "/home/me/client-acme/api", false),
+            // "**" spans any number of directories, none included
+            ("/home/me/**/acme", "/home/me/acme", true),
+            ("/home/me/**/acme", "/home/me/a/b/acme", true),
+            ("/home/me/work/**", "/home/me/work/a/b", true),
+            ("/home/me/**/acme", "/home/me/a/b/other", false),
+        ];
+        for (pattern, path, expected) in cases {
+            assert_eq!(
+                directory_pattern_matches(pattern, Path::new(path)),
+                expected,
+                "{} against {}",
+                pattern,
+                path
+            );
+        }
     }
 }
@@ -0,0 +1,137 @@
+use crate::paths;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use thiserror::Error;
+
+// Reads the accounts the GitHub CLI is logged in to. gh lists them in
+// hosts.yml:
+//   github.com:
+//       users:
+//           octocat:
+//               oauth_token: gho_...
+//       user: octocat
+// The token is only there when gh was told to store it in plain text;
+// otherwise it is in the system keyring, which gh itself reads for us.
+
+#[derive(Error, Debug)]
+pub enum GhImportError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} can't be read: {1}")]
+    InvalidConfig(String, String),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GhHost {
+    // The active user
+    #[serde(default)]
+    user: Option<String>,
+    // Written by gh versions from before multiple accounts
+    #[serde(default)]
+    oauth_token: Option<String>,
+    #[serde(default)]
+    users: BTreeMap<String, Option<GhUser>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GhUser {
+    #[serde(default)]
+    oauth_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GhLogin {
+    pub host: String,
+    pub username: String,
+    // Err explains why the token couldn't be read
+    pub token: Result<String, String>,
+}
+
+// $GH_CONFIG_DIR, else gh's directory under the user's config directory
+pub fn config_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("GH_CONFIG_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = std::env::var_os("AppData").filter(|dir| !dir.is_empty()) {
+            return Ok(PathBuf::from(dir).join("GitHub CLI"));
+        }
+    }
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir).join("gh"));
+    }
+    Ok(paths::home_dir()?.join(".config").join("gh"))
+}
+
+// Every host and user gh has logged in, with the token where it can be read.
+// None when gh has no hosts.yml.
+pub fn read_logins() -> Result<Option<Vec<GhLogin>>, GhImportError> {
+    let path = config_dir()?.join("hosts.yml");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let hosts: Option<BTreeMap<String, Option<GhHost>>> = serde_yaml::from_str(&content)
+        .map_err(|e| GhImportError::InvalidConfig(path.display().to_string(), e.to_string()))?;
+
+    let mut logins = Vec::new();
+    for (host, entry) in hosts.unwrap_or_default() {
+        let entry = entry.unwrap_or_default();
+        let mut users: Vec<(String, Option<String>)> = entry
+            .users
+            .into_iter()
+            .map(|(username, user)| (username, user.and_then(|user| user.oauth_token)))
+            .collect();
+        if let Some(active) = &entry.user {
+            match users.iter_mut().find(|(username, _)| username == active) {
+                Some((_, token)) if token.is_none() => token.clone_from(&entry.oauth_token),
+                Some(_) => {}
+                None => users.push((active.clone(), entry.oauth_token.clone())),
+            }
+        }
+
+        for (username, token) in users {
+            let token = match token.filter(|token| !token.is_empty()) {
+                Some(token) => Ok(token),
+                None => keyring_token(&host, &username),
+            };
+            logins.push(GhLogin {
+                host: host.clone(),
+                username,
+                token,
+            });
+        }
+    }
+    Ok(Some(logins))
+}
+
+// Asks gh for a token it keeps in the keyring. The token variables are
+// cleared, or gh would hand those back instead.
+fn keyring_token(host: &str, username: &str) -> Result<String, String> {
+    let output = Command::new("gh")
+        .args(["auth", "token", "--hostname", host, "--user", username])
+        .env_remove("GH_TOKEN")
+        .env_remove("GITHUB_TOKEN")
+        .env_remove("GH_ENTERPRISE_TOKEN")
+        .env_remove("GITHUB_ENTERPRISE_TOKEN")
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                "the token is in the system keyring and gh isn't installed to read it".to_string()
+            }
+            _ => format!("gh can't be run: {}", e),
+        })?;
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || token.is_empty() {
+        return Err(format!(
+            "gh can't read the token: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(token)
+}
@@ -0,0 +1,431 @@
+use crate::paths;
+use crate::remote;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GitConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Git config failed: {0}")]
+    Git(String),
+    #[error("Invalid scope: {0}")]
+    InvalidScope(String),
+}
+
+// Where a git config value is read from or written to
+#[derive(Debug, Clone)]
+pub enum GitConfigScope {
+    Global,
+    // The .git/config of the repository at this path
+    Repository(PathBuf),
+    // A config file of our own, pulled in with include or includeIf
+    File(PathBuf),
+}
+
+impl GitConfigScope {
+    // "global", or "local" together with a repository path
+    pub fn parse(scope: &str, repo_path: Option<&str>) -> Result<Self, GitConfigError> {
+        match (scope, repo_path) {
+            ("global", _) => Ok(GitConfigScope::Global),
+            ("local", Some(path)) if !path.is_empty() => {
+                Ok(GitConfigScope::Repository(PathBuf::from(path)))
+            }
+            ("local", _) => Err(GitConfigError::InvalidScope(
+                "the local scope needs a repository path".to_string(),
+            )),
+            (other, _) => Err(GitConfigError::InvalidScope(format!(
+                "expected \"global\" or \"local\", got \"{}\"",
+                other
+            ))),
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new("git");
+        match self {
+            GitConfigScope::Global => {
+                command.args(["config", "--global"]);
+            }
+            GitConfigScope::Repository(path) => {
+                command.arg("-C").arg(path).args(["config", "--local"]);
+            }
+            GitConfigScope::File(path) => {
+                command.args(["config", "--file"]).arg(path);
+            }
+        }
+        command
+    }
+}
+
+// A value together with where git read it from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigEntry {
+    // "system", "global", "local", "worktree" or "command"
+    pub scope: String,
+    // e.g. "file:/home/me/.gitconfig"
+    pub origin: String,
+    pub key: String,
+    pub value: String,
+}
+
+// Every entry whose key matches `pattern`, in the order git reads them:
+// system, global and, inside a repository, local config
+pub fn get_entries(
+    repo_path: Option<&Path>,
+    pattern: &str,
+) -> Result<Vec<ConfigEntry>, GitConfigError> {
+    let mut command = Command::new("git");
+    if let Some(path) = repo_path {
+        command.arg("-C").arg(path);
+    }
+    let output = command
+        .args([
+            "config",
+            "--show-scope",
+            "--show-origin",
+            "-z",
+            "--get-regexp",
+            pattern,
+        ])
+        .output()?;
+    match output.status.code() {
+        Some(0) => {}
+        Some(1) => return Ok(Vec::new()),
+        _ => {
+            return Err(GitConfigError::Git(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    }
+
+    // Each entry is "scope\0origin\0key\nvalue\0", or "key\0" without a value
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.split('\0');
+    let mut entries = Vec::new();
+    while let (Some(scope), Some(origin), Some(pair)) =
+        (fields.next(), fields.next(), fields.next())
+    {
+        let (key, value) = pair.split_once('\n').unwrap_or((pair, ""));
+        entries.push(ConfigEntry {
+            scope: scope.to_string(),
+            origin: origin.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+pub fn get_value(scope: &GitConfigScope, key: &str) -> Result<Option<String>, GitConfigError> {
+    let output = scope.command().args(["--get", key]).output()?;
+    // Exit code 1 means the key is not set
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )),
+        Some(1) => Ok(None),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+// The url git fetches a remote from once insteadOf rules are applied
+pub fn remote_get_url(repo_path: &Path, remote: &str) -> Result<String, GitConfigError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["ls-remote", "--get-url", remote])
+        .output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Clones without ever prompting: there is no terminal to prompt on, and
+// credentials come from the helper or the SSH config
+pub fn clone_repository(url: &str, destination: &Path) -> Result<(), GitConfigError> {
+    let output = Command::new("git")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .args(["clone", "--", url])
+        .arg(destination)
+        .output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// "2.39.2" from "git version 2.39.2 (Apple Git-143)" or
+// "git version 2.41.0.windows.1"
+pub fn git_version() -> Result<String, GitConfigError> {
+    let output = Command::new("git").arg("--version").output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .split_whitespace()
+        .nth(2)
+        .map(|version| version.to_string())
+        .ok_or_else(|| GitConfigError::Git(format!("unexpected output: {}", stdout.trim())))
+}
+
+// The working tree `dir` is inside, None when it isn't in a repository
+pub fn toplevel(dir: &Path) -> Result<Option<PathBuf>, GitConfigError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!path.is_empty()).then(|| PathBuf::from(path)))
+}
+
+// Where the repository's hooks go, following core.hooksPath
+pub fn hooks_dir(repo_path: &Path) -> Result<PathBuf, GitConfigError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    // Relative paths are relative to the directory git ran in
+    Ok(repo_path.join(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+// Whether one of the repository's remotes is the repository `url` names,
+// whatever its spelling or SSH alias
+pub fn has_remote(repo_path: &Path, url: &str) -> Result<bool, GitConfigError> {
+    let Some((wanted, _)) = remote::mapping_target(url) else {
+        return Ok(false);
+    };
+    Ok(get_entries(Some(repo_path), r"^remote\..*\.url$")?
+        .iter()
+        .filter(|entry| entry.scope == "local")
+        .any(|entry| remote::mapping_target(&entry.value).is_some_and(|(url, _)| url == wanted)))
+}
+
+// Every value of a multi-valued key such as credential.helper, in order
+pub fn get_all_values(scope: &GitConfigScope, key: &str) -> Result<Vec<String>, GitConfigError> {
+    let output = scope.command().args(["--get-all", key]).output()?;
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.to_string())
+            .collect()),
+        Some(1) => Ok(Vec::new()),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+// (key, value) of every entry in the scope whose key matches `pattern`.
+// Section and variable names come back lowercased, subsections as written.
+pub fn get_regexp(
+    scope: &GitConfigScope,
+    pattern: &str,
+) -> Result<Vec<(String, String)>, GitConfigError> {
+    let output = scope
+        .command()
+        .args(["-z", "--get-regexp", pattern])
+        .output()?;
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (key, value) = entry.split_once('\n').unwrap_or((entry, ""));
+                (key.to_string(), value.to_string())
+            })
+            .collect()),
+        Some(1) => Ok(Vec::new()),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+pub fn set_value(scope: &GitConfigScope, key: &str, value: &str) -> Result<(), GitConfigError> {
+    let output = scope.command().args([key, value]).output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Sets whichever of user.name and user.email are given. False when
+// neither was.
+pub fn write_identity(
+    scope: &GitConfigScope,
+    name: Option<&str>,
+    email: Option<&str>,
+) -> Result<bool, GitConfigError> {
+    if let Some(name) = name {
+        set_value(scope, "user.name", name)?;
+    }
+    if let Some(email) = email {
+        set_value(scope, "user.email", email)?;
+    }
+    Ok(name.is_some() || email.is_some())
+}
+
+// Appends a value instead of replacing the existing ones
+pub fn add_value(scope: &GitConfigScope, key: &str, value: &str) -> Result<(), GitConfigError> {
+    let output = scope.command().args(["--add", key, value]).output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Removes only the entries of a multi-valued key equal to `value`
+pub fn unset_fixed_value(
+    scope: &GitConfigScope,
+    key: &str,
+    value: &str,
+) -> Result<(), GitConfigError> {
+    let output = scope
+        .command()
+        .args(["--unset-all", "--fixed-value", key, value])
+        .output()?;
+    match output.status.code() {
+        Some(0) | Some(5) => Ok(()),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+// Points a remote's fetch url, or with `push` its push url, at `new_url`.
+// Only the url equal to `old_url` is replaced when the remote has several.
+pub fn set_remote_url(
+    repo_path: &Path,
+    remote: &str,
+    push: bool,
+    old_url: &str,
+    new_url: &str,
+) -> Result<(), GitConfigError> {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(repo_path).args(["remote", "set-url"]);
+    if push {
+        command.arg("--push");
+    }
+    // The old url is matched as a regular expression
+    let output = command
+        .args([remote, new_url, &format!("^{}$", escape_regex(old_url))])
+        .output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Working tree paths of the submodules listed in the repository's .gitmodules
+pub fn submodule_paths(repo_path: &Path) -> Result<Vec<PathBuf>, GitConfigError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args([
+            "config",
+            "--file",
+            ".gitmodules",
+            "--get-regexp",
+            r"^submodule\..*\.path$",
+        ])
+        .output()?;
+    // Exit code 1 also covers a missing .gitmodules
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(_, path)| repo_path.join(path))
+            .collect()),
+        Some(1) => Ok(Vec::new()),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+// "gitdir:/home/me/work/", which matches every repository under the
+// directory. Paths are case-insensitive on Windows and use forward slashes.
+// None unless the directory is absolute once "~" is expanded.
+pub fn gitdir_condition(directory: &str) -> Option<String> {
+    let expanded = paths::expand_home(directory.trim());
+    if !Path::new(&expanded).is_absolute() {
+        return None;
+    }
+    let dir = expanded.replace('\\', "/");
+    let keyword = if cfg!(windows) { "gitdir/i" } else { "gitdir" };
+    Some(format!("{}:{}/", keyword, dir.trim_end_matches('/')))
+}
+
+// The directory of an includeif.gitdir:<dir>/.path key, as git lists it
+pub fn gitdir_directory(key: &str) -> Option<String> {
+    let condition = key.strip_prefix("includeif.")?.strip_suffix(".path")?;
+    let dir = condition
+        .strip_prefix("gitdir:")
+        .or_else(|| condition.strip_prefix("gitdir/i:"))?;
+    Some(dir.trim_end_matches('/').to_string())
+}
+
+// Removes a whole section, e.g. url.git@github-me:my-org/
+pub fn remove_section(scope: &GitConfigScope, section: &str) -> Result<(), GitConfigError> {
+    let output = scope
+        .command()
+        .args(["--remove-section", section])
+        .output()?;
+    if !output.status.success() {
+        return Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn unset_value(scope: &GitConfigScope, key: &str) -> Result<(), GitConfigError> {
+    let output = scope.command().args(["--unset-all", key]).output()?;
+    // Exit code 5 means there was nothing to unset
+    match output.status.code() {
+        Some(0) | Some(5) => Ok(()),
+        _ => Err(GitConfigError::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
@@ -1,8 +1,20 @@
-use crate::database::Database;
-use crate::keychain::KeychainManager;
-use std::io::{self, BufRead};
+use crate::cache::{self, CachedCredential};
+use crate::challenge::{AuthChallenge, ChallengeHint};
+use crate::chooser::{self, ChooserResult};
+use crate::database::{Account, Database, DatabaseError, NewAuditEntry};
+use crate::git_config::{self, GitConfigScope};
+use crate::github_auth::{GitHubAuth, DEFAULT_GITHUB_HOST};
+use crate::keychain::{KeychainManager, RefreshCredentials};
+use crate::remote::{canonicalize_remote_url, host_from_url, repo_owner};
+use crate::resolver::{self, Resolution, ResolveRequest};
+use crate::settings::SettingKey;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
 use std::process::Command;
+use std::time::Duration;
 use thiserror::Error;
+use tracing::{debug, info, warn};
 
 #[derive(Error, Debug)]
 pub enum GitHelperError {
@@ -12,126 +24,1564 @@ pub enum GitHelperError {
     Database(#[from] crate::database::DatabaseError),
     #[error("Keychain error: {0}")]
     Keychain(#[from] crate::keychain::KeychainError),
+    #[error("GitHub error: {0}")]
+    GitHub(#[from] crate::github_auth::GitHubAuthError),
+    #[error("Git config error: {0}")]
+    GitConfig(#[from] crate::git_config::GitConfigError),
     #[error("Process error: {0}")]
     Process(String),
+    #[error("No account selected")]
+    Cancelled,
+    #[error("Timed out after {0} seconds waiting for an account to be chosen")]
+    TimedOut(u64),
+    #[error("No account is mapped for {0} and the helper is running non-interactively")]
+    NonInteractive(String),
+    #[error("The server rejected the credentials: {0}")]
+    Rejected(String),
+    #[error("GITSWITCHHUB_ACCOUNT names {0}, which is not an account for {1}")]
+    UnknownOverride(String, String),
 }
 
-pub struct GitCredentialHelper {
-    db: Database,
-    keychain: KeychainManager,
+// Scripts and CI can't answer a prompt. Git's own GIT_TERMINAL_PROMPT=0 is
+// honored too, since it means the same thing.
+pub fn interactive_from_env() -> bool {
+    let set = |name: &str| {
+        std::env::var(name).is_ok_and(|value| !value.is_empty() && value != "0" && value != "false")
+    };
+    let terminal_prompt_disabled = std::env::var("GIT_TERMINAL_PROMPT").is_ok_and(|v| v == "0");
+    !(set("GITSWITCHHUB_NON_INTERACTIVE") || set("CI") || terminal_prompt_disabled)
 }
 
-impl GitCredentialHelper {
-    pub fn new(db: Database, keychain: KeychainManager) -> Self {
-        Self { db, keychain }
+// GITSWITCHHUB_ACCOUNT=<username or id> forces an account for a single git
+// command, e.g. `GITSWITCHHUB_ACCOUNT=workuser git push`
+pub fn account_override_from_env() -> Option<String> {
+    std::env::var("GITSWITCHHUB_ACCOUNT")
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Hosts the helper answers for: github.com and every Enterprise host
+pub fn helper_hosts(db: &Database) -> Result<Vec<String>, DatabaseError> {
+    let mut hosts = vec![DEFAULT_GITHUB_HOST.to_string()];
+    hosts.extend(db.get_hosts()?.into_iter().map(|host| host.host));
+    Ok(hosts)
+}
+
+// Without credential.<url>.useHttpPath git only sends the host, so every
+// repository on it would get the same account. Hosts we turn it on for are
+// recorded so uninstall_helper leaves the user's own settings alone.
+pub fn enable_http_path(db: &Database, hosts: &[String]) -> Result<(), GitHelperError> {
+    for host in hosts {
+        if http_path_enabled(host)? {
+            continue;
+        }
+        git_config::set_value(&GitConfigScope::Global, &http_path_key(host), "true")?;
+        db.add_http_path_host(host)?;
+    }
+    Ok(())
+}
+
+pub fn http_path_enabled(host: &str) -> Result<bool, GitHelperError> {
+    Ok(
+        git_config::get_value(&GitConfigScope::Global, &http_path_key(host))?
+            .is_some_and(|value| value == "true"),
+    )
+}
+
+fn http_path_key(host: &str) -> String {
+    format!("credential.https://{}.useHttpPath", host)
+}
+
+fn scoped_helper_key(host: &str) -> String {
+    format!("credential.https://{}.helper", host)
+}
+
+// Helpers that keep their own copy of credentials. Git asks every helper to
+// store the credentials we return, so these end up holding GitHub tokens too.
+const STORING_HELPERS: &[&str] = &[
+    "osxkeychain",
+    "manager",
+    "manager-core",
+    "wincred",
+    "libsecret",
+    "gnome-keyring",
+    "store",
+    "cache",
+];
+
+// "osxkeychain" for "osxkeychain", "/usr/bin/git-credential-osxkeychain" or
+// "store --file ~/.creds"
+pub fn helper_name(value: &str) -> &str {
+    let program = value.split_whitespace().next().unwrap_or("");
+    let program = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    let program = program.strip_suffix(".exe").unwrap_or(program);
+    program.strip_prefix("git-credential-").unwrap_or(program)
+}
+
+pub fn conflicting_helpers(helpers: &[String]) -> Vec<String> {
+    helpers
+        .iter()
+        .filter(|value| STORING_HELPERS.contains(&helper_name(value)))
+        .cloned()
+        .collect()
+}
+
+// A credential helper entry from any config file git reads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfiguredHelper {
+    pub scope: String,
+    pub origin: String,
+    // credential.helper or credential.<url>.helper
+    pub key: String,
+    pub value: String,
+    // "osxkeychain", "manager", ... or empty for a reset
+    pub name: String,
+    pub ours: bool,
+    // Still asked to store credentials for a GitHub host once resets are
+    // taken into account
+    pub conflict: bool,
+    // Global and local entries can be removed from the app, system ones
+    // need an administrator
+    pub removable: bool,
+}
+
+// Lists the credential helpers across system, global and, with a repository
+// path, local config, marking those that will also store GitHub tokens
+pub fn configured_helpers(
+    hosts: &[String],
+    repo_path: Option<&Path>,
+) -> Result<Vec<ConfiguredHelper>, GitHelperError> {
+    let own = own_helper_commands()?;
+    let entries = git_config::get_entries(repo_path, r"^credential\..*helper$")?;
+
+    // Git builds the list for a URL from credential.helper and matching
+    // credential.<url>.helper entries in the order read, an empty value
+    // clearing what came before. Whatever survives for a host is asked to
+    // store what we return.
+    let mut effective = vec![false; entries.len()];
+    for host in hosts {
+        let mut list: Vec<usize> = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let applies = match helper_key_url(&entry.key) {
+                Some("") => true,
+                Some(url) => host_from_url(url).is_some_and(|h| h.eq_ignore_ascii_case(host)),
+                None => false,
+            };
+            if !applies {
+                continue;
+            }
+            if entry.value.is_empty() {
+                list.clear();
+            } else {
+                list.push(i);
+            }
+        }
+        for i in list {
+            effective[i] = true;
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .zip(effective)
+        .filter(|(entry, _)| helper_key_url(&entry.key).is_some())
+        .map(|(entry, effective)| {
+            let ours = own.contains(&entry.value);
+            let name = helper_name(&entry.value).to_string();
+            ConfiguredHelper {
+                conflict: effective && !ours && STORING_HELPERS.contains(&name.as_str()),
+                removable: matches!(entry.scope.as_str(), "global" | "local"),
+                ours,
+                name,
+                scope: entry.scope,
+                origin: entry.origin,
+                key: entry.key,
+                value: entry.value,
+            }
+        })
+        .collect())
+}
+
+// "" for credential.helper, the URL for credential.<url>.helper, None for
+// other keys such as credential.<url>.useHttpPath
+fn helper_key_url(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix("credential.")?;
+    if rest == "helper" {
+        return Some("");
+    }
+    rest.strip_suffix(".helper")
+}
+
+// Removes one helper entry, the fix offered for a conflicting helper
+pub fn remove_configured_helper(
+    scope: &GitConfigScope,
+    key: &str,
+    value: &str,
+) -> Result<(), GitHelperError> {
+    if helper_key_url(key).is_none() {
+        return Err(GitHelperError::Process(format!(
+            "{} is not a credential helper setting",
+            key
+        )));
+    }
+    git_config::unset_fixed_value(scope, key, value)?;
+    Ok(())
+}
+
+// How long another helper gets to answer when its credentials are imported.
+// One that prompts despite being told not to is given up on.
+const HELPER_QUERY_TIMEOUT: Duration = Duration::from_secs(15);
+
+// A credential another helper has stored for a host
+#[derive(Debug, Clone)]
+pub struct StoredCredential {
+    pub username: String,
+    pub password: String,
+}
+
+// What `helper` has stored for https://<host>, None when it has nothing
+pub fn fill_from_helper(
+    helper: &str,
+    host: &str,
+) -> Result<Option<StoredCredential>, GitHelperError> {
+    let input = format!("protocol=https\nhost={}\n\n", host);
+    let Some(output) = run_single_helper(helper, "fill", &input)? else {
+        return Ok(None);
+    };
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value.to_string()),
+            Some(("password", value)) => password = Some(value.to_string()),
+            _ => {}
+        }
     }
+    Ok(match (username, password) {
+        (Some(username), Some(password)) if !password.is_empty() => {
+            Some(StoredCredential { username, password })
+        }
+        _ => None,
+    })
+}
 
-    pub fn run(&self) -> Result<(), GitHelperError> {
-        let stdin = io::stdin();
-        let lines = stdin.lock().lines();
+// Has `helper` forget the credential, once it has been imported
+pub fn erase_from_helper(
+    helper: &str,
+    host: &str,
+    credential: &StoredCredential,
+) -> Result<(), GitHelperError> {
+    let input = format!(
+        "protocol=https\nhost={}\nusername={}\npassword={}\n\n",
+        host, credential.username, credential.password
+    );
+    match run_single_helper(helper, "reject", &input)? {
+        Some(_) => Ok(()),
+        None => Err(GitHelperError::Process(format!(
+            "{} didn't erase the credential",
+            helper_name(helper)
+        ))),
+    }
+}
 
-        let mut url = String::new();
-        let mut protocol = String::new();
-        let mut host = String::new();
-        let mut _path = String::new();
+// Runs `git credential <operation>` with `helper` as the only helper, so
+// neither ours nor a prompt can answer. None when git fails or times out.
+fn run_single_helper(
+    helper: &str,
+    operation: &str,
+    input: &str,
+) -> Result<Option<String>, GitHelperError> {
+    let mut child = Command::new("git")
+        .args(["-c", "credential.helper=", "-c"])
+        .arg(format!("credential.helper={}", helper))
+        .args(["-c", "core.askPass=", "credential", operation])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .env_remove("GIT_ASKPASS")
+        .env_remove("SSH_ASKPASS")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
 
-        // Parse Git credential helper input
-        for line in lines {
+    let deadline = std::time::Instant::now() + HELPER_QUERY_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            warn!(helper, operation, "credential helper didn't answer in time");
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        return Ok(None);
+    }
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        io::Read::read_to_string(&mut stdout, &mut output)?;
+    }
+    Ok(Some(output))
+}
+
+// The credential.helper value that makes git run `exe`. Git hands "!"
+// helpers to a POSIX shell on every platform, Git for Windows included (it
+// bundles sh), never to cmd.exe. So the path is quoted for sh, and on
+// Windows written with forward slashes, which that shell and the Windows
+// APIs both accept, e.g.
+//   !'C:/Program Files/GitSwitchHub/gitswitchhub.exe' credential-helper
+pub fn helper_command_for(exe: &Path) -> String {
+    format!("!{} credential-helper", sh_path(exe))
+}
+
+// `exe` as written in a command for sh, see helper_command_for
+pub fn sh_path(exe: &Path) -> String {
+    sh_path_for(&exe.to_string_lossy(), cfg!(windows))
+}
+
+fn sh_path_for(path: &str, windows: bool) -> String {
+    if windows {
+        shell_quote(&path.replace('\\', "/"))
+    } else {
+        shell_quote(path)
+    }
+}
+
+// Single quotes keep everything literal in sh, a quote inside is closed,
+// escaped and reopened
+pub fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+=:@,".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+// Our entry as written now, first, and as written before paths were quoted,
+// so an upgrade neither mistakes the old entry for another helper nor
+// leaves it behind
+pub fn own_helper_commands() -> Result<Vec<String>, GitHelperError> {
+    let current_exe = std::env::current_exe()?;
+    let mut commands = vec![helper_command_for(&current_exe)];
+    let legacy = format!("!{} credential-helper", current_exe.display());
+    if !commands.contains(&legacy) {
+        commands.push(legacy);
+    }
+    Ok(commands)
+}
+
+// Whether every helper host has this helper configured for its URL
+pub fn scoped_install_enabled(hosts: &[String]) -> Result<bool, GitHelperError> {
+    let own = own_helper_commands()?;
+    for host in hosts {
+        let values = git_config::get_all_values(&GitConfigScope::Global, &scoped_helper_key(host))?;
+        if !values.iter().any(|value| own.contains(value)) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Installs the helper without removing the ones already configured, which
+// are recorded so uninstall_helper can put them back. By default ours goes
+// first in credential.helper: it answers for GitHub hosts and stays silent
+// for the rest, so git moves on to the next helper. Scoped installs leave
+// credential.helper alone and configure ours only for the GitHub hosts.
+// Returns the helpers left in place that will also store GitHub credentials.
+pub fn install_helper(db: &Database, scoped: bool) -> Result<Vec<String>, GitHelperError> {
+    let own = own_helper_commands()?;
+    let helper = &own[0];
+    let hosts = helper_hosts(db)?;
+    let others: Vec<String> =
+        git_config::get_all_values(&GitConfigScope::Global, "credential.helper")?
+            .into_iter()
+            .filter(|value| !own.contains(value))
+            .collect();
+    // Reinstalling must not forget the helpers found the first time, even
+    // when some were removed by hand since
+    let mut replaced = db.get_replaced_helpers()?;
+    let known = replaced.len();
+    for value in &others {
+        if !replaced.contains(value) {
+            replaced.push(value.clone());
+        }
+    }
+    if replaced.len() > known {
+        db.set_replaced_helpers(&replaced)?;
+    }
+
+    if scoped {
+        // Only our entry goes, so the generic helpers keep their place ahead
+        // of the host-specific list that resets them
+        for value in &own {
+            git_config::unset_fixed_value(&GitConfigScope::Global, "credential.helper", value)?;
+        }
+    } else {
+        git_config::unset_value(&GitConfigScope::Global, "credential.helper")?;
+        for value in std::iter::once(helper).chain(&others) {
+            git_config::add_value(&GitConfigScope::Global, "credential.helper", value)?;
+        }
+    }
+
+    for host in &hosts {
+        configure_host(host, &own, scoped)?;
+    }
+
+    // Send the repository path so mappings can tell repositories apart
+    enable_http_path(db, &hosts)?;
+
+    if scoped {
+        Ok(Vec::new())
+    } else {
+        Ok(conflicting_helpers(&others))
+    }
+}
+
+fn configure_host(host: &str, own: &[String], scoped: bool) -> Result<(), GitHelperError> {
+    remove_scoped_helper(host, own)?;
+    if scoped {
+        // The empty value drops the generic helpers for this host
+        let key = scoped_helper_key(host);
+        git_config::add_value(&GitConfigScope::Global, &key, "")?;
+        git_config::add_value(&GitConfigScope::Global, &key, &own[0])?;
+    }
+    Ok(())
+}
+
+// Sets up a host added after the helper was installed the way install_helper
+// set up the others, scoped or not. Returns false when the helper isn't
+// installed.
+pub fn install_for_host(db: &Database, host: &str) -> Result<bool, GitHelperError> {
+    let own = own_helper_commands()?;
+    let global = git_config::get_all_values(&GitConfigScope::Global, "credential.helper")?
+        .iter()
+        .any(|value| own.contains(value));
+    // github.com is set up by every install
+    let scoped = scoped_install_enabled(&[DEFAULT_GITHUB_HOST.to_string()])?;
+    if !global && !scoped {
+        return Ok(false);
+    }
+    configure_host(host, &own, scoped && !global)?;
+    enable_http_path(db, &[host.to_string()])?;
+    Ok(true)
+}
+
+// A host-specific helper list is only ours to remove when it includes us
+fn remove_scoped_helper(host: &str, own: &[String]) -> Result<(), GitHelperError> {
+    let key = scoped_helper_key(host);
+    if git_config::get_all_values(&GitConfigScope::Global, &key)?
+        .iter()
+        .any(|value| own.contains(value))
+    {
+        git_config::unset_value(&GitConfigScope::Global, &key)?;
+    }
+    Ok(())
+}
+
+// Removes this helper and restores the ones it replaced, keeping any that were
+// configured since. Returns the restored helpers.
+pub fn uninstall_helper(db: &Database) -> Result<Vec<String>, GitHelperError> {
+    let own = own_helper_commands()?;
+    let restored = db.get_replaced_helpers()?;
+    let added_since: Vec<String> =
+        git_config::get_all_values(&GitConfigScope::Global, "credential.helper")?
+            .into_iter()
+            .filter(|value| !own.contains(value) && !restored.contains(value))
+            .collect();
+
+    git_config::unset_value(&GitConfigScope::Global, "credential.helper")?;
+    for value in restored.iter().chain(&added_since) {
+        git_config::add_value(&GitConfigScope::Global, "credential.helper", value)?;
+    }
+    for host in helper_hosts(db)? {
+        remove_scoped_helper(&host, &own)?;
+    }
+    // Other helpers would otherwise store a credential per repository
+    for host in db.get_http_path_hosts()? {
+        git_config::unset_value(&GitConfigScope::Global, &http_path_key(&host))?;
+    }
+    db.clear_http_path_hosts()?;
+
+    db.set_replaced_helpers(&[])?;
+    Ok(restored)
+}
+
+const ACCOUNT_STATE_PREFIX: &str = "gitswitchhub.account=";
+// How long a rejection steers the next attempt at the same repository away
+// from that account
+const REJECTION_WINDOW: chrono::Duration = chrono::Duration::minutes(30);
+
+enum TokenChoice {
+    // Named by the repository mapping
+    Named(String),
+    // A fine-grained token for the repository owner
+    Scoped { name: String, owner: String },
+    Account,
+}
+
+impl TokenChoice {
+    fn describe(&self) -> String {
+        match self {
+            TokenChoice::Named(name) => format!("\"{}\", named by the mapping", name),
+            TokenChoice::Scoped { name, owner } => {
+                format!("\"{}\", scoped to {}", name, owner)
+            }
+            TokenChoice::Account => "the account's own token".to_string(),
+        }
+    }
+}
+
+// The attributes git sends on stdin, one "key=value" per line
+#[derive(Debug, Default)]
+pub struct CredentialRequest {
+    pub protocol: String,
+    pub host: String,
+    pub path: String,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    // WWW-Authenticate headers of the response that rejected the last attempt
+    pub wwwauth: Vec<String>,
+    // Protocol extensions git understands, e.g. "authtype" and "state"
+    pub capabilities: Vec<String>,
+    // Non-basic credentials, sent back on store and erase
+    pub authtype: String,
+    pub credential: String,
+    // Values from our own get response, echoed back on store and erase
+    pub state: Vec<String>,
+}
+
+impl CredentialRequest {
+    pub fn parse(input: impl BufRead) -> Result<Self, GitHelperError> {
+        let mut request = CredentialRequest::default();
+        for line in input.lines() {
             let line = line?;
             if line.is_empty() {
                 break;
             }
 
             if let Some((key, value)) = line.split_once('=') {
+                let value = value.to_string();
                 match key {
-                    "url" => url = value.to_string(),
-                    "protocol" => protocol = value.to_string(),
-                    "host" => host = value.to_string(),
-                    "path" => _path = value.to_string(),
+                    "url" => request.url = value,
+                    "protocol" => request.protocol = value,
+                    "host" => request.host = value.to_lowercase(),
+                    "path" => request.path = value,
+                    "username" => request.username = value,
+                    "password" => request.password = value,
+                    "wwwauth[]" => request.wwwauth.push(value),
+                    "capability[]" => request.capabilities.push(value),
+                    "authtype" => request.authtype = value,
+                    "credential" => request.credential = value,
+                    "state[]" => request.state.push(value),
                     _ => {}
                 }
             }
         }
 
-        // Determine the repository URL
-        let repo_url = if !url.is_empty() {
-            url
-        } else if !protocol.is_empty() && !host.is_empty() {
-            format!("{}://{}", protocol, host)
+        if request.host.is_empty() {
+            request.host = host_from_url(&request.url).unwrap_or_default();
+        }
+        Ok(request)
+    }
+
+    // The repository URL, in the form mappings are stored in
+    pub fn repo_url(&self) -> Result<String, GitHelperError> {
+        let url = if !self.url.is_empty() {
+            self.url.clone()
+        } else if !self.protocol.is_empty() && !self.host.is_empty() && !self.path.is_empty() {
+            format!(
+                "{}://{}/{}",
+                self.protocol,
+                self.host,
+                self.path.trim_start_matches('/')
+            )
+        } else if !self.protocol.is_empty() && !self.host.is_empty() {
+            format!("{}://{}", self.protocol, self.host)
         } else {
             return Err(GitHelperError::Process(
                 "No repository URL found".to_string(),
             ));
         };
+        Ok(canonicalize_remote_url(&url))
+    }
+
+    pub fn owner(&self) -> Option<String> {
+        repo_owner(&self.url, &self.path)
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
 
-        // Check if we have a remembered account for this repository
-        if let Some(mapping) = self.db.get_repository_mapping(&repo_url)? {
-            if let Some(account) = self.db.get_account_by_username(&mapping.account_id)? {
-                if let Ok(token) = self.keychain.get_token(&account.username) {
-                    // Return credentials to Git
-                    println!("username={}", account.username);
-                    println!("password={}", token);
-                    return Ok(());
+    // The account our get response named, as recorded in the state git
+    // hands back
+    pub fn state_account_id(&self) -> Option<&str> {
+        self.state
+            .iter()
+            .find_map(|state| state.strip_prefix(ACCOUNT_STATE_PREFIX))
+    }
+
+    pub fn challenges(&self) -> Vec<AuthChallenge> {
+        self.wwwauth
+            .iter()
+            .filter_map(|value| AuthChallenge::parse(value))
+            .collect()
+    }
+}
+
+pub struct GitCredentialHelper {
+    db: Database,
+    keychain: KeychainManager,
+    // When false an unmapped repository fails straight away instead of
+    // waiting for someone to choose an account
+    interactive: bool,
+    // Username or id of an account to use whatever the mappings say
+    account_override: Option<String>,
+}
+
+impl GitCredentialHelper {
+    pub fn new(db: Database, keychain: KeychainManager) -> Self {
+        Self {
+            db,
+            keychain,
+            interactive: true,
+            account_override: None,
+        }
+    }
+
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    pub fn with_account_override(mut self, account: Option<String>) -> Self {
+        self.account_override = account;
+        self
+    }
+
+    // Git passes the operation as the helper's first argument
+    pub fn run(&self, operation: &str) -> Result<(), GitHelperError> {
+        let request = CredentialRequest::parse(io::stdin().lock())?;
+        debug!(
+            operation,
+            protocol = %request.protocol,
+            host = %request.host,
+            path = %request.path,
+            "credential request"
+        );
+        // Stay silent for anything else so git falls through to other helpers
+        if !self.handles(&request)? {
+            debug!(host = %request.host, "not a GitHub host, leaving it to other helpers");
+            return Ok(());
+        }
+
+        match operation {
+            "get" => self.get(&request),
+            "store" => self.store(&request),
+            "erase" => self.erase(&request),
+            // Helpers are expected to ignore operations they do not know
+            _ => Ok(()),
+        }
+    }
+
+    // Only HTTPS requests for github.com or a configured Enterprise host are
+    // ours to answer
+    fn handles(&self, request: &CredentialRequest) -> Result<bool, GitHelperError> {
+        if !matches!(request.protocol.as_str(), "https" | "http" | "") {
+            return Ok(false);
+        }
+        if request.host == DEFAULT_GITHUB_HOST || self.db.get_host(&request.host)?.is_some() {
+            return Ok(true);
+        }
+        Ok(self
+            .db
+            .get_accounts()?
+            .iter()
+            .any(|account| account.host.eq_ignore_ascii_case(&request.host)))
+    }
+
+    fn get(&self, request: &CredentialRequest) -> Result<(), GitHelperError> {
+        let repo_url = request.repo_url()?;
+        let owner = request.owner();
+        let host = request.host.as_str();
+
+        // Challenges only come along when the server turned the last attempt
+        // down. When they say why, sending the same token again would only
+        // fail the same way.
+        let challenges = request.challenges();
+        if !challenges.is_empty() {
+            debug!(repo = %repo_url, ?challenges, "server challenges");
+        }
+        if let Some(hint) = ChallengeHint::from_challenges(&challenges, owner.as_deref()) {
+            let account = self.request_account(request)?;
+            let message = hint.message(account.as_ref().map(|a| a.username.as_str()));
+            let outcome = match hint {
+                ChallengeHint::SsoRequired { .. } => "sso_required",
+                ChallengeHint::MissingScope { .. } => "missing_scope",
+            };
+            warn!(repo = %repo_url, "{}", message);
+            self.record_audit(
+                &repo_url,
+                host,
+                account.as_ref(),
+                None,
+                outcome,
+                Some(&message),
+            );
+            if let Some(account) = &account {
+                let _ = cache::erase(&account.host, &account.username);
+            }
+            println!("quit=1");
+            return Err(GitHelperError::Rejected(message));
+        }
+
+        // An override is a one-off, so it neither reads nor fills the cache
+        if let Some(name) = &self.account_override {
+            return self.get_override(request, &repo_url, name, owner.as_deref());
+        }
+
+        let cache_ttl = self
+            .db
+            .get_setting(SettingKey::HelperCacheTtlSecs)?
+            .as_u64()
+            .unwrap_or(0);
+        let cache_key = (cache_ttl > 0)
+            .then(|| cache::cache_key(&repo_url, std::env::current_dir().ok().as_deref()));
+        if let Some(key) = &cache_key {
+            if let Some((account, token)) = self.cached_credentials(key)? {
+                info!(repo = %repo_url, account = %account.username, "returning cached credentials");
+                self.record_audit(
+                    &repo_url,
+                    host,
+                    Some(&account),
+                    Some("cache"),
+                    "success",
+                    None,
+                );
+                self.write_credentials(request, &account, &token)?;
+                return Ok(());
+            }
+        }
+
+        match self.resolve_credentials(&repo_url, host, owner.as_deref()) {
+            Ok((account, token, source)) => {
+                info!(repo = %repo_url, account = %account.username, source, "returning credentials");
+                self.record_audit(
+                    &repo_url,
+                    host,
+                    Some(&account),
+                    Some(source),
+                    "success",
+                    None,
+                );
+                if let Some(key) = &cache_key {
+                    let cached = CachedCredential {
+                        account_id: account.id.clone(),
+                        host: account.host.clone(),
+                        username: account.username.clone(),
+                        token: token.clone(),
+                        source: source.to_string(),
+                    };
+                    // The cache only saves time, so failing to reach it is fine
+                    if let Err(e) = cache::store(key, &cached, cache_ttl) {
+                        debug!("credential cache unavailable: {}", e);
+                    }
                 }
+
+                self.write_credentials(request, &account, &token)
+            }
+            Err(e) => {
+                warn!(repo = %repo_url, "no credentials returned: {}", e);
+                let outcome = match e {
+                    GitHelperError::Cancelled => "cancelled",
+                    GitHelperError::TimedOut(_) => "timeout",
+                    _ => "failure",
+                };
+                self.record_audit(&repo_url, host, None, None, outcome, Some(&e.to_string()));
+
+                // quit=1 stops git from asking other helpers or prompting for
+                // a password, so it aborts with the message instead
+                if matches!(
+                    e,
+                    GitHelperError::Cancelled
+                        | GitHelperError::TimedOut(_)
+                        | GitHelperError::NonInteractive(_)
+                ) {
+                    println!("quit=1");
+                }
+                Err(e)
             }
         }
+    }
 
-        // No remembered account, need to show account chooser
-        self.show_account_chooser(&repo_url)?;
+    fn get_override(
+        &self,
+        request: &CredentialRequest,
+        repo_url: &str,
+        name: &str,
+        owner: Option<&str>,
+    ) -> Result<(), GitHelperError> {
+        let host = request.host.as_str();
+        let result = self.override_account(host, name).and_then(|account| {
+            let token = self.select_token(&account, None, owner)?;
+            Ok((account, token))
+        });
+        match result {
+            Ok((account, token)) => {
+                info!(repo = %repo_url, account = %account.username, "returning credentials for GITSWITCHHUB_ACCOUNT");
+                self.record_audit(
+                    repo_url,
+                    host,
+                    Some(&account),
+                    Some("env_override"),
+                    "success",
+                    None,
+                );
+                self.write_credentials(request, &account, &token)
+            }
+            Err(e) => {
+                warn!(repo = %repo_url, "GITSWITCHHUB_ACCOUNT not usable: {}", e);
+                self.record_audit(
+                    repo_url,
+                    host,
+                    None,
+                    Some("env_override"),
+                    "failure",
+                    Some(&e.to_string()),
+                );
+                // Quietly falling back to another account is exactly what
+                // the override is there to prevent
+                println!("quit=1");
+                Err(e)
+            }
+        }
+    }
 
+    // Accepts an account id or a username on the requested host
+    fn override_account(&self, host: &str, name: &str) -> Result<Account, GitHelperError> {
+        let account = match self.db.get_account(name)? {
+            Some(account) => Some(account),
+            None => self.db.get_account_by_username(host, name)?,
+        };
+        account
+            .filter(|account| account.host.eq_ignore_ascii_case(host))
+            .ok_or_else(|| GitHelperError::UnknownOverride(name.to_string(), host.to_string()))
+    }
+
+    // Returns credentials to git. With git's authtype capability the token
+    // can go out as a Bearer credential instead of a basic auth password.
+    fn write_credentials(
+        &self,
+        request: &CredentialRequest,
+        account: &Account,
+        token: &str,
+    ) -> Result<(), GitHelperError> {
+        if self.use_bearer(request)? {
+            println!("capability[]=authtype");
+            println!("authtype=Bearer");
+            println!("credential={}", token);
+        } else {
+            println!("username={}", account.username);
+            println!("password={}", token);
+        }
+        // Bearer credentials carry no username, so name the account for the
+        // store or erase that follows
+        if request.supports("state") {
+            println!("capability[]=state");
+            println!("state[]={}{}", ACCOUNT_STATE_PREFIX, account.id);
+        }
         Ok(())
     }
 
-    fn show_account_chooser(&self, _repo_url: &str) -> Result<(), GitHelperError> {
-        // Get all available accounts
-        let accounts = self.db.get_accounts()?;
+    fn use_bearer(&self, request: &CredentialRequest) -> Result<bool, GitHelperError> {
+        if !request.supports("authtype") {
+            return Ok(false);
+        }
+        // A server that only offers Bearer gets it whatever the setting says
+        let challenges = request.challenges();
+        if !challenges.is_empty() && challenges.iter().all(|c| c.is_scheme("Bearer")) {
+            return Ok(true);
+        }
+        Ok(self.db.get_setting(SettingKey::HelperAuthType)?.as_str() == Some("bearer"))
+    }
+
+    // The account behind the credentials git sends back, if they were ours
+    fn request_account(
+        &self,
+        request: &CredentialRequest,
+    ) -> Result<Option<Account>, GitHelperError> {
+        if let Some(account_id) = request.state_account_id() {
+            return Ok(self.db.get_account(account_id)?);
+        }
+        if request.username.is_empty() {
+            return Ok(None);
+        }
+        Ok(self
+            .db
+            .get_account_by_username(&request.host, &request.username)?)
+    }
+
+    // Git calls store once the credentials it got were accepted, so this is
+    // where account and mapping use is recorded
+    fn store(&self, request: &CredentialRequest) -> Result<(), GitHelperError> {
+        let account = match self.request_account(request)? {
+            Some(account) => account,
+            // Credentials from another helper or typed in by the user
+            None => return Ok(()),
+        };
+        let repo_url = request.repo_url()?;
+        let owner = request.owner();
+
+        let resolution = self.resolve(&repo_url, &request.host, owner.as_deref())?;
+        let mapping_id = resolution
+            .candidates
+            .iter()
+            .find(|candidate| candidate.account.id == account.id)
+            .and_then(|candidate| candidate.mapping_id.as_deref());
+        if let Some(mapping_id) = mapping_id {
+            self.db.record_mapping_use(mapping_id)?;
+        }
+        self.db.record_account_use(&account.id)?;
+        self.db.set_token_rejected(&account.id, false)?;
+        info!(repo = %repo_url, account = %account.username, "credentials accepted");
+
+        // An account that worked after others were rejected becomes the
+        // repository's mapping, so the failed ones aren't tried first again
+        let rejected = self.recent_rejections(&repo_url)?;
+        if !rejected.is_empty() {
+            if owner.is_some() && !rejected.contains(&account.id) {
+                info!(repo = %repo_url, account = %account.username, "remembering the account that worked");
+                self.db
+                    .set_repository_mapping(&repo_url, &account.id, true, None)?;
+            }
+            self.db.clear_credential_rejections(&repo_url)?;
+        }
+
+        self.record_audit(
+            &repo_url,
+            &request.host,
+            Some(&account),
+            None,
+            "accepted",
+            None,
+        );
+        Ok(())
+    }
+
+    // Git calls erase when the server rejected the credentials. The token is
+    // kept, since the failure may be temporary, but flagged for the UI.
+    fn erase(&self, request: &CredentialRequest) -> Result<(), GitHelperError> {
+        let account = match self.request_account(request)? {
+            Some(account) => account,
+            None => return Ok(()),
+        };
+        let repo_url = request.repo_url()?;
+
+        self.db.set_token_rejected(&account.id, true)?;
+        self.db.add_credential_rejection(&repo_url, &account.id)?;
+        if let Err(e) = cache::erase(&account.host, &account.username) {
+            debug!("failed to clear cached credentials: {}", e);
+        }
+        warn!(repo = %repo_url, account = %account.username, "credentials rejected by the server");
+        self.record_audit(
+            &repo_url,
+            &request.host,
+            Some(&account),
+            None,
+            "rejected",
+            None,
+        );
+        Ok(())
+    }
 
+    // A cached answer, unless its account was removed in the meantime
+    fn cached_credentials(&self, key: &str) -> Result<Option<(Account, String)>, GitHelperError> {
+        let cached = match cache::get(key) {
+            Some(cached) => cached,
+            None => return Ok(None),
+        };
+        Ok(self
+            .db
+            .get_account(&cached.account_id)?
+            .map(|account| (account, cached.token)))
+    }
+
+    fn resolve(
+        &self,
+        repo_url: &str,
+        host: &str,
+        owner: Option<&str>,
+    ) -> Result<Resolution, GitHelperError> {
+        // Git runs the helper from the repository's working directory, so
+        // directory rules can match the checkout location
+        let cwd = std::env::current_dir().ok();
+        Ok(resolver::resolve(
+            &self.db,
+            &ResolveRequest {
+                repo_url,
+                host,
+                owner,
+                path: cwd.as_deref(),
+            },
+        )?)
+    }
+
+    // Picks the account for a request and how it was chosen
+    fn resolve_credentials(
+        &self,
+        repo_url: &str,
+        host: &str,
+        owner: Option<&str>,
+    ) -> Result<(Account, String, &'static str), GitHelperError> {
+        let resolution = self.resolve(repo_url, host, owner)?;
+        let rejected = self.recent_rejections(repo_url)?;
+
+        // Fall through to the next candidate when one has no usable token
+        for candidate in resolution.candidates {
+            if rejected.contains(&candidate.account.id) {
+                debug!(
+                    account = %candidate.account.username,
+                    "skipping candidate the server just rejected"
+                );
+                continue;
+            }
+            let token =
+                match self.select_token(&candidate.account, candidate.token_name.as_deref(), owner)
+                {
+                    Ok(token) => token,
+                    Err(e) => {
+                        debug!(
+                            account = %candidate.account.username,
+                            source = candidate.source.as_str(),
+                            "skipping candidate without a usable token: {}",
+                            e
+                        );
+                        continue;
+                    }
+                };
+            return Ok((candidate.account, token, candidate.source.as_str()));
+        }
+
+        // After a 401 or 403 try the next plausible account instead of
+        // sending the same credentials again
+        if !rejected.is_empty() {
+            if let Some((account, token)) = self.alternate_account(host, owner, &rejected)? {
+                info!(repo = %repo_url, account = %account.username, "retrying with another account");
+                return Ok((account, token, "retry"));
+            }
+            // Every account has been tried, so the next attempt starts over
+            self.db.clear_credential_rejections(repo_url)?;
+            return Err(GitHelperError::Process(format!(
+                "Every account for {} was rejected for {}",
+                host, repo_url
+            )));
+        }
+
+        // No remembered account, need to show account chooser
+        let (account, token) = self.show_account_chooser(repo_url, host, owner)?;
+        Ok((account, token, "chooser"))
+    }
+
+    fn recent_rejections(&self, repo_url: &str) -> Result<Vec<String>, GitHelperError> {
+        Ok(self
+            .db
+            .get_credential_rejections(repo_url, chrono::Utc::now() - REJECTION_WINDOW)?)
+    }
+
+    // The first alternate with a usable token
+    fn alternate_account(
+        &self,
+        host: &str,
+        owner: Option<&str>,
+        rejected: &[String],
+    ) -> Result<Option<(Account, String)>, GitHelperError> {
+        for account in self.alternate_accounts(host, owner, rejected)? {
+            match self.select_token(&account, None, owner) {
+                Ok(token) => return Ok(Some((account, token))),
+                Err(e) => debug!(account = %account.username, "skipping alternate: {}", e),
+            }
+        }
+        Ok(None)
+    }
+
+    // Accounts not yet rejected, best first: one with a token scoped to the
+    // repository owner, then the most recently used
+    fn alternate_accounts(
+        &self,
+        host: &str,
+        owner: Option<&str>,
+        rejected: &[String],
+    ) -> Result<Vec<Account>, GitHelperError> {
+        let profile = resolver::active_profile_accounts(&self.db)?;
+        let mut alternates = Vec::new();
+        for account in self.db.get_accounts()? {
+            if !account.host.eq_ignore_ascii_case(host)
+                || rejected.contains(&account.id)
+                || profile
+                    .as_ref()
+                    .is_some_and(|ids| !ids.contains(&account.id))
+            {
+                continue;
+            }
+            let scoped = matches!(
+                self.token_choice(&account, None, owner)?,
+                TokenChoice::Scoped { .. }
+            );
+            alternates.push((scoped, account));
+        }
+        alternates
+            .sort_by_key(|(scoped, account)| std::cmp::Reverse((*scoped, account.last_used_at)));
+        Ok(alternates.into_iter().map(|(_, account)| account).collect())
+    }
+
+    fn show_account_chooser(
+        &self,
+        repo_url: &str,
+        host: &str,
+        owner: Option<&str>,
+    ) -> Result<(Account, String), GitHelperError> {
+        let accounts = resolver::chooser_accounts(&self.db, host)?;
         if accounts.is_empty() {
-            return Err(GitHelperError::Process(
-                "No GitHub accounts configured".to_string(),
-            ));
+            return Err(GitHelperError::Process(format!(
+                "No GitHub accounts configured for {}",
+                host
+            )));
         }
 
-        // For now, we'll use the first account as a fallback
-        // In a real implementation, this would spawn a GUI window
-        // For CLI mode, we'll need to implement a simple text-based chooser
-        let account = &accounts[0];
+        let prompt = accounts.len() > 1
+            && self
+                .db
+                .get_setting(SettingKey::HelperPromptWhenUnmapped)?
+                .as_bool()
+                .unwrap_or(true);
+        // A mapping needs the repository path, which git only sends with
+        // credential.useHttpPath
+        let offer_remember = owner.is_some()
+            && self
+                .db
+                .get_setting(SettingKey::HelperRememberChoice)?
+                .as_bool()
+                .unwrap_or(true);
 
-        if let Ok(token) = self.keychain.get_token(&account.username) {
-            println!("username={}", account.username);
-            println!("password={}", token);
+        if prompt && !self.interactive {
+            return Err(GitHelperError::NonInteractive(repo_url.to_string()));
+        }
+
+        let (index, remember) = if prompt {
+            let timeout = self
+                .db
+                .get_setting(SettingKey::HelperChooserTimeoutSecs)?
+                .as_u64()
+                .unwrap_or(60);
+            // The terminal git runs in, then the app's chooser window for git
+            // started without one, e.g. from an IDE
+            let mut result = chooser::prompt_terminal(
+                &accounts,
+                repo_url,
+                offer_remember,
+                Duration::from_secs(timeout),
+            )?;
+            if matches!(result, ChooserResult::Unavailable) {
+                result = chooser::prompt_gui(
+                    &accounts,
+                    repo_url,
+                    host,
+                    offer_remember,
+                    Duration::from_secs(timeout),
+                )?;
+            }
+            match result {
+                ChooserResult::Chosen { index, remember } => (index, remember),
+                ChooserResult::Cancelled => return Err(GitHelperError::Cancelled),
+                ChooserResult::TimedOut => return Err(GitHelperError::TimedOut(timeout)),
+                // With nobody to ask fall back to the default account
+                ChooserResult::Unavailable => (0, false),
+            }
         } else {
-            return Err(GitHelperError::Process(
+            (0, false)
+        };
+        let account = accounts.into_iter().nth(index).unwrap();
+        info!(repo = %repo_url, account = %account.username, remember, "account chosen");
+        if remember {
+            self.db
+                .set_repository_mapping(repo_url, &account.id, true, None)?;
+            if self
+                .db
+                .get_setting(SettingKey::WriteRepoIdentity)?
+                .as_bool()
+                .unwrap_or(false)
+            {
+                self.write_repo_identity(repo_url, &account);
+            }
+        }
+
+        match self.select_token(&account, None, owner) {
+            Ok(token) => Ok((account, token)),
+            Err(_) => Err(GitHelperError::Process(
                 "No token found for account".to_string(),
-            ));
+            )),
+        }
+    }
+
+    // Git runs the helper from the working tree, except while cloning when
+    // that's the parent directory, so the checkout must have the remote.
+    // Failing to write the identity must never block a push.
+    fn write_repo_identity(&self, repo_url: &str, account: &Account) {
+        let result = std::env::current_dir()
+            .map_err(git_config::GitConfigError::from)
+            .and_then(|cwd| git_config::toplevel(&cwd))
+            .and_then(|toplevel| match toplevel {
+                Some(path) if git_config::has_remote(&path, repo_url)? => {
+                    git_config::write_identity(
+                        &GitConfigScope::Repository(path),
+                        account.git_name.as_deref(),
+                        account.commit_email().as_deref(),
+                    )
+                }
+                _ => Ok(false),
+            });
+        match result {
+            Ok(written) => {
+                debug!(repo = %repo_url, account = %account.username, written, "repository identity")
+            }
+            Err(e) => {
+                warn!(repo = %repo_url, error = %e, "failed to write the repository identity")
+            }
         }
+    }
 
-        Ok(())
+    // Failing to write the audit log must never block a push
+    fn record_audit(
+        &self,
+        repo_url: &str,
+        host: &str,
+        account: Option<&Account>,
+        source: Option<&str>,
+        outcome: &str,
+        message: Option<&str>,
+    ) {
+        let _ = self.db.add_audit_entry(&NewAuditEntry {
+            repo_url,
+            host,
+            account_id: account.map(|a| a.id.as_str()),
+            username: account.map(|a| a.username.as_str()),
+            source,
+            outcome,
+            message,
+        });
     }
 
-    pub fn install_git_helper(&self) -> Result<(), GitHelperError> {
-        let current_exe = std::env::current_exe()?;
-        let helper_command = format!("!{} credential-helper", current_exe.display());
+    // Picks the token pinned by the mapping, then a named token scoped to the
+    // repository owner, then the account's default token
+    fn select_token(
+        &self,
+        account: &Account,
+        token_name: Option<&str>,
+        owner: Option<&str>,
+    ) -> Result<String, GitHelperError> {
+        match self.token_choice(account, token_name, owner)? {
+            TokenChoice::Named(name) | TokenChoice::Scoped { name, .. } => Ok(self
+                .keychain
+                .get_named_token(&account.host, &account.username, &name)?),
+            TokenChoice::Account => self.resolve_token(account),
+        }
+    }
 
-        // Clear existing credential helpers
-        let _ = Command::new("git")
-            .args(["config", "--global", "--unset-all", "credential.helper"])
-            .output();
+    // Which of the account's tokens goes out: the one a mapping names, one
+    // scoped to the repository owner, or the account's own
+    fn token_choice(
+        &self,
+        account: &Account,
+        token_name: Option<&str>,
+        owner: Option<&str>,
+    ) -> Result<TokenChoice, GitHelperError> {
+        if let Some(name) = token_name {
+            return Ok(TokenChoice::Named(name.to_string()));
+        }
 
-        // Set our credential helper
-        let output = Command::new("git")
-            .args(["config", "--global", "credential.helper", &helper_command])
-            .output()?;
+        if let Some(owner) = owner {
+            let tokens = self.db.get_account_tokens(&account.id)?;
+            let scoped = tokens.into_iter().find(|t| {
+                t.owner
+                    .as_deref()
+                    .is_some_and(|o| o.eq_ignore_ascii_case(owner))
+            });
+            if let Some(token) = scoped {
+                return Ok(TokenChoice::Scoped {
+                    name: token.name,
+                    owner: owner.to_string(),
+                });
+            }
+        }
 
-        if !output.status.success() {
-            return Err(GitHelperError::Process(
-                String::from_utf8_lossy(&output.stderr).to_string(),
-            ));
+        Ok(TokenChoice::Account)
+    }
+
+    // Walks through how a get for `url` would be answered, without
+    // returning, refreshing or printing any token
+    pub fn explain(&self, url: &str, out: &mut impl Write) -> Result<(), GitHelperError> {
+        let repo_url = canonicalize_remote_url(url);
+        let request = CredentialRequest::parse(format!("url={}\n", repo_url).as_bytes())?;
+        let host = request.host.as_str();
+        let owner = request.owner();
+        let cwd = std::env::current_dir().ok();
+
+        writeln!(out, "Repository: {}", repo_url)?;
+        if let Some(cwd) = &cwd {
+            writeln!(out, "Working directory: {}", cwd.display())?;
+        }
+        if !url.trim().to_lowercase().starts_with("http") {
+            writeln!(
+                out,
+                "Note: git only asks the credential helper for HTTPS remotes; SSH remotes authenticate with keys"
+            )?;
+        }
+        if host.is_empty() {
+            writeln!(out, "Not a remote URL the helper understands")?;
+            return Ok(());
+        }
+        if !self.handles(&request)? {
+            writeln!(
+                out,
+                "{} is not github.com or a configured Enterprise host, so the helper stays silent and git asks other helpers",
+                host
+            )?;
+            return Ok(());
+        }
+
+        if let Some(name) = &self.account_override {
+            writeln!(out, "GITSWITCHHUB_ACCOUNT={} overrides every rule", name)?;
+            match self.override_account(host, name) {
+                Ok(account) => {
+                    if !self.explain_token(&account, None, owner.as_deref(), out)? {
+                        writeln!(out, "  git would stop without asking for a password")?;
+                    }
+                }
+                Err(e) => writeln!(out, "  {}; git would stop without asking for a password", e)?,
+            }
+            return Ok(());
+        }
+
+        let cache_ttl = self
+            .db
+            .get_setting(SettingKey::HelperCacheTtlSecs)?
+            .as_u64()
+            .unwrap_or(0);
+        if cache_ttl > 0 {
+            match cache::get(&cache::cache_key(&repo_url, cwd.as_deref())) {
+                Some(cached) => writeln!(
+                    out,
+                    "Cache: {} is cached (from {}) and would be returned until it expires",
+                    cached.username, cached.source
+                )?,
+                None => writeln!(out, "Cache: nothing cached for this repository")?,
+            }
         }
 
+        let resolution = self.resolve(&repo_url, host, owner.as_deref())?;
+        let rejected = self.recent_rejections(&repo_url)?;
+        for skipped in &resolution.skipped {
+            writeln!(out, "Skipped: {}", skipped)?;
+        }
+        if resolution.candidates.is_empty() {
+            writeln!(out, "No mapping, rule or default applies")?;
+        }
+        for (i, candidate) in resolution.candidates.iter().enumerate() {
+            writeln!(
+                out,
+                "{}. [{}] {}",
+                i + 1,
+                candidate.source.as_str(),
+                candidate.reason
+            )?;
+            if rejected.contains(&candidate.account.id) {
+                writeln!(
+                    out,
+                    "  The server rejected {} for this repository recently, so it is skipped",
+                    candidate.account.username
+                )?;
+                continue;
+            }
+            match self.explain_token(
+                &candidate.account,
+                candidate.token_name.as_deref(),
+                owner.as_deref(),
+                out,
+            ) {
+                Ok(true) => return Ok(()),
+                Ok(false) => writeln!(out, "  falling through to the next candidate")?,
+                Err(e) => writeln!(out, "  {}, falling through to the next candidate", e)?,
+            }
+        }
+
+        if !rejected.is_empty() {
+            match self
+                .alternate_accounts(host, owner.as_deref(), &rejected)?
+                .first()
+            {
+                Some(account) => writeln!(
+                    out,
+                    "After the recent rejection {} would be tried next",
+                    account.username
+                )?,
+                None => writeln!(
+                    out,
+                    "Every account for {} was rejected recently; the next attempt would fail and start over",
+                    host
+                )?,
+            }
+            return Ok(());
+        }
+
+        // The default account, else the most recently used
+        let Some(fallback) = resolver::chooser_accounts(&self.db, host)?
+            .into_iter()
+            .next()
+        else {
+            writeln!(out, "No accounts are configured for {}", host)?;
+            return Ok(());
+        };
+        let prompt = self
+            .db
+            .get_setting(SettingKey::HelperPromptWhenUnmapped)?
+            .as_bool()
+            .unwrap_or(true);
+        if !prompt {
+            writeln!(
+                out,
+                "Prompting is turned off, so {} would be used",
+                fallback.username
+            )?;
+        } else if !self.interactive {
+            writeln!(
+                out,
+                "The helper is non-interactive, so git would stop without credentials"
+            )?;
+        } else {
+            writeln!(
+                out,
+                "The account chooser would be shown with {} preselected, and {} used with nobody to ask",
+                fallback.username, fallback.username
+            )?;
+        }
         Ok(())
     }
 
+    // Reports which token an account would send and whether it is usable
+    fn explain_token(
+        &self,
+        account: &Account,
+        token_name: Option<&str>,
+        owner: Option<&str>,
+        out: &mut impl Write,
+    ) -> Result<bool, GitHelperError> {
+        writeln!(out, "  Account: {} on {}", account.username, account.host)?;
+        let choice = self.token_choice(account, token_name, owner)?;
+        let found = match &choice {
+            TokenChoice::Named(name) | TokenChoice::Scoped { name, .. } => self
+                .keychain
+                .get_named_token(&account.host, &account.username, name)
+                .is_ok(),
+            TokenChoice::Account => self
+                .keychain
+                .get_token(&account.host, &account.username)
+                .is_ok(),
+        };
+        writeln!(out, "  Token: {}", choice.describe())?;
+        if !found {
+            writeln!(out, "  The token is missing from the keychain")?;
+            return Ok(false);
+        }
+        if matches!(choice, TokenChoice::Account) {
+            let expiring = self
+                .keychain
+                .get_refresh_credentials(&account.host, &account.username)?
+                .is_some_and(|credentials| credentials.access_token_expiring());
+            if expiring {
+                writeln!(out, "  It expires soon and would be refreshed first")?;
+            }
+        }
+        if let Some(rejected_at) = account.token_rejected_at {
+            writeln!(
+                out,
+                "  GitHub last rejected it at {}, so the push or fetch may fail",
+                rejected_at.to_rfc3339()
+            )?;
+        }
+        Ok(true)
+    }
+
+    // Returns the stored token, renewing it first when it is an expiring
+    // OAuth token that is about to lapse
+    fn resolve_token(&self, account: &Account) -> Result<String, GitHelperError> {
+        let token = self.keychain.get_token(&account.host, &account.username)?;
+        let credentials = match self
+            .keychain
+            .get_refresh_credentials(&account.host, &account.username)?
+        {
+            Some(credentials) if credentials.access_token_expiring() => credentials,
+            _ => return Ok(token),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let host = self.db.get_host(&account.host)?;
+        let github_auth = GitHubAuth::for_host(
+            &account.host,
+            host.as_ref().map(|h| h.api_url.as_str()),
+            host.as_ref().and_then(|h| h.oauth_client_id.as_deref()),
+        );
+        let response =
+            match runtime.block_on(github_auth.refresh_access_token(&credentials.refresh_token)) {
+                Ok(response) => response,
+                // The token works until it expires, renewing can wait for the next request
+                Err(e) if !credentials.access_token_expired() => {
+                    warn!(
+                        account = %account.username,
+                        error = %e,
+                        "couldn't renew the access token, using it until it expires"
+                    );
+                    return Ok(token);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        self.keychain
+            .replace_token(&account.host, &account.username, &response.access_token)?;
+        info!(account = %account.username, "refreshed expiring access token");
+        if let Some(refreshed) = RefreshCredentials::from_token_response(&response) {
+            self.keychain.store_refresh_credentials(
+                &account.host,
+                &account.username,
+                &refreshed,
+            )?;
+
+            if let Some(mut metadata) = self.db.get_token_metadata(&account.id)? {
+                metadata.expires_at = refreshed.access_token_expires_at;
+                self.db.set_token_metadata(&metadata)?;
+            }
+        }
+
+        Ok(response.access_token)
+    }
+
+    pub fn install_git_helper(&self) -> Result<(), GitHelperError> {
+        install_helper(&self.db, false).map(|_| ())
+    }
+
     pub fn get_git_helper_status(&self) -> Result<bool, GitHelperError> {
         let output = Command::new("git")
             .args(["config", "--global", "credential.helper"])
@@ -142,9 +1592,135 @@ impl GitCredentialHelper {
         }
 
         let config = String::from_utf8_lossy(&output.stdout);
-        let current_exe = std::env::current_exe()?;
-        let expected_helper = format!("!{} credential-helper", current_exe.display());
+        Ok(own_helper_commands()?
+            .iter()
+            .any(|own| config.trim() == own))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_leaves_plain_paths_alone() {
+        assert_eq!(
+            shell_quote("/usr/local/bin/gitswitchhub"),
+            "/usr/local/bin/gitswitchhub"
+        );
+        assert_eq!(
+            shell_quote("C:/Tools/gitswitchhub.exe"),
+            "C:/Tools/gitswitchhub.exe"
+        );
+    }
+
+    #[test]
+    fn shell_quote_quotes_spaces_and_quotes() {
+        assert_eq!(
+            shell_quote("/Applications/Git Switch Hub.app/gitswitchhub"),
+            "'/Applications/Git Switch Hub.app/gitswitchhub'"
+        );
+        assert_eq!(
+            shell_quote("/home/o'brien/bin/gitswitchhub"),
+            r"'/home/o'\''brien/bin/gitswitchhub'"
+        );
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("$HOME/`x`"), "'$HOME/`x`'");
+    }
+
+    #[test]
+    fn sh_path_uses_forward_slashes_on_windows() {
+        assert_eq!(
+            sh_path_for(r"C:\Program Files\GitSwitchHub\gitswitchhub.exe", true),
+            "'C:/Program Files/GitSwitchHub/gitswitchhub.exe'"
+        );
+        assert_eq!(
+            sh_path_for(r"C:\Users\o'brien\gitswitchhub.exe", true),
+            r"'C:/Users/o'\''brien/gitswitchhub.exe'"
+        );
+        assert_eq!(
+            sh_path_for(r"\\server\share\gitswitchhub.exe", true),
+            "//server/share/gitswitchhub.exe"
+        );
+        // Backslashes are only separators on Windows
+        assert_eq!(sh_path_for(r"/tmp/a\b", false), r"'/tmp/a\b'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn helper_command_quotes_paths_with_spaces() {
+        assert_eq!(
+            helper_command_for(Path::new("/Applications/Git Switch Hub.app/gitswitchhub")),
+            "!'/Applications/Git Switch Hub.app/gitswitchhub' credential-helper"
+        );
+        assert_eq!(
+            helper_command_for(Path::new("/usr/bin/gitswitchhub")),
+            "!/usr/bin/gitswitchhub credential-helper"
+        );
+    }
+
+    // sh must read the quoted path back as the single original argument
+    #[cfg(unix)]
+    #[test]
+    fn quoted_paths_survive_sh() {
+        for path in [
+            "/Applications/Git Switch Hub.app/gitswitchhub",
+            "/home/o'brien/it's here/gitswitchhub",
+            "/tmp/$HOME/`id`/a\\b/*",
+        ] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf '%s' {}", shell_quote(path)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), path);
+        }
+    }
+
+    // What git itself runs for the credential.helper value
+    #[cfg(unix)]
+    #[test]
+    fn git_runs_helpers_from_quoted_paths() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("gitswitchhub test {}'s", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("fake helper");
+        std::fs::write(
+            &exe,
+            "#!/bin/sh\n[ \"$1\" = credential-helper ] && [ \"$2\" = get ] && printf 'username=octocat\\npassword=secret\\n'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut child = std::process::Command::new("git")
+            .args(["-c", "credential.helper="])
+            .arg("-c")
+            .arg(format!("credential.helper={}", helper_command_for(&exe)))
+            .args(["credential", "fill"])
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"protocol=https\nhost=github.com\n\n")
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("password=secret"), "{}", stdout);
+    }
 
-        Ok(config.trim() == expected_helper)
+    #[test]
+    fn own_helper_commands_start_with_the_current_entry() {
+        let exe = std::env::current_exe().unwrap();
+        let commands = own_helper_commands().unwrap();
+        assert_eq!(commands[0], helper_command_for(&exe));
+        let mut unique = commands.clone();
+        unique.dedup();
+        assert_eq!(unique, commands);
     }
 }
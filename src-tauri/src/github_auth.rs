@@ -1,9 +1,24 @@
-use reqwest::Client;
+use crate::github_client::GitHubClient;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::time::sleep;
 
+pub const DEFAULT_GITHUB_HOST: &str = "github.com";
+// GitHub OAuth App ID for GitSwitchHub
+const GITHUB_CLIENT_ID: &str = "Ov23liA2BpF0gI3E4nUX";
+// Requested by every sign-in
+pub const DEFAULT_SCOPES: &[&str] = &["repo", "user"];
+// Scopes test_connection reports as missing. Without workflow, pushes that
+// change .github/workflows are rejected.
+pub const RECOMMENDED_SCOPES: &[&str] = &["repo", "workflow"];
+// Added to the polling interval on every slow_down, as RFC 8628 asks
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+// How quickly a cancelled device flow stops waiting
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(Error, Debug)]
 pub enum GitHubAuthError {
     #[error("HTTP request failed: {0}")]
@@ -12,12 +27,33 @@ pub enum GitHubAuthError {
     Timeout,
     #[error("Device flow denied")]
     Denied,
+    #[error("Device flow cancelled")]
+    Cancelled,
+    #[error("GitHub rejected the device code: {0}")]
+    DeviceFlowRejected(String),
     #[error("Invalid token")]
     InvalidToken,
+    #[error("Token refresh failed: {0}")]
+    RefreshFailed(String),
+    #[error("No OAuth app is configured for {0}")]
+    NoClientId(String),
+    #[error("The token is missing the {0} scope")]
+    MissingScope(String),
+    #[error("GitHub refused the request: {0}")]
+    Rejected(String),
+    #[error("Authorization code exchange failed: {0}")]
+    CodeExchangeFailed(String),
     #[error("JSON parsing error: {0}")]
     Json(#[from] serde_json::Error),
 }
 
+impl GitHubAuthError {
+    // GitHub couldn't be reached at all, as opposed to refusing the request
+    pub fn is_network(&self) -> bool {
+        matches!(self, GitHubAuthError::Http(e) if e.is_connect() || e.is_timeout())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeviceCodeResponse {
     pub device_code: String,
@@ -33,9 +69,40 @@ pub struct DeviceTokenResponse {
     pub access_token: String,
     pub token_type: String,
     pub scope: String,
+    // Only returned when the OAuth app has expiring user tokens enabled
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub refresh_token_expires_in: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Reported on every poll while the user hasn't approved the code yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceFlowProgress {
+    Pending,
+    // GitHub asked us to poll less often, now every `interval`
+    SlowDown { interval: Duration },
+}
+
+// How often a device code may be polled for, and until when
+#[derive(Debug, Clone, Copy)]
+pub struct DevicePolling {
+    pub interval: Duration,
+    pub expires_at: Instant,
+}
+
+impl From<&DeviceCodeResponse> for DevicePolling {
+    fn from(response: &DeviceCodeResponse) -> Self {
+        Self {
+            interval: Duration::from_secs(response.interval.max(1)),
+            expires_at: Instant::now() + Duration::from_secs(response.expires_in),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GitHubUser {
     pub login: String,
     pub id: u64,
@@ -44,8 +111,87 @@ pub struct GitHubUser {
     pub email: Option<String>,
 }
 
+// What kind of credential a token is. Fine-grained PATs are limited to the
+// repositories picked when they were created, and have no OAuth scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    Classic,
+    FineGrained,
+    OAuth,
+    Unknown,
+}
+
+impl TokenKind {
+    // GitHub's token prefixes. Tokens from before they were introduced are
+    // 40 hex characters and can't be told apart this way.
+    pub fn from_prefix(token: &str) -> Self {
+        let token = token.trim();
+        if token.starts_with("github_pat_") {
+            Self::FineGrained
+        } else if token.starts_with("ghp_") {
+            Self::Classic
+        } else if token.starts_with("gho_") || token.starts_with("ghu_") {
+            Self::OAuth
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Classic => "classic personal access token",
+            Self::FineGrained => "fine-grained personal access token",
+            Self::OAuth => "OAuth token",
+            Self::Unknown => "token",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitHubOrg {
+    pub login: String,
+    pub id: u64,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+// A key registered with the account on GitHub
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitHubKey {
+    pub id: u64,
+    pub key: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GitHubGpgKey {
+    pub id: u64,
+    pub key_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TokenDetails {
+    pub user: GitHubUser,
+    pub kind: TokenKind,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
 pub struct GitHubAuth {
-    client: Client,
+    client: GitHubClient,
+    host: String,
+    web_url: String,
+    api_url: String,
+    // Enterprise hosts only have one once an OAuth app is registered there
+    client_id: Option<String>,
 }
 
 impl Default for GitHubAuth {
@@ -56,21 +202,71 @@ impl Default for GitHubAuth {
 
 impl GitHubAuth {
     pub fn new() -> Self {
+        Self::for_host(DEFAULT_GITHUB_HOST, None, None)
+    }
+
+    // For a GitHub Enterprise Server host the API defaults to
+    // https://<host>/api/v3
+    pub fn for_host(host: &str, api_url: Option<&str>, client_id: Option<&str>) -> Self {
+        let host = host.to_lowercase();
+        let is_github = host == DEFAULT_GITHUB_HOST;
+        let api_url = match api_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None if is_github => "https://api.github.com".to_string(),
+            None => format!("https://{}/api/v3", host),
+        };
+        let client_id = client_id
+            .map(str::to_string)
+            .or_else(|| is_github.then(|| GITHUB_CLIENT_ID.to_string()));
         Self {
-            client: Client::new(),
+            client: GitHubClient::new(),
+            web_url: format!("https://{}", host),
+            host,
+            api_url,
+            client_id,
         }
     }
 
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn has_client_id(&self) -> bool {
+        self.client_id.is_some()
+    }
+
+    fn client_id(&self) -> Result<&str, GitHubAuthError> {
+        self.client_id
+            .as_deref()
+            .ok_or_else(|| GitHubAuthError::NoClientId(self.host.clone()))
+    }
+
+    fn web(&self, path: &str) -> String {
+        format!("{}{}", self.web_url, path)
+    }
+
+    fn api(&self, path: &str) -> String {
+        format!("{}{}", self.api_url, path)
+    }
+
     pub async fn start_device_flow(&self) -> Result<DeviceCodeResponse, GitHubAuthError> {
-        let client_id = "Ov23liA2BpF0gI3E4nUX"; // GitHub OAuth App ID for GitSwitchHub
+        self.start_device_flow_with_scopes(&sign_in_scopes(&[]))
+            .await
+    }
+
+    pub async fn start_device_flow_with_scopes(
+        &self,
+        scopes: &[String],
+    ) -> Result<DeviceCodeResponse, GitHubAuthError> {
+        let client_id = self.client_id()?;
+        let scope = scopes.join(",");
 
-        let response = self
+        let request = self
             .client
-            .post("https://github.com/login/device/code")
+            .post(self.web("/login/device/code"))
             .header("Accept", "application/json")
-            .form(&[("client_id", client_id), ("scope", "repo,user")])
-            .send()
-            .await?;
+            .form(&[("client_id", client_id), ("scope", scope.as_str())]);
+        let response = self.client.send(request).await?;
 
         if !response.status().is_success() {
             return Err(GitHubAuthError::Http(
@@ -83,29 +279,47 @@ impl GitHubAuth {
     }
 
     pub async fn poll_for_token(
+        &self,
+        code: &DeviceCodeResponse,
+    ) -> Result<DeviceTokenResponse, GitHubAuthError> {
+        self.poll_for_token_with_progress(
+            &code.device_code,
+            code.into(),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .await
+    }
+
+    // Polls at the interval GitHub asks for until the code is approved,
+    // calling `on_progress` while it isn't and giving up with Cancelled once
+    // `cancelled` is set
+    pub async fn poll_for_token_with_progress(
         &self,
         device_code: &str,
+        polling: DevicePolling,
+        cancelled: &AtomicBool,
+        mut on_progress: impl FnMut(DeviceFlowProgress),
     ) -> Result<DeviceTokenResponse, GitHubAuthError> {
-        let client_id = "Ov23liA2BpF0gI3E4nUX";
-        let max_attempts = 60; // 5 minutes with 5-second intervals
-        let mut attempts = 0;
+        let client_id = self.client_id()?;
+        let mut interval = polling.interval;
 
         loop {
-            if attempts >= max_attempts {
+            wait_unless_cancelled(interval, cancelled).await?;
+            if Instant::now() >= polling.expires_at {
                 return Err(GitHubAuthError::Timeout);
             }
 
-            let response = self
+            let request = self
                 .client
-                .post("https://github.com/login/oauth/access_token")
+                .post(self.web("/login/oauth/access_token"))
                 .header("Accept", "application/json")
                 .form(&[
                     ("client_id", client_id),
                     ("device_code", device_code),
                     ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
-                ])
-                .send()
-                .await?;
+                ]);
+            let response = self.client.send(request).await?;
 
             if !response.status().is_success() {
                 return Err(GitHubAuthError::Http(
@@ -113,42 +327,151 @@ impl GitHubAuth {
                 ));
             }
 
-            let text = response.text().await?;
-
-            // Check for error responses
-            if text.contains("authorization_pending") {
-                attempts += 1;
-                sleep(Duration::from_secs(5)).await;
-                continue;
+            // Errors come back as 200 with an "error" field
+            let value: serde_json::Value = response.json().await?;
+            let error = match value.get("error").and_then(|e| e.as_str()) {
+                Some(error) => error,
+                None => return Ok(serde_json::from_value(value)?),
+            };
+            match error {
+                "authorization_pending" => on_progress(DeviceFlowProgress::Pending),
+                "slow_down" => {
+                    // GitHub says what the interval is now; otherwise back
+                    // off by the increment
+                    interval = value
+                        .get("interval")
+                        .and_then(|i| i.as_u64())
+                        .map(Duration::from_secs)
+                        .filter(|new| *new > interval)
+                        .unwrap_or(interval + SLOW_DOWN_INCREMENT);
+                    on_progress(DeviceFlowProgress::SlowDown { interval });
+                }
+                "access_denied" => return Err(GitHubAuthError::Denied),
+                "expired_token" => return Err(GitHubAuthError::Timeout),
+                _ => {
+                    let description = value
+                        .get("error_description")
+                        .and_then(|d| d.as_str())
+                        .unwrap_or(error);
+                    return Err(GitHubAuthError::DeviceFlowRejected(description.to_string()));
+                }
             }
+        }
+    }
 
-            if text.contains("access_denied") {
-                return Err(GitHubAuthError::Denied);
-            }
+    // The page that asks the user to authorize the app, redirecting to
+    // `redirect_uri` with a code afterwards
+    pub fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+        code_challenge: &str,
+    ) -> Result<String, GitHubAuthError> {
+        let client_id = self.client_id()?;
+        let scope = DEFAULT_SCOPES.join(" ");
+        let url = reqwest::Url::parse_with_params(
+            &self.web("/login/oauth/authorize"),
+            &[
+                ("client_id", client_id),
+                ("redirect_uri", redirect_uri),
+                ("scope", scope.as_str()),
+                ("state", state),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(|url| url.to_string())
+        .unwrap_or_default();
+        Ok(url)
+    }
 
-            if text.contains("expired_token") {
-                return Err(GitHubAuthError::Timeout);
-            }
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        redirect_uri: &str,
+    ) -> Result<DeviceTokenResponse, GitHubAuthError> {
+        let client_id = self.client_id()?;
+        let mut form = vec![
+            ("client_id", client_id),
+            ("code", code),
+            ("code_verifier", code_verifier),
+            ("redirect_uri", redirect_uri),
+        ];
+        // A desktop app can't keep a secret, PKCE is what protects the code.
+        // Builds for an OAuth app that still insists on one can supply it.
+        if let Some(secret) = option_env!("GITSWITCHHUB_OAUTH_CLIENT_SECRET") {
+            form.push(("client_secret", secret));
+        }
 
-            // Try to parse as successful response
-            if let Ok(token_response) = serde_json::from_str::<DeviceTokenResponse>(&text) {
-                return Ok(token_response);
-            }
+        let request = self
+            .client
+            .post(self.web("/login/oauth/access_token"))
+            .header("Accept", "application/json")
+            .form(&form);
+        let response = self.client.send(request).await?;
 
-            attempts += 1;
-            sleep(Duration::from_secs(5)).await;
+        if !response.status().is_success() {
+            return Err(GitHubAuthError::Http(
+                response.error_for_status().unwrap_err(),
+            ));
         }
+
+        // Errors come back as 200 with an "error" field
+        let value: serde_json::Value = response.json().await?;
+        if let Some(error) = value.get("error").and_then(|e| e.as_str()) {
+            let description = value
+                .get("error_description")
+                .and_then(|d| d.as_str())
+                .unwrap_or(error);
+            return Err(GitHubAuthError::CodeExchangeFailed(description.to_string()));
+        }
+
+        let token_response: DeviceTokenResponse = serde_json::from_value(value)?;
+        Ok(token_response)
+    }
+
+    pub async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<DeviceTokenResponse, GitHubAuthError> {
+        let client_id = self.client_id()?;
+
+        let request = self
+            .client
+            .post(self.web("/login/oauth/access_token"))
+            .header("Accept", "application/json")
+            .form(&[
+                ("client_id", client_id),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ]);
+        let response = self.client.send(request).await?;
+
+        if !response.status().is_success() {
+            return Err(GitHubAuthError::Http(
+                response.error_for_status().unwrap_err(),
+            ));
+        }
+
+        // Errors come back as 200 with an "error" field
+        let value: serde_json::Value = response.json().await?;
+        if let Some(error) = value.get("error").and_then(|e| e.as_str()) {
+            return Err(GitHubAuthError::RefreshFailed(error.to_string()));
+        }
+
+        let token_response: DeviceTokenResponse = serde_json::from_value(value)?;
+        Ok(token_response)
     }
 
     pub async fn validate_token(&self, token: &str) -> Result<GitHubUser, GitHubAuthError> {
-        let response = self
+        let request = self
             .client
-            .get("https://api.github.com/user")
+            .get(self.api("/user"))
             .header("Authorization", &format!("Bearer {}", token))
             .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", "GitSwitchHub/1.0")
-            .send()
-            .await?;
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
 
         if !response.status().is_success() {
             return Err(GitHubAuthError::InvalidToken);
@@ -159,14 +482,13 @@ impl GitHubAuth {
     }
 
     pub async fn test_token_scopes(&self, token: &str) -> Result<Vec<String>, GitHubAuthError> {
-        let response = self
+        let request = self
             .client
-            .get("https://api.github.com/user")
+            .get(self.api("/user"))
             .header("Authorization", &format!("Bearer {}", token))
             .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", "GitSwitchHub/1.0")
-            .send()
-            .await?;
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
 
         if !response.status().is_success() {
             return Err(GitHubAuthError::InvalidToken);
@@ -183,24 +505,519 @@ impl GitHubAuth {
         Ok(scopes)
     }
 
+    pub async fn get_token_details(&self, token: &str) -> Result<TokenDetails, GitHubAuthError> {
+        let request = self
+            .client
+            .get(self.api("/user"))
+            .header("Authorization", &format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
+
+        if !response.status().is_success() {
+            return Err(GitHubAuthError::InvalidToken);
+        }
+
+        let scopes_header = response
+            .headers()
+            .get("X-OAuth-Scopes")
+            .and_then(|h| h.to_str().ok());
+        // Only classic PATs and OAuth tokens have scopes, so an older token
+        // that reports them is a classic one
+        let kind = match TokenKind::from_prefix(token) {
+            TokenKind::Unknown if scopes_header.is_some() => TokenKind::Classic,
+            kind => kind,
+        };
+        let scopes = scopes_header
+            .map(|s| {
+                s.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Only present for tokens that expire (fine-grained PATs, expiring OAuth tokens)
+        let expires_at = response
+            .headers()
+            .get("GitHub-Authentication-Token-Expiration")
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_token_expiration);
+
+        let user: GitHubUser = response.json().await?;
+        Ok(TokenDetails {
+            user,
+            kind,
+            scopes,
+            expires_at,
+        })
+    }
+
+    // Organizations the token's user belongs to. Private memberships are
+    // only listed for tokens with the user or read:org scope.
+    pub async fn list_user_orgs(&self, token: &str) -> Result<Vec<GitHubOrg>, GitHubAuthError> {
+        self.get_pages(token, "/user/orgs").await
+    }
+
+    // SSH keys for authenticating to the account. Needs the read:public_key
+    // scope or better.
+    pub async fn list_ssh_keys(&self, token: &str) -> Result<Vec<GitHubKey>, GitHubAuthError> {
+        self.get_pages(token, "/user/keys").await
+    }
+
+    // Needs the admin:public_key scope. A key that's already gone counts as
+    // deleted.
+    pub async fn delete_ssh_key(&self, token: &str, id: u64) -> Result<(), GitHubAuthError> {
+        let request = self
+            .client
+            .delete(self.api(&format!("/user/keys/{}", id)))
+            .header("Authorization", &format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
+
+        let status = response.status();
+        if status.is_success() || status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(GitHubAuthError::InvalidToken);
+        }
+        if let Some(scope) = missing_scope(response.headers()) {
+            return Err(GitHubAuthError::MissingScope(scope));
+        }
+        Err(GitHubAuthError::Http(
+            response.error_for_status().unwrap_err(),
+        ))
+    }
+
+    // Every page of a list endpoint, up to a thousand items
+    async fn get_pages<T: serde::de::DeserializeOwned>(
+        &self,
+        token: &str,
+        path: &str,
+    ) -> Result<Vec<T>, GitHubAuthError> {
+        const PER_PAGE: usize = 100;
+        const MAX_PAGES: usize = 10;
+
+        let mut items = Vec::new();
+        for page in 1..=MAX_PAGES {
+            let request = self
+                .client
+                .get(self.api(path))
+                .query(&[("per_page", PER_PAGE), ("page", page)])
+                .header("Authorization", &format!("Bearer {}", token))
+                .header("Accept", "application/vnd.github.v3+json")
+                .header("User-Agent", "GitSwitchHub/1.0");
+            let response = self.client.send(request).await?;
+
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                return Err(GitHubAuthError::InvalidToken);
+            }
+            if !response.status().is_success() {
+                if let Some(scope) = missing_scope(response.headers()) {
+                    return Err(GitHubAuthError::MissingScope(scope));
+                }
+                return Err(GitHubAuthError::Http(
+                    response.error_for_status().unwrap_err(),
+                ));
+            }
+
+            let batch: Vec<T> = response.json().await?;
+            let last = batch.len() < PER_PAGE;
+            items.extend(batch);
+            if last {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    // Adds an SSH key for authenticating to the account. Needs the
+    // write:public_key or admin:public_key scope.
+    pub async fn add_ssh_key(
+        &self,
+        token: &str,
+        title: &str,
+        key: &str,
+    ) -> Result<GitHubKey, GitHubAuthError> {
+        self.post_json(
+            token,
+            "/user/keys",
+            &serde_json::json!({ "title": title, "key": key }),
+        )
+        .await
+    }
+
+    // Adds an SSH key GitHub verifies commit signatures with. Needs the
+    // write:ssh_signing_key or admin:ssh_signing_key scope.
+    pub async fn add_ssh_signing_key(
+        &self,
+        token: &str,
+        title: &str,
+        key: &str,
+    ) -> Result<GitHubKey, GitHubAuthError> {
+        self.post_json(
+            token,
+            "/user/ssh_signing_keys",
+            &serde_json::json!({ "title": title, "key": key }),
+        )
+        .await
+    }
+
+    // Adds an ASCII-armored GPG public key. Needs the write:gpg_key or
+    // admin:gpg_key scope.
+    pub async fn add_gpg_key(
+        &self,
+        token: &str,
+        name: &str,
+        armored_public_key: &str,
+    ) -> Result<GitHubGpgKey, GitHubAuthError> {
+        self.post_json(
+            token,
+            "/user/gpg_keys",
+            &serde_json::json!({ "name": name, "armored_public_key": armored_public_key }),
+        )
+        .await
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(
+        &self,
+        token: &str,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<T, GitHubAuthError> {
+        let request = self
+            .client
+            .post(self.api(path))
+            .json(body)
+            .header("Authorization", &format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(GitHubAuthError::InvalidToken);
+        }
+        if let Some(scope) = missing_scope(response.headers()) {
+            return Err(GitHubAuthError::MissingScope(scope));
+        }
+        let message = response
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| error_message(&body))
+            .unwrap_or_else(|| status.to_string());
+        Err(GitHubAuthError::Rejected(message))
+    }
+
+    // The token's user's role on `owner/repo`, None when the token can't see
+    // it. GitHub answers 404 rather than 403 for repositories a token wasn't
+    // granted.
+    pub async fn get_repo_permission(
+        &self,
+        token: &str,
+        full_name: &str,
+    ) -> Result<RepoPermission, GitHubAuthError> {
+        let request = self
+            .client
+            .get(self.api(&format!("/repos/{}", full_name)))
+            .header("Authorization", &format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => {
+                let repo: serde_json::Value = response.json().await?;
+                Ok(RepoPermission::from_permissions(repo.get("permissions")))
+            }
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::FORBIDDEN => {
+                Ok(RepoPermission::None)
+            }
+            reqwest::StatusCode::UNAUTHORIZED => Err(GitHubAuthError::InvalidToken),
+            _ => Err(GitHubAuthError::Http(
+                response.error_for_status().unwrap_err(),
+            )),
+        }
+    }
+
+    // Whether the token can act in `org`. A token that hasn't been
+    // authorized for an org enforcing SAML single sign-on gets a 403 with an
+    // X-GitHub-SSO header pointing at the page that authorizes it.
     pub async fn check_sso_requirement(
         &self,
         token: &str,
         org: &str,
-    ) -> Result<bool, GitHubAuthError> {
-        let response = self
+    ) -> Result<SsoStatus, GitHubAuthError> {
+        let request = self
             .client
-            .get(format!(
-                "https://api.github.com/orgs/{}/memberships/me",
-                org
-            ))
+            .get(self.api(&format!("/orgs/{}/memberships/me", org)))
             .header("Authorization", &format!("Bearer {}", token))
             .header("Accept", "application/vnd.github.v3+json")
-            .header("User-Agent", "GitSwitchHub/1.0")
-            .send()
-            .await?;
+            .header("User-Agent", "GitSwitchHub/1.0");
+        let response = self.client.send(request).await?;
+
+        let sso = response
+            .headers()
+            .get("X-GitHub-SSO")
+            .and_then(|h| h.to_str().ok())
+            .and_then(SsoHeader::parse);
+        let status = response.status();
+        Ok(match (status, sso) {
+            (_, Some(SsoHeader::Required { url })) => SsoStatus::Required { url },
+            (status, _) if status.is_success() => SsoStatus::Authorized,
+            (reqwest::StatusCode::UNAUTHORIZED, _) => return Err(GitHubAuthError::InvalidToken),
+            (reqwest::StatusCode::NOT_FOUND, _) => SsoStatus::NotMember,
+            (status, _) => SsoStatus::Failed {
+                http_status: status.as_u16(),
+            },
+        })
+    }
+}
+
+// Access to a repository, lowest first. Public repositories are readable
+// by everyone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepoPermission {
+    None,
+    Read,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+}
+
+impl RepoPermission {
+    // From the "permissions" object GitHub includes for authenticated requests
+    fn from_permissions(permissions: Option<&serde_json::Value>) -> Self {
+        let has = |name: &str| {
+            permissions
+                .and_then(|p| p.get(name))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false)
+        };
+        [
+            ("admin", Self::Admin),
+            ("maintain", Self::Maintain),
+            ("push", Self::Write),
+            ("triage", Self::Triage),
+        ]
+        .into_iter()
+        .find(|(name, _)| has(name))
+        .map_or(Self::Read, |(_, permission)| permission)
+    }
+
+    pub fn can_push(&self) -> bool {
+        *self >= Self::Write
+    }
+}
+
+// Result of checking a token against an organization
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SsoStatus {
+    Authorized,
+    // The org enforces SAML single sign-on and the token needs authorizing
+    // for it, at `url` when GitHub said where
+    Required { url: Option<String> },
+    // Not a member, or the org is hidden from this token
+    NotMember,
+    Failed { http_status: u16 },
+}
+
+// The X-GitHub-SSO header, e.g.
+//   required; url=https://github.com/orgs/acme/sso?authorization_request=...
+// Listings also send "partial-results", which isn't acted on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SsoHeader {
+    Required { url: Option<String> },
+}
+
+impl SsoHeader {
+    pub fn parse(value: &str) -> Option<Self> {
+        let kind = value.split(';').next()?.trim();
+        let param = |name: &str| {
+            value
+                .split(';')
+                .skip(1)
+                .filter_map(|part| part.trim().split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim().to_string())
+        };
+        if kind.eq_ignore_ascii_case("required") {
+            Some(Self::Required {
+                url: param("url").filter(|url| url.starts_with("https://")),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+// The default scopes followed by `extra`, without duplicates
+pub fn sign_in_scopes(extra: &[String]) -> Vec<String> {
+    let mut scopes: Vec<String> = DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect();
+    for scope in extra {
+        let scope = scope.trim();
+        if !scope.is_empty() && !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    scopes
+}
+
+// The scopes in `needed` that `granted` doesn't cover. Broader scopes cover
+// narrower ones: admin:org covers write:org and read:org, user covers
+// read:user and user:email.
+pub fn missing_scopes(granted: &[String], needed: &[&str]) -> Vec<String> {
+    let covers = |granted: &str, needed: &str| {
+        if granted == needed {
+            return true;
+        }
+        if granted == "user" {
+            return needed.starts_with("user:") || needed == "read:user";
+        }
+        if granted == "repo" {
+            return needed.starts_with("repo:") || needed == "public_repo";
+        }
+        let (granted_level, granted_resource) = match granted.split_once(':') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        match needed.split_once(':') {
+            Some((needed_level, resource)) if resource == granted_resource => matches!(
+                (granted_level, needed_level),
+                ("admin", "write") | ("admin", "read") | ("write", "read")
+            ),
+            _ => false,
+        }
+    };
+    needed
+        .iter()
+        .filter(|needed| !granted.iter().any(|granted| covers(granted, needed)))
+        .map(|needed| needed.to_string())
+        .collect()
+}
+
+// The scopes an endpoint accepts, when the token has none of them. Only
+// classic and OAuth tokens report scopes; fine-grained ones are left to the
+// error message.
+fn missing_scope(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let scopes = |name: &str| -> Option<Vec<String>> {
+        let value = headers.get(name)?.to_str().ok()?;
+        Some(
+            value
+                .split(',')
+                .map(|scope| scope.trim().to_string())
+                .filter(|scope| !scope.is_empty())
+                .collect(),
+        )
+    };
+    let granted = scopes("X-OAuth-Scopes")?;
+    let accepted = scopes("X-Accepted-OAuth-Scopes")?;
+    if accepted.is_empty() || accepted.iter().any(|scope| granted.contains(scope)) {
+        return None;
+    }
+    Some(accepted.join(" or "))
+}
+
+// "key is already in use" rather than just "Validation Failed"
+fn error_message(body: &serde_json::Value) -> Option<String> {
+    let details: Vec<&str> = body
+        .get("errors")
+        .and_then(|errors| errors.as_array())
+        .map(|errors| {
+            errors
+                .iter()
+                .filter_map(|error| error.get("message").and_then(|m| m.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if !details.is_empty() {
+        return Some(details.join("; "));
+    }
+    body.get("message")
+        .and_then(|m| m.as_str())
+        .map(str::to_string)
+}
+
+// GitHub sends e.g. "2024-03-01 12:00:00 UTC" or "2024-03-01 12:00:00 -0800"
+// Sleeps for `duration`, returning Cancelled as soon as `cancelled` is set
+async fn wait_unless_cancelled(
+    duration: Duration,
+    cancelled: &AtomicBool,
+) -> Result<(), GitHubAuthError> {
+    let deadline = Instant::now() + duration;
+    loop {
+        if cancelled.load(Ordering::SeqCst) {
+            return Err(GitHubAuthError::Cancelled);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(());
+        }
+        sleep(remaining.min(CANCEL_CHECK_INTERVAL)).await;
+    }
+}
+
+fn parse_token_expiration(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(date) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z") {
+        return Some(date.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(value.trim_end_matches("UTC").trim(), "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|date| date.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sso_header_required_with_url() {
+        assert_eq!(
+            SsoHeader::parse(
+                "required; url=https://github.com/orgs/acme/sso?authorization_request=AbC1"
+            ),
+            Some(SsoHeader::Required {
+                url: Some(
+                    "https://github.com/orgs/acme/sso?authorization_request=AbC1".to_string()
+                ),
+            })
+        );
+        assert_eq!(
+            SsoHeader::parse("Required;URL=https://github.com/orgs/acme/sso"),
+            Some(SsoHeader::Required {
+                url: Some("https://github.com/orgs/acme/sso".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn sso_header_drops_unusable_urls() {
+        assert_eq!(
+            SsoHeader::parse("required"),
+            Some(SsoHeader::Required { url: None })
+        );
+        assert_eq!(
+            SsoHeader::parse("required; url=javascript:alert(1)"),
+            Some(SsoHeader::Required { url: None })
+        );
+    }
 
-        // If we get a 401 or 403, it might be due to SSO requirement
-        Ok(!response.status().is_success())
+    #[test]
+    fn sso_header_ignores_other_kinds() {
+        assert_eq!(
+            SsoHeader::parse("partial-results; organizations=21955855,20582480"),
+            None
+        );
+        assert_eq!(SsoHeader::parse(""), None);
     }
 }
@@ -0,0 +1,286 @@
+// Every request to GitHub goes through GitHubClient. It keeps track of the
+// rate limit each host reports, waits out short rate limits instead of
+// failing, and retries server errors and dropped connections with backoff.
+use crate::database::Database;
+use crate::settings::SettingKey;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Client, NoProxy, Proxy, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+const MAX_RETRIES: u32 = 3;
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+// Longer rate limits are reported to the caller rather than waited out
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+// Secondary rate limits without a Retry-After header ask for at least a minute
+const SECONDARY_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum GitHubClientError {
+    #[error("Invalid proxy {0}: {1}")]
+    InvalidProxy(String, String),
+    #[error("Failed to read CA bundle {0}: {1}")]
+    CaBundle(String, String),
+    #[error("Failed to set up HTTP client: {0}")]
+    Build(#[from] reqwest::Error),
+}
+
+// How requests reach GitHub. Without an explicit proxy the usual
+// HTTP_PROXY, HTTPS_PROXY and NO_PROXY variables apply.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkSettings {
+    pub proxy: Option<String>,
+    // Comma-separated hosts that bypass `proxy`
+    pub no_proxy: Option<String>,
+    // PEM certificates trusted in addition to the system's, for proxies
+    // that intercept TLS
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl NetworkSettings {
+    pub fn from_db(db: &Database) -> Self {
+        let string = |key| {
+            db.get_setting(key)
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .filter(|value| !value.trim().is_empty())
+        };
+        Self {
+            proxy: string(SettingKey::HttpProxy),
+            no_proxy: string(SettingKey::NoProxy),
+            ca_bundle: string(SettingKey::CaBundlePath).map(PathBuf::from),
+        }
+    }
+
+    pub fn build_client(&self) -> Result<Client, GitHubClientError> {
+        let mut builder = Client::builder();
+        if let Some(url) = &self.proxy {
+            let proxy = Proxy::all(url)
+                .map_err(|e| GitHubClientError::InvalidProxy(url.clone(), e.to_string()))?
+                .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string));
+            builder = builder.proxy(proxy);
+        }
+        if let Some(path) = &self.ca_bundle {
+            let display = path.display().to_string();
+            let pem = fs::read(path)
+                .map_err(|e| GitHubClientError::CaBundle(display.clone(), e.to_string()))?;
+            let certificates = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| GitHubClientError::CaBundle(display.clone(), e.to_string()))?;
+            if certificates.is_empty() {
+                return Err(GitHubClientError::CaBundle(
+                    display,
+                    "no certificates found".to_string(),
+                ));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(builder.build()?)
+    }
+}
+
+fn shared_client() -> &'static RwLock<Option<Client>> {
+    static CLIENT: OnceLock<RwLock<Option<Client>>> = OnceLock::new();
+    CLIENT.get_or_init(Default::default)
+}
+
+// Applies network settings to every GitHubClient created afterwards
+pub fn configure(settings: &NetworkSettings) -> Result<(), GitHubClientError> {
+    let client = settings.build_client()?;
+    *shared_client().write().unwrap() = Some(client);
+    Ok(())
+}
+
+// Like configure, reading the settings from the database. A broken setting
+// is logged and leaves the default client in place.
+pub fn configure_from_db(db: &Database) {
+    if let Err(e) = configure(&NetworkSettings::from_db(db)) {
+        warn!(error = %e, "ignoring network settings");
+    }
+}
+
+// The most recent rate limit a host reported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitStatus {
+    pub host: String,
+    // "core", "search", "graphql", ...
+    pub resource: String,
+    pub limit: u64,
+    pub remaining: u64,
+    pub used: u64,
+    pub reset_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn rate_limits() -> &'static Mutex<HashMap<String, RateLimitStatus>> {
+    static RATE_LIMITS: OnceLock<Mutex<HashMap<String, RateLimitStatus>>> = OnceLock::new();
+    RATE_LIMITS.get_or_init(Default::default)
+}
+
+// Rate limits seen by this process, one per host
+pub fn rate_limit_status() -> Vec<RateLimitStatus> {
+    let mut statuses: Vec<_> = rate_limits().lock().unwrap().values().cloned().collect();
+    statuses.sort_by(|a, b| a.host.cmp(&b.host));
+    statuses
+}
+
+#[derive(Clone)]
+pub struct GitHubClient {
+    client: Client,
+}
+
+impl Default for GitHubClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GitHubClient {
+    pub fn new() -> Self {
+        // Clients share their connection pool, so this is cheap
+        let client = shared_client().read().unwrap().clone();
+        Self {
+            client: client.unwrap_or_default(),
+        }
+    }
+
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn delete(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.delete(url)
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let mut attempt = 0;
+        loop {
+            // Requests with a streaming body can't be repeated
+            let current = match request.try_clone() {
+                Some(current) => current,
+                None => return request.send().await,
+            };
+
+            let response = match current.send().await {
+                Ok(response) => response,
+                Err(e) if attempt < MAX_RETRIES && (e.is_connect() || e.is_timeout()) => {
+                    let wait = backoff(attempt);
+                    debug!(error = %e, ?wait, "GitHub request failed, retrying");
+                    sleep(wait).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            record_rate_limit(&response);
+            if attempt >= MAX_RETRIES {
+                return Ok(response);
+            }
+            let wait = match retry_wait(&response, attempt) {
+                Some(wait) => wait,
+                None => return Ok(response),
+            };
+            warn!(
+                status = %response.status(),
+                url = %response.url(),
+                ?wait,
+                "GitHub asked to retry later"
+            );
+            sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+// How long to wait before repeating a request, None when the response should
+// go back to the caller as it is
+fn retry_wait(response: &Response, attempt: u32) -> Option<Duration> {
+    let status = response.status();
+    let headers = response.headers();
+    if status == StatusCode::FORBIDDEN || status == StatusCode::TOO_MANY_REQUESTS {
+        // Secondary rate limits say how long to back off
+        if let Some(secs) = header_u64(headers, "retry-after") {
+            return Some(Duration::from_secs(secs)).filter(|wait| *wait <= MAX_RATE_LIMIT_WAIT);
+        }
+        // The primary rate limit resets at a fixed time
+        if header_u64(headers, "x-ratelimit-remaining") == Some(0) {
+            let reset = header_u64(headers, "x-ratelimit-reset")?;
+            let wait = (reset as i64 - Utc::now().timestamp()).max(1) as u64;
+            return Some(Duration::from_secs(wait)).filter(|wait| *wait <= MAX_RATE_LIMIT_WAIT);
+        }
+        // Anything else that's a 403 is a permission problem
+        return (status == StatusCode::TOO_MANY_REQUESTS).then_some(SECONDARY_LIMIT_WAIT);
+    }
+
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+    .then(|| backoff(attempt))
+}
+
+// Exponential backoff with up to 50% jitter, so clients don't retry in step
+fn backoff(attempt: u32) -> Duration {
+    let base = BASE_BACKOFF * 2u32.pow(attempt);
+    let jitter = base.mul_f64((OsRng.next_u32() % 500) as f64 / 1000.0);
+    base + jitter
+}
+
+fn record_rate_limit(response: &Response) {
+    let headers = response.headers();
+    let (limit, remaining) = match (
+        header_u64(headers, "x-ratelimit-limit"),
+        header_u64(headers, "x-ratelimit-remaining"),
+    ) {
+        (Some(limit), Some(remaining)) => (limit, remaining),
+        // Enterprise hosts with rate limiting turned off send neither
+        _ => return,
+    };
+    let host = match response.url().host_str() {
+        // api.github.com is github.com's API
+        Some(host) => host.strip_prefix("api.").unwrap_or(host).to_lowercase(),
+        None => return,
+    };
+
+    let status = RateLimitStatus {
+        resource: headers
+            .get("x-ratelimit-resource")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("core")
+            .to_string(),
+        limit,
+        remaining,
+        used: header_u64(headers, "x-ratelimit-used").unwrap_or(limit.saturating_sub(remaining)),
+        reset_at: header_u64(headers, "x-ratelimit-reset")
+            .and_then(|secs| Utc.timestamp_opt(secs as i64, 0).single()),
+        updated_at: Utc::now(),
+        host: host.clone(),
+    };
+    if remaining == 0 {
+        warn!(host = %host, reset_at = ?status.reset_at, "GitHub rate limit exhausted");
+    }
+    rate_limits().lock().unwrap().insert(host, status);
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
@@ -0,0 +1,179 @@
+use crate::database::{Database, DatabaseError};
+use crate::git_config::{self, GitConfigError};
+use crate::git_helper::sh_path;
+use crate::remote;
+use crate::resolver::{self, ResolutionSource, ResolveRequest};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Hooks that stop commits and pushes going out under the wrong identity.
+// They call back into the app, which compares the repository's user.email
+// with the account assigned to it, so they follow later mapping changes.
+
+#[derive(Error, Debug)]
+pub enum IdentityGuardError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+    #[error("Git config error: {0}")]
+    GitConfig(#[from] GitConfigError),
+    #[error("{0} already exists and wasn't installed by GitSwitchHub")]
+    ForeignHook(String),
+}
+
+pub const HOOKS: [&str; 2] = ["pre-commit", "pre-push"];
+
+const HOOK_MARKER: &str = "# GitSwitchHub identity guard";
+
+// Writes the hooks, replacing earlier versions of ours. Hooks of other tools
+// are never overwritten. Returns the paths written.
+pub fn install(repo_path: &Path, exe: &Path) -> Result<Vec<PathBuf>, IdentityGuardError> {
+    let hooks_dir = git_config::hooks_dir(repo_path)?;
+    let hooks: Vec<PathBuf> = HOOKS.iter().map(|hook| hooks_dir.join(hook)).collect();
+    for path in &hooks {
+        if path.exists() && !is_ours(path)? {
+            return Err(IdentityGuardError::ForeignHook(path.display().to_string()));
+        }
+    }
+
+    fs::create_dir_all(&hooks_dir)?;
+    for (hook, path) in HOOKS.iter().zip(&hooks) {
+        fs::write(path, hook_script(hook, exe))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(hooks)
+}
+
+// Removes our hooks, returning the paths removed
+pub fn uninstall(repo_path: &Path) -> Result<Vec<PathBuf>, IdentityGuardError> {
+    let hooks_dir = git_config::hooks_dir(repo_path)?;
+    let mut removed = Vec::new();
+    for hook in HOOKS {
+        let path = hooks_dir.join(hook);
+        if path.exists() && is_ours(&path)? {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+fn is_ours(path: &Path) -> io::Result<bool> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .any(|line| line.trim() == HOOK_MARKER))
+}
+
+// The hook lets everything through once the app is gone, rather than
+// blocking every commit
+fn hook_script(hook: &str, exe: &Path) -> String {
+    format!(
+        "#!/bin/sh\n\
+         {}\n\
+         # Checks user.email against the account GitSwitchHub assigns to this\n\
+         # repository. Skip it once with --no-verify, or delete this file.\n\
+         exe={}\n\
+         [ -x \"$exe\" ] || exit 0\n\
+         exec \"$exe\" identity-guard {} \"$@\"\n",
+        HOOK_MARKER,
+        sh_path(exe),
+        hook
+    )
+}
+
+// Why the commit or push should be stopped, None when the identity matches
+// or the repository has no account assigned. Only explicit assignments
+// count: the host default and the last used account are guesses.
+// For pre-push `remote_url` is the url being pushed to, otherwise origin or
+// the first remote is checked.
+pub fn check(
+    db: &Database,
+    repo_path: &Path,
+    hook: &str,
+    remote_url: Option<&str>,
+) -> Result<Option<String>, IdentityGuardError> {
+    let remote_url = match remote_url {
+        Some(url) => Some(url.to_string()),
+        None => repository_remote(repo_path)?,
+    };
+    let Some((repo_url, host)) = remote_url.as_deref().and_then(remote::mapping_target) else {
+        return Ok(None);
+    };
+    let toplevel = git_config::toplevel(repo_path)?;
+    let resolution = resolver::resolve(
+        db,
+        &ResolveRequest {
+            repo_url: &repo_url,
+            host: &host,
+            owner: remote::repo_owner(&repo_url, "").as_deref(),
+            path: toplevel.as_deref().or(Some(repo_path)),
+        },
+    )?;
+    let Some(candidate) = resolution.candidates.into_iter().next() else {
+        return Ok(None);
+    };
+    if matches!(
+        candidate.source,
+        ResolutionSource::HostDefault | ResolutionSource::LastUsed
+    ) {
+        return Ok(None);
+    }
+    let account = candidate.account;
+    let Some(expected) = account.commit_email() else {
+        return Ok(None);
+    };
+
+    // The value git uses: the last one read, local config last
+    let email = git_config::get_entries(Some(repo_path), r"^user\.email$")?
+        .pop()
+        .map(|entry| entry.value);
+    let allowed = [account.git_email.clone(), account.noreply_email()];
+    if let Some(email) = &email {
+        if allowed
+            .iter()
+            .flatten()
+            .any(|allowed| allowed.eq_ignore_ascii_case(email))
+        {
+            return Ok(None);
+        }
+    }
+
+    let action = if hook == "pre-push" { "push" } else { "commit" };
+    let authored = match &email {
+        Some(email) => format!("commits are authored as {}", email),
+        None => "no user.email is set".to_string(),
+    };
+    Ok(Some(
+        [
+            format!(
+                "GitSwitchHub: this repository uses the {} account ({}), but {}.",
+                account.username, candidate.reason, authored
+            ),
+            "Set the account's email for this repository with".to_string(),
+            String::new(),
+            format!("    git config user.email \"{}\"", expected),
+            String::new(),
+            format!("or {} anyway with --no-verify.", action),
+        ]
+        .join("\n"),
+    ))
+}
+
+fn repository_remote(repo_path: &Path) -> Result<Option<String>, IdentityGuardError> {
+    let entries: Vec<_> = git_config::get_entries(Some(repo_path), r"^remote\..*\.url$")?
+        .into_iter()
+        .filter(|entry| entry.scope == "local")
+        .collect();
+    Ok(entries
+        .iter()
+        .find(|entry| entry.key == "remote.origin.url")
+        .or(entries.first())
+        .map(|entry| entry.value.clone()))
+}
@@ -1,21 +1,127 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use crate::github_auth::{DeviceTokenResponse, DEFAULT_GITHUB_HOST};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 use thiserror::Error;
 
+// Each platform module exposes the same set of free functions operating on
+// (service, account) pairs, so KeychainManager stays platform agnostic.
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos as platform;
+
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "windows")]
+use windows as platform;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+use linux as platform;
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod memory;
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+use memory as platform;
+
+mod biometric;
+mod vault;
+
+use biometric::BiometricGate;
+pub use biometric::BiometricSettings;
+use vault::Vault;
+pub use vault::{seal, unseal};
+
+const SERVICE_NAME: &str = "com.gitswitchhub.app";
+// Entries written before host namespacing used "github:{username}"
+const LEGACY_ACCOUNT_PREFIX: &str = "github:";
+// Additional named tokens are stored as "{host}:{username}#{name}"
+const TOKEN_NAME_SEPARATOR: char = '#';
+// Refresh tokens live next to the access token as "refresh:{host}:{username}"
+const REFRESH_PREFIX: &str = "refresh:";
+const VAULT_PASSWORD_ENV: &str = "GITSWITCHHUB_VAULT_PASSWORD";
+
 #[derive(Error, Debug)]
 pub enum KeychainError {
     #[error("Keychain access denied")]
     AccessDenied,
     #[error("Item not found")]
     ItemNotFound,
+    #[error("Keychain service unavailable: {0}")]
+    Unavailable(String),
+    #[error("Vault is locked")]
+    Locked,
+    #[error("Incorrect master password")]
+    InvalidPassword,
+    #[error("Keychain error: {0}")]
+    Platform(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeychainStatus {
+    pub backend: String,
+    pub available: bool,
+    pub locked: bool,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeychainEntry {
+    pub key: String,
+    pub host: Option<String>,
+    pub username: Option<String>,
+    pub legacy: bool,
+    pub refresh: bool,
+    pub token_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshCredentials {
+    pub refresh_token: String,
+    pub access_token_expires_at: Option<DateTime<Utc>>,
+    pub refresh_token_expires_at: Option<DateTime<Utc>>,
+}
+
+impl RefreshCredentials {
+    pub fn from_token_response(response: &DeviceTokenResponse) -> Option<Self> {
+        let now = Utc::now();
+        let refresh_token = response.refresh_token.clone()?;
+        Some(Self {
+            refresh_token,
+            access_token_expires_at: response
+                .expires_in
+                .map(|secs| now + chrono::Duration::seconds(secs as i64)),
+            refresh_token_expires_at: response
+                .refresh_token_expires_in
+                .map(|secs| now + chrono::Duration::seconds(secs as i64)),
+        })
+    }
+
+    // Renew a little early so git never receives a token that expires mid-operation
+    pub fn access_token_expiring(&self) -> bool {
+        self.access_token_expires_at
+            .map(|expires_at| expires_at - chrono::Duration::minutes(5) <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    pub fn access_token_expired(&self) -> bool {
+        self.access_token_expires_at
+            .map(|expires_at| expires_at <= Utc::now())
+            .unwrap_or(false)
+    }
+}
+
 pub struct KeychainManager {
-    // For now, use in-memory storage
-    // In production, this would use macOS Keychain
-    storage: Arc<Mutex<HashMap<String, String>>>,
+    service: String,
+    native_available: bool,
+    vault: Mutex<Vault>,
+    biometric: BiometricGate,
 }
 
 impl Default for KeychainManager {
@@ -26,41 +132,351 @@ impl Default for KeychainManager {
 
 impl KeychainManager {
     pub fn new() -> Self {
+        let vault_path = Vault::default_path().unwrap_or_else(|_| PathBuf::from("vault"));
+        let mut vault = Vault::new(vault_path);
+
+        // Headless environments (e.g. the credential helper on a server) can
+        // unlock the vault non-interactively
+        if vault.exists() {
+            if let Ok(password) = std::env::var(VAULT_PASSWORD_ENV) {
+                let _ = vault.unlock(&password);
+            }
+        }
+
+        let biometric_path =
+            BiometricGate::default_path().unwrap_or_else(|_| PathBuf::from("biometric.json"));
+
         Self {
-            storage: Arc::new(Mutex::new(HashMap::new())),
+            service: SERVICE_NAME.to_string(),
+            native_available: platform::probe().is_ok(),
+            vault: Mutex::new(vault),
+            biometric: BiometricGate::new(biometric_path),
         }
     }
 
-    pub fn store_token(&self, account: &str, token: &str) -> Result<(), KeychainError> {
-        let account_key = format!("github:{}", account);
-        let mut storage = self.storage.lock().unwrap();
-        storage.insert(account_key, token.to_string());
-        Ok(())
+    // The vault takes over once it has been created, or whenever the native
+    // keychain cannot be reached at all.
+    fn vault_active(&self) -> bool {
+        !self.native_available || self.vault.lock().unwrap().exists()
     }
 
-    pub fn get_token(&self, account: &str) -> Result<String, KeychainError> {
-        let account_key = format!("github:{}", account);
-        let storage = self.storage.lock().unwrap();
-        storage
-            .get(&account_key)
-            .cloned()
-            .ok_or(KeychainError::ItemNotFound)
+    pub fn status(&self) -> KeychainStatus {
+        if self.vault_active() {
+            let mut vault = self.vault.lock().unwrap();
+            let (available, locked, message) = if !vault.exists() {
+                (false, true, Some("No master password set".to_string()))
+            } else if vault.is_locked() {
+                (false, true, Some(KeychainError::Locked.to_string()))
+            } else {
+                (true, false, None)
+            };
+            return KeychainStatus {
+                backend: vault::BACKEND_NAME.to_string(),
+                available,
+                locked,
+                message,
+            };
+        }
+
+        match platform::probe() {
+            Ok(()) => KeychainStatus {
+                backend: platform::BACKEND_NAME.to_string(),
+                available: true,
+                locked: false,
+                message: None,
+            },
+            Err(e) => KeychainStatus {
+                backend: platform::BACKEND_NAME.to_string(),
+                available: false,
+                locked: false,
+                message: Some(e.to_string()),
+            },
+        }
     }
 
-    pub fn delete_token(&self, account: &str) -> Result<(), KeychainError> {
-        let account_key = format!("github:{}", account);
-        let mut storage = self.storage.lock().unwrap();
-        storage.remove(&account_key);
+    pub fn set_master_password(
+        &self,
+        current_password: Option<&str>,
+        new_password: &str,
+    ) -> Result<(), KeychainError> {
+        let mut vault = self.vault.lock().unwrap();
+        let creating = !vault.exists();
+        vault.set_master_password(current_password, new_password)?;
+
+        // Carry over tokens already in the native keychain so switching to
+        // the vault doesn't strand existing accounts
+        if creating && self.native_available {
+            for account_key in platform::list_accounts(&self.service).unwrap_or_default() {
+                if let Ok(secret) = platform::get_secret(&self.service, &account_key) {
+                    vault.set(&account_key, &secret)?;
+                }
+            }
+        }
         Ok(())
     }
 
-    pub fn list_tokens(&self) -> Result<Vec<String>, KeychainError> {
-        let storage = self.storage.lock().unwrap();
-        let accounts: Vec<String> = storage
-            .keys()
-            .filter(|key| key.starts_with("github:"))
-            .map(|key| key.strip_prefix("github:").unwrap_or(key).to_string())
+    pub fn unlock_vault(&self, password: &str) -> Result<(), KeychainError> {
+        self.vault.lock().unwrap().unlock(password)
+    }
+
+    pub fn lock_vault(&self) {
+        self.vault.lock().unwrap().lock();
+    }
+
+    pub fn set_vault_auto_lock(&self, after: Duration) {
+        self.vault.lock().unwrap().set_auto_lock(after);
+    }
+
+    pub fn biometric_settings(&self) -> BiometricSettings {
+        self.biometric.settings()
+    }
+
+    pub fn set_biometric_settings(&self, settings: BiometricSettings) -> Result<(), KeychainError> {
+        self.biometric.set_settings(settings)
+    }
+
+    pub fn store_token(&self, host: &str, account: &str, token: &str) -> Result<(), KeychainError> {
+        self.set_secret(&account_key(host, account), token)
+    }
+
+    pub fn get_token(&self, host: &str, account: &str) -> Result<String, KeychainError> {
+        self.biometric
+            .require_user_presence(&format!("access the GitHub token for {}", account))?;
+
+        match self.get_secret(&account_key(host, account)) {
+            Err(KeychainError::ItemNotFound) if host == DEFAULT_GITHUB_HOST => {
+                // Move a legacy entry to the namespaced key on first use
+                let legacy_key = format!("{}{}", LEGACY_ACCOUNT_PREFIX, account);
+                let token = self.get_secret(&legacy_key)?;
+                if self.set_secret(&account_key(host, account), &token).is_ok() {
+                    let _ = self.delete_secret(&legacy_key);
+                }
+                Ok(token)
+            }
+            result => result,
+        }
+    }
+
+    // Swaps the stored token, restoring the previous one if the new token
+    // cannot be read back.
+    pub fn replace_token(
+        &self,
+        host: &str,
+        account: &str,
+        new_token: &str,
+    ) -> Result<(), KeychainError> {
+        let key = account_key(host, account);
+        let previous = self.get_secret(&key).ok();
+
+        let result = self
+            .set_secret(&key, new_token)
+            .and_then(|_| self.get_secret(&key))
+            .and_then(|stored| {
+                if stored == new_token {
+                    Ok(())
+                } else {
+                    Err(KeychainError::Platform(
+                        "Stored token does not match the new token".to_string(),
+                    ))
+                }
+            });
+
+        if result.is_err() {
+            if let Some(previous) = previous {
+                let _ = self.set_secret(&key, &previous);
+            }
+        }
+        result
+    }
+
+    pub fn store_named_token(
+        &self,
+        host: &str,
+        account: &str,
+        name: &str,
+        token: &str,
+    ) -> Result<(), KeychainError> {
+        self.set_secret(&named_token_key(host, account, name), token)
+    }
+
+    pub fn get_named_token(
+        &self,
+        host: &str,
+        account: &str,
+        name: &str,
+    ) -> Result<String, KeychainError> {
+        self.biometric.require_user_presence(&format!(
+            "access the GitHub token \"{}\" for {}",
+            name, account
+        ))?;
+        self.get_secret(&named_token_key(host, account, name))
+    }
+
+    pub fn delete_named_token(
+        &self,
+        host: &str,
+        account: &str,
+        name: &str,
+    ) -> Result<(), KeychainError> {
+        match self.delete_secret(&named_token_key(host, account, name)) {
+            Err(KeychainError::ItemNotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    pub fn store_refresh_credentials(
+        &self,
+        host: &str,
+        account: &str,
+        credentials: &RefreshCredentials,
+    ) -> Result<(), KeychainError> {
+        let value = serde_json::to_string(credentials)
+            .map_err(|e| KeychainError::Platform(e.to_string()))?;
+        self.set_secret(&refresh_key(host, account), &value)
+    }
+
+    pub fn get_refresh_credentials(
+        &self,
+        host: &str,
+        account: &str,
+    ) -> Result<Option<RefreshCredentials>, KeychainError> {
+        match self.get_secret(&refresh_key(host, account)) {
+            Ok(value) => serde_json::from_str(&value)
+                .map(Some)
+                .map_err(|e| KeychainError::Platform(e.to_string())),
+            Err(KeychainError::ItemNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn delete_token(&self, host: &str, account: &str) -> Result<(), KeychainError> {
+        let _ = self.delete_secret(&refresh_key(host, account));
+        if host == DEFAULT_GITHUB_HOST {
+            let _ = self.delete_secret(&format!("{}{}", LEGACY_ACCOUNT_PREFIX, account));
+        }
+        match self.delete_secret(&account_key(host, account)) {
+            // Deleting something that is already gone is not an error
+            Err(KeychainError::ItemNotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    // Returns (host, username) pairs for every stored token
+    pub fn list_tokens(&self) -> Result<Vec<(String, String)>, KeychainError> {
+        let accounts: Vec<(String, String)> = self
+            .list_accounts()?
+            .into_iter()
+            .filter_map(|key| parse_account_key(&key))
             .collect();
         Ok(accounts)
     }
+
+    // Raw view of every entry under our service, including legacy and
+    // unparseable keys, for auditing.
+    pub fn list_entries(&self) -> Result<Vec<KeychainEntry>, KeychainError> {
+        let entries = self
+            .list_accounts()?
+            .into_iter()
+            .map(|key| {
+                let refresh = key.starts_with(REFRESH_PREFIX);
+                let base = key.strip_prefix(REFRESH_PREFIX).unwrap_or(&key);
+                let (base, token_name) = match base.split_once(TOKEN_NAME_SEPARATOR) {
+                    Some((base, name)) => (base, Some(name.to_string())),
+                    None => (base, None),
+                };
+                let parsed = parse_account_key(base);
+                KeychainEntry {
+                    legacy: key.starts_with(LEGACY_ACCOUNT_PREFIX),
+                    refresh,
+                    token_name,
+                    host: parsed.as_ref().map(|(host, _)| host.clone()),
+                    username: parsed.map(|(_, username)| username),
+                    key,
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    pub fn delete_entry(&self, key: &str) -> Result<(), KeychainError> {
+        match self.delete_secret(key) {
+            Err(KeychainError::ItemNotFound) => Ok(()),
+            result => result,
+        }
+    }
+
+    // Moves a legacy "github:{username}" entry to its namespaced key without
+    // prompting. Returns false when there was nothing to migrate.
+    pub fn migrate_legacy_entry(&self, account: &str) -> Result<bool, KeychainError> {
+        let legacy_key = format!("{}{}", LEGACY_ACCOUNT_PREFIX, account);
+        let token = match self.get_secret(&legacy_key) {
+            Ok(token) => token,
+            Err(KeychainError::ItemNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let key = account_key(DEFAULT_GITHUB_HOST, account);
+        if matches!(self.get_secret(&key), Err(KeychainError::ItemNotFound)) {
+            self.set_secret(&key, &token)?;
+        }
+        self.delete_secret(&legacy_key)?;
+        Ok(true)
+    }
+
+    fn set_secret(&self, account_key: &str, secret: &str) -> Result<(), KeychainError> {
+        if self.vault_active() {
+            return self.vault.lock().unwrap().set(account_key, secret);
+        }
+        platform::set_secret(&self.service, account_key, secret)
+    }
+
+    fn get_secret(&self, account_key: &str) -> Result<String, KeychainError> {
+        if self.vault_active() {
+            return self.vault.lock().unwrap().get(account_key);
+        }
+        platform::get_secret(&self.service, account_key)
+    }
+
+    fn delete_secret(&self, account_key: &str) -> Result<(), KeychainError> {
+        if self.vault_active() {
+            return self.vault.lock().unwrap().delete(account_key);
+        }
+        platform::delete_secret(&self.service, account_key)
+    }
+
+    fn list_accounts(&self) -> Result<Vec<String>, KeychainError> {
+        if self.vault_active() {
+            return self.vault.lock().unwrap().keys();
+        }
+        platform::list_accounts(&self.service)
+    }
+}
+
+fn account_key(host: &str, account: &str) -> String {
+    format!("{}:{}", host.to_lowercase(), account)
+}
+
+fn named_token_key(host: &str, account: &str, name: &str) -> String {
+    format!(
+        "{}{}{}",
+        account_key(host, account),
+        TOKEN_NAME_SEPARATOR,
+        name
+    )
+}
+
+fn refresh_key(host: &str, account: &str) -> String {
+    format!("{}{}", REFRESH_PREFIX, account_key(host, account))
+}
+
+fn parse_account_key(key: &str) -> Option<(String, String)> {
+    if key.starts_with(REFRESH_PREFIX) || key.contains(TOKEN_NAME_SEPARATOR) {
+        return None;
+    }
+    if let Some(account) = key.strip_prefix(LEGACY_ACCOUNT_PREFIX) {
+        return Some((DEFAULT_GITHUB_HOST.to_string(), account.to_string()));
+    }
+    // Hosts may carry a port, usernames never contain ':'
+    key.rsplit_once(':')
+        .filter(|(host, account)| !host.is_empty() && !account.is_empty())
+        .map(|(host, account)| (host.to_string(), account.to_string()))
 }
@@ -0,0 +1,164 @@
+use super::KeychainError;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+const DEFAULT_GRACE_PERIOD_SECS: u64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BiometricSettings {
+    pub enabled: bool,
+    pub grace_period_secs: u64,
+}
+
+impl Default for BiometricSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            grace_period_secs: DEFAULT_GRACE_PERIOD_SECS,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct GateState {
+    settings: BiometricSettings,
+    // Unix timestamp of the last successful verification. Persisted so that
+    // short-lived credential helper processes share the grace period.
+    last_verified_at: Option<i64>,
+}
+
+pub struct BiometricGate {
+    path: PathBuf,
+}
+
+impl BiometricGate {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn default_path() -> Result<PathBuf, KeychainError> {
+        Ok(crate::paths::app_data_dir()?.join("biometric.json"))
+    }
+
+    pub fn settings(&self) -> BiometricSettings {
+        self.load().settings
+    }
+
+    pub fn set_settings(&self, settings: BiometricSettings) -> Result<(), KeychainError> {
+        let current = self.settings();
+        // Turning the protection off or asking less often takes the same check
+        // as reading a token, or any process running as the user could skip it
+        if current.enabled
+            && (!settings.enabled || settings.grace_period_secs > current.grace_period_secs)
+        {
+            verify_user("lower the biometric protection for GitHub tokens")?;
+        } else if settings.enabled && !current.enabled {
+            // Make sure the prompt actually works before locking tokens behind it
+            verify_user("enable biometric protection for GitHub tokens")?;
+        }
+        let state = GateState {
+            settings,
+            last_verified_at: None,
+        };
+        self.save(&state)
+    }
+
+    pub fn require_user_presence(&self, reason: &str) -> Result<(), KeychainError> {
+        let mut state = self.load();
+        if !state.settings.enabled {
+            return Ok(());
+        }
+
+        let now = Utc::now().timestamp();
+        if let Some(last) = state.last_verified_at {
+            let elapsed = now.saturating_sub(last);
+            if elapsed >= 0 && (elapsed as u64) < state.settings.grace_period_secs {
+                return Ok(());
+            }
+        }
+
+        verify_user(reason)?;
+        state.last_verified_at = Some(now);
+        self.save(&state)
+    }
+
+    fn load(&self) -> GateState {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, state: &GateState) -> Result<(), KeychainError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents =
+            serde_json::to_string(state).map_err(|e| KeychainError::Platform(e.to_string()))?;
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn verify_user(reason: &str) -> Result<(), KeychainError> {
+    use block2::RcBlock;
+    use objc2::runtime::Bool;
+    use objc2_foundation::{NSError, NSString};
+    use objc2_local_authentication::{LAContext, LAPolicy};
+    use std::sync::mpsc;
+
+    // Touch ID with the account password as fallback
+    let policy = LAPolicy::DeviceOwnerAuthentication;
+    let context = unsafe { LAContext::new() };
+    unsafe { context.canEvaluatePolicy_error(policy) }
+        .map_err(|e| KeychainError::Unavailable(e.localizedDescription().to_string()))?;
+
+    let (tx, rx) = mpsc::channel();
+    let reply = RcBlock::new(move |success: Bool, _error: *mut NSError| {
+        let _ = tx.send(success.as_bool());
+    });
+    unsafe {
+        context.evaluatePolicy_localizedReason_reply(policy, &NSString::from_str(reason), &reply)
+    };
+
+    match rx.recv() {
+        Ok(true) => Ok(()),
+        _ => Err(KeychainError::AccessDenied),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn verify_user(reason: &str) -> Result<(), KeychainError> {
+    use windows::core::HSTRING;
+    use windows::Security::Credentials::UI::{
+        UserConsentVerificationResult, UserConsentVerifier, UserConsentVerifierAvailability,
+    };
+
+    let availability = UserConsentVerifier::CheckAvailabilityAsync()
+        .and_then(|op| op.get())
+        .map_err(|e| KeychainError::Platform(e.to_string()))?;
+    if availability != UserConsentVerifierAvailability::Available {
+        return Err(KeychainError::Unavailable(
+            "Windows Hello is not set up on this device".to_string(),
+        ));
+    }
+
+    let result = UserConsentVerifier::RequestVerificationAsync(&HSTRING::from(reason))
+        .and_then(|op| op.get())
+        .map_err(|e| KeychainError::Platform(e.to_string()))?;
+    if result == UserConsentVerificationResult::Verified {
+        Ok(())
+    } else {
+        Err(KeychainError::AccessDenied)
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn verify_user(_reason: &str) -> Result<(), KeychainError> {
+    Err(KeychainError::Unavailable(
+        "Biometric authentication is not supported on this platform".to_string(),
+    ))
+}
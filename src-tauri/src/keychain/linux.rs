@@ -0,0 +1,98 @@
+use super::KeychainError;
+use secret_service::blocking::{Collection, Item, SecretService};
+use secret_service::{EncryptionType, Error as SsError};
+use std::collections::HashMap;
+
+pub const BACKEND_NAME: &str = "Secret Service";
+
+impl From<SsError> for KeychainError {
+    fn from(err: SsError) -> Self {
+        match err {
+            SsError::NoResult => KeychainError::ItemNotFound,
+            SsError::Locked | SsError::Prompt => KeychainError::AccessDenied,
+            SsError::Unavailable | SsError::Zbus(_) => KeychainError::Unavailable(err.to_string()),
+            _ => KeychainError::Platform(err.to_string()),
+        }
+    }
+}
+
+fn connect() -> Result<SecretService<'static>, KeychainError> {
+    // Any failure here means there is no usable keyring daemon on the session bus
+    SecretService::connect(EncryptionType::Dh)
+        .map_err(|e| KeychainError::Unavailable(e.to_string()))
+}
+
+fn collection<'a>(service: &'a SecretService<'a>) -> Result<Collection<'a>, KeychainError> {
+    let collection = service
+        .get_default_collection()
+        .or_else(|_| service.get_any_collection())?;
+    collection.ensure_unlocked()?;
+    Ok(collection)
+}
+
+fn find_items<'a>(
+    service: &'a SecretService<'a>,
+    attributes: HashMap<&str, &str>,
+) -> Result<Vec<Item<'a>>, KeychainError> {
+    let result = service.search_items(attributes)?;
+    let mut items = result.unlocked;
+    for item in result.locked {
+        item.unlock()?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+pub fn probe() -> Result<(), KeychainError> {
+    let service = connect()?;
+    collection(&service)?;
+    Ok(())
+}
+
+pub fn set_secret(service: &str, account: &str, secret: &str) -> Result<(), KeychainError> {
+    let ss = connect()?;
+    let collection = collection(&ss)?;
+    let attributes = HashMap::from([("service", service), ("account", account)]);
+    collection.create_item(
+        &format!("GitSwitchHub token for {}", account),
+        attributes,
+        secret.as_bytes(),
+        true, // replace an existing item with the same attributes
+        "text/plain",
+    )?;
+    Ok(())
+}
+
+pub fn get_secret(service: &str, account: &str) -> Result<String, KeychainError> {
+    let ss = connect()?;
+    let attributes = HashMap::from([("service", service), ("account", account)]);
+    let items = find_items(&ss, attributes)?;
+    let item = items.first().ok_or(KeychainError::ItemNotFound)?;
+    String::from_utf8(item.get_secret()?)
+        .map_err(|_| KeychainError::Platform("Stored token is not valid UTF-8".to_string()))
+}
+
+pub fn delete_secret(service: &str, account: &str) -> Result<(), KeychainError> {
+    let ss = connect()?;
+    let attributes = HashMap::from([("service", service), ("account", account)]);
+    let items = find_items(&ss, attributes)?;
+    if items.is_empty() {
+        return Err(KeychainError::ItemNotFound);
+    }
+    for item in items {
+        item.delete()?;
+    }
+    Ok(())
+}
+
+pub fn list_accounts(service: &str) -> Result<Vec<String>, KeychainError> {
+    let ss = connect()?;
+    let items = find_items(&ss, HashMap::from([("service", service)]))?;
+    let mut accounts = Vec::new();
+    for item in items {
+        if let Some(account) = item.get_attributes()?.remove("account") {
+            accounts.push(account);
+        }
+    }
+    Ok(accounts)
+}
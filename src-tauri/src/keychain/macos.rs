@@ -0,0 +1,68 @@
+use super::KeychainError;
+use security_framework::base::Error as SecError;
+use security_framework::item::{ItemClass, ItemSearchOptions, Limit};
+use security_framework::passwords::{
+    delete_generic_password, get_generic_password, set_generic_password,
+};
+
+pub const BACKEND_NAME: &str = "macOS Keychain";
+
+// OSStatus codes from Security/SecBase.h
+const ERR_SEC_ITEM_NOT_FOUND: i32 = -25300;
+const ERR_SEC_AUTH_FAILED: i32 = -25293;
+const ERR_SEC_INTERACTION_NOT_ALLOWED: i32 = -25308;
+const ERR_SEC_USER_CANCELED: i32 = -128;
+
+impl From<SecError> for KeychainError {
+    fn from(err: SecError) -> Self {
+        match err.code() {
+            ERR_SEC_ITEM_NOT_FOUND => KeychainError::ItemNotFound,
+            ERR_SEC_AUTH_FAILED | ERR_SEC_INTERACTION_NOT_ALLOWED | ERR_SEC_USER_CANCELED => {
+                KeychainError::AccessDenied
+            }
+            _ => KeychainError::Platform(err.to_string()),
+        }
+    }
+}
+
+pub fn probe() -> Result<(), KeychainError> {
+    Ok(())
+}
+
+pub fn set_secret(service: &str, account: &str, secret: &str) -> Result<(), KeychainError> {
+    // set_generic_password updates the item in place if it already exists
+    set_generic_password(service, account, secret.as_bytes())?;
+    Ok(())
+}
+
+pub fn get_secret(service: &str, account: &str) -> Result<String, KeychainError> {
+    let bytes = get_generic_password(service, account)?;
+    String::from_utf8(bytes)
+        .map_err(|_| KeychainError::Platform("Stored token is not valid UTF-8".to_string()))
+}
+
+pub fn delete_secret(service: &str, account: &str) -> Result<(), KeychainError> {
+    delete_generic_password(service, account)?;
+    Ok(())
+}
+
+pub fn list_accounts(service: &str) -> Result<Vec<String>, KeychainError> {
+    let results = match ItemSearchOptions::new()
+        .class(ItemClass::generic_password())
+        .service(service)
+        .load_attributes(true)
+        .limit(Limit::All)
+        .search()
+    {
+        Ok(results) => results,
+        Err(e) if e.code() == ERR_SEC_ITEM_NOT_FOUND => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let accounts = results
+        .iter()
+        .filter_map(|result| result.simplify_dict())
+        .filter_map(|attrs| attrs.get("acct").cloned())
+        .collect();
+    Ok(accounts)
+}
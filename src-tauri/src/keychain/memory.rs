@@ -0,0 +1,50 @@
+use super::KeychainError;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+pub const BACKEND_NAME: &str = "In-memory";
+
+// Process-local storage for platforms without a native keychain backend.
+// Tokens stored here do not survive a restart.
+fn storage() -> &'static Mutex<HashMap<(String, String), String>> {
+    static STORAGE: OnceLock<Mutex<HashMap<(String, String), String>>> = OnceLock::new();
+    STORAGE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn probe() -> Result<(), KeychainError> {
+    Ok(())
+}
+
+pub fn set_secret(service: &str, account: &str, secret: &str) -> Result<(), KeychainError> {
+    let mut storage = storage().lock().unwrap();
+    storage.insert(
+        (service.to_string(), account.to_string()),
+        secret.to_string(),
+    );
+    Ok(())
+}
+
+pub fn get_secret(service: &str, account: &str) -> Result<String, KeychainError> {
+    let storage = storage().lock().unwrap();
+    storage
+        .get(&(service.to_string(), account.to_string()))
+        .cloned()
+        .ok_or(KeychainError::ItemNotFound)
+}
+
+pub fn delete_secret(service: &str, account: &str) -> Result<(), KeychainError> {
+    let mut storage = storage().lock().unwrap();
+    storage
+        .remove(&(service.to_string(), account.to_string()))
+        .map(|_| ())
+        .ok_or(KeychainError::ItemNotFound)
+}
+
+pub fn list_accounts(service: &str) -> Result<Vec<String>, KeychainError> {
+    let storage = storage().lock().unwrap();
+    Ok(storage
+        .keys()
+        .filter(|(s, _)| s == service)
+        .map(|(_, account)| account.clone())
+        .collect())
+}
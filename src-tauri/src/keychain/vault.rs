@@ -0,0 +1,264 @@
+use super::KeychainError;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+pub const BACKEND_NAME: &str = "Encrypted vault";
+
+const VAULT_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const DEFAULT_AUTO_LOCK: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+// AES-256-GCM encrypted token store keyed by a master password (Argon2id).
+// The decrypted entries only live in memory while the vault is unlocked.
+pub struct Vault {
+    path: PathBuf,
+    salt: Vec<u8>,
+    key: Option<Zeroizing<[u8; 32]>>,
+    entries: HashMap<String, Zeroizing<String>>,
+    last_access: Instant,
+    auto_lock_after: Duration,
+}
+
+impl Vault {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            salt: Vec::new(),
+            key: None,
+            entries: HashMap::new(),
+            last_access: Instant::now(),
+            auto_lock_after: DEFAULT_AUTO_LOCK,
+        }
+    }
+
+    pub fn default_path() -> Result<PathBuf, KeychainError> {
+        Ok(crate::paths::app_data_dir()?.join("vault"))
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn is_locked(&mut self) -> bool {
+        if self.key.is_some() && self.last_access.elapsed() >= self.auto_lock_after {
+            self.lock();
+        }
+        self.key.is_none()
+    }
+
+    pub fn set_auto_lock(&mut self, after: Duration) {
+        self.auto_lock_after = after;
+    }
+
+    pub fn lock(&mut self) {
+        self.key = None;
+        self.entries.clear();
+    }
+
+    pub fn unlock(&mut self, password: &str) -> Result<(), KeychainError> {
+        let (salt, key, plaintext) = open_envelope(password, &fs::read_to_string(&self.path)?)?;
+        let entries: HashMap<String, String> = serde_json::from_slice(&plaintext)
+            .map_err(|e| KeychainError::Platform(format!("Corrupt vault contents: {}", e)))?;
+
+        self.salt = salt;
+        self.key = Some(key);
+        self.entries = entries
+            .into_iter()
+            .map(|(k, v)| (k, Zeroizing::new(v)))
+            .collect();
+        self.last_access = Instant::now();
+        Ok(())
+    }
+
+    // Creates the vault, or re-encrypts it under a new password when it
+    // already exists. Changing the password requires the current one.
+    pub fn set_master_password(
+        &mut self,
+        current_password: Option<&str>,
+        new_password: &str,
+    ) -> Result<(), KeychainError> {
+        if new_password.is_empty() {
+            return Err(KeychainError::Platform(
+                "Master password must not be empty".to_string(),
+            ));
+        }
+
+        if self.exists() {
+            let current = current_password.ok_or(KeychainError::InvalidPassword)?;
+            self.unlock(current)?;
+        }
+
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        self.key = Some(derive_key(new_password, &salt)?);
+        self.salt = salt;
+        self.last_access = Instant::now();
+        self.save()
+    }
+
+    pub fn set(&mut self, key: &str, secret: &str) -> Result<(), KeychainError> {
+        self.ensure_unlocked()?;
+        self.entries
+            .insert(key.to_string(), Zeroizing::new(secret.to_string()));
+        self.save()
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<String, KeychainError> {
+        self.ensure_unlocked()?;
+        self.entries
+            .get(key)
+            .map(|secret| secret.to_string())
+            .ok_or(KeychainError::ItemNotFound)
+    }
+
+    pub fn delete(&mut self, key: &str) -> Result<(), KeychainError> {
+        self.ensure_unlocked()?;
+        if self.entries.remove(key).is_none() {
+            return Err(KeychainError::ItemNotFound);
+        }
+        self.save()
+    }
+
+    pub fn keys(&mut self) -> Result<Vec<String>, KeychainError> {
+        self.ensure_unlocked()?;
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    fn ensure_unlocked(&mut self) -> Result<(), KeychainError> {
+        if !self.exists() {
+            return Err(KeychainError::Unavailable(
+                "Set a master password to create the encrypted vault".to_string(),
+            ));
+        }
+        if self.is_locked() {
+            return Err(KeychainError::Locked);
+        }
+        self.last_access = Instant::now();
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), KeychainError> {
+        let key = self.key.as_ref().ok_or(KeychainError::Locked)?;
+
+        let entries: HashMap<&str, &str> = self
+            .entries
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&entries).map_err(|e| KeychainError::Platform(e.to_string()))?,
+        );
+
+        let contents = seal_envelope(key, &self.salt, &plaintext)?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated vault
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+// Password-encrypts arbitrary data using the same envelope as the vault file
+pub fn seal(password: &str, plaintext: &[u8]) -> Result<String, KeychainError> {
+    if password.is_empty() {
+        return Err(KeychainError::Platform(
+            "Password must not be empty".to_string(),
+        ));
+    }
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(password, &salt)?;
+    seal_envelope(&key, &salt, plaintext)
+}
+
+pub fn unseal(password: &str, sealed: &str) -> Result<Zeroizing<Vec<u8>>, KeychainError> {
+    let (_, _, plaintext) = open_envelope(password, sealed)?;
+    Ok(plaintext)
+}
+
+fn seal_envelope(key: &[u8; 32], salt: &[u8], plaintext: &[u8]) -> Result<String, KeychainError> {
+    let cipher =
+        Aes256Gcm::new_from_slice(key).map_err(|e| KeychainError::Platform(e.to_string()))?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| KeychainError::Platform(e.to_string()))?;
+
+    let file = VaultFile {
+        version: VAULT_VERSION,
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    serde_json::to_string(&file).map_err(|e| KeychainError::Platform(e.to_string()))
+}
+
+type OpenedEnvelope = (Vec<u8>, Zeroizing<[u8; 32]>, Zeroizing<Vec<u8>>);
+
+fn open_envelope(password: &str, contents: &str) -> Result<OpenedEnvelope, KeychainError> {
+    let file: VaultFile = serde_json::from_str(contents)
+        .map_err(|e| KeychainError::Platform(format!("Corrupt vault file: {}", e)))?;
+    if file.version != VAULT_VERSION {
+        return Err(KeychainError::Platform(format!(
+            "Unsupported vault version {}",
+            file.version
+        )));
+    }
+
+    let salt = decode(&file.salt)?;
+    let nonce = decode(&file.nonce)?;
+    let ciphertext = decode(&file.ciphertext)?;
+    let key = derive_key(password, &salt)?;
+
+    let cipher = Aes256Gcm::new_from_slice(key.as_ref())
+        .map_err(|e| KeychainError::Platform(e.to_string()))?;
+    // A failed authentication tag means the password was wrong
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| KeychainError::InvalidPassword)?,
+    );
+    Ok((salt, key, plaintext))
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, KeychainError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|e| KeychainError::Platform(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, KeychainError> {
+    BASE64
+        .decode(value)
+        .map_err(|e| KeychainError::Platform(format!("Corrupt vault file: {}", e)))
+}
@@ -0,0 +1,124 @@
+use super::KeychainError;
+use std::ffi::c_void;
+use std::ptr;
+use windows_sys::Win32::Foundation::{
+    GetLastError, ERROR_ACCESS_DENIED, ERROR_NOT_FOUND, ERROR_NO_SUCH_LOGON_SESSION,
+};
+use windows_sys::Win32::Security::Credentials::{
+    CredDeleteW, CredEnumerateW, CredFree, CredReadW, CredWriteW, CREDENTIALW,
+    CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+};
+
+pub const BACKEND_NAME: &str = "Windows Credential Manager";
+
+// Credential Manager has a single flat namespace, so entries are stored
+// under "<service>/<account>" target names.
+fn target_name(service: &str, account: &str) -> String {
+    format!("{}/{}", service, account)
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+    value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+unsafe fn from_wide(ptr: *const u16) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+}
+
+fn last_error() -> KeychainError {
+    match unsafe { GetLastError() } {
+        ERROR_NOT_FOUND => KeychainError::ItemNotFound,
+        ERROR_ACCESS_DENIED => KeychainError::AccessDenied,
+        ERROR_NO_SUCH_LOGON_SESSION => {
+            KeychainError::Platform("No logon session available".to_string())
+        }
+        code => KeychainError::Platform(format!("Credential Manager error {}", code)),
+    }
+}
+
+pub fn probe() -> Result<(), KeychainError> {
+    Ok(())
+}
+
+pub fn set_secret(service: &str, account: &str, secret: &str) -> Result<(), KeychainError> {
+    let mut target = to_wide(&target_name(service, account));
+    let mut user_name = to_wide(account);
+    let mut blob = secret.as_bytes().to_vec();
+
+    let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+    credential.Type = CRED_TYPE_GENERIC;
+    credential.TargetName = target.as_mut_ptr();
+    credential.UserName = user_name.as_mut_ptr();
+    credential.CredentialBlobSize = blob.len() as u32;
+    credential.CredentialBlob = blob.as_mut_ptr();
+    credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+
+    // CredWriteW overwrites an existing credential with the same target name
+    if unsafe { CredWriteW(&credential, 0) } == 0 {
+        return Err(last_error());
+    }
+    Ok(())
+}
+
+pub fn get_secret(service: &str, account: &str) -> Result<String, KeychainError> {
+    let target = to_wide(&target_name(service, account));
+    let mut credential: *mut CREDENTIALW = ptr::null_mut();
+
+    if unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+        return Err(last_error());
+    }
+
+    let secret = unsafe {
+        let blob = std::slice::from_raw_parts(
+            (*credential).CredentialBlob,
+            (*credential).CredentialBlobSize as usize,
+        );
+        let secret = String::from_utf8(blob.to_vec());
+        CredFree(credential as *const c_void);
+        secret
+    };
+
+    secret.map_err(|_| KeychainError::Platform("Stored token is not valid UTF-8".to_string()))
+}
+
+pub fn delete_secret(service: &str, account: &str) -> Result<(), KeychainError> {
+    let target = to_wide(&target_name(service, account));
+    if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+        return Err(last_error());
+    }
+    Ok(())
+}
+
+pub fn list_accounts(service: &str) -> Result<Vec<String>, KeychainError> {
+    let prefix = format!("{}/", service);
+    let filter = to_wide(&format!("{}*", prefix));
+    let mut count: u32 = 0;
+    let mut credentials: *mut *mut CREDENTIALW = ptr::null_mut();
+
+    if unsafe { CredEnumerateW(filter.as_ptr(), 0, &mut count, &mut credentials) } == 0 {
+        return match last_error() {
+            KeychainError::ItemNotFound => Ok(Vec::new()),
+            e => Err(e),
+        };
+    }
+
+    let mut accounts = Vec::new();
+    unsafe {
+        for i in 0..count as usize {
+            let credential = *credentials.add(i);
+            let target = from_wide((*credential).TargetName);
+            if let Some(account) = target.strip_prefix(&prefix) {
+                accounts.push(account.to_string());
+            }
+        }
+        CredFree(credentials as *const c_void);
+    }
+    Ok(accounts)
+}
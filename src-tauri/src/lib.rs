@@ -1,9 +1,28 @@
+pub mod cache;
+pub mod challenge;
+pub mod chooser;
+pub mod cli;
 pub mod commands;
+pub mod conditional_include;
+pub mod config;
 pub mod database;
+pub mod gh_import;
+pub mod git_config;
 pub mod git_helper;
 pub mod github_auth;
+pub mod github_client;
+pub mod identity_guard;
 pub mod keychain;
+pub mod logging;
+pub mod oauth;
+pub mod paths;
+pub mod remote;
+pub mod resolver;
+pub mod scanner;
+pub mod settings;
 pub mod ssh;
+pub mod tray;
+pub mod url_rewrite;
 
 use tauri::Manager;
 
@@ -13,20 +32,126 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
         .invoke_handler(tauri::generate_handler![
             commands::get_accounts,
             commands::add_account,
+            commands::import_from_gh,
+            commands::discover_stored_credentials,
+            commands::adopt_stored_credential,
             commands::remove_account,
+            commands::update_account,
+            commands::get_deleted_accounts,
+            commands::restore_account,
+            commands::purge_account,
+            commands::get_git_identity,
+            commands::set_git_identity,
+            commands::get_signing_config,
+            commands::set_signing_config,
+            commands::apply_signing_config,
+            commands::configure_ssh_signing,
+            commands::upload_signing_key,
+            commands::set_clone_directory,
+            commands::suggest_clone_path,
+            commands::clone_repository,
             commands::test_connection,
+            commands::rotate_token,
+            commands::add_account_token,
+            commands::get_account_tokens,
+            commands::remove_account_token,
+            commands::get_account_token_info,
+            commands::get_account_orgs,
+            commands::check_org_sso,
+            commands::check_repo_access,
+            commands::suggest_account,
+            commands::get_keychain_status,
+            commands::get_api_status,
+            commands::keychain_audit,
+            commands::export_vault,
+            commands::import_vault,
+            commands::set_master_password,
+            commands::unlock_vault,
+            commands::lock_vault,
+            commands::set_vault_auto_lock,
+            commands::get_biometric_settings,
+            commands::set_biometric_settings,
             commands::get_repository_mappings,
             commands::set_repository_mapping,
             commands::remove_repository_mapping,
+            commands::get_org_mappings,
+            commands::set_org_mapping,
+            commands::remove_org_mapping,
+            commands::get_hosts,
+            commands::add_host,
+            commands::remove_host,
+            commands::get_default_account,
+            commands::set_default_account,
+            commands::get_host_default_account,
+            commands::set_host_default_account,
+            commands::clear_host_default_account,
+            commands::get_directory_rules,
+            commands::add_directory_rule,
+            commands::remove_directory_rule,
+            commands::resolve_account_for_path,
+            commands::resolve_account,
+            commands::scan_repositories,
+            commands::get_profiles,
+            commands::create_profile,
+            commands::set_profile_accounts,
+            commands::switch_profile,
+            commands::delete_profile,
+            commands::get_audit_log,
+            commands::export_config,
+            commands::import_config,
+            commands::list_backups,
+            commands::create_backup,
+            commands::restore_backup,
+            commands::db_doctor,
             commands::install_git_helper,
+            commands::uninstall_git_helper,
             commands::get_git_helper_status,
+            commands::run_doctor,
+            commands::start_device_flow,
+            commands::poll_device_flow,
+            commands::cancel_device_flow,
+            commands::sign_in_with_browser,
+            commands::cancel_browser_sign_in,
+            commands::reauthenticate_account,
+            commands::get_token_expiry,
+            commands::reauthorize_account,
+            commands::remove_credential_helper,
             commands::generate_ssh_key,
+            commands::get_ssh_key_types,
+            commands::upload_ssh_key,
             commands::get_ssh_config,
+            commands::analyze_ssh_config,
+            commands::detect_ssh_keys,
+            commands::set_account_ssh_key,
             commands::convert_remote_to_ssh,
+            commands::apply_ssh_remote,
+            commands::set_repo_ssh_command,
+            commands::test_ssh_connection,
+            commands::diagnose_ssh,
+            commands::get_ssh_key_details,
+            commands::probe_ssh_connectivity,
+            commands::set_ssh_port_443,
+            commands::get_ssh_config_changes,
+            commands::undo_ssh_change,
+            commands::get_ssh_url_rewrites,
+            commands::set_ssh_url_rewrites,
+            commands::remove_ssh_url_rewrites,
+            commands::get_conditional_includes,
+            commands::set_conditional_include,
+            commands::remove_conditional_include,
+            commands::install_identity_guard,
+            commands::uninstall_identity_guard,
             commands::show_account_chooser,
+            commands::get_chooser_request,
+            commands::answer_chooser_request,
+            commands::get_setting,
+            commands::set_setting,
+            commands::get_all_settings,
+            commands::get_recent_logs,
             commands::get_auto_detection_status,
             commands::toggle_auto_detection,
             commands::start_background_service,
@@ -36,11 +161,40 @@ pub fn run() {
         .setup(|app| {
             // Initialize database on startup
             let db = database::Database::new()?;
-            app.manage(db);
+            if let Err(e) = logging::init_with_settings(&db, "app") {
+                eprintln!("Logging disabled: {}", e);
+            }
+
+            github_client::configure_from_db(&db);
 
             // Initialize keychain manager
             let keychain = keychain::KeychainManager::new();
+
+            // Finish removals whose undo period has run out
+            if let Err(e) = commands::purge_expired_accounts(&db, &keychain) {
+                tracing::warn!(error = %e, "failed to purge removed accounts");
+            }
+
+            app.manage(db);
             app.manage(keychain);
+            app.manage(commands::SignInFlows::default());
+            app.manage(tray::TrayRefresh::default());
+
+            // Answer credential helpers asking which account to use
+            let handle = app.handle().clone();
+            std::thread::spawn(move || commands::watch_chooser_requests(handle));
+
+            // Ask which account new clones should use
+            let handle = app.handle().clone();
+            std::thread::spawn(move || commands::watch_project_directories(handle));
+
+            // Warn before tokens expire
+            tauri::async_runtime::spawn(commands::watch_token_expiry(app.handle().clone()));
+            tauri::async_runtime::spawn(commands::watch_unverified_accounts(app.handle().clone()));
+
+            // The default account in the menu bar or system tray
+            tray::create(app.handle())?;
+            tauri::async_runtime::spawn(tray::watch(app.handle().clone()));
 
             Ok(())
         })
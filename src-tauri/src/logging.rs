@@ -0,0 +1,83 @@
+use crate::database::Database;
+use crate::paths;
+use crate::settings::SettingKey;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+
+// Daily files, so a week of history per process
+const MAX_LOG_FILES: usize = 7;
+const LOG_SUFFIX: &str = "log";
+
+#[derive(Error, Debug)]
+pub enum LoggingError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to set up logging: {0}")]
+    Init(String),
+}
+
+pub fn log_dir() -> io::Result<PathBuf> {
+    let dir = paths::app_data_dir()?.join("logs");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Sends this process's tracing events to "<process>.<date>.log" in the log
+// directory. Git swallows the helper's stderr, so this is the only place its
+// failures can be looked at later. The level is read once at start up.
+pub fn init(process: &str, level: &str) -> Result<(), LoggingError> {
+    let level = level
+        .parse::<LevelFilter>()
+        .map_err(|e| LoggingError::Init(e.to_string()))?;
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(process)
+        .filename_suffix(LOG_SUFFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir()?)
+        .map_err(|e| LoggingError::Init(e.to_string()))?;
+
+    tracing_subscriber::fmt()
+        .with_writer(appender)
+        .with_ansi(false)
+        .with_max_level(level)
+        .try_init()
+        .map_err(|e| LoggingError::Init(e.to_string()))
+}
+
+// The last `limit` lines across every process's logs, oldest first. Lines
+// start with an RFC 3339 timestamp, so sorting them interleaves the files.
+pub fn recent_logs(limit: usize) -> Result<Vec<String>, LoggingError> {
+    let mut lines = Vec::new();
+    for entry in fs::read_dir(log_dir()?)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(LOG_SUFFIX) {
+            continue;
+        }
+        let contents = fs::read(&path)?;
+        lines.extend(
+            String::from_utf8_lossy(&contents)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string()),
+        );
+    }
+
+    lines.sort();
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines.split_off(skip))
+}
+
+// Initializes logging at the level chosen in settings
+pub fn init_with_settings(db: &Database, process: &str) -> Result<(), LoggingError> {
+    let level = db
+        .get_setting(SettingKey::LogLevel)
+        .ok()
+        .and_then(|value| value.as_str().map(|level| level.to_string()))
+        .unwrap_or_else(|| "info".to_string());
+    init(process, &level)
+}
@@ -1,27 +1,96 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use clap::Parser;
+use gitswitchhub_lib::cache;
+use gitswitchhub_lib::cli::{self, AccountsCommand, Cli, CliCommand};
 use gitswitchhub_lib::database::Database;
-use gitswitchhub_lib::git_helper::GitCredentialHelper;
+use gitswitchhub_lib::git_helper::{self, GitCredentialHelper};
+use gitswitchhub_lib::github_client;
+use gitswitchhub_lib::identity_guard;
 use gitswitchhub_lib::keychain::KeychainManager;
+use gitswitchhub_lib::logging;
 use std::env;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let command = match Cli::parse().command {
+        Some(command) => command,
+        // Run in GUI mode
+        None => return gitswitchhub_lib::run(),
+    };
 
-    // Check if we're being called as a Git credential helper
-    if args.len() > 1 && args[1] == "credential-helper" {
-        // Run in CLI mode for Git credential helper
-        let db = Database::new().expect("Failed to initialize database");
-        let keychain = KeychainManager::new();
-        let helper = GitCredentialHelper::new(db, keychain);
+    match command {
+        CliCommand::CredentialHelper {
+            explain,
+            non_interactive,
+            operation,
+        } => {
+            // Run in CLI mode for Git credential helper
+            let db = Database::new().expect("Failed to initialize database");
+            if let Err(e) = logging::init_with_settings(&db, "helper") {
+                eprintln!("GitSwitchHub credential helper logging disabled: {}", e);
+            }
+            github_client::configure_from_db(&db);
+            let keychain = KeychainManager::new();
 
-        if let Err(e) = helper.run() {
-            eprintln!("GitSwitchHub credential helper error: {}", e);
-            std::process::exit(1);
+            let helper = GitCredentialHelper::new(db, keychain)
+                .with_interactive(!non_interactive && git_helper::interactive_from_env())
+                .with_account_override(git_helper::account_override_from_env());
+            if let Some(url) = explain {
+                if let Err(e) = helper.explain(&url, &mut std::io::stdout().lock()) {
+                    eprintln!("GitSwitchHub credential helper error: {}", e);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            if let Err(e) = helper.run(&operation) {
+                tracing::error!(operation, "credential helper failed: {}", e);
+                eprintln!("GitSwitchHub credential helper error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        CliCommand::IdentityGuard { hook, url, .. } => {
+            let result = Database::new().map_err(|e| e.to_string()).and_then(|db| {
+                let cwd = env::current_dir().map_err(|e| e.to_string())?;
+                identity_guard::check(&db, &cwd, &hook, url.as_deref()).map_err(|e| e.to_string())
+            });
+            match result {
+                Ok(Some(message)) => {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
+                }
+                Ok(None) => {}
+                // A check that can't run must not stop the commit
+                Err(e) => eprintln!("GitSwitchHub identity guard skipped: {}", e),
+            }
+        }
+        CliCommand::CredentialCacheDaemon => {
+            if let Err(e) = cache::run_daemon() {
+                eprintln!("GitSwitchHub credential cache error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        command => {
+            let db = Database::new().expect("Failed to initialize database");
+            if let Err(e) = logging::init_with_settings(&db, "cli") {
+                eprintln!("GitSwitchHub logging disabled: {}", e);
+            }
+            let out = &mut std::io::stdout().lock();
+            let result = match command {
+                CliCommand::Accounts(AccountsCommand::List) => cli::accounts_list(&db, out),
+                CliCommand::Use { account, repo } => {
+                    cli::use_account(&db, &account, repo.as_deref(), out)
+                }
+                CliCommand::Whoami { repo } => {
+                    cli::whoami(&db, &KeychainManager::new(), repo.as_deref(), out)
+                }
+                CliCommand::Map { url, account } => cli::map(&db, &url, &account, out),
+                _ => unreachable!("handled above"),
+            };
+            if let Err(e) = result {
+                eprintln!("gitswitchhub: {}", e);
+                std::process::exit(1);
+            }
         }
-    } else {
-        // Run in GUI mode
-        gitswitchhub_lib::run()
     }
 }
@@ -0,0 +1,259 @@
+// Browser sign-in: GitHub redirects back to a listener on the loopback
+// interface, which GitHub accepts on any port, and PKCE ties the code to this
+// process so another app catching the redirect can't use it.
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::warn;
+
+const CALLBACK_PATH: &str = "/callback";
+// How often a wait for the redirect checks whether it was cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+// How long a connection gets to send its request before it is dropped;
+// browsers open idle ones ahead of time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum OAuthError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Timed out waiting for GitHub to redirect back")]
+    Timeout,
+    #[error("Sign in was cancelled")]
+    Cancelled,
+    #[error("GitHub refused the sign in: {0}")]
+    Denied(String),
+}
+
+pub struct Pkce {
+    pub verifier: String,
+    // base64url of the verifier's SHA-256, sent with the authorize request
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Self {
+        let verifier = random_string(32);
+        let challenge = BASE64_URL.encode(Sha256::digest(verifier.as_bytes()));
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+impl Default for Pkce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unguessable value for the state parameter
+pub fn random_state() -> String {
+    random_string(16)
+}
+
+fn random_string(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buffer);
+    BASE64_URL.encode(buffer)
+}
+
+pub struct CallbackListener {
+    listener: TcpListener,
+    pub redirect_uri: String,
+}
+
+impl CallbackListener {
+    pub async fn bind() -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let port = listener.local_addr()?.port();
+        Ok(Self {
+            listener,
+            redirect_uri: format!("http://127.0.0.1:{}{}", port, CALLBACK_PATH),
+        })
+    }
+
+    // Waits for the redirect carrying `state` and returns its code. Anything
+    // else that connects, like a favicon request, is turned away, and a
+    // connection that fails or stalls is dropped without ending the wait.
+    pub async fn wait_for_code(
+        &self,
+        state: &str,
+        cancelled: &AtomicBool,
+        timeout: Duration,
+    ) -> Result<String, OAuthError> {
+        let deadline = Instant::now() + timeout;
+        // Each connection is read on its own, and those still open when the
+        // wait ends are dropped with the set
+        let mut connections = tokio::task::JoinSet::new();
+        loop {
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(OAuthError::Cancelled);
+            }
+            if Instant::now() >= deadline {
+                return Err(OAuthError::Timeout);
+            }
+
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let state = state.to_string();
+                        connections.spawn(async move {
+                            tokio::time::timeout(REQUEST_TIMEOUT, handle_callback(stream, &state))
+                                .await
+                        });
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "failed to accept a sign in connection");
+                        tokio::time::sleep(CANCEL_CHECK_INTERVAL).await;
+                    }
+                },
+                Some(handled) = connections.join_next() => match handled {
+                    Ok(Ok(Ok(Some(result)))) => return result,
+                    Ok(Ok(Ok(None))) => {}
+                    Ok(Ok(Err(e))) => warn!(error = %e, "failed to read a sign in connection"),
+                    Ok(Err(_)) => warn!("dropped a sign in connection that sent no request"),
+                    Err(e) => warn!(error = %e, "sign in connection handler failed"),
+                },
+                _ = tokio::time::sleep(CANCEL_CHECK_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+// None when the request wasn't the redirect we're waiting for
+async fn handle_callback(
+    mut stream: TcpStream,
+    state: &str,
+) -> Result<Option<Result<String, OAuthError>>, OAuthError> {
+    let mut buffer = vec![0u8; 8192];
+    let mut read = 0;
+    // Only the request line is needed
+    while read < buffer.len() && !buffer[..read].contains(&b'\n') {
+        let n = stream.read(&mut buffer[read..]).await?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let target = request
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or("");
+
+    let query = match target.split_once('?') {
+        Some((CALLBACK_PATH, query)) => query,
+        _ => {
+            respond(&mut stream, "404 Not Found", "Not found").await?;
+            return Ok(None);
+        }
+    };
+    let params = parse_query(query);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if param("state") != Some(state) {
+        respond(
+            &mut stream,
+            "400 Bad Request",
+            "This sign in link has expired.",
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    if let Some(error) = param("error") {
+        let description = param("error_description").unwrap_or(error).to_string();
+        respond(
+            &mut stream,
+            "200 OK",
+            "Sign in was not completed. You can close this window.",
+        )
+        .await?;
+        return Ok(Some(Err(OAuthError::Denied(description))));
+    }
+    match param("code") {
+        Some(code) => {
+            respond(
+                &mut stream,
+                "200 OK",
+                "Signed in to GitSwitchHub. You can close this window.",
+            )
+            .await?;
+            Ok(Some(Ok(code.to_string())))
+        }
+        None => {
+            respond(
+                &mut stream,
+                "400 Bad Request",
+                "Missing authorization code.",
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+    let body = format!(
+        "<!doctype html><html><body style=\"font-family: sans-serif\"><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+static MIGRATE_LEGACY_DIR: Once = Once::new();
+
+pub fn home_dir() -> io::Result<PathBuf> {
+    dirs::home_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Home directory not found"))
+}
+
+// Expands a leading "~" to the home directory
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), home_dir()) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            format!("{}{}", home.display(), rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+pub fn ssh_dir() -> io::Result<PathBuf> {
+    Ok(home_dir()?.join(".ssh"))
+}
+
+// Per-user application data: ~/Library/Application Support/GitSwitchHub on
+// macOS, %APPDATA%\GitSwitchHub on Windows and $XDG_DATA_HOME/gitswitchhub on
+// Linux. Data from the old ~/.gitswitchhub location is moved over on first use.
+pub fn app_data_dir() -> io::Result<PathBuf> {
+    let base = dirs::data_dir().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "Application data directory not found",
+        )
+    })?;
+    let app_dir = if cfg!(target_os = "linux") {
+        base.join("gitswitchhub")
+    } else {
+        base.join("GitSwitchHub")
+    };
+
+    let mut result = Ok(());
+    MIGRATE_LEGACY_DIR.call_once(|| {
+        if let Ok(home) = home_dir() {
+            result = migrate_legacy_dir(&home.join(".gitswitchhub"), &app_dir);
+        }
+    });
+    result?;
+
+    fs::create_dir_all(&app_dir)?;
+    Ok(app_dir)
+}
+
+fn migrate_legacy_dir(legacy: &Path, app_dir: &Path) -> io::Result<()> {
+    if !legacy.is_dir() || legacy == app_dir || app_dir.join("database.db").exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(app_dir)?;
+    for entry in fs::read_dir(legacy)? {
+        let entry = entry?;
+        let target = app_dir.join(entry.file_name());
+        if target.exists() {
+            continue;
+        }
+        // rename fails across filesystems, fall back to copying
+        if fs::rename(entry.path(), &target).is_err() {
+            copy_recursive(&entry.path(), &target)?;
+            if entry.path().is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+
+    // Only succeeds once everything has been moved
+    let _ = fs::remove_dir(legacy);
+    Ok(())
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
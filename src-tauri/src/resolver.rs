@@ -0,0 +1,236 @@
+use crate::database::{Account, Database, DatabaseError};
+use crate::remote::canonicalize_remote_url;
+use crate::settings::SettingKey;
+use serde::Serialize;
+use std::path::Path;
+
+// Where an account choice came from, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionSource {
+    Mapping,
+    DirectoryRule,
+    CloneDirectory,
+    OrgDefault,
+    HostDefault,
+    LastUsed,
+}
+
+impl ResolutionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mapping => "mapping",
+            Self::DirectoryRule => "directory_rule",
+            Self::CloneDirectory => "clone_directory",
+            Self::OrgDefault => "org_default",
+            Self::HostDefault => "host_default",
+            Self::LastUsed => "last_used",
+        }
+    }
+}
+
+pub struct ResolveRequest<'a> {
+    pub repo_url: &'a str,
+    pub host: &'a str,
+    pub owner: Option<&'a str>,
+    // Working directory of the repository, for directory rules
+    pub path: Option<&'a Path>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub account: Account,
+    pub source: ResolutionSource,
+    pub mapping_id: Option<String>,
+    pub token_name: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    // Matching accounts, the winner first. Later candidates are only used
+    // when an earlier one has no usable token.
+    pub candidates: Vec<Candidate>,
+    // Rules that matched but could not apply, with why
+    pub skipped: Vec<String>,
+}
+
+// Precedence, most specific first:
+//   1. repository mapping, exact url before the most specific pattern
+//   2. directory rule for the checkout location
+//   3. account whose clone directory holds the checkout
+//   4. organization default for the repository owner
+//   5. host default
+//   6. account last used on the host, whose token hasn't been rejected since
+// A repository mapping is an explicit choice and applies regardless of the
+// active profile; the defaults only apply to accounts in it.
+pub fn resolve(db: &Database, request: &ResolveRequest) -> Result<Resolution, DatabaseError> {
+    let mut resolution = Resolution::default();
+
+    if let Some(mapping) = db.get_repository_mapping(request.repo_url)? {
+        let matched = if mapping.remote_url == canonicalize_remote_url(request.repo_url) {
+            format!("exact mapping for {}", mapping.remote_url)
+        } else {
+            format!("mapping pattern {}", mapping.remote_url)
+        };
+        match db.get_account(&mapping.account_id)? {
+            Some(account) if account.host.eq_ignore_ascii_case(request.host) => {
+                resolution.candidates.push(Candidate {
+                    reason: format!("{} assigns {}", matched, account.username),
+                    account,
+                    source: ResolutionSource::Mapping,
+                    mapping_id: Some(mapping.id),
+                    token_name: mapping.token_name,
+                })
+            }
+            Some(account) => resolution.skipped.push(format!(
+                "{} assigns {}, which is registered for {} rather than {}",
+                matched, account.username, account.host, request.host
+            )),
+            None => resolution.skipped.push(format!(
+                "{} refers to an account that no longer exists",
+                matched
+            )),
+        }
+    }
+
+    let directory_default = match request.path {
+        Some(path) => db
+            .find_directory_rule(path)?
+            .map(|rule| (rule.account_id, format!("directory rule {}", rule.pattern))),
+        None => None,
+    };
+    let clone_directory = match request.path {
+        Some(path) => db.find_account_by_clone_dir(path)?.map(|account| {
+            let matched = format!(
+                "clone directory {}",
+                account.clone_dir.as_deref().unwrap_or_default()
+            );
+            (account.id, matched)
+        }),
+        None => None,
+    };
+    let org_default = match request.owner {
+        Some(owner) => db.get_org_mapping(request.host, owner)?.map(|mapping| {
+            (
+                mapping.account_id,
+                format!("organization default for {}", owner),
+            )
+        }),
+        None => None,
+    };
+    let host_default = db
+        .get_host_default_account(request.host)?
+        .map(|account_id| (account_id, format!("host default for {}", request.host)));
+
+    let profile = active_profile_accounts(db)?;
+    let defaults = [
+        (directory_default, ResolutionSource::DirectoryRule),
+        (clone_directory, ResolutionSource::CloneDirectory),
+        (org_default, ResolutionSource::OrgDefault),
+        (host_default, ResolutionSource::HostDefault),
+    ];
+    for (default, source) in defaults {
+        let (account_id, matched) = match default {
+            Some(default) => default,
+            None => continue,
+        };
+        let account = match db.get_account(&account_id)? {
+            Some(account) => account,
+            None => {
+                resolution
+                    .skipped
+                    .push(format!("{} refers to a missing account", matched));
+                continue;
+            }
+        };
+        if !account.host.eq_ignore_ascii_case(request.host) {
+            resolution.skipped.push(format!(
+                "{} assigns {}, which is registered for {}",
+                matched, account.username, account.host
+            ));
+            continue;
+        }
+        if profile
+            .as_ref()
+            .is_some_and(|ids| !ids.contains(&account.id))
+        {
+            resolution.skipped.push(format!(
+                "{} assigns {}, which is not in the active profile",
+                matched, account.username
+            ));
+            continue;
+        }
+
+        resolution.candidates.push(Candidate {
+            reason: format!("{} assigns {}", matched, account.username),
+            account,
+            source,
+            mapping_id: None,
+            token_name: None,
+        });
+    }
+
+    // Only when nothing was configured, so the chooser is left for hosts
+    // with no history at all
+    if resolution.candidates.is_empty() {
+        let last_used = db
+            .get_accounts()?
+            .into_iter()
+            .filter(|account| account.host.eq_ignore_ascii_case(request.host))
+            .filter(|account| account.token_rejected_at.is_none())
+            .filter(|account| profile.as_ref().is_none_or(|ids| ids.contains(&account.id)))
+            .filter(|account| account.last_used_at.is_some())
+            .max_by_key(|account| account.last_used_at);
+        if let Some(account) = last_used {
+            resolution.candidates.push(Candidate {
+                reason: format!(
+                    "{} was the last account used on {}",
+                    account.username, request.host
+                ),
+                account,
+                source: ResolutionSource::LastUsed,
+                mapping_id: None,
+                token_name: None,
+            });
+        }
+    }
+
+    Ok(resolution)
+}
+
+// What the account chooser offers for a host: its accounts in the active
+// profile, the default account first and then the most recently used. The
+// first leads the menu, is preselected and is the one picked when there is
+// nobody to ask.
+pub fn chooser_accounts(db: &Database, host: &str) -> Result<Vec<Account>, DatabaseError> {
+    let profile = active_profile_accounts(db)?;
+    let default = default_account_id(db)?;
+    let mut accounts: Vec<_> = db
+        .get_accounts()?
+        .into_iter()
+        .filter(|account| account.host.eq_ignore_ascii_case(host))
+        .filter(|account| profile.as_ref().is_none_or(|ids| ids.contains(&account.id)))
+        .collect();
+    accounts.sort_by_key(|account| {
+        std::cmp::Reverse((default.as_ref() == Some(&account.id), account.last_used_at))
+    });
+    Ok(accounts)
+}
+
+// The account set with set_default_account, or None
+pub fn default_account_id(db: &Database) -> Result<Option<String>, DatabaseError> {
+    Ok(db
+        .get_setting(SettingKey::DefaultAccount)?
+        .as_str()
+        .map(|id| id.to_string()))
+}
+
+// Account ids of the active profile, or None when no profile is active
+pub fn active_profile_accounts(db: &Database) -> Result<Option<Vec<String>>, DatabaseError> {
+    let profile_id = match db.get_setting(SettingKey::ActiveProfile)? {
+        serde_json::Value::String(id) => id,
+        _ => return Ok(None),
+    };
+    Ok(db.get_profile(&profile_id)?.map(|p| p.account_ids))
+}
@@ -0,0 +1,132 @@
+use crate::git_config::{self, GitConfigError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Directories below a scanned root that are searched for checkouts. Deep
+// enough for ~/src/<host>/<owner>/<repo> layouts.
+pub const MAX_DEPTH: usize = 5;
+// Stops a scan of a home directory or a drive from running away
+pub const MAX_REPOSITORIES: usize = 2000;
+
+// Never checkouts of their own, and often huge
+const SKIPPED_DIRS: [&str; 4] = ["node_modules", "target", "vendor", "Library"];
+
+// What a checkout's config says, read the way git reads it
+#[derive(Debug, Clone, Default)]
+pub struct ScannedRepository {
+    pub path: PathBuf,
+    // (name, url) of each remote, in config order
+    pub remotes: Vec<(String, String)>,
+    pub user_name: Option<String>,
+    pub user_email: Option<String>,
+    // "local", "global" or "system", where user.email was set
+    pub user_email_scope: Option<String>,
+}
+
+// Every git checkout under `root`, not looking inside checkouts themselves,
+// hidden directories or symlinks
+pub fn find_repositories(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let (mut repositories, _) = search(root, root)?;
+    repositories.sort();
+    Ok(repositories)
+}
+
+// The checkouts find_repositories finds at or below `dir`, a directory under
+// `root`, and the directories it looked through to find them. Watching those
+// directories is enough to see new checkouts appear.
+pub fn search(root: &Path, dir: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut repositories = Vec::new();
+    let mut searched = Vec::new();
+    let Ok(relative) = dir.strip_prefix(root) else {
+        return Ok((repositories, searched));
+    };
+    for component in relative.components() {
+        if is_skipped(&component.as_os_str().to_string_lossy()) {
+            return Ok((repositories, searched));
+        }
+    }
+    let mut pending = vec![(dir.to_path_buf(), relative.components().count())];
+    while let Some((dir, depth)) = pending.pop() {
+        if depth > MAX_DEPTH {
+            continue;
+        }
+        if dir.join(".git").exists() {
+            repositories.push(dir);
+            if repositories.len() >= MAX_REPOSITORIES {
+                break;
+            }
+            continue;
+        }
+        searched.push(dir.clone());
+        if depth == MAX_DEPTH {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // The root must exist; unreadable directories below it are skipped
+            Err(e) if dir != root && e.kind() == io::ErrorKind::PermissionDenied => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if is_skipped(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            pending.push((entry.path(), depth + 1));
+        }
+    }
+    Ok((repositories, searched))
+}
+
+fn is_skipped(name: &str) -> bool {
+    name.starts_with('.') || SKIPPED_DIRS.contains(&name)
+}
+
+// The checkout a changed path is part of, when the change is inside a .git
+// directory find_repositories would have found under `root`
+pub fn repository_for_change(root: &Path, path: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut repository = root.to_path_buf();
+    for (depth, component) in relative.components().enumerate() {
+        let name = component.as_os_str().to_string_lossy();
+        if name == ".git" {
+            return Some(repository);
+        }
+        if depth >= MAX_DEPTH || is_skipped(&name) {
+            return None;
+        }
+        repository.push(component);
+    }
+    None
+}
+
+pub fn read_repository(path: &Path) -> Result<ScannedRepository, GitConfigError> {
+    let mut repository = ScannedRepository {
+        path: path.to_path_buf(),
+        ..Default::default()
+    };
+    let entries = git_config::get_entries(Some(path), r"^(remote\..*\.url|user\.(name|email))$")?;
+    // Later entries override earlier ones, as in git
+    for entry in entries {
+        match entry.key.as_str() {
+            "user.name" => repository.user_name = Some(entry.value),
+            "user.email" => {
+                repository.user_email = Some(entry.value);
+                repository.user_email_scope = Some(entry.scope);
+            }
+            key => {
+                if let Some(name) = key
+                    .strip_prefix("remote.")
+                    .and_then(|key| key.strip_suffix(".url"))
+                {
+                    repository.remotes.push((name.to_string(), entry.value));
+                }
+            }
+        }
+    }
+    Ok(repository)
+}
@@ -0,0 +1,212 @@
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("Unknown setting: {0}")]
+    UnknownKey(String),
+    #[error("Invalid value for {key}: {reason}")]
+    InvalidValue { key: &'static str, reason: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettingKey {
+    DefaultAccount,
+    PreferredProtocol,
+    Theme,
+    HelperRememberChoice,
+    HelperPromptWhenUnmapped,
+    HelperChooserTimeoutSecs,
+    HelperCacheTtlSecs,
+    HelperAuthType,
+    LogLevel,
+    ActiveProfile,
+    DeletedAccountRetentionDays,
+    ExpiryNotifications,
+    HttpProxy,
+    NoProxy,
+    CaBundlePath,
+    ProjectDirectories,
+    WriteRepoIdentity,
+    ShowTray,
+}
+
+impl SettingKey {
+    pub const ALL: &'static [SettingKey] = &[
+        SettingKey::DefaultAccount,
+        SettingKey::PreferredProtocol,
+        SettingKey::Theme,
+        SettingKey::HelperRememberChoice,
+        SettingKey::HelperPromptWhenUnmapped,
+        SettingKey::HelperChooserTimeoutSecs,
+        SettingKey::HelperCacheTtlSecs,
+        SettingKey::HelperAuthType,
+        SettingKey::LogLevel,
+        SettingKey::ActiveProfile,
+        SettingKey::DeletedAccountRetentionDays,
+        SettingKey::ExpiryNotifications,
+        SettingKey::HttpProxy,
+        SettingKey::NoProxy,
+        SettingKey::CaBundlePath,
+        SettingKey::ProjectDirectories,
+        SettingKey::WriteRepoIdentity,
+        SettingKey::ShowTray,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingKey::DefaultAccount => "default_account",
+            SettingKey::PreferredProtocol => "preferred_protocol",
+            SettingKey::Theme => "theme",
+            SettingKey::HelperRememberChoice => "helper_remember_choice",
+            SettingKey::HelperPromptWhenUnmapped => "helper_prompt_when_unmapped",
+            SettingKey::HelperChooserTimeoutSecs => "helper_chooser_timeout_secs",
+            SettingKey::HelperCacheTtlSecs => "helper_cache_ttl_secs",
+            SettingKey::HelperAuthType => "helper_auth_type",
+            SettingKey::LogLevel => "log_level",
+            SettingKey::ActiveProfile => "active_profile",
+            SettingKey::DeletedAccountRetentionDays => "deleted_account_retention_days",
+            SettingKey::ExpiryNotifications => "expiry_notifications",
+            SettingKey::HttpProxy => "http_proxy",
+            SettingKey::NoProxy => "no_proxy",
+            SettingKey::CaBundlePath => "ca_bundle_path",
+            SettingKey::ProjectDirectories => "project_directories",
+            SettingKey::WriteRepoIdentity => "write_repo_identity",
+            SettingKey::ShowTray => "show_tray",
+        }
+    }
+
+    pub fn parse(key: &str) -> Result<Self, SettingsError> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|k| k.as_str() == key)
+            .ok_or_else(|| SettingsError::UnknownKey(key.to_string()))
+    }
+
+    pub fn default_value(&self) -> Value {
+        match self {
+            SettingKey::DefaultAccount
+            | SettingKey::ActiveProfile
+            | SettingKey::HttpProxy
+            | SettingKey::NoProxy
+            | SettingKey::CaBundlePath => Value::Null,
+            SettingKey::PreferredProtocol => Value::from("https"),
+            SettingKey::Theme => Value::from("system"),
+            SettingKey::HelperRememberChoice => Value::from(true),
+            SettingKey::HelperPromptWhenUnmapped => Value::from(true),
+            SettingKey::HelperChooserTimeoutSecs => Value::from(60),
+            SettingKey::HelperCacheTtlSecs => Value::from(0),
+            SettingKey::HelperAuthType => Value::from("basic"),
+            SettingKey::LogLevel => Value::from("info"),
+            SettingKey::DeletedAccountRetentionDays => Value::from(30),
+            SettingKey::ExpiryNotifications => Value::from(true),
+            SettingKey::ProjectDirectories => Value::Array(Vec::new()),
+            SettingKey::WriteRepoIdentity => Value::from(false),
+            SettingKey::ShowTray => Value::from(true),
+        }
+    }
+
+    pub fn validate(&self, value: &Value) -> Result<(), SettingsError> {
+        let invalid = |reason: &str| SettingsError::InvalidValue {
+            key: self.as_str(),
+            reason: reason.to_string(),
+        };
+
+        match self {
+            // Account id, or null for "no default"
+            SettingKey::DefaultAccount => match value {
+                Value::Null => Ok(()),
+                Value::String(s) if !s.is_empty() => Ok(()),
+                _ => Err(invalid("expected an account id or null")),
+            },
+            // Profile id, or null when every account is in play
+            SettingKey::ActiveProfile => match value {
+                Value::Null => Ok(()),
+                Value::String(s) if !s.is_empty() => Ok(()),
+                _ => Err(invalid("expected a profile id or null")),
+            },
+            // Null falls back to the HTTP_PROXY and HTTPS_PROXY variables
+            SettingKey::HttpProxy => match value {
+                Value::Null => Ok(()),
+                Value::String(s) if s.starts_with("http://") || s.starts_with("https://") => Ok(()),
+                _ => Err(invalid("expected an http:// or https:// URL, or null")),
+            },
+            // Comma-separated hosts to reach without the proxy
+            SettingKey::NoProxy => match value {
+                Value::Null | Value::String(_) => Ok(()),
+                _ => Err(invalid("expected a comma-separated list of hosts or null")),
+            },
+            // PEM file with extra certificates to trust, for proxies that
+            // intercept TLS
+            SettingKey::CaBundlePath => match value {
+                Value::Null => Ok(()),
+                Value::String(s) if !s.is_empty() => Ok(()),
+                _ => Err(invalid("expected a file path or null")),
+            },
+            // Where the user keeps checkouts, for scan_repositories
+            SettingKey::ProjectDirectories => match value.as_array() {
+                Some(dirs)
+                    if dirs
+                        .iter()
+                        .all(|dir| dir.as_str().is_some_and(|s| !s.is_empty())) =>
+                {
+                    Ok(())
+                }
+                _ => Err(invalid("expected a list of directories")),
+            },
+            SettingKey::PreferredProtocol => match value.as_str() {
+                Some("https") | Some("ssh") => Ok(()),
+                _ => Err(invalid("expected \"https\" or \"ssh\"")),
+            },
+            SettingKey::Theme => match value.as_str() {
+                Some("system") | Some("light") | Some("dark") => Ok(()),
+                _ => Err(invalid("expected \"system\", \"light\" or \"dark\"")),
+            },
+            SettingKey::HelperRememberChoice
+            | SettingKey::HelperPromptWhenUnmapped
+            | SettingKey::ExpiryNotifications
+            // Whether remembering an account for a repository also sets its
+            // user.name and user.email
+            | SettingKey::WriteRepoIdentity
+            // The tray icon with the default account
+            | SettingKey::ShowTray => {
+                if value.is_boolean() {
+                    Ok(())
+                } else {
+                    Err(invalid("expected a boolean"))
+                }
+            }
+            // How long the helper waits for an account to be chosen in the app or
+            // on the terminal
+            SettingKey::HelperChooserTimeoutSecs => match value.as_u64() {
+                Some(secs) if (10..=600).contains(&secs) => Ok(()),
+                _ => Err(invalid("expected a number of seconds between 10 and 600")),
+            },
+            // How long the helper caches an answer in memory, 0 turns caching off
+            SettingKey::HelperCacheTtlSecs => match value.as_u64() {
+                Some(secs) if secs <= 86_400 => Ok(()),
+                _ => Err(invalid("expected a number of seconds up to 86400")),
+            },
+            // "bearer" only takes effect with git versions that support it
+            SettingKey::HelperAuthType => match value.as_str() {
+                Some("basic") | Some("bearer") => Ok(()),
+                _ => Err(invalid("expected \"basic\" or \"bearer\"")),
+            },
+            // Applies from the next start of the app or helper
+            SettingKey::LogLevel => match value.as_str() {
+                Some("error") | Some("warn") | Some("info") | Some("debug") | Some("trace") => {
+                    Ok(())
+                }
+                _ => Err(invalid(
+                    "expected \"error\", \"warn\", \"info\", \"debug\" or \"trace\"",
+                )),
+            },
+            // How long a removed account can be restored before it is purged
+            SettingKey::DeletedAccountRetentionDays => match value.as_u64() {
+                Some(days) if (1..=365).contains(&days) => Ok(()),
+                _ => Err(invalid("expected a number of days between 1 and 365")),
+            },
+        }
+    }
+}
@@ -1,6 +1,13 @@
+use crate::paths;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,6 +18,145 @@ pub enum SSHError {
     Process(String),
     #[error("SSH key not found")]
     KeyNotFound,
+    #[error("Unknown SSH key type: {0}")]
+    UnknownKeyType(String),
+    #[error("This OpenSSH can't create {0} keys")]
+    KeyTypeUnavailable(String),
+    #[error("{0} has a \"{BEGIN_MARKER}\" line without a matching \"{END_MARKER}\"")]
+    UnterminatedBlock(String),
+    #[error("SSH key error: {0}")]
+    Key(#[from] ssh_key::Error),
+    #[error("{0} already exists")]
+    KeyExists(String),
+    #[error("{0} has been changed since, so it was left alone")]
+    ChangedSinceWrite(String),
+}
+
+// Everything GitSwitchHub writes to the SSH config sits between these lines,
+// and nothing outside them is touched
+const BEGIN_MARKER: &str = "# BEGIN gitswitchhub";
+const END_MARKER: &str = "# END gitswitchhub";
+// Holds the host aliases, in ~/.ssh next to the config that includes it
+const INCLUDE_FILE: &str = "gitswitchhub_config";
+const INCLUDE_HEADER: &str = "# Managed by GitSwitchHub, changes here are overwritten";
+// The first OpenSSH with the Include directive
+const INCLUDE_MIN_VERSION: (u32, u32) = (7, 3);
+// What ssh.github.com's host key is recorded under in known_hosts
+const GITHUB_HOST_KEY_ALIAS: &str = "github.com";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// Under the app data directory, copies of SSH config files from before
+// each change
+const BACKUP_DIR: &str = "ssh_backups";
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+// How deep ssh itself follows Include directives
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Options where ssh uses the first value it finds, so an earlier section
+// setting them for an alias wins over GitSwitchHub's entry
+const FIRST_VALUE_OPTIONS: &[&str] = &[
+    "hostname",
+    "user",
+    "port",
+    "identitiesonly",
+    "proxycommand",
+    "proxyjump",
+];
+
+// Key types generate_key can create. The -sk types keep the private key on
+// a FIDO2 security key, which has to be plugged in and touched, so only
+// ssh-keygen can make them; the others are generated in-process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SshKeyType {
+    #[default]
+    Ed25519,
+    #[serde(rename = "rsa-4096")]
+    Rsa4096,
+    Ecdsa,
+    Ed25519Sk,
+    EcdsaSk,
+}
+
+impl SshKeyType {
+    pub const ALL: [SshKeyType; 5] = [
+        SshKeyType::Ed25519,
+        SshKeyType::Rsa4096,
+        SshKeyType::Ecdsa,
+        SshKeyType::Ed25519Sk,
+        SshKeyType::EcdsaSk,
+    ];
+
+    pub fn parse(value: &str) -> Result<Self, SSHError> {
+        Self::ALL
+            .into_iter()
+            .find(|key_type| key_type.as_str() == value.trim().to_lowercase())
+            .ok_or_else(|| SSHError::UnknownKeyType(value.to_string()))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SshKeyType::Ed25519 => "ed25519",
+            SshKeyType::Rsa4096 => "rsa-4096",
+            SshKeyType::Ecdsa => "ecdsa",
+            SshKeyType::Ed25519Sk => "ed25519-sk",
+            SshKeyType::EcdsaSk => "ecdsa-sk",
+        }
+    }
+
+    pub fn is_security_key(self) -> bool {
+        matches!(self, SshKeyType::Ed25519Sk | SshKeyType::EcdsaSk)
+    }
+
+    // None for the security key types. ssh-key makes 4096-bit RSA keys.
+    fn key_algorithm(self) -> Option<ssh_key::Algorithm> {
+        match self {
+            SshKeyType::Ed25519 => Some(ssh_key::Algorithm::Ed25519),
+            SshKeyType::Rsa4096 => Some(ssh_key::Algorithm::Rsa { hash: None }),
+            SshKeyType::Ecdsa => Some(ssh_key::Algorithm::Ecdsa {
+                curve: ssh_key::EcdsaCurve::NistP256,
+            }),
+            SshKeyType::Ed25519Sk | SshKeyType::EcdsaSk => None,
+        }
+    }
+
+    fn keygen_args(self) -> &'static [&'static str] {
+        match self {
+            SshKeyType::Ed25519 => &["-t", "ed25519"],
+            SshKeyType::Rsa4096 => &["-t", "rsa", "-b", "4096"],
+            SshKeyType::Ecdsa => &["-t", "ecdsa", "-b", "256"],
+            SshKeyType::Ed25519Sk => &["-t", "ed25519-sk"],
+            SshKeyType::EcdsaSk => &["-t", "ecdsa-sk"],
+        }
+    }
+
+    // The algorithm as listed by `ssh -Q key`
+    fn algorithm(self) -> &'static str {
+        match self {
+            SshKeyType::Ed25519 => "ssh-ed25519",
+            SshKeyType::Rsa4096 => "ssh-rsa",
+            SshKeyType::Ecdsa => "ecdsa-sha2-nistp256",
+            SshKeyType::Ed25519Sk => "sk-ssh-ed25519@openssh.com",
+            SshKeyType::EcdsaSk => "sk-ecdsa-sha2-nistp256@openssh.com",
+        }
+    }
+}
+
+// Key types generate_key can create here. Security key types need OpenSSH
+// 8.2 or later, so they are only offered when the local ssh lists them; the
+// rest don't need OpenSSH at all.
+pub fn available_key_types() -> Result<Vec<SshKeyType>, SSHError> {
+    let algorithms = match openssh("ssh").args(["-Q", "key"]).output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        _ => String::new(),
+    };
+    let algorithms: Vec<&str> = algorithms.lines().map(str::trim).collect();
+    Ok(SshKeyType::ALL
+        .into_iter()
+        .filter(|key_type| {
+            key_type.key_algorithm().is_some() || algorithms.contains(&key_type.algorithm())
+        })
+        .collect())
 }
 
 pub struct SSHManager;
@@ -26,151 +172,254 @@ impl SSHManager {
         Self
     }
 
-    pub fn generate_key(&self, username: &str) -> Result<SSHKeyInfo, SSHError> {
-        let home_dir = std::env::var("HOME").map_err(|_| {
-            SSHError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "HOME directory not found",
-            ))
-        })?;
+    pub fn generate_key(
+        &self,
+        username: &str,
+        key_type: SshKeyType,
+    ) -> Result<SSHKeyInfo, SSHError> {
+        if !available_key_types()?.contains(&key_type) {
+            return Err(SSHError::KeyTypeUnavailable(key_type.as_str().to_string()));
+        }
 
-        let ssh_dir = PathBuf::from(home_dir).join(".ssh");
+        let ssh_dir = paths::ssh_dir()?;
         fs::create_dir_all(&ssh_dir)?;
 
         let key_name = format!("gitswitchhub_{}", username);
         let private_key_path = ssh_dir.join(&key_name);
         let public_key_path = ssh_dir.join(format!("{}.pub", key_name));
+        if let Some(existing) = [&private_key_path, &public_key_path]
+            .into_iter()
+            .find(|path| path.exists())
+        {
+            return Err(SSHError::KeyExists(existing.display().to_string()));
+        }
+        let comment = format!("{}@gitswitchhub", username);
 
-        // Generate SSH key
-        let output = Command::new("ssh-keygen")
-            .args([
-                "-t",
-                "ed25519",
-                "-f",
-                &private_key_path.to_string_lossy(),
-                "-C",
-                &format!("{}@gitswitchhub", username),
-                "-N",
-                "", // No passphrase
-            ])
-            .output()?;
+        match key_type.key_algorithm() {
+            Some(algorithm) => {
+                let mut private_key =
+                    ssh_key::PrivateKey::random(&mut ssh_key::rand_core::OsRng, algorithm)?;
+                private_key.set_comment(comment);
+                // Readable by the owner only on Unix, as ssh insists
+                private_key.write_openssh_file(&private_key_path, ssh_key::LineEnding::LF)?;
+                private_key
+                    .public_key()
+                    .write_openssh_file(&public_key_path)?;
+            }
+            None => {
+                // Waits for the security key to be touched
+                let output = openssh("ssh-keygen")
+                    .args(key_type.keygen_args())
+                    .args([
+                        "-f",
+                        &private_key_path.to_string_lossy(),
+                        "-C",
+                        &comment,
+                        "-N",
+                        "", // No passphrase
+                    ])
+                    .output()?;
 
-        if !output.status.success() {
-            return Err(SSHError::Process(
-                String::from_utf8_lossy(&output.stderr).to_string(),
-            ));
+                if !output.status.success() {
+                    return Err(SSHError::Process(
+                        String::from_utf8_lossy(&output.stderr).to_string(),
+                    ));
+                }
+            }
         }
 
-        // Read public key
         let public_key = fs::read_to_string(&public_key_path)?;
 
         Ok(SSHKeyInfo {
             public_key: public_key.trim().to_string(),
             private_key_path: private_key_path.to_string_lossy().to_string(),
             key_id: key_name,
+            key_type,
         })
     }
 
-    pub fn get_ssh_config(&self, username: &str) -> Result<SSHConfig, SSHError> {
-        let home_dir = std::env::var("HOME").map_err(|_| {
-            SSHError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "HOME directory not found",
-            ))
-        })?;
+    pub fn public_key_path(&self, username: &str) -> Result<PathBuf, SSHError> {
+        Ok(paths::ssh_dir()?.join(format!("gitswitchhub_{}.pub", username)))
+    }
 
-        let key_name = format!("gitswitchhub_{}", username);
-        let private_key_path = format!("{}/.ssh/{}", home_dir, key_name);
+    // Deletes the key pair generate_key made, returning the files removed
+    pub fn delete_key(&self, username: &str) -> Result<Vec<PathBuf>, SSHError> {
+        let private_key_path = paths::ssh_dir()?.join(format!("gitswitchhub_{}", username));
+        let mut removed = Vec::new();
+        for path in [private_key_path, self.public_key_path(username)?] {
+            match fs::remove_file(&path) {
+                Ok(()) => removed.push(path),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(removed)
+    }
+
+    // The alias for the account, using `key` when the account was linked to
+    // an existing key and the generated one otherwise
+    pub fn get_ssh_config(
+        &self,
+        username: &str,
+        key: Option<&Path>,
+    ) -> Result<SSHConfig, SSHError> {
+        let private_key_path = self.private_key_path(username, key)?;
 
         Ok(SSHConfig {
             host: format!("github-{}", username),
             hostname: "github.com".to_string(),
             user: "git".to_string(),
-            identity_file: private_key_path,
+            identity_file: config_path(&private_key_path)?,
+            port: None,
         })
     }
 
-    pub fn add_to_ssh_config(&self, username: &str) -> Result<(), SSHError> {
-        let home_dir = std::env::var("HOME").map_err(|_| {
-            SSHError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "HOME directory not found",
-            ))
-        })?;
-
-        let ssh_config_path = PathBuf::from(home_dir).join(".ssh").join("config");
-        let config = self.get_ssh_config(username)?;
-
-        let ssh_config_entry = format!(
-            "\nHost {}\n\
-             HostName {}\n\
-             User {}\n\
-             IdentityFile {}\n\
-             IdentitiesOnly yes\n",
-            config.host, config.hostname, config.user, config.identity_file
-        );
-
-        // Append to SSH config
-        use std::io::Write;
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&ssh_config_path)?
-            .write_all(ssh_config_entry.as_bytes())?;
-
-        Ok(())
+    // `key` when the account was linked to an existing key, otherwise the
+    // one generate_key made
+    fn private_key_path(&self, username: &str, key: Option<&Path>) -> Result<PathBuf, SSHError> {
+        match key {
+            Some(key) => Ok(key.to_path_buf()),
+            None => Ok(paths::ssh_dir()?.join(format!("gitswitchhub_{}", username))),
+        }
     }
 
-    pub fn remove_from_ssh_config(&self, username: &str) -> Result<(), SSHError> {
-        let home_dir = std::env::var("HOME").map_err(|_| {
-            SSHError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "HOME directory not found",
-            ))
-        })?;
-
-        let ssh_config_path = PathBuf::from(home_dir).join(".ssh").join("config");
+    // A core.sshCommand using the account's key, for repositories whose
+    // remotes stay on git@github.com instead of going through the alias. Git
+    // runs it through a shell.
+    pub fn ssh_command(
+        &self,
+        username: &str,
+        key: Option<&Path>,
+        port_443: bool,
+    ) -> Result<String, SSHError> {
+        let private_key_path = self.private_key_path(username, key)?;
+        if !private_key_path.exists() {
+            return Err(SSHError::KeyNotFound);
+        }
+        let path = private_key_path.to_string_lossy();
+        let path = if cfg!(windows) {
+            path.replace('\\', "/")
+        } else {
+            path.into_owned()
+        };
+        let mut command = format!("ssh -i {} -o IdentitiesOnly=yes", shell_quote(&path));
+        if port_443 {
+            command.push_str(&format!(
+                " -o HostName=ssh.github.com -p 443 -o HostKeyAlias={}",
+                GITHUB_HOST_KEY_ALIAS
+            ));
+        }
+        Ok(command)
+    }
 
-        if !ssh_config_path.exists() {
-            return Ok(()); // Nothing to remove
+    // Adds the account's host alias to the managed block, replacing the
+    // alias if it is already there. Returns the files written, see
+    // ConfigWrite.
+    pub fn add_to_ssh_config(
+        &self,
+        username: &str,
+        key: Option<&Path>,
+        port_443: bool,
+    ) -> Result<Vec<ConfigWrite>, SSHError> {
+        let mut config = self.get_ssh_config(username, key)?;
+        if port_443 {
+            config = config.with_port_443();
         }
+        self.update_ssh_config(&config.host, Some(&config.entry()))
+    }
 
-        let content = fs::read_to_string(&ssh_config_path)?;
-        let host_pattern = format!("github-{}", username);
+    pub fn remove_from_ssh_config(&self, username: &str) -> Result<Vec<ConfigWrite>, SSHError> {
+        let config = self.get_ssh_config(username, None)?;
+        self.update_ssh_config(&config.host, None)
+    }
 
-        // Remove the host block
-        let lines: Vec<&str> = content.lines().collect();
-        let mut new_lines = Vec::new();
-        let mut skip_until_next_host = false;
+    // Sets or removes the alias. With OpenSSH 7.3 or later the aliases live in
+    // their own file, which the config pulls in with a single Include;
+    // otherwise they go in the managed block of the config itself.
+    fn update_ssh_config(
+        &self,
+        host: &str,
+        entry: Option<&str>,
+    ) -> Result<Vec<ConfigWrite>, SSHError> {
+        let mut writes = Vec::new();
+        let ssh_dir = paths::ssh_dir()?;
+        let ssh_config_path = ssh_dir.join("config");
+        let content = read_if_exists(&ssh_config_path)?;
+        let config = ManagedConfig::parse(&content)
+            .ok_or_else(|| SSHError::UnterminatedBlock(ssh_config_path.display().to_string()))?;
 
-        for line in lines {
-            if line.trim().starts_with("Host ") && line.contains(&host_pattern) {
-                skip_until_next_host = true;
-                continue;
+        let updated = if supports_include() {
+            let include_path = ssh_dir.join(INCLUDE_FILE);
+            let included = read_if_exists(&include_path)?;
+            let mut sections = parse_sections(&included.lines().collect::<Vec<_>>());
+            // Entries an earlier version kept in the config itself move over
+            for (alias, section) in parse_sections(&config.block) {
+                if !sections.iter().any(|(existing, _)| *existing == alias) {
+                    sections.push((alias, section));
+                }
             }
+            set_section(&mut sections, host, entry);
 
-            if skip_until_next_host {
-                if line.trim().starts_with("Host ") {
-                    skip_until_next_host = false;
-                    new_lines.push(line);
+            let body = join_sections(&sections);
+            match &body {
+                Some(body) => {
+                    let file = format!("{}\n{}", INCLUDE_HEADER, body);
+                    if file != included {
+                        fs::create_dir_all(&ssh_dir)?;
+                        writes.push(write_config_file(&include_path, Some(&file))?);
+                    }
+                }
+                None if include_path.exists() => {
+                    writes.push(write_config_file(&include_path, None)?)
                 }
-                // Skip lines until next Host
-                continue;
+                None => {}
             }
+            let include = format!("Include {}\n", INCLUDE_FILE);
+            config.render(host, body.map(|_| include.as_str()), true)
+        } else {
+            let mut sections = parse_sections(&config.block);
+            set_section(&mut sections, host, entry);
+            config.render(host, join_sections(&sections).as_deref(), false)
+        };
 
-            new_lines.push(line);
+        if updated != content {
+            fs::create_dir_all(&ssh_dir)?;
+            writes.push(write_config_file(&ssh_config_path, Some(&updated))?);
         }
-
-        fs::write(&ssh_config_path, new_lines.join("\n"))?;
-        Ok(())
+        Ok(writes)
     }
 
-    pub fn test_ssh_connection(&self, username: &str) -> Result<bool, SSHError> {
-        let config = self.get_ssh_config(username)?;
+    // Signs in to GitHub with the account's key and nothing else: keys from
+    // the agent or other IdentityFile entries would otherwise be tried first
+    // and could belong to another account
+    pub fn test_ssh_connection(
+        &self,
+        username: &str,
+        key: Option<&Path>,
+        port_443: bool,
+    ) -> Result<SshConnectionTest, SSHError> {
+        let mut config = self.get_ssh_config(username, key)?;
+        if port_443 {
+            config = config.with_port_443();
+        }
+        let private_key_path = self.private_key_path(username, key)?;
+        if !private_key_path.exists() {
+            return Err(SSHError::KeyNotFound);
+        }
 
-        let output = Command::new("ssh")
+        let mut command = openssh("ssh");
+        command.arg("-T").arg("-i").arg(&private_key_path);
+        if let Some(port) = config.port {
+            command
+                .args(["-p", &port.to_string()])
+                .args(["-o", &format!("HostKeyAlias={}", GITHUB_HOST_KEY_ALIAS)]);
+        }
+        let output = command
             .args([
-                "-T",
+                "-o",
+                "IdentitiesOnly=yes",
+                "-o",
+                "BatchMode=yes",
                 "-o",
                 "StrictHostKeyChecking=no",
                 "-o",
@@ -179,13 +428,985 @@ impl SSHManager {
             ])
             .output()?;
 
-        // GitHub returns exit code 1 for successful SSH connections
-        // but with a message about successful authentication
+        // GitHub exits with 1 either way; only the greeting tells them apart
         let stderr = String::from_utf8_lossy(&output.stderr);
-        Ok(stderr.contains("successfully authenticated")
-            || stderr.contains("Hi")
-            || output.status.code() == Some(1))
+        Ok(SshConnectionTest {
+            authenticated_as: parse_greeting(&stderr),
+            output: stderr.trim().to_string(),
+        })
+    }
+}
+
+// The user in "Hi octocat! You've successfully authenticated, but GitHub
+// does not provide shell access."
+fn parse_greeting(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (user, _) = line.trim().strip_prefix("Hi ")?.split_once('!')?;
+        Some(user.to_string()).filter(|user| !user.is_empty() && !user.contains(' '))
+    })
+}
+
+// How ssh resolves a destination, from ssh -G
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedHost {
+    pub hostname: String,
+    pub port: Option<u16>,
+    pub identity_files: Vec<String>,
+    pub identities_only: bool,
+}
+
+// A sign-in to GitHub made the way git would make it, with ssh -v
+#[derive(Debug, Clone)]
+pub struct SshTrace {
+    pub resolved: ResolvedHost,
+    pub authenticated_as: Option<String>,
+    // The keys ssh offered, in order, as ssh -v describes them
+    pub offered_keys: Vec<String>,
+    // "SHA256:..." of the key the server accepted
+    pub accepted_fingerprint: Option<String>,
+    // What ssh printed besides its debug lines
+    pub output: String,
+}
+
+// Connects to a remote's destination as git does: through the repository's
+// core.sshCommand, which git runs with a shell, or else plain ssh and the
+// user's config
+pub fn trace_connection(
+    ssh_command: Option<&str>,
+    destination: &str,
+    port: Option<u16>,
+) -> Result<SshTrace, SSHError> {
+    let run = |args: &[&str]| -> Result<std::process::Output, SSHError> {
+        let mut command = match ssh_command {
+            Some(ssh_command) => {
+                let mut command = Command::new("sh");
+                command
+                    .arg("-c")
+                    .arg(format!("{} \"$@\"", ssh_command))
+                    .arg("ssh");
+                command
+            }
+            None => openssh("ssh"),
+        };
+        if let Some(port) = port {
+            command.args(["-p", &port.to_string()]);
+        }
+        Ok(command.args(args).arg(destination).output()?)
+    };
+
+    let output = run(&["-G"])?;
+    if !output.status.success() {
+        return Err(SSHError::Process(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let resolved = parse_resolved_host(&String::from_utf8_lossy(&output.stdout));
+
+    let output = run(&[
+        "-v",
+        "-T",
+        "-o",
+        "BatchMode=yes",
+        "-o",
+        "StrictHostKeyChecking=no",
+        "-o",
+        "ConnectTimeout=10",
+    ])?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut offered_keys = Vec::new();
+    let mut accepted_fingerprint = None;
+    let mut lines = Vec::new();
+    for line in stderr.lines() {
+        if let Some(key) = line.strip_prefix("debug1: Offering public key: ") {
+            offered_keys.push(key.trim().to_string());
+        } else if let Some(key) = line.strip_prefix("debug1: Server accepts key: ") {
+            // Older versions only name the algorithm here
+            accepted_fingerprint = key
+                .split_whitespace()
+                .find(|word| word.starts_with("SHA256:"))
+                .map(|fingerprint| fingerprint.to_string());
+        } else if !line.starts_with("debug") && !line.starts_with("OpenSSH_") {
+            lines.push(line);
+        }
+    }
+    Ok(SshTrace {
+        resolved,
+        authenticated_as: parse_greeting(&stderr),
+        offered_keys,
+        accepted_fingerprint,
+        output: lines.join("\n").trim().to_string(),
+    })
+}
+
+fn parse_resolved_host(output: &str) -> ResolvedHost {
+    let mut resolved = ResolvedHost::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+        match key {
+            "hostname" => resolved.hostname = value.to_string(),
+            "port" => resolved.port = value.parse().ok(),
+            "identityfile" => resolved.identity_files.push(value.to_string()),
+            "identitiesonly" => resolved.identities_only = value == "yes",
+            _ => {}
+        }
+    }
+    resolved
+}
+
+// "SHA256:..." of each key the agent holds, empty without an agent
+pub fn agent_fingerprints() -> Result<Vec<String>, SSHError> {
+    let output = openssh("ssh-add").args(["-l", "-E", "sha256"]).output()?;
+    // 1 means the agent holds no keys, 2 that there is no agent
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .filter(|fingerprint| fingerprint.starts_with("SHA256:"))
+            .map(|fingerprint| fingerprint.to_string())
+            .collect()),
+        Some(1) | Some(2) => Ok(Vec::new()),
+        _ => Err(SSHError::Process(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+// The local OpenSSH's version, from `ssh -V` output such as
+// "OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13" or
+// "OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2"
+pub fn openssh_version() -> Result<(u32, u32), SSHError> {
+    let output = openssh("ssh").arg("-V").output()?;
+    let banner = String::from_utf8_lossy(&output.stderr);
+    parse_openssh_version(&banner)
+        .ok_or_else(|| SSHError::Process(format!("Unrecognized ssh -V output: {}", banner.trim())))
+}
+
+fn parse_openssh_version(banner: &str) -> Option<(u32, u32)> {
+    let version = banner.split("OpenSSH_").nth(1)?;
+    let version = version.strip_prefix("for_Windows_").unwrap_or(version);
+    let (major, rest) = version.split_once('.')?;
+    let minor: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+// An OpenSSH that can't be asked is assumed to be too old
+fn supports_include() -> bool {
+    openssh_version().is_ok_and(|version| version >= INCLUDE_MIN_VERSION)
+}
+
+fn read_if_exists(path: &Path) -> Result<String, SSHError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// A command for one of the OpenSSH programs. GUI apps on Windows don't
+// always have the built-in OpenSSH client on their PATH, so its install
+// location is tried when the program isn't found there.
+fn openssh(program: &str) -> Command {
+    Command::new(openssh_program(program))
+}
+
+fn openssh_program(program: &str) -> PathBuf {
+    if cfg!(windows) {
+        let exe = format!("{}.exe", program);
+        let on_path = env::var_os("PATH")
+            .is_some_and(|path| env::split_paths(&path).any(|dir| dir.join(&exe).is_file()));
+        if !on_path {
+            if let Some(root) = env::var_os("SystemRoot") {
+                let builtin = PathBuf::from(root)
+                    .join("System32")
+                    .join("OpenSSH")
+                    .join(&exe);
+                if builtin.is_file() {
+                    return builtin;
+                }
+            }
+        }
+    }
+    PathBuf::from(program)
+}
+
+// A path as written in ssh_config. Paths in the home directory use "~",
+// and separators are forward slashes, which both the Windows OpenSSH client
+// and the MSYS ssh bundled with Git for Windows understand, e.g.
+//   IdentityFile ~/.ssh/gitswitchhub_octocat
+fn config_path(path: &Path) -> Result<String, SSHError> {
+    let home = paths::home_dir()?;
+    let written = match path.strip_prefix(&home) {
+        Ok(relative) => format!("~/{}", relative.to_string_lossy()),
+        Err(_) => path.to_string_lossy().into_owned(),
+    };
+    let written = if cfg!(windows) {
+        written.replace('\\', "/")
+    } else {
+        written
+    };
+    if written.contains(char::is_whitespace) {
+        Ok(format!("\"{}\"", written))
+    } else {
+        Ok(written)
+    }
+}
+
+// A change GitSwitchHub made to an SSH config file. What the file held
+// before is copied to backup_path, None when there was no file.
+#[derive(Debug, Clone)]
+pub struct ConfigWrite {
+    pub path: PathBuf,
+    pub backup_path: Option<PathBuf>,
+    // Of the new content, None when the file was removed
+    pub sha256: Option<String>,
+    pub diff: String,
+}
+
+// Writes, or with None removes, an SSH config file after backing up what
+// it held
+fn write_config_file(path: &Path, content: Option<&str>) -> Result<ConfigWrite, SSHError> {
+    let before = match fs::read_to_string(path) {
+        Ok(before) => Some(before),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let backup_path = match &before {
+        Some(before) => {
+            let backup_dir = paths::app_data_dir()?.join(BACKUP_DIR);
+            fs::create_dir_all(&backup_dir)?;
+            let name = path
+                .file_name()
+                .map_or_else(|| "config".into(), |name| name.to_string_lossy());
+            let stamp = Utc::now().format(BACKUP_TIMESTAMP_FORMAT).to_string();
+            // Never overwrite an older backup a recorded change points to
+            let mut attempt = 0;
+            let (backup_path, mut file) = loop {
+                let backup_path = match attempt {
+                    0 => backup_dir.join(format!("{}-{}", stamp, name)),
+                    n => backup_dir.join(format!("{}-{}-{}", stamp, n, name)),
+                };
+                match fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&backup_path)
+                {
+                    Ok(file) => break (backup_path, file),
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            file.write_all(before.as_bytes())?;
+            Some(backup_path)
+        }
+        None => None,
+    };
+
+    match content {
+        Some(content) => fs::write(path, content)?,
+        None if before.is_some() => fs::remove_file(path)?,
+        None => {}
+    }
+    Ok(ConfigWrite {
+        path: path.to_path_buf(),
+        backup_path,
+        sha256: content.map(sha256_hex),
+        diff: line_diff(before.as_deref().unwrap_or(""), content.unwrap_or("")),
+    })
+}
+
+// Puts a file back the way it was before a ConfigWrite, refusing when it
+// has been changed since. Restoring is itself a write that can be undone.
+pub fn undo_config_write(
+    path: &Path,
+    backup_path: Option<&Path>,
+    sha256: Option<&str>,
+) -> Result<ConfigWrite, SSHError> {
+    if !unchanged_since_write(path, sha256)? {
+        return Err(SSHError::ChangedSinceWrite(path.display().to_string()));
+    }
+    let previous = backup_path.map(fs::read_to_string).transpose()?;
+    write_config_file(path, previous.as_deref())
+}
+
+// Whether the file still holds what a ConfigWrite left in it
+pub fn unchanged_since_write(path: &Path, sha256: Option<&str>) -> Result<bool, SSHError> {
+    let current = match fs::read_to_string(path) {
+        Ok(current) => Some(current),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    Ok(current.as_deref().map(sha256_hex).as_deref() == sha256)
+}
+
+fn sha256_hex(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+// The removed ("-") and added ("+") lines, in file order, with the old
+// version of a changed line first
+fn line_diff(before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] > common[i + 1][j]) {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
+
+// Single quotes for anything a POSIX shell would split or expand
+fn shell_quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-~:+@".contains(c))
+    {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+// An SSH config split around its managed block
+struct ManagedConfig<'a> {
+    before: Vec<&'a str>,
+    block: Vec<&'a str>,
+    after: Vec<&'a str>,
+    found: bool,
+}
+
+impl<'a> ManagedConfig<'a> {
+    // None when the block has no end marker
+    fn parse(content: &'a str) -> Option<Self> {
+        let lines: Vec<&str> = content.lines().collect();
+        let begin = match lines.iter().position(|line| line.trim() == BEGIN_MARKER) {
+            Some(begin) => begin,
+            None => {
+                return Some(Self {
+                    before: lines,
+                    block: Vec::new(),
+                    after: Vec::new(),
+                    found: false,
+                })
+            }
+        };
+        let end = begin
+            + lines[begin..]
+                .iter()
+                .position(|line| line.trim() == END_MARKER)?;
+        Some(Self {
+            before: lines[..begin].to_vec(),
+            block: lines[begin + 1..end].to_vec(),
+            after: lines[end + 1..].to_vec(),
+            found: true,
+        })
+    }
+
+    // The config with `body` as the block, or without a block when there is
+    // none. A new block goes at the end, or the top with `at_top`, where an
+    // Include has to be to apply to every host. Entries older versions
+    // appended for `host` are dropped.
+    fn render(&self, host: &str, body: Option<&str>, at_top: bool) -> String {
+        let mut rest = String::new();
+        for line in remove_legacy_entry(&self.before, host) {
+            rest.push_str(line);
+            rest.push('\n');
+        }
+        let mut tail = String::new();
+        for line in remove_legacy_entry(&self.after, host) {
+            tail.push_str(line);
+            tail.push('\n');
+        }
+
+        let block = body.map(|body| format!("{}\n{}{}\n", BEGIN_MARKER, body, END_MARKER));
+        let updated = match block {
+            Some(block) if at_top => {
+                // Moves a block an earlier version left further down
+                let rest = format!("{}{}", rest, tail);
+                let rest = rest.trim_matches('\n');
+                if rest.is_empty() {
+                    block
+                } else {
+                    format!("{}\n{}\n", block, rest)
+                }
+            }
+            Some(block) => {
+                if !self.found && !rest.is_empty() && !rest.ends_with("\n\n") {
+                    rest.push('\n');
+                }
+                format!("{}{}{}", rest, block, tail)
+            }
+            None => format!("{}{}", rest, tail),
+        };
+        // Without the block, the blank line written next to it goes as well
+        if body.is_none() && self.found {
+            let trimmed = updated.trim_matches('\n');
+            return if trimmed.is_empty() {
+                String::new()
+            } else {
+                format!("{}\n", trimmed)
+            };
+        }
+        updated
+    }
+}
+
+// Host sections by alias, from lines holding nothing but GitSwitchHub's
+// entries
+fn parse_sections(lines: &[&str]) -> Vec<(String, String)> {
+    let mut sections: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        match host_alias(line) {
+            Some(alias) => sections.push((alias.to_string(), format!("{}\n", line))),
+            // Comments before the first Host line aren't part of a section
+            None => {
+                if let Some((_, section)) = sections.last_mut() {
+                    section.push_str(line);
+                    section.push('\n');
+                }
+            }
+        }
+    }
+    sections
+}
+
+fn set_section(sections: &mut Vec<(String, String)>, host: &str, entry: Option<&str>) {
+    match (sections.iter().position(|(alias, _)| alias == host), entry) {
+        (Some(i), Some(entry)) => sections[i].1 = entry.to_string(),
+        (Some(i), None) => {
+            sections.remove(i);
+        }
+        (None, Some(entry)) => sections.push((host.to_string(), entry.to_string())),
+        (None, None) => {}
+    }
+}
+
+fn join_sections(sections: &[(String, String)]) -> Option<String> {
+    (!sections.is_empty()).then(|| {
+        sections
+            .iter()
+            .map(|(_, section)| section.as_str())
+            .collect()
+    })
+}
+
+fn host_alias(line: &str) -> Option<&str> {
+    let (keyword, value) = line.trim().split_once(char::is_whitespace)?;
+    keyword.eq_ignore_ascii_case("host").then(|| value.trim())
+}
+
+// Drops the unmarked entry older versions appended for `host`. Only an exact
+// match of what they wrote is removed, so edited entries are left alone.
+fn remove_legacy_entry<'a>(lines: &[&'a str], host: &str) -> Vec<&'a str> {
+    let is_legacy = |section: &[&str]| {
+        section.len() == 5
+            && section[0].trim() == format!("Host {}", host)
+            && section[1].trim() == "HostName github.com"
+            && section[2].trim() == "User git"
+            && section[3].trim().starts_with("IdentityFile ")
+            && section[3].contains("gitswitchhub_")
+            && section[4].trim() == "IdentitiesOnly yes"
+    };
+    // The section ends at a blank line, the next Host or Match, or the end
+    let ends_at = |i: usize| {
+        lines.get(i).is_none_or(|line| {
+            let line = line.trim();
+            line.is_empty()
+                || host_alias(line).is_some()
+                || line
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|keyword| keyword.eq_ignore_ascii_case("match"))
+        })
+    };
+    let mut kept = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        if lines.len() - i >= 5 && is_legacy(&lines[i..i + 5]) && ends_at(i + 5) {
+            // Along with the blank line that was written before it
+            if kept
+                .last()
+                .is_some_and(|line: &&str| line.trim().is_empty())
+            {
+                kept.pop();
+            }
+            i += 5;
+            continue;
+        }
+        kept.push(lines[i]);
+        i += 1;
     }
+    kept
+}
+
+// Lists the key in an allowed signers file (see "ALLOWED SIGNERS" in
+// ssh-keygen(1)), which git checks SSH signatures against. An earlier line
+// for the same key is replaced.
+pub fn add_allowed_signer(path: &Path, principal: &str, public_key: &str) -> Result<(), SSHError> {
+    let key = public_key
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let content = read_if_exists(path)?;
+    let mut lines: Vec<&str> = content
+        .lines()
+        .filter(|line| !line.contains(&key))
+        .collect();
+    let entry = format!("{} namespaces=\"git\" {}", principal, key);
+    lines.push(&entry);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{}\n", lines.join("\n")))?;
+    Ok(())
+}
+
+// What a public key file says about the key, read without OpenSSH
+#[derive(Debug, Clone)]
+pub struct KeyDetails {
+    pub public_key: String,
+    // e.g. "ssh-ed25519"
+    pub algorithm: String,
+    // "SHA256:..."
+    pub fingerprint: String,
+    pub comment: String,
+    // When the file was written, as the key itself carries no date. Falls
+    // back to the modification time where creation times aren't kept.
+    pub created_at: Option<SystemTime>,
+}
+
+pub fn key_details(public_key_path: &Path) -> Result<KeyDetails, SSHError> {
+    let metadata = fs::metadata(public_key_path)?;
+    let key = ssh_key::PublicKey::read_openssh_file(public_key_path)?;
+    Ok(KeyDetails {
+        public_key: key.to_openssh()?,
+        algorithm: key.algorithm().as_str().to_string(),
+        fingerprint: key.fingerprint(ssh_key::HashAlg::Sha256).to_string(),
+        comment: key.comment().to_string(),
+        created_at: metadata.created().or_else(|_| metadata.modified()).ok(),
+    })
+}
+
+// A public key in ~/.ssh, as described by ssh-keygen -l
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedKey {
+    pub public_key_path: String,
+    // None when only the public half is on disk, e.g. for keys that live in
+    // an agent or on a security key
+    pub private_key_path: Option<String>,
+    // "ED25519", "RSA", "ECDSA-SK", ...
+    pub key_type: String,
+    pub bits: u32,
+    // "SHA256:..."
+    pub fingerprint: String,
+    pub comment: String,
+}
+
+// Every public key in ~/.ssh, by path
+pub fn detect_keys() -> Result<Vec<DetectedKey>, SSHError> {
+    let entries = match fs::read_dir(paths::ssh_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut keys = Vec::new();
+    for entry in entries {
+        let public_key_path = entry?.path();
+        if public_key_path.extension().is_none_or(|ext| ext != "pub") || !public_key_path.is_file()
+        {
+            continue;
+        }
+        let output = openssh("ssh-keygen")
+            .args(["-l", "-E", "sha256", "-f"])
+            .arg(&public_key_path)
+            .output()?;
+        // Not a key after all
+        if !output.status.success() {
+            continue;
+        }
+        let description = String::from_utf8_lossy(&output.stdout);
+        let (bits, fingerprint, comment, key_type) = match parse_key_description(&description) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let private_key_path = public_key_path.with_extension("");
+        keys.push(DetectedKey {
+            public_key_path: public_key_path.display().to_string(),
+            private_key_path: private_key_path
+                .is_file()
+                .then(|| private_key_path.display().to_string()),
+            key_type,
+            bits,
+            fingerprint,
+            comment,
+        });
+    }
+    keys.sort_by(|a, b| a.public_key_path.cmp(&b.public_key_path));
+    Ok(keys)
+}
+
+// "256 SHA256:abc... octocat@example.com (ED25519)"
+fn parse_key_description(description: &str) -> Option<(u32, String, String, String)> {
+    let description = description.lines().next()?.trim();
+    let (bits, rest) = description.split_once(' ')?;
+    let (fingerprint, rest) = rest.split_once(' ')?;
+    let open = rest.rfind('(')?;
+    let key_type = rest[open + 1..].strip_suffix(')')?.to_string();
+    let comment = match rest[..open].trim() {
+        "no comment" => "",
+        comment => comment,
+    };
+    Some((
+        bits.parse().ok()?,
+        fingerprint.to_string(),
+        comment.to_string(),
+        key_type,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SshConfigIssueKind {
+    // A section of the user's own for github.com itself
+    GithubHost,
+    // An IdentityFile ssh also offers when connecting through an alias
+    IdentityFile,
+    // Options set for an alias before GitSwitchHub's entry, which win
+    ShadowedAlias,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshConfigIssue {
+    pub kind: SshConfigIssueKind,
+    pub file: String,
+    pub line: usize,
+    // The section's patterns as written, "*" for options before any Host
+    pub host: String,
+    pub alias: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshConfigAnalysis {
+    pub config_path: String,
+    pub include_supported: bool,
+    // Aliases GitSwitchHub's block or file already defines
+    pub managed_aliases: Vec<String>,
+    pub issues: Vec<SshConfigIssue>,
+}
+
+// A Host or Match section of the SSH config, with the files it includes
+// read in place
+struct ConfigSection {
+    file: PathBuf,
+    line: usize,
+    // None for Match sections, whose conditions aren't evaluated
+    patterns: Option<Vec<String>>,
+    // The options before the first Host line, which apply to every host
+    global: bool,
+    managed: bool,
+    // (lowercase keyword, value)
+    options: Vec<(String, String)>,
+}
+
+// Looks through the user's SSH config for entries that interfere with the
+// host aliases in `aliases`, before or after they are set up
+pub fn analyze_config(aliases: &[String]) -> Result<SshConfigAnalysis, SSHError> {
+    let ssh_dir = paths::ssh_dir()?;
+    let config_path = ssh_dir.join("config");
+    let mut sections = Vec::new();
+    read_config_sections(&config_path, &ssh_dir, false, 0, &mut sections)?;
+    let include_supported = supports_include();
+
+    let mut managed_aliases: Vec<String> = Vec::new();
+    for section in sections.iter().filter(|section| section.managed) {
+        for pattern in section.patterns.iter().flatten() {
+            if !managed_aliases.contains(pattern) {
+                managed_aliases.push(pattern.clone());
+            }
+        }
+    }
+    let mut checked = aliases.to_vec();
+    checked.extend(managed_aliases.iter().cloned());
+    checked.sort();
+    checked.dedup();
+
+    // Where each alias's entry is, or would be written by add_to_ssh_config
+    let managed_position = |alias: &str| {
+        sections
+            .iter()
+            .position(|section| {
+                section.managed && section.patterns.iter().flatten().any(|p| p == alias)
+            })
+            .unwrap_or(if include_supported { 0 } else { sections.len() })
+    };
+
+    let mut issues = Vec::new();
+    for (index, section) in sections.iter().enumerate() {
+        let patterns = match (&section.patterns, section.managed) {
+            (Some(patterns), false) => patterns,
+            _ => continue,
+        };
+        let issue = |kind, alias: Option<&str>, message: String| SshConfigIssue {
+            kind,
+            file: section.file.display().to_string(),
+            line: section.line,
+            host: patterns.join(" "),
+            alias: alias.map(str::to_string),
+            message,
+        };
+        let location = format!(
+            "{} (line {} of {})",
+            if section.global {
+                "The options before any Host".to_string()
+            } else {
+                format!("Host {}", patterns.join(" "))
+            },
+            section.line,
+            section.file.display()
+        );
+
+        // Catch-all sections such as "Host * !work" aren't about github.com
+        let names_github = patterns
+            .iter()
+            .filter(|p| !p.starts_with('!') && *p != "*")
+            .any(|p| wildcard_match(&p.to_lowercase(), "github.com"));
+        if names_github && host_matches(patterns, "github.com") {
+            issues.push(issue(
+                SshConfigIssueKind::GithubHost,
+                None,
+                format!(
+                    "{} applies to git@github.com remotes; only remotes switched to an account alias leave it",
+                    location
+                ),
+            ));
+        }
+
+        for alias in checked.iter().filter(|alias| host_matches(patterns, alias)) {
+            let before = index < managed_position(alias);
+            for (_, value) in section.options.iter().filter(|(k, _)| k == "identityfile") {
+                issues.push(issue(
+                    SshConfigIssueKind::IdentityFile,
+                    Some(alias),
+                    if before {
+                        format!(
+                            "{} offers {} for {} before its own key, which can sign in as another account",
+                            location, value, alias
+                        )
+                    } else {
+                        format!("{} also offers {} for {}", location, value, alias)
+                    },
+                ));
+            }
+
+            let shadowed: Vec<&str> = section
+                .options
+                .iter()
+                .map(|(keyword, _)| keyword.as_str())
+                .filter(|keyword| FIRST_VALUE_OPTIONS.contains(keyword))
+                .collect();
+            if before && !shadowed.is_empty() {
+                issues.push(issue(
+                    SshConfigIssueKind::ShadowedAlias,
+                    Some(alias),
+                    format!(
+                        "{} sets {} for {} ahead of GitSwitchHub's entry, so ssh uses those values",
+                        location,
+                        shadowed.join(", "),
+                        alias
+                    ),
+                ));
+            }
+        }
+    }
+
+    Ok(SshConfigAnalysis {
+        config_path: config_path.display().to_string(),
+        include_supported,
+        managed_aliases,
+        issues,
+    })
+}
+
+fn read_config_sections(
+    path: &Path,
+    ssh_dir: &Path,
+    managed: bool,
+    depth: usize,
+    sections: &mut Vec<ConfigSection>,
+) -> Result<(), SSHError> {
+    let content = read_if_exists(path)?;
+    let mut in_block = false;
+    // The section this file's options belong to; an included file's
+    // sections end where the Include was
+    let mut current: Option<usize> = None;
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed == BEGIN_MARKER || trimmed == END_MARKER {
+            in_block = trimmed == BEGIN_MARKER;
+            continue;
+        }
+        let (keyword, value) = match config_option(trimmed) {
+            Some(option) => option,
+            None => continue,
+        };
+        let section_managed = managed || in_block;
+
+        match keyword.as_str() {
+            "host" | "match" => {
+                current = Some(sections.len());
+                sections.push(ConfigSection {
+                    file: path.to_path_buf(),
+                    line: index + 1,
+                    patterns: (keyword == "host")
+                        .then(|| value.split_whitespace().map(str::to_string).collect()),
+                    global: false,
+                    managed: section_managed,
+                    options: Vec::new(),
+                });
+            }
+            "include" if depth < MAX_INCLUDE_DEPTH => {
+                for included in value.split_whitespace() {
+                    for file in include_files(included, ssh_dir) {
+                        let ours = file.file_name().is_some_and(|name| name == INCLUDE_FILE);
+                        read_config_sections(
+                            &file,
+                            ssh_dir,
+                            section_managed || ours,
+                            depth + 1,
+                            sections,
+                        )?;
+                    }
+                }
+            }
+            _ => {
+                let section = *current.get_or_insert_with(|| {
+                    sections.push(ConfigSection {
+                        file: path.to_path_buf(),
+                        line: index + 1,
+                        patterns: Some(vec!["*".to_string()]),
+                        global: true,
+                        managed: section_managed,
+                        options: Vec::new(),
+                    });
+                    sections.len() - 1
+                });
+                sections[section].options.push((keyword, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+// "Keyword value" or "Keyword=value", without comments
+fn config_option(line: &str) -> Option<(String, String)> {
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let split = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let keyword = line[..split].to_lowercase();
+    let value = line[split..]
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+        .trim()
+        .trim_matches('"');
+    Some((keyword, value.to_string()))
+}
+
+// Files an Include names: relative paths are in ~/.ssh, and wildcards are
+// only expanded in the file name
+fn include_files(pattern: &str, ssh_dir: &Path) -> Vec<PathBuf> {
+    let path = PathBuf::from(paths::expand_home(pattern));
+    let path = if path.is_absolute() {
+        path
+    } else {
+        ssh_dir.join(path)
+    };
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return vec![path];
+    }
+    let dir = path.parent().unwrap_or(ssh_dir);
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|file| {
+            file.file_name()
+                .is_some_and(|file_name| wildcard_match(&name, &file_name.to_string_lossy()))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// ssh's Host matching: any pattern matches and no negated one does
+fn host_matches(patterns: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    let mut matched = false;
+    for pattern in patterns.iter().map(|pattern| pattern.to_lowercase()) {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, &host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(&pattern, &host),
+        }
+    }
+    matched
+}
+
+// "*" matches any run of characters and "?" any single one
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
 #[derive(Debug, Clone)]
@@ -193,6 +1414,14 @@ pub struct SSHKeyInfo {
     pub public_key: String,
     pub private_key_path: String,
     pub key_id: String,
+    pub key_type: SshKeyType,
+}
+
+#[derive(Debug, Clone)]
+pub struct SshConnectionTest {
+    // The GitHub user the key signed in as, None when it was refused
+    pub authenticated_as: Option<String>,
+    pub output: String,
 }
 
 #[derive(Debug, Clone)]
@@ -201,4 +1430,284 @@ pub struct SSHConfig {
     pub hostname: String,
     pub user: String,
     pub identity_file: String,
+    // Only set when going through port 443
+    pub port: Option<u16>,
+}
+
+impl SSHConfig {
+    // Goes to ssh.github.com:443, which GitHub serves SSH on for networks
+    // that block port 22
+    pub fn with_port_443(self) -> Self {
+        Self {
+            hostname: "ssh.github.com".to_string(),
+            port: Some(443),
+            ..self
+        }
+    }
+
+    // The Host section for this alias
+    fn entry(&self) -> String {
+        let mut entry = format!("Host {}\n    HostName {}\n", self.host, self.hostname);
+        if let Some(port) = self.port {
+            // ssh.github.com has github.com's host keys, so the known_hosts
+            // entries for github.com keep working
+            entry.push_str(&format!(
+                "    Port {}\n    HostKeyAlias {}\n",
+                port, GITHUB_HOST_KEY_ALIAS
+            ));
+        }
+        entry.push_str(&format!(
+            "    User {}\n    IdentityFile {}\n    IdentitiesOnly yes\n",
+            self.user, self.identity_file
+        ));
+        entry
+    }
+}
+
+// Whether GitHub's SSH servers answer on either port
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshPortProbe {
+    pub port_22: bool,
+    pub port_443: bool,
+}
+
+// Checks that an SSH server greets us on github.com:22 and
+// ssh.github.com:443. A proxy that accepts the connection but doesn't pass
+// SSH through counts as blocked.
+pub fn probe_ssh_ports() -> SshPortProbe {
+    std::thread::scope(|scope| {
+        let port_22 = scope.spawn(|| ssh_banner_reachable("github.com", 22));
+        let port_443 = scope.spawn(|| ssh_banner_reachable("ssh.github.com", 443));
+        SshPortProbe {
+            port_22: port_22.join().unwrap_or(false),
+            port_443: port_443.join().unwrap_or(false),
+        }
+    })
+}
+
+fn ssh_banner_reachable(host: &str, port: u16) -> bool {
+    use std::io::Read;
+    use std::net::{TcpStream, ToSocketAddrs};
+
+    let Ok(addresses) = (host, port).to_socket_addrs() else {
+        return false;
+    };
+    addresses.into_iter().any(|address| {
+        let Ok(mut stream) = TcpStream::connect_timeout(&address, PROBE_TIMEOUT) else {
+            return false;
+        };
+        let mut banner = [0u8; 4];
+        stream.set_read_timeout(Some(PROBE_TIMEOUT)).is_ok()
+            && stream.read_exact(&mut banner).is_ok()
+            && &banner == b"SSH-"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OCTOCAT: &str = "Host github-octocat\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/gitswitchhub_octocat\n    IdentitiesOnly yes\n";
+    const HUBOT: &str = "Host github-hubot\n    HostName github.com\n    User git\n    IdentityFile ~/.ssh/gitswitchhub_hubot\n    IdentitiesOnly yes\n";
+
+    // The config without an Include, as for an OpenSSH older than 7.3
+    fn update_block(content: &str, host: &str, entry: Option<&str>) -> Option<String> {
+        let config = ManagedConfig::parse(content)?;
+        let mut sections = parse_sections(&config.block);
+        set_section(&mut sections, host, entry);
+        Some(config.render(host, join_sections(&sections).as_deref(), false))
+    }
+
+    #[test]
+    fn adds_block_after_existing_config() {
+        let content = "Host example.com\n    User me\n";
+        let updated = update_block(content, "github-octocat", Some(OCTOCAT)).unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                "Host example.com\n    User me\n\n{}\n{}{}\n",
+                BEGIN_MARKER, OCTOCAT, END_MARKER
+            )
+        );
+        assert_eq!(
+            update_block("", "github-octocat", Some(OCTOCAT)).unwrap(),
+            format!("{}\n{}{}\n", BEGIN_MARKER, OCTOCAT, END_MARKER)
+        );
+    }
+
+    #[test]
+    fn replaces_entries_in_place() {
+        let content = format!(
+            "Host example.com\n    User me\n\n{}\n{}{}{}\nHost *\n    AddKeysToAgent yes\n",
+            BEGIN_MARKER, OCTOCAT, HUBOT, END_MARKER
+        );
+        let changed = OCTOCAT.replace("gitswitchhub_octocat", "id_ed25519");
+        let updated = update_block(&content, "github-octocat", Some(&changed)).unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                "Host example.com\n    User me\n\n{}\n{}{}{}\nHost *\n    AddKeysToAgent yes\n",
+                BEGIN_MARKER, changed, HUBOT, END_MARKER
+            )
+        );
+        // Writing the same entry again changes nothing
+        assert_eq!(
+            update_block(&updated, "github-octocat", Some(&changed)).unwrap(),
+            updated
+        );
+    }
+
+    #[test]
+    fn removes_entries_and_empty_block() {
+        let content = format!(
+            "Host example.com\n    User me\n\n{}\n{}{}{}\n",
+            BEGIN_MARKER, OCTOCAT, HUBOT, END_MARKER
+        );
+        let updated = update_block(&content, "github-hubot", None).unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                "Host example.com\n    User me\n\n{}\n{}{}\n",
+                BEGIN_MARKER, OCTOCAT, END_MARKER
+            )
+        );
+        assert_eq!(
+            update_block(&updated, "github-octocat", None).unwrap(),
+            "Host example.com\n    User me\n"
+        );
+        // Removing an alias that isn't there leaves the config alone
+        assert_eq!(
+            update_block("Host example.com\n", "github-octocat", None).unwrap(),
+            "Host example.com\n"
+        );
+    }
+
+    #[test]
+    fn replaces_legacy_entries() {
+        let content = format!("Host example.com\n    User me\n\n{}", OCTOCAT);
+        let updated = update_block(&content, "github-octocat", Some(OCTOCAT)).unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                "Host example.com\n    User me\n\n{}\n{}{}\n",
+                BEGIN_MARKER, OCTOCAT, END_MARKER
+            )
+        );
+
+        // An entry edited by hand isn't ours to remove
+        let edited = format!("{}    Port 443\n", OCTOCAT);
+        assert_eq!(
+            update_block(&edited, "github-octocat", None).unwrap(),
+            edited
+        );
+    }
+
+    #[test]
+    fn unterminated_block_is_refused() {
+        let content = format!("{}\n{}", BEGIN_MARKER, OCTOCAT);
+        assert_eq!(update_block(&content, "github-octocat", None), None);
+    }
+
+    #[test]
+    fn include_goes_at_the_top() {
+        let content = format!(
+            "Host example.com\n    User me\n\n{}\n{}{}\n",
+            BEGIN_MARKER, OCTOCAT, END_MARKER
+        );
+        let config = ManagedConfig::parse(&content).unwrap();
+        assert_eq!(
+            config.render(
+                "github-octocat",
+                Some("Include gitswitchhub_config\n"),
+                true
+            ),
+            format!(
+                "{}\nInclude gitswitchhub_config\n{}\n\nHost example.com\n    User me\n",
+                BEGIN_MARKER, END_MARKER
+            )
+        );
+    }
+
+    #[test]
+    fn parses_openssh_versions() {
+        assert_eq!(
+            parse_openssh_version("OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024"),
+            Some((9, 6))
+        );
+        assert_eq!(
+            parse_openssh_version("OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2"),
+            Some((8, 1))
+        );
+        assert_eq!(
+            parse_openssh_version("OpenSSH_7.10, OpenSSL 1.0.2k"),
+            Some((7, 10))
+        );
+        assert_eq!(parse_openssh_version("ssh: command not found"), None);
+    }
+
+    #[test]
+    fn wildcard_match_handles_star_and_question_mark() {
+        assert!(wildcard_match("github.com", "github.com"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*.github.com", "ssh.github.com"));
+        assert!(!wildcard_match("*.github.com", "github.com"));
+        assert!(wildcard_match("git?ub.com", "github.com"));
+        assert!(!wildcard_match("git?ub.com", "gitub.com"));
+        assert!(wildcard_match("g*b*m", "github.com"));
+        assert!(!wildcard_match("g*b*x", "github.com"));
+    }
+
+    #[test]
+    fn host_matches_like_ssh() {
+        let patterns = |patterns: &[&str]| -> Vec<String> {
+            patterns.iter().map(|pattern| pattern.to_string()).collect()
+        };
+        assert!(host_matches(&patterns(&["*"]), "github-octocat"));
+        assert!(host_matches(
+            &patterns(&["gitlab.com", "GitHub.com"]),
+            "github.com"
+        ));
+        assert!(!host_matches(&patterns(&["gitlab.com"]), "github.com"));
+        // A negated pattern wins over any match, and alone matches nothing
+        assert!(!host_matches(
+            &patterns(&["*", "!github-*"]),
+            "github-octocat"
+        ));
+        assert!(host_matches(&patterns(&["*", "!github-*"]), "github.com"));
+        assert!(!host_matches(&patterns(&["!github.com"]), "example.com"));
+    }
+
+    #[test]
+    fn parses_github_greeting() {
+        assert_eq!(
+            parse_greeting(
+                "Warning: Permanently added 'github.com' (ED25519) to the list of known hosts.\n\
+                 Hi octocat! You've successfully authenticated, but GitHub does not provide shell access.\n"
+            ),
+            Some("octocat".to_string())
+        );
+        assert_eq!(
+            parse_greeting("Hi octo-org/deploy-repo! You've successfully authenticated"),
+            Some("octo-org/deploy-repo".to_string())
+        );
+        assert_eq!(
+            parse_greeting("git@github.com: Permission denied (publickey)."),
+            None
+        );
+        assert_eq!(parse_greeting("Hi there, welcome!"), None);
+        assert_eq!(
+            parse_greeting("Hi ! You've successfully authenticated"),
+            None
+        );
+    }
+
+    #[test]
+    fn line_diff_lists_changed_lines() {
+        assert_eq!(line_diff("", ""), "");
+        assert_eq!(line_diff("a\nb\n", "a\nb\n"), "");
+        assert_eq!(line_diff("", "a\nb\n"), "+a\n+b\n");
+        assert_eq!(line_diff("a\nb\n", ""), "-a\n-b\n");
+        assert_eq!(line_diff("a\nb\nc\n", "a\nB\nc\n"), "-b\n+B\n");
+        assert_eq!(line_diff("a\nb\nc\nd\n", "a\nc\nd\ne\n"), "-b\n+e\n");
+    }
 }
@@ -0,0 +1,324 @@
+use crate::commands::{self, DoctorCheckInfo};
+use crate::database::{Account, Database, DatabaseError};
+use crate::github_auth::DEFAULT_GITHUB_HOST;
+use crate::github_client::GitHubClient;
+use crate::resolver;
+use crate::settings::SettingKey;
+use tauri::image::Image;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Emitter, Listener, Manager, Wry};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::Notify;
+use tracing::warn;
+
+// The tray icon shows the default account's avatar, and its menu switches the
+// default without opening the window. It is rebuilt when the commands emit
+// accounts-changed, and when the pointer moves over it, which picks up
+// changes made with `gitswitchhub use`.
+
+const TRAY_ID: &str = "gitswitchhub";
+const ACCOUNT_ITEM_PREFIX: &str = "account:";
+const DOCTOR_ITEM: &str = "doctor";
+const OPEN_ITEM: &str = "open";
+const QUIT_ITEM: &str = "quit";
+const MAIN_WINDOW: &str = "main";
+
+// Asks the window to show a view, "doctor"
+pub const OPEN_VIEW_EVENT: &str = "open-view";
+// Pixels, asked of GitHub and enforced for servers that ignore the size
+const AVATAR_SIZE: u32 = 64;
+
+// Wakes the watcher to rebuild the tray
+#[derive(Default)]
+pub struct TrayRefresh(Notify);
+
+// What the tray shows
+#[derive(Debug)]
+struct TrayState {
+    visible: bool,
+    default_id: Option<String>,
+    accounts: Vec<TrayAccount>,
+}
+
+#[derive(Debug)]
+struct TrayAccount {
+    id: String,
+    username: String,
+    host: String,
+    label: Option<String>,
+    avatar_url: Option<String>,
+}
+
+impl From<Account> for TrayAccount {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            username: account.username,
+            host: account.host,
+            label: account.label,
+            avatar_url: account.avatar_url,
+        }
+    }
+}
+
+impl TrayState {
+    fn default_account(&self) -> Option<&TrayAccount> {
+        self.accounts
+            .iter()
+            .find(|account| self.default_id.as_deref() == Some(account.id.as_str()))
+    }
+}
+
+// Shows the app icon until the watcher has read the accounts
+pub fn create(app: &AppHandle) -> tauri::Result<TrayIcon> {
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("GitSwitchHub")
+        .show_menu_on_left_click(true)
+        .on_menu_event(on_menu_event)
+        // Linux sends no tray events, there only the app's own changes show
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Enter { .. } = event {
+                refresh(tray.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)
+}
+
+// Shows the accounts, the default account and the show_tray setting as they
+// are now, then again on every refresh. Runs for the lifetime of the app.
+pub async fn watch(app: AppHandle) {
+    let handle = app.clone();
+    app.listen_any(commands::ACCOUNTS_CHANGED_EVENT, move |_| refresh(&handle));
+    // The avatar last downloaded, by url
+    let mut avatar: Option<(String, Image<'static>)> = None;
+    let requests = app.state::<TrayRefresh>();
+    loop {
+        match read_state(&app).await {
+            Ok(state) => {
+                if let Err(e) = update(&app, &state, &mut avatar).await {
+                    warn!(error = %e, "failed to update the tray");
+                }
+            }
+            Err(e) => warn!(error = %e, "failed to read the accounts for the tray"),
+        }
+        requests.0.notified().await;
+    }
+}
+
+// Has the watcher rebuild the tray
+pub fn refresh(app: &AppHandle) {
+    app.state::<TrayRefresh>().0.notify_one();
+}
+
+async fn read_state(app: &AppHandle) -> Result<TrayState, DatabaseError> {
+    app.state::<Database>()
+        .run(|db| {
+            let mut accounts: Vec<TrayAccount> = db
+                .get_accounts()?
+                .into_iter()
+                .map(TrayAccount::from)
+                .collect();
+            accounts.sort_by_key(|account| account.username.to_lowercase());
+            Ok(TrayState {
+                visible: db
+                    .get_setting(SettingKey::ShowTray)?
+                    .as_bool()
+                    .unwrap_or(true),
+                default_id: resolver::default_account_id(db)?,
+                accounts,
+            })
+        })
+        .await
+}
+
+async fn update(
+    app: &AppHandle,
+    state: &TrayState,
+    avatar: &mut Option<(String, Image<'static>)>,
+) -> tauri::Result<()> {
+    let tray = match app.tray_by_id(TRAY_ID) {
+        Some(tray) => tray,
+        None => create(app)?,
+    };
+    tray.set_visible(state.visible)?;
+    if !state.visible {
+        return Ok(());
+    }
+    tray.set_menu(Some(build_menu(app, state)?))?;
+
+    let default = state.default_account();
+    tray.set_tooltip(Some(match default {
+        Some(account) => format!("GitSwitchHub: {}", account_title(account)),
+        None => "GitSwitchHub: no default account".to_string(),
+    }))?;
+
+    match default.and_then(|account| account.avatar_url.as_deref()) {
+        Some(url) if avatar.as_ref().is_some_and(|(shown, _)| shown == url) => {}
+        Some(url) => match fetch_avatar(url).await {
+            Ok(image) => *avatar = Some((url.to_string(), image)),
+            Err(e) => {
+                warn!(error = %e, "failed to download the avatar for the tray");
+                *avatar = None;
+            }
+        },
+        None => *avatar = None,
+    }
+    let icon = match avatar {
+        Some((_, image)) => Some(image.clone()),
+        None => app.default_window_icon().cloned(),
+    };
+    tray.set_icon(icon)
+}
+
+fn build_menu(app: &AppHandle, state: &TrayState) -> tauri::Result<Menu<Wry>> {
+    let menu = Menu::new(app)?;
+    if state.accounts.is_empty() {
+        menu.append(&MenuItem::new(app, "No accounts yet", false, None::<&str>)?)?;
+    } else {
+        menu.append(&MenuItem::new(app, "Default account", false, None::<&str>)?)?;
+        for account in &state.accounts {
+            menu.append(&CheckMenuItem::with_id(
+                app,
+                format!("{}{}", ACCOUNT_ITEM_PREFIX, account.id),
+                account_title(account),
+                true,
+                state.default_id.as_deref() == Some(account.id.as_str()),
+                None::<&str>,
+            )?)?;
+        }
+    }
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        DOCTOR_ITEM,
+        "Run Doctor",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        OPEN_ITEM,
+        "Open GitSwitchHub",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app)?)?;
+    menu.append(&MenuItem::with_id(
+        app,
+        QUIT_ITEM,
+        "Quit GitSwitchHub",
+        true,
+        None::<&str>,
+    )?)?;
+    Ok(menu)
+}
+
+// "octocat", "octocat (work)", or "octocat on ghe.example.com" for
+// accounts on other hosts
+fn account_title(account: &TrayAccount) -> String {
+    let mut title = account.username.clone();
+    if account.host != DEFAULT_GITHUB_HOST {
+        title.push_str(&format!(" on {}", account.host));
+    }
+    if let Some(label) = &account.label {
+        title.push_str(&format!(" ({})", label));
+    }
+    title
+}
+
+fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id.as_ref() {
+        DOCTOR_ITEM => {
+            tauri::async_runtime::spawn(run_doctor(app.clone()));
+        }
+        OPEN_ITEM => show_window(app, None),
+        QUIT_ITEM => app.exit(0),
+        id => {
+            if let Some(account_id) = id.strip_prefix(ACCOUNT_ITEM_PREFIX) {
+                tauri::async_runtime::spawn(set_default(app.clone(), account_id.to_string()));
+            }
+        }
+    }
+}
+
+// Success emits accounts-changed, which rebuilds the menu. On failure it is
+// rebuilt anyway, since clicking ticked the item.
+async fn set_default(app: AppHandle, account_id: String) {
+    if let Err(e) = commands::set_default_account(app.clone(), app.state(), Some(account_id)).await
+    {
+        warn!(error = %e, "failed to set the default account from the tray");
+        refresh(&app);
+    }
+}
+
+// Reports the outcome as a notification, and opens the doctor in the window
+// when something needs fixing
+async fn run_doctor(app: AppHandle) {
+    let report = match commands::run_doctor(app.state(), app.state()).await {
+        Ok(report) => report,
+        Err(e) => {
+            warn!(error = %e, "failed to run the doctor from the tray");
+            return;
+        }
+    };
+    let problems: Vec<&DoctorCheckInfo> = report
+        .checks
+        .iter()
+        .filter(|check| check.status == "warning" || check.status == "error")
+        .collect();
+    let body = match problems[..] {
+        [] => "Everything checks out".to_string(),
+        [check] => check.message.clone(),
+        [check, ref rest @ ..] => format!("{} (and {} more)", check.message, rest.len()),
+    };
+    if let Err(e) = app
+        .notification()
+        .builder()
+        .title("GitSwitchHub doctor")
+        .body(&body)
+        .show()
+    {
+        warn!(error = %e, "failed to show the doctor notification");
+    }
+    if !report.ok {
+        show_window(&app, Some(DOCTOR_ITEM));
+    }
+}
+
+fn show_window(app: &AppHandle, view: Option<&str>) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    if let Some(view) = view {
+        let _ = app.emit(OPEN_VIEW_EVENT, view);
+    }
+}
+
+async fn fetch_avatar(url: &str) -> Result<Image<'static>, String> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let client = GitHubClient::new();
+    let bytes = client
+        .send(client.get(format!("{}{}s={}", url, separator, AVATAR_SIZE)))
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| e.to_string())?
+        .resize(
+            AVATAR_SIZE,
+            AVATAR_SIZE,
+            image::imageops::FilterType::Triangle,
+        )
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+    Ok(Image::new_owned(image.into_raw(), width, height))
+}
@@ -0,0 +1,174 @@
+use crate::git_config::{self, GitConfigError, GitConfigScope};
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+// Routes whole GitHub owners through an account's SSH alias with git's
+// url.<base>.insteadOf, as an alternative to editing every remote:
+//   [url "git@github-me:my-org/"]
+//       insteadOf = https://github.com/my-org/
+//       insteadOf = git@github.com:my-org/
+// The rules go in the global config, or in a file per alias pulled in with
+// includeIf "gitdir:<dir>/" so that only repositories under <dir> use them.
+
+#[derive(Error, Debug)]
+pub enum UrlRewriteError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Git config error: {0}")]
+    GitConfig(#[from] GitConfigError),
+    #[error("Invalid GitHub owner: {0}")]
+    InvalidOwner(String),
+    #[error("Not an absolute directory: {0}")]
+    InvalidDirectory(String),
+}
+
+// The spellings of a GitHub url a rule rewrites
+const GITHUB_PREFIXES: [&str; 3] = [
+    "https://github.com/",
+    "git@github.com:",
+    "ssh://git@github.com/",
+];
+
+const RULES_DIR: &str = "gitconfig";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlRewrite {
+    pub alias: String,
+    pub owner: String,
+    // Where repositories use the rule; empty when it is in the global config
+    pub directories: Vec<String>,
+}
+
+// Replaces the rules of the alias with ones for `owners`, in the global
+// config or, given a directory, for the repositories under it only
+pub fn set_rules(
+    alias: &str,
+    owners: &[String],
+    directory: Option<&str>,
+) -> Result<(), UrlRewriteError> {
+    if let Some(owner) = owners.iter().find(|owner| {
+        owner.is_empty() || !owner.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    }) {
+        return Err(UrlRewriteError::InvalidOwner(owner.clone()));
+    }
+    let directory = directory
+        .map(|dir| {
+            git_config::gitdir_condition(dir)
+                .ok_or_else(|| UrlRewriteError::InvalidDirectory(dir.to_string()))
+        })
+        .transpose()?;
+
+    remove_rules(alias)?;
+    let scope = match directory {
+        Some(condition) => {
+            let file = rules_file(alias)?;
+            fs::create_dir_all(file.parent().unwrap_or(Path::new(".")))?;
+            git_config::set_value(
+                &GitConfigScope::Global,
+                &format!("includeIf.{}.path", condition),
+                &file.display().to_string(),
+            )?;
+            GitConfigScope::File(file)
+        }
+        None => GitConfigScope::Global,
+    };
+    for owner in owners {
+        let key = format!("url.git@{}:{}/.insteadOf", alias, owner);
+        for prefix in GITHUB_PREFIXES {
+            git_config::add_value(&scope, &key, &format!("{}{}/", prefix, owner))?;
+        }
+    }
+    Ok(())
+}
+
+// Removes the alias's rules wherever they are
+pub fn remove_rules(alias: &str) -> Result<(), UrlRewriteError> {
+    for (_, owner) in read_rules(&GitConfigScope::Global)?
+        .into_iter()
+        .filter(|(rule_alias, _)| rule_alias == alias)
+    {
+        git_config::remove_section(
+            &GitConfigScope::Global,
+            &format!("url.git@{}:{}/", alias, owner),
+        )?;
+    }
+
+    let file = rules_file(alias)?;
+    let file_value = file.display().to_string();
+    for (key, value) in includes()? {
+        if value == file_value {
+            git_config::unset_fixed_value(&GitConfigScope::Global, &key, &value)?;
+        }
+    }
+    match fs::remove_file(&file) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Every rule for an SSH alias, global ones and those in includeIf'd files
+pub fn list_rules() -> Result<Vec<UrlRewrite>, UrlRewriteError> {
+    let mut rules: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+    for rule in read_rules(&GitConfigScope::Global)? {
+        rules.entry(rule).or_default();
+    }
+
+    let rules_dir = paths::app_data_dir()?.join(RULES_DIR);
+    for (key, value) in includes()? {
+        let file = PathBuf::from(&value);
+        if file.parent() != Some(rules_dir.as_path()) || !file.exists() {
+            continue;
+        }
+        let directory = git_config::gitdir_directory(&key);
+        for rule in read_rules(&GitConfigScope::File(file))? {
+            let directories = rules.entry(rule).or_default();
+            directories.extend(directory.clone());
+        }
+    }
+
+    Ok(rules
+        .into_iter()
+        .map(|((alias, owner), directories)| UrlRewrite {
+            alias,
+            owner,
+            directories,
+        })
+        .collect())
+}
+
+// (alias, owner) of the url.git@github-*:<owner>/ sections in the scope
+fn read_rules(scope: &GitConfigScope) -> Result<Vec<(String, String)>, UrlRewriteError> {
+    let mut rules = Vec::new();
+    for (key, _) in git_config::get_regexp(scope, r"^url\.git@github-.*\.insteadof$")? {
+        let Some((alias, owner)) = key
+            .strip_prefix("url.git@")
+            .and_then(|base| base.strip_suffix("/.insteadof"))
+            .and_then(|base| base.split_once(':'))
+        else {
+            continue;
+        };
+        let rule = (alias.to_string(), owner.to_string());
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+    Ok(rules)
+}
+
+fn includes() -> Result<Vec<(String, String)>, UrlRewriteError> {
+    Ok(git_config::get_regexp(
+        &GitConfigScope::Global,
+        r"^includeif\..*\.path$",
+    )?)
+}
+
+fn rules_file(alias: &str) -> io::Result<PathBuf> {
+    Ok(paths::app_data_dir()?
+        .join(RULES_DIR)
+        .join(format!("{}.gitconfig", alias)))
+}
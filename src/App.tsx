@@ -1,5 +1,7 @@
-import React, { useState, useEffect } from 'react';
+import React, { useState, useEffect, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { getCurrentWindow } from '@tauri-apps/api/window';
+import { listen } from '@tauri-apps/api/event';
 import './App.css';
 
 interface Account {
@@ -34,8 +36,193 @@ interface AccountSelectionPopup {
   activity: GitActivity | null;
 }
 
+// A checkout that appeared in a project directory with no mapping yet
+interface NewRepository {
+  path: string;
+  remote_url: string;
+  repo_name: string | null;
+  suggested_account: Account | null;
+  reason: string | null;
+}
+
+// A token about to expire, or one GitHub no longer accepts
+interface TokenExpiry {
+  account_id: string;
+  username: string;
+  host: string;
+  status: 'expiring' | 'expired' | 'invalid';
+  days_left: number | null;
+  message: string;
+}
+
+interface DoctorFix {
+  label: string;
+  // Command to invoke with args; null for steps done by hand
+  command: string | null;
+  args: Record<string, unknown> | null;
+}
+
+interface DoctorCheck {
+  kind: string;
+  account_id: string | null;
+  status: 'ok' | 'warning' | 'error' | 'skipped';
+  message: string;
+  fixes: DoctorFix[];
+}
+
+interface DoctorReport {
+  ok: boolean;
+  checks: DoctorCheck[];
+}
+
+interface ChooserAccount {
+  id: string;
+  username: string;
+  host: string;
+  avatar_url?: string;
+  display_name?: string;
+  label?: string;
+}
+
+interface ChooserRequest {
+  id: string;
+  repo_url: string;
+  repo_name?: string;
+  host: string;
+  accounts: ChooserAccount[];
+  preselected_id?: string;
+  offer_remember: boolean;
+}
+
+// Shown in the window the app opens for a credential helper waiting on
+// index.html?chooser=<request id>. Closing the window counts as cancelling.
+function ChooserView({ requestId }: { requestId: string }) {
+  const [request, setRequest] = useState<ChooserRequest | null>(null);
+  const [selectedId, setSelectedId] = useState<string | null>(null);
+  const [remember, setRemember] = useState(true);
+  const [error, setError] = useState<string | null>(null);
+  const answered = useRef(false);
+
+  useEffect(() => {
+    invoke<ChooserRequest>('get_chooser_request', { requestId })
+      .then(data => {
+        setRequest(data);
+        setSelectedId(data.preselected_id ?? data.accounts[0]?.id ?? null);
+      })
+      .catch(err => setError(`Failed to load the request: ${err}`));
+
+    const unlisten = getCurrentWindow().onCloseRequested(async () => {
+      if (!answered.current) {
+        answered.current = true;
+        await invoke('answer_chooser_request', { requestId, accountId: null, remember: false })
+          .catch(err => console.error('Failed to cancel the request:', err));
+      }
+    });
+    return () => {
+      unlisten.then(fn => fn());
+    };
+  }, [requestId]);
+
+  const answer = async (accountId: string | null) => {
+    if (answered.current) return;
+    answered.current = true;
+    try {
+      await invoke('answer_chooser_request', {
+        requestId,
+        accountId,
+        remember: accountId !== null && !!request?.offer_remember && remember
+      });
+      await getCurrentWindow().close();
+    } catch (err) {
+      answered.current = false;
+      setError(`Failed to answer git: ${err}`);
+    }
+  };
+
+  useEffect(() => {
+    const onKeyDown = (e: KeyboardEvent) => {
+      if (e.key === 'Escape') {
+        answer(null);
+      } else if (e.key === 'Enter' && selectedId) {
+        answer(selectedId);
+      }
+    };
+    window.addEventListener('keydown', onKeyDown);
+    return () => window.removeEventListener('keydown', onKeyDown);
+  });
+
+  return (
+    <div className="chooser">
+      <div className="popup-header">
+        <h3>Choose a GitHub account</h3>
+        <p>
+          Git needs credentials for{' '}
+          <strong>{request ? (request.repo_name ?? request.repo_url) : '…'}</strong>
+          {request && request.host !== 'github.com' && <> on {request.host}</>}
+        </p>
+      </div>
+
+      {error && (
+        <div className="alert error">
+          <span>{error}</span>
+        </div>
+      )}
+
+      <div className="account-selection">
+        {request?.accounts.map(account => (
+          <div
+            key={account.id}
+            className={`account-option ${selectedId === account.id ? 'selected' : ''}`}
+            onClick={() => setSelectedId(account.id)}
+            onDoubleClick={() => answer(account.id)}
+          >
+            {account.avatar_url && (
+              <img src={account.avatar_url} alt={account.username} className="account-avatar" />
+            )}
+            <div className="account-details">
+              <h4>{account.username}</h4>
+              <p>{[account.display_name, account.label].filter(Boolean).join(' · ') || account.host}</p>
+            </div>
+          </div>
+        ))}
+      </div>
+
+      {request?.offer_remember && (
+        <div className="form-group">
+          <label>
+            <input
+              type="checkbox"
+              checked={remember}
+              onChange={(e) => setRemember(e.target.checked)}
+            />
+            Remember this account for {request.repo_name ?? request.repo_url}
+          </label>
+        </div>
+      )}
+
+      <div className="popup-actions chooser-actions">
+        <button onClick={() => answer(null)} className="btn-secondary">
+          Cancel
+        </button>
+        <button onClick={() => selectedId && answer(selectedId)} disabled={!selectedId}>
+          Use account
+        </button>
+      </div>
+    </div>
+  );
+}
+
 function App() {
+  const chooserId = new URLSearchParams(window.location.search).get('chooser');
+  if (chooserId) {
+    return <ChooserView requestId={chooserId} />;
+  }
+  return <MainApp />;
+}
+
+function MainApp() {
   const [accounts, setAccounts] = useState<Account[]>([]);
+  const [defaultAccountId, setDefaultAccountId] = useState<string | null>(null);
   const [mappings, setMappings] = useState<RepositoryMapping[]>([]);
   const [gitHelperStatus, setGitHelperStatus] = useState<GitHelperStatus>({ installed: false, configured: false });
   const [activeTab, setActiveTab] = useState<'accounts' | 'mappings' | 'settings'>('accounts');
@@ -60,10 +247,46 @@ function App() {
   const [newMappingAccountId, setNewMappingAccountId] = useState('');
   const [newMappingRemember, setNewMappingRemember] = useState(true);
 
+  const [newRepository, setNewRepository] = useState<NewRepository | null>(null);
+  const [tokenExpiries, setTokenExpiries] = useState<TokenExpiry[]>([]);
+
+  const [doctorReport, setDoctorReport] = useState<DoctorReport | null>(null);
+  const [doctorRunning, setDoctorRunning] = useState(false);
+
   useEffect(() => {
     loadData();
     checkAutoDetectionStatus();
-    setupEventListeners();
+    invoke<TokenExpiry[]>('get_token_expiry')
+      .then(setTokenExpiries)
+      .catch(err => console.error('Failed to check token expiry:', err));
+  }, []);
+
+  // Events from the backend: the tray asking for a view, account changes
+  // made outside this window, new checkouts and tokens needing attention
+  useEffect(() => {
+    const unlisteners = [
+      listen<string>('open-view', (event) => {
+        if (event.payload === 'doctor') {
+          setActiveTab('settings');
+          runDoctor();
+        }
+      }),
+      listen('accounts-changed', () => {
+        loadData();
+      }),
+      listen<NewRepository>('new-repository', (event) => {
+        setNewRepository(event.payload);
+      }),
+      listen<TokenExpiry>('token-expiry', (event) => {
+        setTokenExpiries(current => [
+          ...current.filter(expiry => expiry.account_id !== event.payload.account_id),
+          event.payload
+        ]);
+      }),
+    ];
+    return () => {
+      unlisteners.forEach(unlisten => unlisten.then(fn => fn()));
+    };
   }, []);
 
   const checkAutoDetectionStatus = async () => {
@@ -76,22 +299,18 @@ function App() {
     }
   };
 
-  const setupEventListeners = () => {
-    // Listen for Git activity events from the backend
-    // This would be implemented when we add the actual background monitoring
-    // For now, this is a placeholder for future implementation
-  };
-
   const loadData = async () => {
     setLoading(true);
     try {
-      const [accountsData, mappingsData, gitStatus] = await Promise.all([
+      const [accountsData, mappingsData, gitStatus, defaultAccount] = await Promise.all([
         invoke<Account[]>('get_accounts'),
         invoke<RepositoryMapping[]>('get_repository_mappings'),
-        invoke<GitHelperStatus>('get_git_helper_status')
+        invoke<GitHelperStatus>('get_git_helper_status'),
+        invoke<Account | null>('get_default_account')
       ]);
       
       setAccounts(accountsData);
+      setDefaultAccountId(defaultAccount?.id ?? null);
       setMappings(mappingsData);
       setGitHelperStatus(gitStatus);
       setError(null);
@@ -102,6 +321,18 @@ function App() {
     }
   };
 
+  const setDefaultAccount = async (accountId: string) => {
+    setLoading(true);
+    try {
+      await invoke('set_default_account', { accountId });
+      setDefaultAccountId(accountId);
+    } catch (err) {
+      setError(`Failed to set the default account: ${err}`);
+    } finally {
+      setLoading(false);
+    }
+  };
+
   const addAccount = async (e: React.FormEvent) => {
     e.preventDefault();
     if (!newAccountUsername || !newAccountToken) {
@@ -213,6 +444,31 @@ function App() {
     }
   };
 
+  const runDoctor = async () => {
+    setDoctorRunning(true);
+    try {
+      setDoctorReport(await invoke<DoctorReport>('run_doctor'));
+    } catch (err) {
+      setError(`Failed to run the doctor: ${err}`);
+    } finally {
+      setDoctorRunning(false);
+    }
+  };
+
+  const applyDoctorFix = async (fix: DoctorFix) => {
+    if (!fix.command) return;
+    setLoading(true);
+    try {
+      await invoke(fix.command, fix.args ?? {});
+      setSuccess(`${fix.label}: done`);
+      await runDoctor();
+    } catch (err) {
+      setError(`${fix.label} failed: ${err}`);
+    } finally {
+      setLoading(false);
+    }
+  };
+
   const clearMessages = () => {
     setError(null);
     setSuccess(null);
@@ -280,6 +536,45 @@ function App() {
     setAccountSelectionPopup({ visible: false, activity: null });
   };
 
+  const reauthenticate = async (expiry: TokenExpiry) => {
+    setLoading(true);
+    try {
+      await invoke('reauthenticate_account', { accountId: expiry.account_id });
+      setTokenExpiries(current => current.filter(other => other.account_id !== expiry.account_id));
+      setSuccess(`Signed in again as ${expiry.username}`);
+      await loadData();
+    } catch (err) {
+      setError(`Failed to sign in again: ${err}`);
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  const dismissTokenExpiry = (accountId: string) => {
+    setTokenExpiries(current => current.filter(expiry => expiry.account_id !== accountId));
+  };
+
+  const mapNewRepository = async (accountId: string) => {
+    if (!newRepository) return;
+
+    setLoading(true);
+    try {
+      await invoke('set_repository_mapping', {
+        remoteUrl: newRepository.remote_url,
+        accountId,
+        remember: true,
+        repoPath: newRepository.path
+      });
+      setNewRepository(null);
+      setSuccess(`${newRepository.repo_name ?? newRepository.path} mapped`);
+      await loadData();
+    } catch (err) {
+      setError(`Failed to add mapping: ${err}`);
+    } finally {
+      setLoading(false);
+    }
+  };
+
   return (
     <div className="app">
       <header className="app-header">
@@ -322,6 +617,22 @@ function App() {
         </div>
       )}
 
+      {tokenExpiries.map(expiry => (
+        <div key={expiry.account_id} className="alert warning">
+          <span>{expiry.message}</span>
+          <div>
+            <button
+              onClick={() => reauthenticate(expiry)}
+              disabled={loading}
+              className="alert-action"
+            >
+              Sign in again
+            </button>
+            <button onClick={() => dismissTokenExpiry(expiry.account_id)}>×</button>
+          </div>
+        </div>
+      ))}
+
       <main className="app-content">
         {activeTab === 'accounts' && (
           <div className="tab-content">
@@ -340,7 +651,10 @@ function App() {
                       <img src={account.avatar_url} alt={account.username} className="avatar" />
                     )}
                     <div>
-                      <h3>{account.username}</h3>
+                      <h3>
+                        {account.username}
+                        {account.id === defaultAccountId && <span className="badge">Default</span>}
+                      </h3>
                       <p>Added: {new Date(account.created_at).toLocaleDateString()}</p>
                     </div>
                   </div>
@@ -351,6 +665,14 @@ function App() {
                     >
                       Test Connection
                     </button>
+                    {account.id !== defaultAccountId && (
+                      <button
+                        onClick={() => setDefaultAccount(account.id)}
+                        disabled={loading}
+                      >
+                        Make Default
+                      </button>
+                    )}
                     <button 
                       onClick={() => removeAccount(account.id)}
                       disabled={loading}
@@ -536,6 +858,45 @@ function App() {
               </div>
             </div>
 
+            <div className="settings-section">
+              <h3>Doctor</h3>
+              <div className="setting-item">
+                <div className="setting-info">
+                  <h4>Health check</h4>
+                  <p>
+                    {doctorReport
+                      ? (doctorReport.ok ? 'Everything checks out' : 'Some checks need attention')
+                      : 'Checks git, the credential helper, tokens, SSH and the database'}
+                  </p>
+                </div>
+                <button onClick={runDoctor} disabled={doctorRunning}>
+                  {doctorRunning ? 'Checking...' : 'Run Doctor'}
+                </button>
+              </div>
+              {doctorReport && (
+                <div className="doctor-checks">
+                  {doctorReport.checks.map((check, i) => (
+                    <div key={`${check.kind}-${check.account_id ?? i}`} className="doctor-check">
+                      <span className={`status ${check.status}`}>{check.status}</span>
+                      <span className="doctor-message">{check.message}</span>
+                      {check.fixes.map(fix => fix.command ? (
+                        <button
+                          key={fix.label}
+                          onClick={() => applyDoctorFix(fix)}
+                          disabled={loading}
+                          className="secondary"
+                        >
+                          {fix.label}
+                        </button>
+                      ) : (
+                        <small key={fix.label}>{fix.label}</small>
+                      ))}
+                    </div>
+                  ))}
+                </div>
+              )}
+            </div>
+
             <div className="settings-section">
               <h3>About</h3>
               <p>
@@ -604,6 +965,56 @@ function App() {
           </div>
         </div>
       )}
+
+      {/* New checkout in a project directory */}
+      {newRepository && (
+        <div className="popup-overlay">
+          <div className="popup-content">
+            <div className="popup-header">
+              <h3>New Repository</h3>
+              <p>Which account should {newRepository.repo_name ?? newRepository.path} use?</p>
+            </div>
+
+            <div className="activity-info">
+              <div className="activity-item">
+                <strong>Path:</strong> {newRepository.path}
+              </div>
+              <div className="activity-item">
+                <strong>Remote:</strong> {newRepository.remote_url}
+              </div>
+              {newRepository.reason && (
+                <div className="activity-item">
+                  <strong>For now:</strong> {newRepository.reason}
+                </div>
+              )}
+            </div>
+
+            <div className="account-selection">
+              {accounts.map(account => (
+                <div
+                  key={account.id}
+                  className={`account-option${account.id === newRepository.suggested_account?.id ? ' selected' : ''}`}
+                  onClick={() => !loading && mapNewRepository(account.id)}
+                >
+                  {account.avatar_url && (
+                    <img src={account.avatar_url} alt={account.username} className="account-avatar" />
+                  )}
+                  <div className="account-details">
+                    <h4>{account.username}</h4>
+                    <p>Added: {new Date(account.created_at).toLocaleDateString()}</p>
+                  </div>
+                </div>
+              ))}
+            </div>
+
+            <div className="popup-actions">
+              <button onClick={() => setNewRepository(null)} className="btn-secondary">
+                Not now
+              </button>
+            </div>
+          </div>
+        </div>
+      )}
     </div>
   );
 }